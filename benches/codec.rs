@@ -0,0 +1,232 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::{Deserialize, Serialize};
+use window_post_snark_server::codec::{decode, encode, supported_encodings};
+
+// Payloads shaped as the window PoSt vanilla proofs and public inputs filecoin-proofs
+// serializes, without building sealed sectors: a domain element is 32 bytes, and an
+// inclusion proof has one path element per level of each of the trees of the shape.
+
+type Domain = [u8; 32];
+
+#[derive(Serialize, Deserialize)]
+struct PathElement {
+    hashes: Vec<Domain>,
+    index: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InclusionPath {
+    path: Vec<PathElement>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SingleProof {
+    root: Domain,
+    leaf: Domain,
+    path: InclusionPath,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubProof {
+    base_proof: InclusionPath,
+    sub_proof: InclusionPath,
+    root: Domain,
+    leaf: Domain,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TopProof {
+    base_proof: InclusionPath,
+    sub_proof: InclusionPath,
+    top_proof: InclusionPath,
+    root: Domain,
+    leaf: Domain,
+}
+
+#[derive(Serialize, Deserialize)]
+enum ProofData {
+    Single(SingleProof),
+    Sub(SubProof),
+    Top(TopProof),
+}
+
+#[derive(Serialize, Deserialize)]
+struct MerkleProof {
+    data: ProofData,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SectorProof {
+    inclusion_proofs: Vec<MerkleProof>,
+    comm_c: Domain,
+    comm_r_last: Domain,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VanillaProof {
+    sectors: Vec<SectorProof>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PublicSector {
+    id: u64,
+    comm_r: Domain,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PublicInputs {
+    randomness: Domain,
+    prover_id: Domain,
+    sectors: Vec<PublicSector>,
+    k: Option<usize>,
+}
+
+/// Window PoSt of one partition of a sector size
+struct Shape {
+    name: &'static str,
+    // sectors proved per partition
+    sector_count: usize,
+    challenge_count: usize,
+    // levels of the oct base trees, and whether there are sub and top trees
+    base_levels: usize,
+    sub_arity: usize,
+    top_arity: usize,
+}
+
+const SHAPES: [Shape; 2] = [
+    Shape {
+        name: "32GiB",
+        sector_count: 2349,
+        challenge_count: 10,
+        base_levels: 9,
+        sub_arity: 8,
+        top_arity: 0,
+    },
+    Shape {
+        name: "64GiB",
+        sector_count: 2300,
+        challenge_count: 10,
+        base_levels: 9,
+        sub_arity: 8,
+        top_arity: 2,
+    },
+];
+
+fn domain(seed: usize) -> Domain {
+    let mut d = [0u8; 32];
+    for (i, b) in d.iter_mut().enumerate() {
+        *b = (seed.wrapping_mul(31).wrapping_add(i * 7) % 251) as u8;
+    }
+    d
+}
+
+fn path(levels: usize, arity: usize, seed: usize) -> InclusionPath {
+    InclusionPath {
+        path: (0..levels)
+            .map(|l| PathElement {
+                hashes: (0..arity - 1)
+                    .map(|h| domain(seed + l * arity + h))
+                    .collect(),
+                index: (seed + l) % arity,
+            })
+            .collect(),
+    }
+}
+
+fn merkle_proof(shape: &Shape, seed: usize) -> MerkleProof {
+    let base_proof = path(shape.base_levels, 8, seed);
+    let data = match (shape.sub_arity, shape.top_arity) {
+        (0, _) => ProofData::Single(SingleProof {
+            root: domain(seed),
+            leaf: domain(seed + 1),
+            path: base_proof,
+        }),
+        (sub, 0) => ProofData::Sub(SubProof {
+            base_proof,
+            sub_proof: path(1, sub, seed),
+            root: domain(seed),
+            leaf: domain(seed + 1),
+        }),
+        (sub, top) => ProofData::Top(TopProof {
+            base_proof,
+            sub_proof: path(1, sub, seed),
+            top_proof: path(1, top, seed),
+            root: domain(seed),
+            leaf: domain(seed + 1),
+        }),
+    };
+    MerkleProof { data }
+}
+
+fn vanilla_proofs(shape: &Shape) -> Vec<VanillaProof> {
+    vec![VanillaProof {
+        sectors: (0..shape.sector_count)
+            .map(|s| SectorProof {
+                inclusion_proofs: (0..shape.challenge_count)
+                    .map(|c| merkle_proof(shape, s * shape.challenge_count + c))
+                    .collect(),
+                comm_c: domain(s),
+                comm_r_last: domain(s + 1),
+            })
+            .collect(),
+    }]
+}
+
+fn pub_inputs(shape: &Shape) -> PublicInputs {
+    PublicInputs {
+        randomness: domain(1),
+        prover_id: domain(2),
+        sectors: (0..shape.sector_count)
+            .map(|s| PublicSector {
+                id: s as u64,
+                comm_r: domain(s),
+            })
+            .collect(),
+        k: Some(0),
+    }
+}
+
+fn bench_vanilla_proofs(c: &mut Criterion) {
+    for shape in SHAPES.iter() {
+        let proofs = vanilla_proofs(shape);
+        let mut group = c.benchmark_group(format!("vanilla_proofs/{}", shape.name));
+        // a partition is tens of megabytes, keep the run short
+        group.sample_size(10);
+        for encoding in supported_encodings() {
+            let bytes = encode(encoding, &proofs).unwrap();
+            group.throughput(Throughput::Bytes(bytes.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new("encode", encoding),
+                &proofs,
+                |b, proofs| b.iter(|| encode(encoding, proofs).unwrap()),
+            );
+            group.bench_with_input(BenchmarkId::new("decode", encoding), &bytes, |b, bytes| {
+                b.iter(|| decode::<Vec<VanillaProof>>(encoding, bytes).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_pub_inputs(c: &mut Criterion) {
+    for shape in SHAPES.iter() {
+        let pub_in = pub_inputs(shape);
+        let mut group = c.benchmark_group(format!("pub_inputs/{}", shape.name));
+        for encoding in supported_encodings() {
+            let bytes = encode(encoding, &pub_in).unwrap();
+            group.throughput(Throughput::Bytes(bytes.len() as u64));
+            group.bench_with_input(
+                BenchmarkId::new("encode", encoding),
+                &pub_in,
+                |b, pub_in| b.iter(|| encode(encoding, pub_in).unwrap()),
+            );
+            group.bench_with_input(BenchmarkId::new("decode", encoding), &bytes, |b, bytes| {
+                b.iter(|| decode::<PublicInputs>(encoding, bytes).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_vanilla_proofs, bench_pub_inputs);
+criterion_main!(benches);
@@ -1,4 +1,6 @@
+use std::env;
 use std::path::PathBuf;
+use std::process::Command;
 
 fn main() {
     let out_dir = PathBuf::from("src");
@@ -6,4 +8,35 @@ fn main() {
         .out_dir(out_dir)
         .compile(&["src/snark_proof_grpc.proto"], &["src"])
         .unwrap();
+
+    // build info reported in the worker status
+    println!(
+        "cargo:rustc-env=WPS_GIT_COMMIT={}",
+        command_output("git", &["rev-parse", "--short", "HEAD"])
+    );
+    println!(
+        "cargo:rustc-env=WPS_BUILD_DATE={}",
+        command_output("date", &["-u", "+%Y-%m-%dT%H:%M:%SZ"])
+    );
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(k, _)| {
+            k.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .filter(|f| f != "default")
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=WPS_FEATURES={}", features.join(","));
+}
+
+// trimmed stdout of the command, empty when it is missing or fails, e.g. outside a checkout
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
 }
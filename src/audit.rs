@@ -0,0 +1,199 @@
+use crate::dump::sanitize;
+use crate::error::Result;
+use crate::tasks::TaskInfo;
+use crate::utils;
+use filecoin_proofs::PoStConfig;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What was proved for whom and how it went, written for every finished task,
+/// so each proof has a self-contained record for operators
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub task_id: String,
+    // client id the task was submitted with
+    pub tenant: String,
+    pub trace_id: String,
+    pub post_config: Option<PoStConfig>,
+    pub replicas_len: usize,
+    pub priority: bool,
+    // sha256 of the payloads as received and of the proof, hex encoded
+    pub pub_in_sha256: String,
+    pub vanilla_proof_sha256: String,
+    // file the vanilla proofs were read from, its digest is then empty
+    #[serde(default)]
+    pub vanilla_proof_path: String,
+    // empty when the task failed
+    pub proof_sha256: String,
+    // Done or Failed
+    pub outcome: String,
+    // proved on the cpu after the gpu failed
+    #[serde(default)]
+    pub degraded: bool,
+    pub error: String,
+    pub created_at_ms: u64,
+    pub started_at_ms: u64,
+    pub finished_at_ms: u64,
+    pub partitions: usize,
+    pub decode_ms: u64,
+    pub load_params_ms: u64,
+    pub prove_ms: u64,
+    pub server_version: String,
+}
+
+impl AuditRecord {
+    /// Record of a finished task, `error` is empty when it succeeded
+    pub fn new(task_info: &TaskInfo, error: &str) -> Self {
+        let proof_sha256 = if task_info.result.is_empty() {
+            String::new()
+        } else {
+            to_hex(&Sha256::digest(&task_info.result))
+        };
+        AuditRecord {
+            task_id: task_info.task_id.clone(),
+            tenant: task_info.client_id.clone(),
+            trace_id: task_info.trace_id().to_string(),
+            post_config: task_info.post_config.clone(),
+            replicas_len: task_info.replicas_len,
+            priority: task_info.priority,
+            pub_in_sha256: to_hex(&task_info.pub_in_digest),
+            vanilla_proof_sha256: to_hex(&task_info.vanilla_proof_digest),
+            vanilla_proof_path: task_info
+                .vanilla_proof_path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default(),
+            proof_sha256,
+            outcome: task_info.task_status.to_string(),
+            degraded: task_info.degraded,
+            error: error.to_string(),
+            created_at_ms: utils::unix_millis(task_info.created_at),
+            started_at_ms: utils::unix_millis(task_info.started_at),
+            finished_at_ms: utils::unix_millis(task_info.finished_at),
+            partitions: task_info.timings.partitions,
+            decode_ms: task_info.timings.decode.as_millis() as u64,
+            load_params_ms: task_info.timings.load_params.as_millis() as u64,
+            prove_ms: task_info.timings.prove.as_millis() as u64,
+            server_version: utils::version().to_string(),
+        }
+    }
+}
+
+/// Write the record as `<finished unix ms>-<task id>.json` into the audit dir
+pub fn write_audit_record(dir: &Path, record: &AuditRecord) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "{}-{}.json",
+        record.finished_at_ms,
+        sanitize(&record.task_id)
+    ));
+    fs::write(&path, serde_json::to_vec_pretty(record)?)?;
+    info!(
+        "audit record of task {} written to {:?}",
+        record.task_id, path
+    );
+    Ok(path)
+}
+
+/// Records of the tasks finished within [from_ms, to_ms), oldest first, to_ms 0 means up to now
+pub fn read_audit_records(dir: &Path, from_ms: u64, to_ms: u64) -> Result<Vec<AuditRecord>> {
+    let mut records = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let record: AuditRecord = match fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|b| serde_json::from_slice(&b).map_err(anyhow::Error::from))
+        {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("skip audit record {:?} with error: {}", path, e);
+                continue;
+            }
+        };
+        if record.finished_at_ms >= from_ms && (to_ms == 0 || record.finished_at_ms < to_ms) {
+            records.push(record);
+        }
+    }
+    records.sort_by(|a, b| (a.finished_at_ms, &a.task_id).cmp(&(b.finished_at_ms, &b.task_id)));
+    Ok(records)
+}
+
+/// Records exported in one page at most, so a report of months fits into a message
+pub const REPORT_PAGE_LIMIT: usize = 1000;
+
+/// The records, oldest first as read, after the task `after_task_id` which finished at
+/// `from_ms`, at most `limit` of them; the flag tells whether records are left after the page
+pub fn report_page(
+    records: Vec<AuditRecord>,
+    from_ms: u64,
+    after_task_id: &str,
+    limit: usize,
+) -> (Vec<AuditRecord>, bool) {
+    let mut page: Vec<AuditRecord> = records
+        .into_iter()
+        .filter(|r| {
+            after_task_id.is_empty()
+                || (r.finished_at_ms, r.task_id.as_str()) > (from_ms, after_task_id)
+        })
+        .collect();
+    let more = page.len() > limit;
+    page.truncate(limit);
+    (page, more)
+}
+
+pub const REPORT_CSV_HEADER: &str = "task_id,tenant,trace_id,sector_size,sector_count,replicas_len,priority,outcome,error,created_at_ms,started_at_ms,finished_at_ms,partitions,decode_ms,load_params_ms,prove_ms,pub_in_sha256,vanilla_proof_sha256,proof_sha256";
+
+/// One line per record, for billing and reviews in a spreadsheet
+pub fn report_csv(records: &[AuditRecord]) -> String {
+    let mut csv = String::from(REPORT_CSV_HEADER);
+    csv.push('\n');
+    for r in records {
+        let (sector_size, sector_count) = match &r.post_config {
+            Some(c) => (u64::from(c.sector_size), c.sector_count),
+            None => (0, 0),
+        };
+        let fields = [
+            csv_field(&r.task_id),
+            csv_field(&r.tenant),
+            csv_field(&r.trace_id),
+            sector_size.to_string(),
+            sector_count.to_string(),
+            r.replicas_len.to_string(),
+            r.priority.to_string(),
+            csv_field(&r.outcome),
+            csv_field(&r.error),
+            r.created_at_ms.to_string(),
+            r.started_at_ms.to_string(),
+            r.finished_at_ms.to_string(),
+            r.partitions.to_string(),
+            r.decode_ms.to_string(),
+            r.load_params_ms.to_string(),
+            r.prove_ms.to_string(),
+            r.pub_in_sha256.clone(),
+            r.vanilla_proof_sha256.clone(),
+            r.proof_sha256.clone(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// quote fields clients chose, which may hold commas, quotes or line breaks
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,261 @@
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use strum_macros::Display;
+use tonic::metadata::{Ascii, MetadataMap, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+pub const AUTHORIZATION_KEY: &str = "authorization";
+// leeway for clocks of the token issuer and this server not agreeing
+pub const JWT_LEEWAY_DEFAULT: Duration = Duration::from_secs(60);
+
+/// Key JWTs are signed with
+#[derive(Clone)]
+pub enum JwtKey {
+    // shared secret, HS256
+    Hmac(Vec<u8>),
+    // PEM encoded public key of the issuer, RS256
+    RsaPem(Vec<u8>),
+}
+
+/// Key and checks JWTs are validated with, the key is parsed once when the config is made
+#[derive(Clone)]
+pub struct JwtConfig {
+    key: DecodingKey<'static>,
+    validation: Validation,
+}
+
+/// Credentials the server accepts, every call is let through when none are configured
+#[derive(Clone, Default)]
+pub struct AuthConfig {
+    // static api tokens of submitters
+    pub tokens: Vec<String>,
+    // static api tokens of operators
+    pub admin_tokens: Vec<String>,
+    pub jwt: Option<JwtConfig>,
+}
+
+/// What a caller may do, an admin may do all a submitter may
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // lock, submit and fetch results of tasks
+    #[strum(to_string = "submitter")]
+    Submitter,
+    // also change the timeouts and parameter cache of the server, dump its state and
+    // export its task history
+    #[strum(to_string = "admin")]
+    Admin,
+}
+
+impl Default for Role {
+    fn default() -> Self {
+        Role::Submitter
+    }
+}
+
+/// Who made a call, added to the request extensions once authenticated
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    // subject of the JWT, empty for static tokens
+    pub subject: String,
+    pub role: Role,
+}
+
+// keep secrets out of the logs
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtKey::Hmac(_) => write!(f, "Hmac(..)"),
+            JwtKey::RsaPem(_) => write!(f, "RsaPem(..)"),
+        }
+    }
+}
+
+impl fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtConfig")
+            .field("algorithms", &self.validation.algorithms)
+            .field("issuer", &self.validation.iss)
+            .field("leeway", &self.validation.leeway)
+            .finish()
+    }
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("tokens", &self.tokens.len())
+            .field("admin_tokens", &self.admin_tokens.len())
+            .field("jwt", &self.jwt)
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    #[serde(default)]
+    sub: String,
+    // tokens without one are of submitters
+    #[serde(default)]
+    role: Role,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || !self.admin_tokens.is_empty() || self.jwt.is_some()
+    }
+
+    /// Check the bearer token of a call, None when auth is not enabled
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<Option<Principal>, Status> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let token = bearer_token(metadata)
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+        // compare digests, so the time taken does not tell how much of a token matched
+        let digest = Sha256::digest(token.as_bytes());
+        let matches = |tokens: &[String]| {
+            tokens
+                .iter()
+                .any(|t| Sha256::digest(t.as_bytes()) == digest)
+        };
+        for (tokens, role) in [
+            (&self.admin_tokens, Role::Admin),
+            (&self.tokens, Role::Submitter),
+        ] {
+            if matches(tokens) {
+                return Ok(Some(Principal {
+                    subject: String::default(),
+                    role,
+                }));
+            }
+        }
+        match &self.jwt {
+            Some(jwt) => jwt.validate(token).map(Some),
+            None => Err(Status::unauthenticated("invalid token")),
+        }
+    }
+}
+
+impl JwtConfig {
+    /// Tokens of other issuers than `issuer` are refused when it is set. Fails on a key
+    /// which can not be parsed, so a bad key stops the server from starting.
+    pub fn new(key: JwtKey, issuer: Option<String>, leeway: Duration) -> anyhow::Result<Self> {
+        let (key, algorithm) = match &key {
+            JwtKey::Hmac(secret) => (
+                DecodingKey::from_secret(secret).into_static(),
+                Algorithm::HS256,
+            ),
+            JwtKey::RsaPem(pem) => (
+                DecodingKey::from_rsa_pem(pem)
+                    .map_err(|e| anyhow::anyhow!("bad jwt public key: {}", e))?
+                    .into_static(),
+                Algorithm::RS256,
+            ),
+        };
+        let validation = Validation {
+            leeway: leeway.as_secs(),
+            validate_exp: true,
+            // nbf is not checked, jsonwebtoken would refuse the tokens without one
+            iss: issuer,
+            algorithms: vec![algorithm],
+            ..Default::default()
+        };
+        Ok(JwtConfig { key, validation })
+    }
+
+    fn validate(&self, token: &str) -> Result<Principal, Status> {
+        match decode::<Claims>(token, &self.key, &self.validation) {
+            Ok(data) => Ok(Principal {
+                subject: data.claims.sub,
+                role: data.claims.role,
+            }),
+            Err(e) => match e.kind() {
+                ErrorKind::ExpiredSignature => Err(Status::unauthenticated("token expired")),
+                _ => Err(Status::unauthenticated(format!("invalid token: {}", e))),
+            },
+        }
+    }
+}
+
+/// Refuse the call unless its caller has the role, all calls are allowed when auth is not enabled
+pub fn authorize<T>(request: &Request<T>, role: Role) -> Result<(), Status> {
+    match request.extensions().get::<Principal>() {
+        Some(p) if p.role < role => Err(Status::permission_denied(format!(
+            "{} role required, caller is a {}",
+            role, p.role
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Read static api tokens, one per line, blank lines and lines starting with # are skipped
+pub fn read_tokens<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Token a client authenticates with, an api token or a JWT
+#[derive(Clone, Default)]
+pub struct BearerToken(Option<MetadataValue<Ascii>>);
+
+impl BearerToken {
+    pub fn new(token: &str) -> anyhow::Result<Self> {
+        Ok(BearerToken(Some(format!("Bearer {}", token).parse()?)))
+    }
+
+    pub fn apply(&self, metadata: &mut MetadataMap) {
+        if let Some(t) = &self.0 {
+            metadata.insert(AUTHORIZATION_KEY, t.clone());
+        }
+    }
+}
+
+impl fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BearerToken({})",
+            if self.0.is_some() { ".." } else { "none" }
+        )
+    }
+}
+
+// lets generated clients send the token with each call
+impl Interceptor for BearerToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        self.apply(request.metadata_mut());
+        Ok(request)
+    }
+}
+
+fn bearer_token(metadata: &MetadataMap) -> Option<&str> {
+    let value = metadata.get(AUTHORIZATION_KEY)?.to_str().ok()?;
+    let token = value
+        .strip_prefix("Bearer ")
+        .or_else(|| value.strip_prefix("bearer "))?;
+    Some(token.trim())
+}
+
+/// Interceptor refusing calls without valid credentials
+pub fn interceptor(
+    config: AuthConfig,
+) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |mut request: Request<()>| {
+        if let Some(principal) = config.authenticate(request.metadata())? {
+            request.extensions_mut().insert(principal);
+        }
+        Ok(request)
+    }
+}
@@ -0,0 +1,132 @@
+//! Tasks submitted together in one SubmitBatch call. Their outcomes are kept with the
+//! batch, so the server moves on to the next task without waiting for each result to be
+//! fetched and acked, and the client collects them all in one report.
+
+use crate::error::FailureKind;
+use crate::status::TaskStatus;
+use crate::tasks::{TaskInfo, TaskTimings};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How a task of a batch ended
+#[derive(Debug, Clone, Default)]
+pub struct BatchOutcome {
+    pub task_status: TaskStatus,
+    pub proof: Vec<u8>,
+    pub pub_in_digest: Vec<u8>,
+    pub error: String,
+    pub error_kind: FailureKind,
+    pub timings: TaskTimings,
+    pub degraded: bool,
+    pub finished_at: Option<SystemTime>,
+}
+
+impl BatchOutcome {
+    /// Outcome of the task which just finished, `error` is empty when it succeeded
+    pub fn new(task_info: &TaskInfo, error: &str, error_kind: FailureKind) -> Self {
+        BatchOutcome {
+            task_status: task_info.task_status.clone(),
+            proof: task_info.result.clone(),
+            pub_in_digest: task_info.pub_in_digest.clone(),
+            error: error.to_string(),
+            error_kind,
+            timings: task_info.timings.clone(),
+            degraded: task_info.degraded,
+            finished_at: task_info.finished_at,
+        }
+    }
+
+    /// A task which never ran, e.g. refused when submitted or dropped from the queue
+    pub fn rejected(task_status: TaskStatus, error: &str) -> Self {
+        BatchOutcome {
+            task_status,
+            error: error.to_string(),
+            finished_at: Some(SystemTime::now()),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.task_status != TaskStatus::Done
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Batch {
+    // in the order they were submitted
+    task_ids: Vec<String>,
+    outcomes: HashMap<String, BatchOutcome>,
+    // when the last task finished
+    complete_at: Option<Instant>,
+    // peer which submitted the batch, empty when not known
+    owner: String,
+}
+
+impl Batch {
+    pub fn task_ids(&self) -> &[String] {
+        &self.task_ids
+    }
+
+    pub fn outcome(&self, task_id: &str) -> Option<&BatchOutcome> {
+        self.outcomes.get(task_id)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete_at.is_some()
+    }
+
+    /// Whether the peer may read the report, like the results of a task only the peer
+    /// which submitted it may. Batches and peers not known are not checked.
+    pub fn is_readable_by(&self, peer: &str) -> bool {
+        self.owner.is_empty() || peer.is_empty() || self.owner == peer
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Batches {
+    batches: HashMap<String, Batch>,
+}
+
+impl Batches {
+    pub fn insert(&mut self, batch_id: &str, task_ids: Vec<String>, owner: &str) {
+        self.batches.insert(
+            batch_id.to_string(),
+            Batch {
+                task_ids,
+                owner: owner.to_string(),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Keep the outcome of a task with its batch
+    pub fn finish(&mut self, batch_id: &str, task_id: &str, outcome: BatchOutcome) {
+        if let Some(batch) = self.batches.get_mut(batch_id) {
+            batch.outcomes.insert(task_id.to_string(), outcome);
+            if batch.outcomes.len() == batch.task_ids.len() {
+                batch.complete_at = Some(Instant::now());
+            }
+        }
+    }
+
+    pub fn get(&self, batch_id: &str) -> Option<&Batch> {
+        self.batches.get(batch_id)
+    }
+
+    /// Forget batches complete for longer than `time_out`, their clients had the time a
+    /// single task has to be fetched in
+    pub fn prune(&mut self, now: Instant, time_out: Duration) {
+        self.batches.retain(|_, b| {
+            b.complete_at
+                .map_or(true, |t| now.saturating_duration_since(t) < time_out)
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+}
@@ -1,43 +1,230 @@
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 use std::{env, process};
+use std::str::FromStr;
 use std::process::exit;
-use log::{error, info, warn};
+use std::path::PathBuf;
+use std::time::Duration;
+use log::{error, info};
 use window_post_snark_server::{utils};
-use window_post_snark_server::run::run;
-use window_post_snark_server::server::{SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT, SERVER_LOCK_TIME_OUT_DEFAULT, SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT};
+use window_post_snark_server::run::{run, RunSettings};
+use window_post_snark_server::auth::{self, AuthConfig, JwtConfig, JwtKey};
+use window_post_snark_server::calibrate::{self, CALIBRATE_CONSTRAINTS_DEFAULT};
+use window_post_snark_server::config::{ServerConfig, TlsConfig};
+use window_post_snark_server::dump::DumpConfig;
+use window_post_snark_server::limits::ConnectionLimits;
+use window_post_snark_server::resources::{RuntimeConfig, RUNTIME_THREAD_NAME_DEFAULT};
+use window_post_snark_server::tasks::{self, PROVE_CMD};
+
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("features jemalloc and mimalloc can not be enabled together");
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+const ALLOCATOR: &str = if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else {
+    "system"
+};
 
 fn main() {
     utils::set_commit_env();
     let cmds = App::new("window-post-snark-server")
         .author(utils::author())
         .version(utils::version())
-        .subcommands(vec![run_cmd(), stop_cmd()]);
+        .subcommands(vec![run_cmd(), stop_cmd(), calibrate_cmd(), prove_cmd()]);
     let mut c = cmds.clone();
     let matches = cmds.get_matches();
     match matches.subcommand_name() {
         Some("run") => {
             env::set_var("RUST_BACKTRACE", "full");
             let run_matched = matches.subcommand_matches("run").unwrap();
+            let mut config = match run_matched.value_of("config") {
+                Some(f) => ServerConfig::load(&PathBuf::from(f)).unwrap_or_else(|e| {
+                    eprintln!("{:?}", e);
+                    exit(1)
+                }),
+                None => ServerConfig::default(),
+            };
+            if let Err(e) = config.apply_env() {
+                eprintln!("{:?}", e);
+                exit(1)
+            }
             if run_matched.is_present("debug") {
                 env::set_var("RUST_LOG", "debug");
             } else {
-                env::set_var("RUST_LOG", "info");
+                env::set_var("RUST_LOG", config.log_level.as_deref().unwrap_or("info"));
+            }
+            // before the lock files of the gpus are taken and the gpus are looked for
+            if let Some(g) = &config.gpu {
+                env::set_var("CUDA_VISIBLE_DEVICES", g);
             }
 
             fil_logger::init();
-            let port = run_matched.value_of("port").unwrap().to_string();
+            info!("allocator: {}", ALLOCATOR);
+            config.port = Some(flag_or(run_matched, "port", config.port));
             if run_matched.is_present("force") {
                 assert_eq!(can_run(true), true);
             } else {
                 assert_eq!(can_run(false), true);
             }
-            run(port,SERVER_LOCK_TIME_OUT_DEFAULT,SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT,SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT)
+            config.timeouts.grace_period = Some(flag_or(run_matched, "grace-period", config.timeouts.grace_period));
+            let task_threads = run_matched.value_of("task-threads").unwrap().parse::<usize>().unwrap();
+            let cpu_utilization = run_matched.value_of("cpu-utilization").map(|c| c.parse::<f32>().unwrap());
+            let limits = ConnectionLimits {
+                max_connections: run_matched.value_of("max-connections").unwrap().parse::<usize>().unwrap(),
+                max_in_flight: run_matched.value_of("max-in-flight").unwrap().parse::<usize>().unwrap(),
+                max_in_flight_per_connection: run_matched.value_of("max-in-flight-per-connection").unwrap().parse::<usize>().unwrap(),
+                idle_time_out: Duration::from_secs(run_matched.value_of("idle-time-out").unwrap().parse::<u64>().unwrap()),
+            };
+            let dump = match run_matched.value_of("dump-dir") {
+                Some(d) => {
+                    let mut dump = DumpConfig::new(PathBuf::from(d));
+                    dump.max_bytes = run_matched.value_of("dump-max-mb").unwrap().parse::<u64>().unwrap() << 20;
+                    dump.keep = run_matched.value_of("dump-keep").unwrap().parse::<usize>().unwrap();
+                    Some(dump)
+                }
+                None => None,
+            };
+            let mut auth = AuthConfig::default();
+            if let Some(f) = run_matched.value_of("api-token-file") {
+                auth.tokens = auth::read_tokens(f).unwrap();
+            }
+            if let Some(f) = run_matched.value_of("admin-token-file") {
+                auth.admin_tokens = auth::read_tokens(f).unwrap();
+            }
+            let jwt_key = match (run_matched.value_of("jwt-secret-file"), run_matched.value_of("jwt-public-key-file")) {
+                (Some(f), None) => Some(JwtKey::Hmac(std::fs::read(f).unwrap())),
+                (None, Some(f)) => Some(JwtKey::RsaPem(std::fs::read(f).unwrap())),
+                (Some(_), Some(_)) => {
+                    error!("only one of --jwt-secret-file and --jwt-public-key-file may be given");
+                    exit(1)
+                }
+                (None, None) => None,
+            };
+            if let Some(key) = jwt_key {
+                let issuer = run_matched.value_of("jwt-issuer").map(|i| i.to_string());
+                let leeway = Duration::from_secs(run_matched.value_of("jwt-leeway").unwrap().parse::<u64>().unwrap());
+                match JwtConfig::new(key, issuer, leeway) {
+                    Ok(jwt) => auth.jwt = Some(jwt),
+                    Err(e) => {
+                        error!("{:?}", e);
+                        exit(1)
+                    }
+                }
+            }
+            config.timeouts.prove_time_out = Some(flag_or(run_matched, "prove-time-out", config.timeouts.prove_time_out));
+            if let Some(dirs) = run_matched.values_of("param-cache") {
+                config.params.cache = dirs.map(PathBuf::from).collect();
+            }
+            if let Some(m) = run_matched.value_of("verify-params") {
+                config.params.manifest = Some(PathBuf::from(m));
+            }
+            if let Some(sizes) = run_matched.values_of("preload-params") {
+                config.params.preload = sizes.map(|s| s.parse::<u64>().expect("sector size should be a number of bytes")).collect();
+            }
+            if let (Some(cert), Some(key)) = (run_matched.value_of("tls-cert"), run_matched.value_of("tls-key")) {
+                config.tls = Some(TlsConfig {
+                    cert: PathBuf::from(cert),
+                    key: PathBuf::from(key),
+                    client_ca: run_matched.value_of("tls-client-ca").map(PathBuf::from),
+                });
+            }
+            let payload_dirs = match run_matched.values_of("payload-dir") {
+                Some(dirs) => dirs.map(PathBuf::from).collect(),
+                None => vec![],
+            };
+            let runtime = RuntimeConfig {
+                worker_threads: run_matched.value_of("runtime-threads").unwrap().parse::<usize>().unwrap(),
+                max_blocking_threads: run_matched.value_of("runtime-blocking-threads").unwrap().parse::<usize>().unwrap(),
+                thread_name: run_matched.value_of("runtime-thread-name").unwrap().to_string(),
+            };
+            let task_queue_capacity = run_matched.value_of("task-queue-capacity").unwrap().parse::<usize>().unwrap();
+            let settings = RunSettings {
+                config,
+                task_threads,
+                cpu_utilization,
+                limits,
+                dump,
+                auth,
+                priority_only: run_matched.is_present("priority-only"),
+                audit_dir: run_matched.value_of("audit-dir").map(PathBuf::from),
+                cpu_fallback: run_matched.is_present("cpu-fallback"),
+                verify_proofs: run_matched.is_present("verify-proofs"),
+                preempt_window_post: run_matched.is_present("preempt-window-post"),
+                payload_dirs,
+                mdns: run_matched.is_present("mdns"),
+                runtime,
+                task_queue_capacity,
+            };
+            if let Err(e) = run(settings) {
+                error!("server failed to start: {:?}", e);
+                utils::del_file_lock();
+                exit(1)
+            }
         }
         Some("stop") => {
             let stop_matched = matches.subcommand_matches("stop").unwrap();
             let pid = stop_matched.value_of("pid").unwrap().to_string();
             stop(pid);
         }
+        Some("calibrate") => {
+            let calibrate_matched = matches.subcommand_matches("calibrate").unwrap();
+            env::set_var("RUST_LOG", "info");
+            fil_logger::init();
+            let constraints = match calibrate_matched.value_of("constraints") {
+                Some(n) => n.parse::<usize>().expect("constraints should be a number"),
+                None => CALIBRATE_CONSTRAINTS_DEFAULT,
+            };
+            let post_configs = calibrate_matched
+                .values_of("sector-size")
+                .unwrap()
+                .map(|s| {
+                    calibrate::window_post_config(s.parse::<u64>().expect("sector size should be a number of bytes"))
+                        .unwrap_or_else(|e| {
+                            error!("{}", e);
+                            exit(1)
+                        })
+                })
+                .collect::<Vec<_>>();
+            // holds the gpus like a server, proving next to one would skew both
+            assert_eq!(can_run(calibrate_matched.is_present("force")), true);
+            let calibrated = calibrate::calibrate(constraints, &post_configs);
+            utils::del_file_lock();
+            let profile = match calibrated {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("calibration failed: {:?}", e);
+                    exit(1)
+                }
+            };
+            let path = calibrate_matched
+                .value_of("output")
+                .map(PathBuf::from)
+                .unwrap_or_else(utils::profile_path);
+            if let Err(e) = profile.save(&path) {
+                error!("{:?}", e);
+                exit(1)
+            }
+            info!("hardware profile written to {:?}", path);
+            println!("{}", serde_json::to_string_pretty(&profile).unwrap());
+        }
+        Some(PROVE_CMD) => {
+            // started by a server for a slot or its cpu fallback, which reads the logs from stderr
+            let prove_matched = matches.subcommand_matches(PROVE_CMD).unwrap();
+            fil_logger::init();
+            if let Err(e) = tasks::prove_task_in_dir(&PathBuf::from(prove_matched.value_of("DIR").unwrap())) {
+                error!("proving the task failed: {:?}", e);
+                exit(1)
+            }
+        }
         _ => {
             c.print_help().unwrap();
             exit(1)
@@ -47,11 +234,117 @@ fn main() {
 
 fn run_cmd() -> App<'static, 'static> {
     App::new("run").about("run window-post-snark-server").args(&[
+        Arg::from_usage("-c, --config=[FILE] 'TOML file with the settings of the server, its WPS_ environment variables and the flags given override it'")
+            .env("WPS_CONFIG")
+            .required(false),
         Arg::from_usage("-d, --debug 'print debug log'").required(false),
         Arg::from_usage("-f, --force 'force run process without num limit'").required(false),
         Arg::from_usage("-p, --port=[PORT] 'specify server port'")
             .default_value("50051")
             .required(false),
+        Arg::from_usage("--grace-period=[SECONDS] 'on shutdown, how long to wait for the running task to finish and be fetched'")
+            .default_value("600")
+            .required(false),
+        Arg::from_usage("--task-threads=[NUM] 'threads a task may prove with, 0 for no limit'")
+            .default_value("0")
+            .required(false),
+        Arg::from_usage("--task-queue-capacity=[NUM] 'tasks which may wait behind the running one, further ones are refused, 0 for no limit'")
+            .default_value("64")
+            .required(false),
+        Arg::from_usage("--runtime-threads=[NUM] 'worker threads of the runtime serving grpc calls, 0 for one per cpu'")
+            .default_value("0")
+            .required(false),
+        Arg::from_usage("--runtime-blocking-threads=[NUM] 'cap of the blocking threads of the runtime, 0 for the tokio default'")
+            .default_value("0")
+            .required(false),
+        Arg::from_usage("--runtime-thread-name=[NAME] 'name of the runtime threads, shows in crash reports and top'")
+            .default_value(RUNTIME_THREAD_NAME_DEFAULT)
+            .required(false),
+        Arg::from_usage("--cpu-utilization=[FRACTION] 'share of the multiexp run on cpu next to the gpu, 0 to 1'")
+            .required(false),
+        Arg::from_usage("--max-connections=[NUM] 'connections served at once, 0 for no limit'")
+            .default_value("1024")
+            .required(false),
+        Arg::from_usage("--max-in-flight=[NUM] 'requests handled at once, 0 for no limit'")
+            .default_value("256")
+            .required(false),
+        Arg::from_usage("--max-in-flight-per-connection=[NUM] 'requests handled at once on one connection, 0 for no limit'")
+            .default_value("32")
+            .required(false),
+        Arg::from_usage("--idle-time-out=[SECONDS] 'close connections without traffic for this long, 0 for never'")
+            .default_value("900")
+            .required(false),
+        Arg::from_usage("--dump-dir=[DIR] 'dump payloads of failed tasks into this dir'")
+            .required(false),
+        Arg::from_usage("--dump-max-mb=[MB] 'do not dump tasks with larger payloads'")
+            .default_value("512")
+            .required(false),
+        Arg::from_usage("--dump-keep=[NUM] 'number of dumps to keep, oldest are removed'")
+            .default_value("20")
+            .required(false),
+        Arg::from_usage("--param-cache=[DIR]... 'groth parameter dir, may be given several times, the first one holding parameters is used'")
+            .number_of_values(1)
+            .required(false),
+        Arg::from_usage("--verify-params=[MANIFEST] 'check the parameter files against this parameters.json on start, stay Unknown if one is corrupt'")
+            .required(false),
+        Arg::from_usage("--preload-params=[BYTES]... 'sector sizes to load the window PoSt groth parameters of on start, may be given several times'")
+            .number_of_values(1)
+            .required(false),
+        Arg::from_usage("--cpu-fallback 'prove a task again on the cpu when proving on the gpu failed'")
+            .required(false),
+        Arg::from_usage("--verify-proofs 'verify every proof before returning it, with --cpu-fallback a proof which does not verify is made again on the cpu'")
+            .required(false),
+        Arg::from_usage("--preempt-window-post 'prove window PoSt partition by partition, so a winning PoSt arriving meanwhile runs at the next partition boundary'")
+            .required(false),
+        Arg::from_usage("--prove-time-out=[SECONDS] 'fail a task still proving after this long, 0 for no limit'")
+            .default_value("0")
+            .required(false),
+        Arg::from_usage("--tls-cert=[FILE] 'PEM certificate chain to serve over TLS with'")
+            .requires("tls-key")
+            .required(false),
+        Arg::from_usage("--tls-key=[FILE] 'PEM private key of the TLS certificate'")
+            .requires("tls-cert")
+            .required(false),
+        Arg::from_usage("--tls-client-ca=[FILE] 'PEM CA certificate, only clients presenting a certificate it signed are served'")
+            .requires("tls-cert")
+            .required(false),
+        Arg::from_usage("--payload-dir=[DIR]... 'dir on shared storage tasks may send the path of their vanilla proofs in, may be given several times'")
+            .number_of_values(1)
+            .required(false),
+        Arg::from_usage("--mdns 'announce the server on the LAN over mDNS, needs the mdns feature'")
+            .required(false),
+        Arg::from_usage("--audit-dir=[DIR] 'write an audit record of every finished task into this dir'")
+            .required(false),
+        Arg::from_usage("--priority-only 'reserve this server for winning PoSt and priority tasks'")
+            .required(false),
+        Arg::from_usage("--api-token-file=[FILE] 'accept the bearer tokens in this file, one per line'")
+            .required(false),
+        Arg::from_usage("--admin-token-file=[FILE] 'accept the bearer tokens in this file, one per line, for operators'")
+            .required(false),
+        Arg::from_usage("--jwt-secret-file=[FILE] 'accept JWTs signed with the HS256 secret in this file'")
+            .required(false),
+        Arg::from_usage("--jwt-public-key-file=[FILE] 'accept JWTs signed with the RS256 key of this PEM file'")
+            .required(false),
+        Arg::from_usage("--jwt-issuer=[ISSUER] 'accept only JWTs of this issuer'")
+            .required(false),
+        Arg::from_usage("--jwt-leeway=[SECONDS] 'tolerated clock skew when checking the expiry of JWTs'")
+            .default_value("60")
+            .required(false),
+    ])
+}
+
+fn calibrate_cmd() -> App<'static, 'static> {
+    App::new("calibrate").about("benchmark the gpus and write the hardware profile the server estimates with").args(&[
+        Arg::from_usage("--constraints=[NUM] 'constraints of the synthetic circuit proved'")
+            .required(false),
+        Arg::from_usage("--sector-size=[BYTES]... 'sector sizes to count the window PoSt constraints of, may be given several times'")
+            .number_of_values(1)
+            .default_value("34359738368")
+            .required(false),
+        Arg::from_usage("-o, --output=[FILE] 'where to write the profile, next to the lock files by default'")
+            .required(false),
+        Arg::from_usage("-f, --force 'run while a server holds the lock files of the gpus'")
+            .required(false),
     ])
 }
 
@@ -63,6 +356,26 @@ fn stop_cmd() -> App<'static, 'static> {
     )
 }
 
+fn prove_cmd() -> App<'static, 'static> {
+    App::new(PROVE_CMD)
+        .about("prove a task a server wrote for a child process, on the cpu or the gpu it is pinned to")
+        .setting(AppSettings::Hidden)
+        .arg(Arg::from_usage("<DIR> 'dir the task was written to'"))
+}
+
+// a flag given wins over the config file, which wins over the default of the flag
+fn flag_or<T: FromStr>(m: &ArgMatches, name: &str, config: Option<T>) -> T {
+    match config {
+        Some(c) if m.occurrences_of(name) == 0 => c,
+        _ => match m.value_of(name).unwrap().parse::<T>() {
+            Ok(v) => v,
+            Err(_) => {
+                error!("bad --{}", name);
+                exit(1)
+            }
+        },
+    }
+}
 
 fn stop(pid_s: String) {
     let pid;
@@ -71,31 +384,23 @@ fn stop(pid_s: String) {
     } else {
         pid = pid_s.parse::<u32>().unwrap()
     }
+    if pid == 0 {
+        error!("no running server found in {:?}", utils::lock_file_path());
+        exit(1)
+    }
     process::Command::new("kill").arg(pid.to_string()).output().unwrap();
 }
 
 fn can_run(is_force: bool) -> bool {
     if !is_force {
-        if utils::is_file_lock_exist() {
-            warn!("file lock existed,will check process is_running by pid");
-            if let Some(p) = utils::check_process_is_running_by_pid() {
-                error!("process double run, old process still running, pid: {}", p);
-                false
-            } else {
-                warn!("old process is not running, let's go on");
+        match utils::acquire_file_lock() {
+            Ok(_) => {
+                info!("write pid into lock file success");
                 true
             }
-        } else {
-            let pid = &process::id().to_string().as_bytes().to_vec();
-            match utils::write_pid_into_file_lock(pid) {
-                Ok(_) => {
-                    info!("write pid into lock file success");
-                    true
-                }
-                Err(e) => {
-                    error!("write pid into lock file failed with error:{}", e);
-                    false
-                }
+            Err(e) => {
+                error!("process double run: {}", e);
+                false
             }
         }
     } else {
@@ -0,0 +1,713 @@
+use clap::{App, Arg, ArgMatches};
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+use uuid::Uuid;
+use window_post_snark_server::auth::BearerToken;
+use window_post_snark_server::client::{
+    client_tls_config, new_channel, new_tls_channel, task_frames, UPLOAD_CHUNK_LEN,
+};
+use window_post_snark_server::codec::{post_config_to_proto, Encoding};
+use window_post_snark_server::compat;
+use window_post_snark_server::dump;
+use window_post_snark_server::inspect;
+use window_post_snark_server::prelude::{
+    AckResultRequest, BaseResponse, CancelTaskRequest, DumpServerStateRequest,
+    ExportTaskReportRequest, GetTaskResultRequest, GetWorkerStatusRequest, LockServerWaitRequest,
+    ManageParamCacheRequest, ParamCacheAction, ReportFormat, SetServerTimeoutsRequest,
+    SnarkTaskRequestParams, SnarkTaskServiceClient, UnlockServerRequest,
+};
+use window_post_snark_server::remote::RemotePoSt;
+use window_post_snark_server::{tasks, utils};
+
+type Client = SnarkTaskServiceClient<InterceptedService<Channel, BearerToken>>;
+
+fn main() {
+    let cmds = App::new("wps-client")
+        .author(utils::author())
+        .version(utils::version())
+        .about("talk to a window-post-snark-server without a miner, for debugging")
+        .args(&[
+            Arg::from_usage("-a, --addr=[ADDR] 'server address'")
+                .default_value("http://127.0.0.1:50051")
+                .global(true),
+            Arg::from_usage("--timeout=[SECONDS] 'timeout of each request'")
+                .default_value("10")
+                .global(true),
+            Arg::from_usage("--tls-ca=[FILE] 'PEM CA certificate to check the server certificate of an https address with'")
+                .global(true),
+            Arg::from_usage("--tls-cert=[FILE] 'PEM client certificate, for servers which require one'")
+                .requires_all(&["tls-ca", "tls-key"])
+                .global(true),
+            Arg::from_usage("--tls-key=[FILE] 'PEM private key of the client certificate'")
+                .requires("tls-cert")
+                .global(true),
+            Arg::from_usage("--token=[TOKEN] 'api token or JWT to authenticate with'")
+                .env("WPS_TOKEN")
+                .global(true),
+        ])
+        .subcommands(vec![
+            status_cmd(),
+            lock_cmd(),
+            unlock_cmd(),
+            cancel_cmd(),
+            submit_cmd(),
+            result_cmd(),
+            prove_cmd(),
+            inspect_cmd(),
+            replay_cmd(),
+            report_cmd(),
+            state_cmd(),
+            params_cmd(),
+            timeouts_cmd(),
+        ]);
+    let mut c = cmds.clone();
+    let matches = cmds.get_matches();
+    let (name, sub_matches) = match matches.subcommand() {
+        (name, Some(m)) => (name, m),
+        _ => {
+            c.print_help().unwrap();
+            exit(1)
+        }
+    };
+    // inspect and replay work on local files only, no server needed
+    let local = match name {
+        "inspect" => Some(inspect_payload(sub_matches)),
+        "replay" => Some(replay(sub_matches)),
+        _ => None,
+    };
+    if let Some(result) = local {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            exit(1)
+        }
+        return;
+    }
+
+    let rt = Runtime::new().expect("failed to build new runtime");
+    let mut client = rt.block_on(connect(sub_matches));
+    let result = match name {
+        "status" => rt.block_on(status(&mut client, sub_matches)),
+        "lock" => rt.block_on(lock(&mut client, sub_matches)),
+        "unlock" => rt.block_on(unlock(&mut client, sub_matches)),
+        "cancel" => rt.block_on(cancel(&mut client, sub_matches)),
+        "submit" => rt.block_on(submit(&mut client, sub_matches)),
+        "result" => rt.block_on(result(&mut client, sub_matches)),
+        "prove" => rt.block_on(prove(&mut client, sub_matches)),
+        "report" => rt.block_on(report(&mut client, sub_matches)),
+        "state" => rt.block_on(state(&mut client, sub_matches)),
+        "params" => rt.block_on(params(&mut client, sub_matches)),
+        "timeouts" => rt.block_on(timeouts(&mut client, sub_matches)),
+        _ => {
+            c.print_help().unwrap();
+            exit(1)
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1)
+    }
+}
+
+fn task_id_arg() -> Arg<'static, 'static> {
+    Arg::from_usage("-t, --task-id=[TASK_ID] 'task id'").required(true)
+}
+
+fn status_cmd() -> App<'static, 'static> {
+    App::new("status")
+        .about("show the status of the server without locking it")
+        .arg(Arg::from_usage(
+            "-t, --task-id=[TASK_ID] 'task to show the lock and queue position of'",
+        ))
+}
+
+fn lock_cmd() -> App<'static, 'static> {
+    App::new("lock").about("lock the server for a task").args(&[
+        task_id_arg(),
+        Arg::from_usage(
+            "--duration=[MILLISECONDS] 'requested lock duration, 0 for server default'",
+        )
+        .default_value("0"),
+        Arg::from_usage("--wait=[SECONDS] 'wait this long for the lock'").default_value("60"),
+        Arg::from_usage("--slot=[SLOT] 'slot to lock, 0 for any free one'").default_value("0"),
+        Arg::from_usage("--priority 'lock for a priority task, as a --priority-only server requires'"),
+    ])
+}
+
+fn unlock_cmd() -> App<'static, 'static> {
+    App::new("unlock")
+        .about("release the lock of a task")
+        .arg(task_id_arg())
+}
+
+fn cancel_cmd() -> App<'static, 'static> {
+    App::new("cancel")
+        .about("drop a queued task or stop the running one")
+        .arg(task_id_arg())
+}
+
+fn submit_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::from_usage("--vanilla=[FILE] 'serialized vanilla proofs'")
+            .required_unless_one(&["vanilla-path", "manifest"]),
+        Arg::from_usage(
+            "--vanilla-path=[PATH] 'serialized vanilla proofs on storage shared with the server, read by the server'",
+        )
+        .conflicts_with("vanilla"),
+        Arg::from_usage(
+            "--manifest=[FILE] 'json randomness, prover_id and replicas of a full PoSt, the server reads the replicas'",
+        )
+        .conflicts_with_all(&["vanilla", "vanilla-path", "pub-in"]),
+        Arg::from_usage("--pub-in=[FILE] 'serialized public inputs'").required_unless("manifest"),
+        Arg::from_usage("--config=[FILE] 'json PoStConfig'").required(true),
+        Arg::from_usage("--replicas=[NUM] 'number of replicas'").required_unless("manifest"),
+        Arg::from_usage("--encoding=[ENCODING] 'encoding of the payload files'")
+            .possible_values(&["json", "bincode", "cbor"])
+            .default_value("json"),
+        Arg::from_usage("--priority 'queue the task as high priority'"),
+        Arg::from_usage("--threads=[NUM] 'threads the task may prove with, 0 for no limit'")
+            .default_value("0"),
+        Arg::from_usage(
+            "--partitions=[NUM] 'partitions to prove the task in, 0 derives them from replicas'",
+        )
+        .default_value("0"),
+        Arg::from_usage(
+            "--stream-partitions 'prove window PoSt partitions one at a time to stream each'",
+        ),
+        Arg::from_usage(
+            "--chunked 'send the payloads in chunks, for those larger than a gRPC message'",
+        ),
+    ]
+}
+
+fn submit_cmd() -> App<'static, 'static> {
+    App::new("submit")
+        .about("submit a task from files, the server must be locked by the task")
+        .arg(task_id_arg())
+        .args(&submit_args())
+}
+
+fn result_cmd() -> App<'static, 'static> {
+    App::new("result")
+        .about("wait for the result of a task and write the proof to a file")
+        .args(&[
+            task_id_arg(),
+            Arg::from_usage("-o, --output=[FILE] 'file to write the proof into'").required(true),
+        ])
+}
+
+fn report_cmd() -> App<'static, 'static> {
+    App::new("report")
+        .about("export the history of tasks finished in a time range, needs an admin token")
+        .args(&[
+            Arg::from_usage("--from=[UNIX_MS] 'first finish time to report'").default_value("0"),
+            Arg::from_usage("--to=[UNIX_MS] 'report tasks finished before this, 0 for up to now'")
+                .default_value("0"),
+            Arg::from_usage("--format=[FORMAT] 'json or csv'")
+                .possible_values(&["json", "csv"])
+                .default_value("csv"),
+            Arg::from_usage("-o, --output=[FILE] 'file to write the report into'").required(true),
+        ])
+}
+
+fn state_cmd() -> App<'static, 'static> {
+    App::new("state")
+        .about("dump the state of the server as JSON for a bug report, needs an admin token")
+        .arg(Arg::from_usage(
+            "-o, --output=[FILE] 'file to write the state into, stdout if not set'",
+        ))
+}
+
+fn params_cmd() -> App<'static, 'static> {
+    App::new("params")
+        .about("list, evict or reload the groth parameters the server keeps in memory, needs an admin token")
+        .args(&[
+            Arg::from_usage("<ACTION> 'what to do with the parameters'")
+                .possible_values(&["list", "evict", "reload"]),
+            Arg::from_usage("--sector-size=[BYTES] 'sector size of the parameters, 0 for all'")
+                .default_value("0"),
+        ])
+}
+
+fn timeouts_cmd() -> App<'static, 'static> {
+    App::new("timeouts")
+        .about("show or change the time outs of a running server, needs an admin token")
+        .args(&[
+            Arg::from_usage("--lock=[SECONDS] 'lock time out of locks asking for no duration'"),
+            Arg::from_usage("--lock-max=[SECONDS] 'longest lock a client may ask for'"),
+            Arg::from_usage("--get-back=[SECONDS] 'how long a result waits to be fetched and acked'"),
+            Arg::from_usage("--exit-after-task-done=[SECONDS] 'how long the server waits for the last result on exit'"),
+        ])
+}
+
+fn prove_cmd() -> App<'static, 'static> {
+    App::new("prove")
+        .about("lock, submit, wait for the result and write the proof to a file")
+        .arg(Arg::from_usage(
+            "-t, --task-id=[TASK_ID] 'task id, random if not set'",
+        ))
+        .args(&submit_args())
+        .arg(Arg::from_usage("-o, --output=[FILE] 'file to write the proof into'").required(true))
+}
+
+fn inspect_cmd() -> App<'static, 'static> {
+    App::new("inspect")
+        .about("decode task payloads and print what they contain, without a server")
+        .args(&[
+            Arg::from_usage("--vanilla=[FILE] 'serialized vanilla proofs'"),
+            Arg::from_usage("--pub-in=[FILE] 'serialized public inputs'"),
+            Arg::from_usage("--config=[FILE] 'json PoStConfig'").required(true),
+            Arg::from_usage("--replicas=[NUM] 'number of replicas'"),
+            Arg::from_usage("--encoding=[ENCODING] 'encoding of the payload files'")
+                .possible_values(&["json", "bincode", "cbor"])
+                .default_value("json"),
+        ])
+}
+
+fn replay_cmd() -> App<'static, 'static> {
+    App::new("replay")
+        .about("prove a task dumped by the server locally, without a server")
+        .args(&[
+            Arg::from_usage("<DUMP> 'dump dir of the task'"),
+            Arg::from_usage("-o, --output=[FILE] 'file to write the proof into'"),
+        ])
+}
+
+async fn connect(m: &ArgMatches<'_>) -> Client {
+    let addr = m.value_of("addr").unwrap().to_string();
+    let timeout = m.value_of("timeout").unwrap().parse::<u64>().unwrap();
+    let token = match m.value_of("token") {
+        Some(t) => BearerToken::new(t).unwrap(),
+        None => BearerToken::default(),
+    };
+    let addr: &'static str = Box::leak(addr.into_boxed_str());
+    let timeout = Duration::from_secs(timeout);
+    let channel = match m.value_of("tls-ca") {
+        Some(ca) => {
+            let identity = match (m.value_of("tls-cert"), m.value_of("tls-key")) {
+                (Some(cert), Some(key)) => Some((read_file(cert), read_file(key))),
+                _ => None,
+            };
+            new_tls_channel(addr, timeout, client_tls_config(read_file(ca), identity)).await
+        }
+        None => new_channel(addr, timeout).await,
+    };
+    match channel {
+        Ok(ch) => SnarkTaskServiceClient::with_interceptor(ch, token),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1)
+        }
+    }
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("failed to read {}: {}", path, e);
+            exit(1)
+        }
+    }
+}
+
+async fn lock_task(
+    client: &mut Client,
+    task_id: &str,
+    lock_duration_ms: u64,
+    wait: Duration,
+    slot: u32,
+    priority: bool,
+) -> anyhow::Result<()> {
+    let res = client
+        .lock_server_wait(Request::new(LockServerWaitRequest {
+            task_id: task_id.to_string(),
+            lock_duration_ms,
+            wait_timeout_ms: wait.as_millis() as u64,
+            slot,
+            priority,
+        }))
+        .await?
+        .into_inner();
+    println!(
+        "task {} locked slot {} for {}ms",
+        task_id, res.slot, res.lock_remaining_ms
+    );
+    Ok(())
+}
+
+async fn status(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let task_id = m.value_of("task-id").unwrap_or_default().to_string();
+    let status = client
+        .get_worker_status(Request::new(GetWorkerStatusRequest {
+            task_id,
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+    let version = status.build.map(|b| b.version).unwrap_or_default();
+    println!(
+        "{} up {}s, version {}, {} gpus",
+        status.status,
+        status.uptime_ms / 1000,
+        version,
+        status.gpu_count
+    );
+    for s in status.slots {
+        println!(
+            "slot {} on {}: {}, task {} {}, {} queued",
+            s.slot, s.device_name, s.status, s.task_id, s.task_status, s.queue_len
+        );
+    }
+    Ok(())
+}
+
+async fn lock(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let task_id = m.value_of("task-id").unwrap();
+    let duration = m.value_of("duration").unwrap().parse::<u64>()?;
+    let wait = m.value_of("wait").unwrap().parse::<u64>()?;
+    let slot = m.value_of("slot").unwrap().parse::<u32>()?;
+    lock_task(client, task_id, duration, Duration::from_secs(wait), slot, m.is_present("priority")).await
+}
+
+async fn unlock(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let task_id = m.value_of("task-id").unwrap().to_string();
+    let res = client
+        .unlock_server(Request::new(UnlockServerRequest { task_id }))
+        .await?;
+    print_response(res.into_inner());
+    Ok(())
+}
+
+async fn cancel(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let task_id = m.value_of("task-id").unwrap().to_string();
+    let res = client
+        .cancel_task(Request::new(CancelTaskRequest { task_id }))
+        .await?;
+    print_response(res.into_inner());
+    Ok(())
+}
+
+fn print_response(res: BaseResponse) {
+    if res.detail.is_empty() {
+        // servers which predate the detail
+        println!("{}", res.msg);
+    } else {
+        println!("{} (server {})", res.detail, res.server_version);
+    }
+}
+
+fn read_task(task_id: &str, m: &ArgMatches<'_>) -> anyhow::Result<SnarkTaskRequestParams> {
+    let encoding = m.value_of("encoding").unwrap().parse::<Encoding>()?;
+    let post_config =
+        compat::decode_post_config(Encoding::Json, &fs::read(m.value_of("config").unwrap())?)?;
+    if let Some(f) = m.value_of("manifest") {
+        let remote: RemotePoSt = serde_json::from_slice(&fs::read(f)?)?;
+        return Ok(SnarkTaskRequestParams {
+            task_id: task_id.to_string(),
+            replicas_len: remote.replicas.len() as u32,
+            priority: m.is_present("priority"),
+            payload_encoding: encoding.to_wire() as i32,
+            post_config: Some(post_config_to_proto(&post_config)),
+            max_threads: m.value_of("threads").unwrap().parse::<u32>()?,
+            partitions: m.value_of("partitions").unwrap().parse::<u32>()?,
+            randomness: remote.randomness.to_vec(),
+            prover_id: remote.prover_id.to_vec(),
+            replicas: remote.replicas_to_proto(),
+            stream_partitions: m.is_present("stream-partitions"),
+            ..Default::default()
+        });
+    }
+    Ok(SnarkTaskRequestParams {
+        task_id: task_id.to_string(),
+        vanilla_proof: match m.value_of("vanilla") {
+            Some(f) => fs::read(f)?,
+            None => vec![],
+        },
+        pub_in: fs::read(m.value_of("pub-in").unwrap())?,
+        legacy_post_config: vec![],
+        replicas_len: m.value_of("replicas").unwrap().parse::<u32>()?,
+        priority: m.is_present("priority"),
+        client_id: String::new(),
+        payload_encoding: encoding.to_wire() as i32,
+        post_config: Some(post_config_to_proto(&post_config)),
+        max_threads: m.value_of("threads").unwrap().parse::<u32>()?,
+        vanilla_proof_path: m.value_of("vanilla-path").unwrap_or_default().to_string(),
+        randomness: vec![],
+        prover_id: vec![],
+        replicas: vec![],
+        partitions: m.value_of("partitions").unwrap().parse::<u32>()?,
+        pub_sectors: vec![],
+        stream_partitions: m.is_present("stream-partitions"),
+        vanilla_proofs: None,
+    })
+}
+
+fn inspect_payload(m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let encoding = m.value_of("encoding").unwrap().parse::<Encoding>()?;
+    let post_config =
+        compat::decode_post_config(Encoding::Json, &fs::read(m.value_of("config").unwrap())?)?;
+    let read = |name: &str| -> anyhow::Result<Vec<u8>> {
+        match m.value_of(name) {
+            Some(f) => Ok(fs::read(f)?),
+            None => Ok(vec![]),
+        }
+    };
+    let replicas_len = match m.value_of("replicas") {
+        Some(r) => Some(r.parse::<usize>()?),
+        None => None,
+    };
+    let summary = inspect::inspect(
+        &post_config,
+        encoding,
+        &read("pub-in")?,
+        &read("vanilla")?,
+        replicas_len,
+    )?;
+    println!("{}", summary);
+    Ok(())
+}
+
+fn replay(m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let (meta, task_info) = dump::load_dump(Path::new(m.value_of("DUMP").unwrap()))?;
+    println!(
+        "replay task {} of client {}, which failed with: {}",
+        meta.task_id, meta.client_id, meta.error
+    );
+    let start = Instant::now();
+    let (proof, timings) = tasks::prove_task(task_info)?;
+    println!("task {} proved in {:?}", meta.task_id, start.elapsed());
+    println!(
+        "{} partitions, decode {:?}, load params {:?}, prove {:?}, per partition {:?}",
+        timings.partitions, timings.decode, timings.load_params, timings.prove, timings.partition_prove
+    );
+    if let Some(output) = m.value_of("output") {
+        fs::write(output, &proof)?;
+        println!("proof written to {}", output);
+    }
+    Ok(())
+}
+
+async fn submit(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let task_id = m.value_of("task-id").unwrap();
+    let params = read_task(task_id, m)?;
+    let res = send_task(client, params, m).await?;
+    print_response(res);
+    Ok(())
+}
+
+async fn send_task(
+    client: &mut Client,
+    params: SnarkTaskRequestParams,
+    m: &ArgMatches<'_>,
+) -> Result<BaseResponse, Status> {
+    let res = if m.is_present("chunked") {
+        let frames = task_frames(params, UPLOAD_CHUNK_LEN);
+        client
+            .do_snark_task_stream(Request::new(futures::stream::iter(frames)))
+            .await?
+    } else {
+        client.do_snark_task(Request::new(params)).await?
+    };
+    Ok(res.into_inner())
+}
+
+async fn wait_result(
+    client: &mut Client,
+    task_id: &str,
+    output: &str,
+    pub_in_digest: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    loop {
+        let res = client
+            .get_snark_task_result(Request::new(GetTaskResultRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?
+            .into_inner();
+        if res.msg == "ok" {
+            if let Some(d) = pub_in_digest {
+                // servers which do not send a digest can not be checked
+                if !res.pub_in_digest.is_empty() && res.pub_in_digest != d {
+                    return Err(anyhow::Error::msg(format!(
+                        "proof of task {} is not for the submitted public inputs",
+                        task_id
+                    )));
+                }
+            }
+            fs::write(output, &res.result)?;
+            println!("proof of task {} written to {}", task_id, output);
+            // the server keeps the proof until it is acked, and the server with it
+            match client
+                .ack_result(Request::new(AckResultRequest {
+                    task_id: task_id.to_string(),
+                }))
+                .await
+            {
+                Ok(ack) => print_response(ack.into_inner()),
+                // servers from before acks free themselves on the first fetch
+                Err(s) if s.code() == Code::Unimplemented => {}
+                Err(s) => return Err(anyhow::Error::from(s)),
+            }
+            if res.degraded {
+                println!("proved on the cpu after the gpu failed");
+            }
+            // servers which do not send timestamps leave them 0
+            if res.finished_at_ms >= res.started_at_ms && res.started_at_ms > 0 {
+                println!(
+                    "waited {}ms in queue, proved in {}ms",
+                    res.started_at_ms.saturating_sub(res.created_at_ms),
+                    res.finished_at_ms - res.started_at_ms
+                );
+            }
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+async fn result(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let task_id = m.value_of("task-id").unwrap();
+    wait_result(client, task_id, m.value_of("output").unwrap(), None).await
+}
+
+async fn report(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let format = match m.value_of("format").unwrap() {
+        "json" => ReportFormat::ReportJson,
+        _ => ReportFormat::ReportCsv,
+    };
+    let mut req = ExportTaskReportRequest {
+        from_ms: m.value_of("from").unwrap().parse()?,
+        to_ms: m.value_of("to").unwrap().parse()?,
+        format: format as i32,
+        ..Default::default()
+    };
+    // the server reports a page at a time, put them together into one file
+    let (mut content, mut json, mut tasks) = (vec![], vec![], 0);
+    loop {
+        let report = client
+            .export_task_report(Request::new(req.clone()))
+            .await?
+            .into_inner();
+        tasks += report.tasks;
+        match format {
+            ReportFormat::ReportJson => {
+                json.extend(serde_json::from_slice::<Vec<serde_json::Value>>(&report.content)?)
+            }
+            // the csv header comes with every page
+            _ if content.is_empty() => content = report.content,
+            _ => content.extend(report.content.splitn(2, |b| *b == b'\n').nth(1).unwrap_or_default()),
+        }
+        if !report.more {
+            break;
+        }
+        req.from_ms = report.next_from_ms;
+        req.after_task_id = report.next_after_task_id;
+    }
+    if format == ReportFormat::ReportJson {
+        content = serde_json::to_vec_pretty(&json)?;
+    }
+    let output = m.value_of("output").unwrap();
+    fs::write(output, &content)?;
+    println!("report of {} tasks written to {}", tasks, output);
+    Ok(())
+}
+
+async fn state(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let state = client
+        .dump_server_state(Request::new(DumpServerStateRequest {}))
+        .await?
+        .into_inner();
+    match m.value_of("output") {
+        Some(output) => {
+            fs::write(output, &state.json)?;
+            println!("state written to {}", output);
+        }
+        None => println!("{}", state.json),
+    }
+    Ok(())
+}
+
+async fn params(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let action = match m.value_of("ACTION").unwrap() {
+        "evict" => ParamCacheAction::ParamsEvict,
+        "reload" => ParamCacheAction::ParamsReload,
+        _ => ParamCacheAction::ParamsList,
+    };
+    let sector_size = m.value_of("sector-size").unwrap().parse::<u64>()?;
+    let cached = client
+        .manage_param_cache(Request::new(ManageParamCacheRequest {
+            action: action as i32,
+            sector_size,
+        }))
+        .await?
+        .into_inner();
+    for p in cached.params {
+        println!(
+            "{} {:?} loaded at {} in {}ms",
+            p.sector_size,
+            p.typ(),
+            p.loaded_at_ms,
+            p.load_ms
+        );
+    }
+    Ok(())
+}
+
+async fn timeouts(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let ms = |name: &str| -> anyhow::Result<u64> {
+        match m.value_of(name) {
+            Some(s) => Ok(s.parse::<u64>()? * 1000),
+            None => Ok(0),
+        }
+    };
+    let timeouts = client
+        .set_server_timeouts(Request::new(SetServerTimeoutsRequest {
+            server_lock_time_out_ms: ms("lock")?,
+            server_lock_time_out_max_ms: ms("lock-max")?,
+            server_task_get_back_time_out_ms: ms("get-back")?,
+            server_exit_time_out_after_task_done_ms: ms("exit-after-task-done")?,
+        }))
+        .await?
+        .into_inner();
+    println!("lock: {}ms", timeouts.server_lock_time_out_ms);
+    println!("lock max: {}ms", timeouts.server_lock_time_out_max_ms);
+    println!("get back: {}ms", timeouts.server_task_get_back_time_out_ms);
+    println!(
+        "exit after task done: {}ms",
+        timeouts.server_exit_time_out_after_task_done_ms
+    );
+    Ok(())
+}
+
+async fn prove(client: &mut Client, m: &ArgMatches<'_>) -> anyhow::Result<()> {
+    let task_id = match m.value_of("task-id") {
+        Some(t) => t.to_string(),
+        None => Uuid::new_v4().to_string(),
+    };
+    let params = read_task(&task_id, m)?;
+    let pub_in_digest = tasks::pub_in_digest(&params.pub_in);
+    lock_task(client, &task_id, 0, Duration::from_secs(600), 0, m.is_present("priority")).await?;
+    if let Err(e) = send_task(client, params, m).await {
+        // do not keep the server locked for nothing
+        let _ = client
+            .unlock_server(Request::new(UnlockServerRequest {
+                task_id: task_id.clone(),
+            }))
+            .await;
+        return Err(anyhow::Error::from(e));
+    }
+    println!("task {} submitted", task_id);
+    wait_result(
+        client,
+        &task_id,
+        m.value_of("output").unwrap(),
+        Some(&pub_in_digest),
+    )
+    .await
+}
@@ -0,0 +1,88 @@
+use clap::{App, Arg};
+use futures::FutureExt;
+use log::info;
+use std::env;
+use std::process::exit;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use window_post_snark_server::auth::BearerToken;
+use window_post_snark_server::gateway::{Gateway, GatewayConfig};
+use window_post_snark_server::limits::ConnectionLimits;
+use window_post_snark_server::prelude::SnarkTaskServiceServer;
+use window_post_snark_server::server::server_builder;
+use window_post_snark_server::utils;
+
+fn main() {
+    let matches = App::new("wps-gateway")
+        .author(utils::author())
+        .version(utils::version())
+        .about("route the tasks of miners to several window-post-snark-servers")
+        .args(&[
+            Arg::from_usage("-p, --port=[PORT] 'port the gateway listens on'")
+                .default_value("50050"),
+            Arg::from_usage("-b, --backend=<ADDR>... 'address of a server, e.g. http://10.0.0.2:50051'")
+                .number_of_values(1),
+            Arg::from_usage("--heartbeat-interval=[SECONDS] 'how often the servers are asked for their status'")
+                .default_value("5"),
+            Arg::from_usage("--token=[TOKEN] 'api token or JWT to call the servers with'")
+                .env("WPS_TOKEN"),
+            Arg::from_usage("--reserved-for-priority=[NUM] 'servers only priority tasks may take'")
+                .default_value("0"),
+            Arg::from_usage("--tenant-limit=[TENANT=NUM]... 'servers a tenant may have tasks on at once'")
+                .number_of_values(1),
+            Arg::from_usage("--debug 'print debug log'"),
+        ])
+        .get_matches();
+
+    env::set_var("RUST_LOG", if matches.is_present("debug") { "debug" } else { "info" });
+    fil_logger::init();
+
+    let mut config = GatewayConfig::new(matches.values_of("backend").unwrap().map(String::from).collect());
+    config.heartbeat_interval = Duration::from_secs(number(matches.value_of("heartbeat-interval").unwrap(), "heartbeat interval"));
+    config.capacity.reserved_for_priority = number(matches.value_of("reserved-for-priority").unwrap(), "reserved for priority") as usize;
+    for limit in matches.values_of("tenant-limit").into_iter().flatten() {
+        let mut parts = limit.splitn(2, '=');
+        let (tenant, num) = match (parts.next(), parts.next()) {
+            (Some(tenant), Some(num)) => (tenant, num),
+            _ => {
+                eprintln!("bad tenant limit {}, expected TENANT=NUM", limit);
+                exit(1)
+            }
+        };
+        config.capacity.tenant_limits.insert(tenant.to_string(), number(num, "tenant limit") as usize);
+    }
+    if let Some(t) = matches.value_of("token") {
+        config.token = BearerToken::new(t).unwrap_or_else(|e| {
+            eprintln!("{:?}", e);
+            exit(1)
+        });
+    }
+
+    let port = matches.value_of("port").unwrap();
+    let addr = format!("0.0.0.0:{}", port).parse().unwrap_or_else(|e| {
+        eprintln!("bad port {}: {}", port, e);
+        exit(1)
+    });
+    let rt = Runtime::new().expect("failed to build new runtime");
+    let result = rt.block_on(async move {
+        let gateway = Gateway::new(config)?;
+        gateway.spawn_heartbeats();
+        info!("gateway listens on {}", addr);
+        server_builder(&ConnectionLimits::default())
+            .add_service(SnarkTaskServiceServer::new(gateway))
+            .serve_with_shutdown(addr, tokio::signal::ctrl_c().map(drop))
+            .await?;
+        Ok::<_, anyhow::Error>(())
+    });
+    if let Err(e) = result {
+        eprintln!("{:?}", e);
+        exit(1)
+    }
+}
+
+fn number(value: &str, what: &str) -> u64 {
+    value.parse().unwrap_or_else(|e| {
+        eprintln!("bad {} {}: {}", what, value, e);
+        exit(1)
+    })
+}
@@ -0,0 +1,134 @@
+use crate::codec::{self, Encoding};
+use crate::error::{Error, Result};
+use crate::remote;
+use crate::snark_proof_grpc::C2TaskRequest;
+use crate::status::TaskStatus;
+use crate::tasks::{invalid_field, TaskInfo, TaskTimings};
+use filecoin_proofs::{seal_commit_phase2, verify_seal, PoRepConfig, SealCommitPhase1Output};
+use log::info;
+use sha2::{Digest, Sha256};
+use std::time::{Instant, SystemTime};
+use storage_proofs_core::{merkle::MerkleTreeTrait, sector::SectorId};
+
+/// Seal commit phase2 of a sector. The output of phase1 is kept as the vanilla proof of
+/// the task, so it is moved, dumped and wiped like the payloads of a PoSt task.
+#[derive(Debug, Clone)]
+pub struct C2Task {
+    pub porep_config: PoRepConfig,
+    pub prover_id: [u8; 32],
+    pub sector_id: u64,
+}
+
+pub fn set_c2_task_info(req: &C2TaskRequest) -> Result<TaskInfo> {
+    if req.commit_phase1_output.is_empty() {
+        return Err(invalid_field(
+            "commit_phase1_output",
+            anyhow::Error::msg("is empty"),
+        ));
+    }
+    let porep_config = match &req.porep_config {
+        Some(p) => {
+            codec::porep_config_from_proto(p).map_err(|e| invalid_field("porep_config", e))?
+        }
+        None => {
+            return Err(invalid_field(
+                "porep_config",
+                anyhow::Error::msg("is not set"),
+            ))
+        }
+    };
+    let payload_encoding = Encoding::from_wire(req.payload_encoding)
+        .map_err(|e| invalid_field("payload_encoding", e))?;
+    let c2 = C2Task {
+        porep_config,
+        prover_id: remote::bytes32("prover_id", &req.prover_id)?,
+        sector_id: req.sector_id,
+    };
+    Ok(TaskInfo {
+        task_id: req.task_id.clone(),
+        vanilla_proof: req.commit_phase1_output.clone(),
+        task_status: TaskStatus::Ready,
+        priority: req.priority,
+        client_id: req.client_id.clone(),
+        payload_encoding,
+        max_threads: req.max_threads as usize,
+        vanilla_proof_digest: Sha256::digest(&req.commit_phase1_output).to_vec(),
+        vanilla_proof_bytes: req.commit_phase1_output.len(),
+        created_at: Some(SystemTime::now()),
+        c2: Some(c2),
+        ..Default::default()
+    })
+}
+
+/// Prove the sealed sector, the result is the proof `seal_commit_phase2` returns
+pub(crate) fn run_c2<Tree: 'static + MerkleTreeTrait>(
+    task_info: &TaskInfo,
+) -> Result<(Vec<u8>, TaskTimings)> {
+    let c2 = match &task_info.c2 {
+        Some(c2) => c2,
+        None => {
+            return Err(anyhow::Error::from(Error::InvalidParameters(format!(
+                "task {} is not a C2 task",
+                task_info.task_id
+            ))))
+        }
+    };
+    let mut timings = TaskTimings {
+        partitions: usize::from(c2.porep_config.partitions),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let phase1_output: SealCommitPhase1Output<Tree> =
+        codec::decode(task_info.payload_encoding, &task_info.vanilla_proof)?;
+    timings.decode = start.elapsed();
+    task_info.cancel.check(&task_info.task_id)?;
+
+    // proving consumes the phase1 output, verifying needs its commitments again
+    let verify = if task_info.verify_proof {
+        Some((
+            phase1_output.comm_r,
+            phase1_output.comm_d,
+            phase1_output.ticket,
+            phase1_output.seed,
+        ))
+    } else {
+        None
+    };
+    // the groth parameters are loaded inside, their time is part of prove
+    let start = Instant::now();
+    let output = seal_commit_phase2(
+        c2.porep_config.clone(),
+        phase1_output,
+        c2.prover_id,
+        SectorId::from(c2.sector_id),
+    )?;
+    timings.prove = start.elapsed();
+    task_info.cancel.check(&task_info.task_id)?;
+    if let Some((comm_r, comm_d, ticket, seed)) = verify {
+        let start = Instant::now();
+        if !verify_seal::<Tree>(
+            c2.porep_config.clone(),
+            comm_r,
+            comm_d,
+            c2.prover_id,
+            SectorId::from(c2.sector_id),
+            ticket,
+            seed,
+            &output.proof,
+        )? {
+            return Err(anyhow::Error::from(Error::ProofNotVerified(
+                task_info.task_id.clone(),
+            )));
+        }
+        info!(
+            "proof of task {} verified in {:?}",
+            task_info.task_id,
+            start.elapsed()
+        );
+    }
+    info!(
+        "C2 of sector {} task {} timings: {} partitions, decode {:?}, prove {:?}",
+        c2.sector_id, task_info.task_id, timings.partitions, timings.decode, timings.prove
+    );
+    Ok((output.proof, timings))
+}
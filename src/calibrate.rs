@@ -0,0 +1,202 @@
+//! Benchmark of the hardware the server proves on. The profile it writes tells how many
+//! constraints per second a proof runs at here, which estimates tasks not proved here
+//! yet and weighs the backends of the gateway.
+
+use crate::error::Result;
+use crate::utils;
+use anyhow::Context;
+use bellperson::groth16;
+use bellperson::util_cs::bench_cs::BenchCS;
+use bellperson::{Circuit, ConstraintSystem, SynthesisError};
+use blstrs::{Bls12, Scalar as Fr};
+use ff::Field;
+use filecoin_proofs::parameters::window_post_setup_params;
+use filecoin_proofs::{
+    with_shape, PoStConfig, PoStType, SectorSize, WINDOW_POST_CHALLENGE_COUNT,
+    WINDOW_POST_SECTOR_COUNT,
+};
+use log::info;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use storage_proofs_core::api_version::ApiVersion;
+use storage_proofs_core::compound_proof::CompoundProof;
+use storage_proofs_core::merkle::MerkleTreeTrait;
+use storage_proofs_core::proof::ProofScheme;
+use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCompound};
+
+// constraints of the synthetic circuit, large enough for the gpu kernels to be used
+pub const CALIBRATE_CONSTRAINTS_DEFAULT: usize = 1 << 18;
+
+// the benchmark proves the same circuit on every run
+const SEED: [u8; 16] = [
+    0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc, 0xe5,
+];
+
+/// Performance of the device a server proves on, as measured by `calibrate`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HardwareProfile {
+    // gpus the benchmark was pinned to, empty for any
+    pub device_id: String,
+    // server version which measured it
+    pub version: String,
+    pub created_at_ms: u64,
+    // of the synthetic circuit
+    pub constraints: usize,
+    pub synthesize_ms: u64,
+    // synthesis included
+    pub prove_ms: u64,
+    pub constraints_per_sec: f64,
+    // sector size to the constraints of one window PoSt partition
+    pub partition_constraints: BTreeMap<u64, usize>,
+}
+
+impl HardwareProfile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("failed to write {:?}", path))?;
+        Ok(())
+    }
+
+    /// Proving time of a window PoSt task, None for sector sizes not calibrated. Loading
+    /// the parameters is not included.
+    pub fn estimate(&self, sector_size: u64, partitions: usize) -> Option<Duration> {
+        let constraints = *self.partition_constraints.get(&sector_size)?;
+        if self.constraints_per_sec <= 0.0 {
+            return None;
+        }
+        let secs = (constraints * partitions.max(1)) as f64 / self.constraints_per_sec;
+        Some(Duration::from_secs_f64(secs))
+    }
+}
+
+/// Prove a synthetic circuit of `constraints` constraints and count the constraints of a
+/// window PoSt partition of each of the post configs. Takes the gpu for minutes, run it
+/// while no server proves on it.
+pub fn calibrate(constraints: usize, post_configs: &[PoStConfig]) -> Result<HardwareProfile> {
+    let rng = &mut XorShiftRng::from_seed(SEED);
+    let circuit = SquaringChain {
+        start: Fr::random(&mut *rng),
+        constraints,
+    };
+
+    let started = Instant::now();
+    let mut cs = BenchCS::<Fr>::new();
+    circuit.clone().synthesize(&mut cs)?;
+    let synthesize = started.elapsed();
+    info!(
+        "synthesized {} constraints in {:?}",
+        cs.num_constraints(),
+        synthesize
+    );
+
+    let params = groth16::generate_random_parameters::<Bls12, _, _>(circuit.clone(), rng)?;
+    // the first proof also sets up the gpu kernels
+    groth16::create_random_proof(circuit.clone(), &params, rng)?;
+    let started = Instant::now();
+    groth16::create_random_proof(circuit, &params, rng)?;
+    let prove = started.elapsed();
+    info!("proved {} constraints in {:?}", constraints, prove);
+
+    let mut partition_constraints = BTreeMap::new();
+    for post_config in post_configs {
+        let sector_size = u64::from(post_config.sector_size);
+        let n = partition_constraints_of(post_config)?;
+        info!(
+            "window PoSt partition of sector size {}: {} constraints",
+            sector_size, n
+        );
+        partition_constraints.insert(sector_size, n);
+    }
+
+    Ok(HardwareProfile {
+        device_id: utils::gpu_device_id(),
+        version: utils::version().to_string(),
+        created_at_ms: utils::unix_millis(Some(SystemTime::now())),
+        constraints,
+        synthesize_ms: synthesize.as_millis() as u64,
+        prove_ms: prove.as_millis() as u64,
+        constraints_per_sec: constraints as f64 / prove.as_secs_f64().max(0.001),
+        partition_constraints,
+    })
+}
+
+/// Window PoSt config of the sector size as the miners use it
+pub fn window_post_config(sector_size: u64) -> Result<PoStConfig> {
+    let sector_count = *WINDOW_POST_SECTOR_COUNT
+        .read()
+        .unwrap()
+        .get(&sector_size)
+        .with_context(|| format!("no window PoSt for sector size {}", sector_size))?;
+    Ok(PoStConfig {
+        sector_size: SectorSize(sector_size),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        sector_count,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    })
+}
+
+/// Constraints of one window PoSt partition of the post config
+pub fn partition_constraints_of(post_config: &PoStConfig) -> Result<usize> {
+    with_shape!(
+        u64::from(post_config.sector_size),
+        blank_partition_constraints,
+        post_config
+    )
+}
+
+fn blank_partition_constraints<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+) -> Result<usize> {
+    let pub_params = FallbackPoSt::<Tree>::setup(&window_post_setup_params(post_config))?;
+    let circuit = FallbackPoStCompound::<Tree>::blank_circuit(&pub_params);
+    let mut cs = BenchCS::<Fr>::new();
+    circuit.synthesize(&mut cs)?;
+    Ok(cs.num_constraints())
+}
+
+// x, x^2, x^4, ... one multiplication per constraint. What the constraints compute
+// hardly matters, the multiexps and FFTs over them take the time.
+#[derive(Clone)]
+struct SquaringChain {
+    start: Fr,
+    constraints: usize,
+}
+
+impl Circuit<Fr> for SquaringChain {
+    fn synthesize<CS: ConstraintSystem<Fr>>(
+        self,
+        cs: &mut CS,
+    ) -> std::result::Result<(), SynthesisError> {
+        let mut value = self.start;
+        let mut var = cs.alloc(|| "x0", || Ok(value))?;
+        for i in 0..self.constraints {
+            let next = value.square();
+            let next_var = if i + 1 == self.constraints {
+                cs.alloc_input(|| format!("x{}", i + 1), || Ok(next))?
+            } else {
+                cs.alloc(|| format!("x{}", i + 1), || Ok(next))?
+            };
+            cs.enforce(
+                || format!("square {}", i),
+                |lc| lc + var,
+                |lc| lc + var,
+                |lc| lc + next_var,
+            );
+            value = next;
+            var = next_var;
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,1146 @@
+use crate::auth::BearerToken;
+use crate::codec::{self, Encoding};
 use crate::error::{Error, Result};
+use crate::pool::ClientPool;
+use crate::pub_inputs::RawPublicInputs;
+use crate::remote::{self, RemotePoSt, RemoteReplica};
+use crate::server::WindowPostSnarkServer;
+use crate::snark_proof_grpc::snark_task_frame::Frame;
 use crate::snark_proof_grpc::snark_task_service_client::SnarkTaskServiceClient;
+use crate::snark_proof_grpc::snark_task_service_server::SnarkTaskServiceServer;
+use crate::snark_proof_grpc::{
+    AckResultRequest, BaseResponse, BatchReport, C2TaskRequest, CachedParams, CancelTaskRequest,
+    DumpServerStateRequest, EstimateTaskRequest, GetBatchReportRequest, GetCapabilitiesRequest,
+    GetTaskResultRequest, GetWorkerStatusRequest, ManageParamCacheRequest, ParamCacheAction,
+    PartitionProof, ServerTimeouts, SetServerTimeoutsRequest, SnarkTaskFrame,
+    SnarkTaskRequestParams, SubmitBatchRequest, SubmitBatchResponse, TaskEstimate,
+    UnlockServerRequest, WorkerStatus,
+};
+use crate::state::ServerState;
+use crate::tasks;
+use crate::vanilla;
+use bellperson::groth16;
+use blstrs::Bls12;
+use filecoin_hashers::{Domain, Hasher};
+use filecoin_proofs::caches::get_post_verifying_key;
+use filecoin_proofs::{
+    get_partitions_for_window_post, with_shape, ChallengeSeed, PoRepConfig, PoStConfig, PoStType,
+    PrivateReplicaInfo, ProverId, SnarkProof, SINGLE_PARTITION_PROOF_LEN,
+};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use tonic::transport::Channel;
+use storage_proofs_core::compound_proof::CompoundProof;
+use storage_proofs_core::merkle::MerkleTreeTrait;
+use storage_proofs_core::multi_proof::MultiProof;
+use storage_proofs_core::sector::SectorId;
+use storage_proofs_post::fallback::{
+    self, ChallengeRequirements, FallbackPoStCompound, PublicInputs,
+};
+use tokio::runtime::{self, Runtime};
+use tokio::task::JoinHandle;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, NamedService};
+use tonic::{Code, Request, Streaming};
+use tonic_health::proto::health_check_response::ServingStatus;
+use tonic_health::proto::health_client::HealthClient;
+use tonic_health::proto::HealthCheckRequest;
+use uuid::Uuid;
+
+/// Payloads of tasks sent with `submit_chunked` are split into chunks of this many bytes
+pub const UPLOAD_CHUNK_LEN: usize = 1 << 20;
+// how remote_generate_window_post waits for servers and proofs
+const REMOTE_POST_CONNECT_TIME_OUT: Duration = Duration::from_secs(10);
+const REMOTE_POST_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const REMOTE_POST_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const REMOTE_POST_ROUNDS: usize = 3;
+
+pub async fn new_channel(addr: &'static str, timeout: Duration) -> Result<Channel> {
+    connect_endpoint(Channel::from_shared(addr)?.timeout(timeout)).await
+}
+
+/// Channel to a server serving over TLS, `addr` is an `https://` address
+pub async fn new_tls_channel(
+    addr: &'static str,
+    timeout: Duration,
+    tls: ClientTlsConfig,
+) -> Result<Channel> {
+    connect_endpoint(
+        Channel::from_shared(addr)?
+            .timeout(timeout)
+            .tls_config(tls)?,
+    )
+    .await
+}
+
+async fn connect_endpoint(endpoint: Endpoint) -> Result<Channel> {
+    match endpoint.connect().await {
+        Ok(ch) => Ok(ch),
+        Err(e) => Err(anyhow::Error::from(Error::NewClientFailed(e.to_string()))),
+    }
+}
+
+/// TLS of the connections to a server: the PEM encoded CA the server certificate is
+/// checked against, and the PEM certificate and key of the client for servers which
+/// only take calls of clients with a certificate
+pub fn client_tls_config(
+    ca_pem: impl AsRef<[u8]>,
+    identity: Option<(Vec<u8>, Vec<u8>)>,
+) -> ClientTlsConfig {
+    let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_pem));
+    match identity {
+        Some((cert_pem, key_pem)) => tls.identity(Identity::from_pem(cert_pem, key_pem)),
+        None => tls,
+    }
+}
 
 pub async fn new_client(
     addr: &'static str,
     timeout: Duration,
 ) -> Result<SnarkTaskServiceClient<Channel>> {
-    match Channel::from_shared(addr)?.timeout(timeout).connect().await {
-        Ok(ch) => Ok(SnarkTaskServiceClient::new(ch)),
-        Err(e) => Err(anyhow::Error::from(Error::NewClientFailed(e.to_string()))),
+    Ok(SnarkTaskServiceClient::new(
+        new_channel(addr, timeout).await?,
+    ))
+}
+
+pub async fn new_tls_client(
+    addr: &'static str,
+    timeout: Duration,
+    tls: ClientTlsConfig,
+) -> Result<SnarkTaskServiceClient<Channel>> {
+    Ok(SnarkTaskServiceClient::new(
+        new_tls_channel(addr, timeout, tls).await?,
+    ))
+}
+
+// success is told by the code, servers which predate it send none, which is OK as well
+fn check_response(res: BaseResponse) -> Result<()> {
+    if res.code != Code::Ok as i32 {
+        return Err(anyhow::Error::msg(format!(
+            "server {} answered with code {}: {}",
+            res.server_version, res.code, res.detail
+        )));
+    }
+    debug!("server {}: {}", res.server_version, res.detail);
+    Ok(())
+}
+
+/// Client speaking in proofs instead of the raw bytes of the gRPC messages.
+/// Every call is a plain async fn running on the runtime of the caller,
+/// see BlockingWindowPostClient for callers without one.
+#[derive(Debug, Clone)]
+pub struct WindowPostClient {
+    inner: SnarkTaskServiceClient<Channel>,
+    // for the health service, shared with inner
+    channel: Channel,
+    // payload encoding agreed with the server, asked for on the first submission
+    encoding: Option<Encoding>,
+    // sent with every call
+    token: BearerToken,
+}
+
+impl WindowPostClient {
+    pub async fn connect(addr: &'static str, timeout: Duration) -> Result<Self> {
+        Ok(Self::with_channel(new_channel(addr, timeout).await?))
+    }
+
+    /// Client of a server serving over TLS, see `client_tls_config`
+    pub async fn connect_tls(
+        addr: &'static str,
+        timeout: Duration,
+        tls: ClientTlsConfig,
+    ) -> Result<Self> {
+        Ok(Self::with_channel(
+            new_tls_channel(addr, timeout, tls).await?,
+        ))
+    }
+
+    /// Client which connects on its first call, so a server which is down now does
+    /// not fail it
+    pub fn connect_lazy(addr: &'static str, timeout: Duration) -> Result<Self> {
+        match Channel::from_static(addr).timeout(timeout).connect_lazy() {
+            Ok(ch) => Ok(Self::with_channel(ch)),
+            Err(e) => Err(anyhow::Error::from(Error::NewClientFailed(e.to_string()))),
+        }
+    }
+
+    fn with_channel(channel: Channel) -> Self {
+        WindowPostClient {
+            inner: SnarkTaskServiceClient::new(channel.clone()),
+            channel,
+            encoding: None,
+            token: BearerToken::default(),
+        }
+    }
+
+    /// Authenticate with an api token or a JWT on the following calls
+    pub fn set_token(&mut self, token: &str) -> Result<()> {
+        self.token = BearerToken::new(token)?;
+        Ok(())
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        self.token.apply(request.metadata_mut());
+        request
+    }
+
+    /// The generated client, for the calls not wrapped here, which have to add the token themselves
+    pub fn raw(&mut self) -> &mut SnarkTaskServiceClient<Channel> {
+        &mut self.inner
+    }
+
+    /// Whether the server answers and takes tasks, to check a list of servers before
+    /// a proving period rather than find dead ones when locking
+    pub async fn is_healthy(&mut self) -> bool {
+        let service = <SnarkTaskServiceServer<WindowPostSnarkServer> as NamedService>::NAME;
+        let mut health = HealthClient::new(self.channel.clone());
+        match health
+            .check(self.request(HealthCheckRequest {
+                service: service.to_string(),
+            }))
+            .await
+        {
+            Ok(res) => res.into_inner().status == ServingStatus::Serving as i32,
+            // servers from before the health service only tell they answer
+            Err(s) if s.code() == Code::Unimplemented => self
+                .inner
+                .get_capabilities(self.request(GetCapabilitiesRequest {}))
+                .await
+                .is_ok(),
+            Err(s) => {
+                debug!("health check failed: {}", s);
+                false
+            }
+        }
+    }
+
+    /// Probe the server every `interval` in the background, the probe answers
+    /// is_healthy from the last result without a call. Needs a tokio runtime.
+    pub fn spawn_health_probe(&self, interval: Duration) -> HealthProbe {
+        let healthy = Arc::new(AtomicBool::new(false));
+        let mut client = self.clone();
+        let probe_healthy = healthy.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                probe_healthy.store(client.is_healthy().await, Ordering::SeqCst);
+                tokio::time::sleep(interval).await;
+            }
+        });
+        HealthProbe { healthy, handle }
+    }
+
+    /// Lock the server for the task if it is free, the status tells whether it was.
+    /// A server reserved for priority tasks refuses the lock unless `priority` is set.
+    pub async fn lock(&mut self, task_id: &str, priority: bool) -> Result<WorkerStatus> {
+        let status = self
+            .inner
+            .lock_server_if_free(self.request(GetWorkerStatusRequest {
+                task_id: task_id.to_string(),
+                priority,
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+        Ok(status)
+    }
+
+    /// Status of the server without locking it, for the caller of the task if not empty
+    pub async fn status(&mut self, task_id: &str) -> Result<WorkerStatus> {
+        let status = self
+            .inner
+            .get_worker_status(self.request(GetWorkerStatusRequest {
+                task_id: task_id.to_string(),
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+        Ok(status)
+    }
+
+    pub async fn unlock(&mut self, task_id: &str) -> Result<()> {
+        let res = self
+            .inner
+            .unlock_server(self.request(UnlockServerRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?;
+        check_response(res.into_inner())
+    }
+
+    /// Drop the task from the queue of the server, or stop it if it is being proved
+    pub async fn cancel(&mut self, task_id: &str) -> Result<()> {
+        let res = self
+            .inner
+            .cancel_task(self.request(CancelTaskRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?;
+        check_response(res.into_inner())
+    }
+
+    /// Encoding the payloads are sent with, the most compact one the server accepts
+    pub async fn negotiate_encoding(&mut self) -> Result<Encoding> {
+        if let Some(e) = self.encoding {
+            return Ok(e);
+        }
+        let encoding = match self
+            .inner
+            .get_capabilities(self.request(GetCapabilitiesRequest {}))
+            .await
+        {
+            Ok(res) => {
+                let accepted: Vec<Encoding> = res
+                    .into_inner()
+                    .payload_encodings
+                    .into_iter()
+                    .filter_map(|e| Encoding::from_wire(e).ok())
+                    .collect();
+                codec::preferred_encoding(&accepted)
+            }
+            // servers from before capabilities only take json
+            Err(s) if s.code() == Code::Unimplemented => Encoding::Json,
+            Err(s) => return Err(anyhow::Error::from(s)),
+        };
+        self.encoding = Some(encoding);
+        Ok(encoding)
+    }
+
+    /// Expected proving time of a task on the server, to check it makes the deadline
+    /// before uploading the payloads
+    pub async fn estimate(
+        &mut self,
+        post_config: &PoStConfig,
+        replicas_len: usize,
+    ) -> Result<TaskEstimate> {
+        let res = self
+            .inner
+            .estimate_task(self.request(EstimateTaskRequest {
+                post_config: Some(codec::post_config_to_proto(post_config)),
+                replicas_len: replicas_len as u32,
+                partitions: 0,
+            }))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    /// Everything the server holds but the payloads, needs an admin token. Attach it to
+    /// bug reports about servers which got stuck.
+    pub async fn dump_state(&mut self) -> Result<ServerState> {
+        let res = self
+            .inner
+            .dump_server_state(self.request(DumpServerStateRequest {}))
+            .await?;
+        Ok(serde_json::from_str(&res.into_inner().json)?)
+    }
+
+    /// List, evict or reload the groth parameters the server keeps in memory, needs an
+    /// admin token. `sector_size` 0 is every sector size in memory.
+    pub async fn manage_param_cache(
+        &mut self,
+        action: ParamCacheAction,
+        sector_size: u64,
+    ) -> Result<Vec<CachedParams>> {
+        let res = self
+            .inner
+            .manage_param_cache(self.request(ManageParamCacheRequest {
+                action: action as i32,
+                sector_size,
+            }))
+            .await?;
+        Ok(res.into_inner().params)
+    }
+
+    /// Change the time outs of a running server, needs an admin token. Time outs left 0
+    /// are kept, the ones in effect are returned.
+    pub async fn set_timeouts(
+        &mut self,
+        timeouts: SetServerTimeoutsRequest,
+    ) -> Result<ServerTimeouts> {
+        let res = self
+            .inner
+            .set_server_timeouts(self.request(timeouts))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    /// Send a window post task, a server not locked for `task_id` queues it when busy.
+    /// `pub_inputs` are the fallback::PublicInputs and `vanilla_proofs` the
+    /// per partition proofs returned by FallbackPoStCompound::prove_vanilla.
+    pub async fn submit_window_post<P: Serialize, V: Serialize>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &P,
+        vanilla_proofs: &V,
+        replicas_len: usize,
+    ) -> Result<()> {
+        let params = self
+            .window_post_params(
+                task_id,
+                post_config,
+                pub_inputs,
+                vanilla_proofs,
+                replicas_len,
+            )
+            .await?;
+        let res = self.inner.do_snark_task(self.request(params)).await?;
+        check_response(res.into_inner())
+    }
+
+    /// Send a winning post task, its `post_config` has to be of PoStType::Winning. The
+    /// server proves it ahead of window post tasks, the result is fetched the same way.
+    pub async fn submit_winning_post<P: Serialize, V: Serialize>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &P,
+        vanilla_proofs: &V,
+    ) -> Result<()> {
+        if post_config.typ != PoStType::Winning {
+            return Err(anyhow::Error::msg(format!(
+                "post config of task {} is not for winning PoSt",
+                task_id
+            )));
+        }
+        // all challenged sectors are in the one partition
+        self.submit_window_post(
+            task_id,
+            post_config,
+            pub_inputs,
+            vanilla_proofs,
+            post_config.sector_count,
+        )
+        .await
+    }
+
+    /// A window post task encoded for the server, to send in a batch
+    pub async fn window_post_params<P: Serialize, V: Serialize>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &P,
+        vanilla_proofs: &V,
+        replicas_len: usize,
+    ) -> Result<SnarkTaskRequestParams> {
+        let encoding = self.negotiate_encoding().await?;
+        Ok(SnarkTaskRequestParams {
+            task_id: task_id.to_string(),
+            vanilla_proof: codec::encode(encoding, vanilla_proofs)?,
+            pub_in: codec::encode(encoding, pub_inputs)?,
+            // read by servers which predate the PoStConfig message only
+            legacy_post_config: codec::encode(encoding, post_config)?,
+            replicas_len: replicas_len as u32,
+            priority: false,
+            client_id: String::new(),
+            payload_encoding: encoding.to_wire() as i32,
+            post_config: Some(codec::post_config_to_proto(post_config)),
+            max_threads: 0,
+            vanilla_proof_path: String::new(),
+            randomness: vec![],
+            prover_id: vec![],
+            replicas: vec![],
+            partitions: 0,
+            pub_sectors: vec![],
+            stream_partitions: false,
+            vanilla_proofs: None,
+        })
+    }
+
+    /// Send a window post task with its public inputs as their parts, the server builds
+    /// the fallback::PublicInputs of them. A server not locked for `task_id` queues it when busy.
+    pub async fn submit_window_post_with_sectors<V: Serialize>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &RawPublicInputs,
+        vanilla_proofs: &V,
+    ) -> Result<()> {
+        let encoding = self.negotiate_encoding().await?;
+        let params = SnarkTaskRequestParams {
+            task_id: task_id.to_string(),
+            vanilla_proof: codec::encode(encoding, vanilla_proofs)?,
+            replicas_len: pub_inputs.sectors.len() as u32,
+            payload_encoding: encoding.to_wire() as i32,
+            post_config: Some(codec::post_config_to_proto(post_config)),
+            randomness: pub_inputs.randomness.to_vec(),
+            prover_id: pub_inputs.prover_id.to_vec(),
+            pub_sectors: pub_inputs.sectors_to_proto(),
+            ..Default::default()
+        };
+        let res = self.inner.do_snark_task(self.request(params)).await?;
+        check_response(res.into_inner())
+    }
+
+    /// Send a task with its payloads in chunks of `chunk_len` bytes instead of in one
+    /// message, e.g. the vanilla proofs of thousands of 64GiB sectors, which are larger
+    /// than a gRPC message may be. The server takes it like one sent whole.
+    pub async fn submit_chunked(
+        &mut self,
+        params: SnarkTaskRequestParams,
+        chunk_len: usize,
+    ) -> Result<()> {
+        let frames = task_frames(params, chunk_len);
+        let res = self
+            .inner
+            .do_snark_task_stream(self.request(futures::stream::iter(frames)))
+            .await?;
+        check_response(res.into_inner())
+    }
+
+    /// The result of the task put together from its chunks once it is done, None while
+    /// it is not. The result is acked once it is received, so the server takes the next task.
+    pub async fn download_result(&mut self, task_id: &str) -> Result<Option<Vec<u8>>> {
+        let mut chunks = self
+            .inner
+            .download_task_result(self.request(GetTaskResultRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?
+            .into_inner();
+        let mut result = vec![];
+        let mut done = false;
+        while let Some(c) = chunks.message().await? {
+            if let Some(header) = c.header {
+                done = header.msg == "ok";
+            }
+            result.extend_from_slice(&c.chunk);
+        }
+        if !done {
+            return Ok(None);
+        }
+        self.ack_received(task_id).await;
+        Ok(Some(result))
+    }
+
+    /// Send a window post task with nothing encoded but the inclusion proofs: the public
+    /// inputs as their parts and the vanilla proofs as messages, so the server checks
+    /// their shape against `post_config` when the task is submitted rather than when it proves
+    pub async fn submit_typed_window_post<Tree: 'static + MerkleTreeTrait>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &RawPublicInputs,
+        vanilla_proofs: &[fallback::Proof<Tree::Proof>],
+    ) -> Result<()> {
+        let encoding = self.negotiate_encoding().await?;
+        let params = SnarkTaskRequestParams {
+            task_id: task_id.to_string(),
+            vanilla_proofs: Some(vanilla::vanilla_proofs_to_proto::<Tree>(
+                vanilla_proofs,
+                encoding,
+            )?),
+            replicas_len: pub_inputs.sectors.len() as u32,
+            payload_encoding: encoding.to_wire() as i32,
+            post_config: Some(codec::post_config_to_proto(post_config)),
+            randomness: pub_inputs.randomness.to_vec(),
+            prover_id: pub_inputs.prover_id.to_vec(),
+            pub_sectors: pub_inputs.sectors_to_proto(),
+            ..Default::default()
+        };
+        let res = self.inner.do_snark_task(self.request(params)).await?;
+        check_response(res.into_inner())
+    }
+
+    /// Send independent tasks at once, no lock needed. The server queues them and keeps
+    /// their results with the batch, poll `batch_report` for them.
+    pub async fn submit_batch(
+        &mut self,
+        tasks: Vec<SnarkTaskRequestParams>,
+    ) -> Result<SubmitBatchResponse> {
+        let res = self
+            .inner
+            .submit_batch(self.request(SubmitBatchRequest { tasks }))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    /// Where the tasks of a batch stand, with the proofs of those done
+    pub async fn batch_report(&mut self, batch_id: &str) -> Result<BatchReport> {
+        let res = self
+            .inner
+            .get_batch_report(self.request(GetBatchReportRequest {
+                batch_id: batch_id.to_string(),
+            }))
+            .await?;
+        Ok(res.into_inner())
+    }
+
+    /// Send a full window post task, the server reads the replicas, which have to be in
+    /// its payload dirs, and generates the vanilla proofs itself
+    pub async fn submit_full_window_post(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        remote: &RemotePoSt,
+    ) -> Result<()> {
+        let params = SnarkTaskRequestParams {
+            task_id: task_id.to_string(),
+            replicas_len: remote.replicas.len() as u32,
+            payload_encoding: Encoding::Json.to_wire() as i32,
+            post_config: Some(codec::post_config_to_proto(post_config)),
+            randomness: remote.randomness.to_vec(),
+            prover_id: remote.prover_id.to_vec(),
+            replicas: remote.replicas_to_proto(),
+            ..Default::default()
+        };
+        let res = self.inner.do_snark_task(self.request(params)).await?;
+        check_response(res.into_inner())
+    }
+
+    /// Send the seal commit phase2 of a sector, `phase1_output` is the SealCommitPhase1Output
+    /// returned by seal_commit_phase1. A server not locked for `task_id` queues it when busy.
+    pub async fn submit_c2<P: Serialize>(
+        &mut self,
+        task_id: &str,
+        porep_config: &PoRepConfig,
+        phase1_output: &P,
+        prover_id: [u8; 32],
+        sector_id: u64,
+    ) -> Result<()> {
+        let encoding = self.negotiate_encoding().await?;
+        let req = C2TaskRequest {
+            task_id: task_id.to_string(),
+            commit_phase1_output: codec::encode(encoding, phase1_output)?,
+            porep_config: Some(codec::porep_config_to_proto(porep_config)),
+            prover_id: prover_id.to_vec(),
+            sector_id,
+            payload_encoding: encoding.to_wire() as i32,
+            ..Default::default()
+        };
+        let res = self.inner.do_c2_task(self.request(req)).await?;
+        check_response(res.into_inner())
+    }
+
+    /// The proof seal_commit_phase2 made once the C2 task is done, None while it is not.
+    /// The proof is acked once it is received.
+    pub async fn get_c2_proof(&mut self, task_id: &str) -> Result<Option<Vec<u8>>> {
+        let res = self
+            .inner
+            .get_snark_task_result(self.request(GetTaskResultRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?
+            .into_inner();
+        if res.msg != "ok" {
+            return Ok(None);
+        }
+        self.ack_received(task_id).await;
+        Ok(Some(res.result))
+    }
+
+    /// One groth16 proof per partition once the task is done, None while it is not.
+    /// The proof is acked once it is decoded, so the server is free for the next task right
+    /// away rather than after the get back time out.
+    pub async fn get_proof(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        replicas_len: usize,
+    ) -> Result<Option<Vec<groth16::Proof<Bls12>>>> {
+        let res = self
+            .inner
+            .get_snark_task_result(self.request(GetTaskResultRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?
+            .into_inner();
+        if res.msg != "ok" {
+            return Ok(None);
+        }
+        let partitions = get_partitions_for_window_post(replicas_len, post_config).unwrap_or(1);
+        let proofs = decode_snark_proof(&res.result, partitions)?;
+        self.ack_received(task_id).await;
+        Ok(Some(proofs))
+    }
+
+    /// The groth proofs of the partitions as the server proves them, in partition order.
+    /// Tasks sent with `stream_partitions` get each as soon as it is proved, others all
+    /// at once when the task is done. The result still has to be fetched, which acks it.
+    pub async fn stream_proofs(&mut self, task_id: &str) -> Result<Streaming<PartitionProof>> {
+        let res = self
+            .inner
+            .stream_task_result(self.request(GetTaskResultRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?;
+        Ok(res.into_inner())
     }
+
+    /// Tell the server the proof of the task is persisted, it keeps the proof and the
+    /// server until then
+    pub async fn ack(&mut self, task_id: &str) -> Result<()> {
+        let res = self
+            .inner
+            .ack_result(self.request(AckResultRequest {
+                task_id: task_id.to_string(),
+            }))
+            .await?;
+        check_response(res.into_inner())
+    }
+
+    // the caller has the result now. A failed ack only keeps the server busy until the
+    // get back time out, the result is not lost for it.
+    async fn ack_received(&mut self, task_id: &str) {
+        if let Err(e) = self.ack(task_id).await {
+            warn!("failed to ack the result of task {}: {}", task_id, e);
+        }
+    }
+
+    /// Poll for the proof of the task until it is done, acked like get_proof does
+    pub async fn wait_proof(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        replicas_len: usize,
+        poll_interval: Duration,
+    ) -> Result<Vec<groth16::Proof<Bls12>>> {
+        loop {
+            if let Some(proofs) = self.get_proof(task_id, post_config, replicas_len).await? {
+                return Ok(proofs);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+/// Background health checks of a server, stopped when dropped
+#[derive(Debug)]
+pub struct HealthProbe {
+    healthy: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl HealthProbe {
+    /// Result of the last check, false until the first one is done
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for HealthProbe {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// WindowPostClient for callers which are not async, each call blocks until it is done
+pub struct BlockingWindowPostClient {
+    inner: WindowPostClient,
+    // the channel spawns its connection tasks here, so it needs a worker of its own
+    rt: Runtime,
+}
+
+impl BlockingWindowPostClient {
+    pub fn connect(addr: &'static str, timeout: Duration) -> Result<Self> {
+        let rt = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let inner = rt.block_on(WindowPostClient::connect(addr, timeout))?;
+        Ok(BlockingWindowPostClient { inner, rt })
+    }
+
+    pub fn connect_tls(
+        addr: &'static str,
+        timeout: Duration,
+        tls: ClientTlsConfig,
+    ) -> Result<Self> {
+        let rt = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let inner = rt.block_on(WindowPostClient::connect_tls(addr, timeout, tls))?;
+        Ok(BlockingWindowPostClient { inner, rt })
+    }
+
+    pub fn set_token(&mut self, token: &str) -> Result<()> {
+        self.inner.set_token(token)
+    }
+
+    pub fn lock(&mut self, task_id: &str, priority: bool) -> Result<WorkerStatus> {
+        self.rt.block_on(self.inner.lock(task_id, priority))
+    }
+
+    pub fn status(&mut self, task_id: &str) -> Result<WorkerStatus> {
+        self.rt.block_on(self.inner.status(task_id))
+    }
+
+    pub fn unlock(&mut self, task_id: &str) -> Result<()> {
+        self.rt.block_on(self.inner.unlock(task_id))
+    }
+
+    pub fn cancel(&mut self, task_id: &str) -> Result<()> {
+        self.rt.block_on(self.inner.cancel(task_id))
+    }
+
+    pub fn is_healthy(&mut self) -> bool {
+        self.rt.block_on(self.inner.is_healthy())
+    }
+
+    /// Probe the server in the background on the runtime of this client
+    pub fn spawn_health_probe(&self, interval: Duration) -> HealthProbe {
+        let _guard = self.rt.enter();
+        self.inner.spawn_health_probe(interval)
+    }
+
+    pub fn submit_window_post<P: Serialize, V: Serialize>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &P,
+        vanilla_proofs: &V,
+        replicas_len: usize,
+    ) -> Result<()> {
+        self.rt.block_on(self.inner.submit_window_post(
+            task_id,
+            post_config,
+            pub_inputs,
+            vanilla_proofs,
+            replicas_len,
+        ))
+    }
+
+    pub fn submit_winning_post<P: Serialize, V: Serialize>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &P,
+        vanilla_proofs: &V,
+    ) -> Result<()> {
+        self.rt.block_on(self.inner.submit_winning_post(
+            task_id,
+            post_config,
+            pub_inputs,
+            vanilla_proofs,
+        ))
+    }
+
+    pub fn submit_full_window_post(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        remote: &RemotePoSt,
+    ) -> Result<()> {
+        self.rt.block_on(
+            self.inner
+                .submit_full_window_post(task_id, post_config, remote),
+        )
+    }
+
+    pub fn submit_window_post_with_sectors<V: Serialize>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &RawPublicInputs,
+        vanilla_proofs: &V,
+    ) -> Result<()> {
+        self.rt.block_on(self.inner.submit_window_post_with_sectors(
+            task_id,
+            post_config,
+            pub_inputs,
+            vanilla_proofs,
+        ))
+    }
+
+    pub fn get_proof(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        replicas_len: usize,
+    ) -> Result<Option<Vec<groth16::Proof<Bls12>>>> {
+        self.rt
+            .block_on(self.inner.get_proof(task_id, post_config, replicas_len))
+    }
+
+    pub fn submit_c2<P: Serialize>(
+        &mut self,
+        task_id: &str,
+        porep_config: &PoRepConfig,
+        phase1_output: &P,
+        prover_id: [u8; 32],
+        sector_id: u64,
+    ) -> Result<()> {
+        self.rt.block_on(self.inner.submit_c2(
+            task_id,
+            porep_config,
+            phase1_output,
+            prover_id,
+            sector_id,
+        ))
+    }
+
+    pub fn get_c2_proof(&mut self, task_id: &str) -> Result<Option<Vec<u8>>> {
+        self.rt.block_on(self.inner.get_c2_proof(task_id))
+    }
+
+    pub fn submit_typed_window_post<Tree: 'static + MerkleTreeTrait>(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &RawPublicInputs,
+        vanilla_proofs: &[fallback::Proof<Tree::Proof>],
+    ) -> Result<()> {
+        self.rt
+            .block_on(self.inner.submit_typed_window_post::<Tree>(
+                task_id,
+                post_config,
+                pub_inputs,
+                vanilla_proofs,
+            ))
+    }
+
+    pub fn submit_chunked(
+        &mut self,
+        params: SnarkTaskRequestParams,
+        chunk_len: usize,
+    ) -> Result<()> {
+        self.rt
+            .block_on(self.inner.submit_chunked(params, chunk_len))
+    }
+
+    pub fn download_result(&mut self, task_id: &str) -> Result<Option<Vec<u8>>> {
+        self.rt.block_on(self.inner.download_result(task_id))
+    }
+
+    pub fn ack(&mut self, task_id: &str) -> Result<()> {
+        self.rt.block_on(self.inner.ack(task_id))
+    }
+
+    pub fn estimate(
+        &mut self,
+        post_config: &PoStConfig,
+        replicas_len: usize,
+    ) -> Result<TaskEstimate> {
+        self.rt
+            .block_on(self.inner.estimate(post_config, replicas_len))
+    }
+
+    pub fn submit_batch(
+        &mut self,
+        tasks: Vec<SnarkTaskRequestParams>,
+    ) -> Result<SubmitBatchResponse> {
+        self.rt.block_on(self.inner.submit_batch(tasks))
+    }
+
+    pub fn batch_report(&mut self, batch_id: &str) -> Result<BatchReport> {
+        self.rt.block_on(self.inner.batch_report(batch_id))
+    }
+
+    pub fn dump_state(&mut self) -> Result<ServerState> {
+        self.rt.block_on(self.inner.dump_state())
+    }
+
+    pub fn manage_param_cache(
+        &mut self,
+        action: ParamCacheAction,
+        sector_size: u64,
+    ) -> Result<Vec<CachedParams>> {
+        self.rt
+            .block_on(self.inner.manage_param_cache(action, sector_size))
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: SetServerTimeoutsRequest) -> Result<ServerTimeouts> {
+        self.rt.block_on(self.inner.set_timeouts(timeouts))
+    }
+
+    pub fn wait_proof(
+        &mut self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        replicas_len: usize,
+        poll_interval: Duration,
+    ) -> Result<Vec<groth16::Proof<Bls12>>> {
+        self.rt.block_on(
+            self.inner
+                .wait_proof(task_id, post_config, replicas_len, poll_interval),
+        )
+    }
+}
+
+/// Frames of a task for DoSnarkTaskStream: its params without the payloads, then the
+/// chunks of the vanilla proofs and of the public inputs
+pub fn task_frames(mut params: SnarkTaskRequestParams, chunk_len: usize) -> Vec<SnarkTaskFrame> {
+    let vanilla_proof = std::mem::take(&mut params.vanilla_proof);
+    let pub_in = std::mem::take(&mut params.pub_in);
+    let mut frames = vec![SnarkTaskFrame {
+        frame: Some(Frame::Params(params)),
+    }];
+    frames.extend(
+        vanilla_proof
+            .chunks(chunk_len.max(1))
+            .map(|c| SnarkTaskFrame {
+                frame: Some(Frame::VanillaProofChunk(c.to_vec())),
+            }),
+    );
+    frames.extend(pub_in.chunks(chunk_len.max(1)).map(|c| SnarkTaskFrame {
+        frame: Some(Frame::PubInChunk(c.to_vec())),
+    }));
+    frames
+}
+
+/// Split the bytes of a window post SnarkProof into the proofs of its partitions
+pub fn decode_snark_proof(bytes: &[u8], partitions: usize) -> Result<Vec<groth16::Proof<Bls12>>> {
+    if bytes.len() != partitions * SINGLE_PARTITION_PROOF_LEN {
+        return Err(anyhow::Error::from(Error::InvalidProof(format!(
+            "expected {} bytes for {} partitions, got {}",
+            partitions * SINGLE_PARTITION_PROOF_LEN,
+            partitions,
+            bytes.len()
+        ))));
+    }
+    bytes
+        .chunks(SINGLE_PARTITION_PROOF_LEN)
+        .map(|chunk| {
+            groth16::Proof::read(chunk)
+                .map_err(|e| anyhow::Error::from(Error::InvalidProof(e.to_string())))
+        })
+        .collect()
+}
+
+/// Bytes of the proofs as filecoin-proofs takes a SnarkProof, e.g. in verify_window_post
+pub fn encode_snark_proof(proofs: &[groth16::Proof<Bls12>]) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(proofs.len() * SINGLE_PARTITION_PROOF_LEN);
+    for proof in proofs {
+        proof.write(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Verify the bytes of a window post proof returned by a server before publishing them.
+/// `pub_inputs` are the fallback::PublicInputs the task was submitted with. The verifying
+/// key is read from the parameter cache, which has to hold the `.vk` file of the sector
+/// size.
+pub fn verify_result<P: Serialize>(
+    post_config: &PoStConfig,
+    pub_inputs: &P,
+    proof_bytes: &[u8],
+) -> Result<bool> {
+    let partitions = (proof_bytes.len() / SINGLE_PARTITION_PROOF_LEN).max(1);
+    let proofs = decode_snark_proof(proof_bytes, partitions)?;
+    // decoded as on the server, into the domain of the tree of the sector size
+    let pub_in = codec::encode(Encoding::Bincode, pub_inputs)?;
+    with_shape!(
+        u64::from(post_config.sector_size),
+        verify_partitions,
+        post_config,
+        &pub_in,
+        proofs
+    )
+}
+
+fn verify_partitions<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    pub_in: &[u8],
+    proofs: Vec<groth16::Proof<Bls12>>,
+) -> Result<bool> {
+    let pub_in: PublicInputs<<Tree::Hasher as Hasher>::Domain> =
+        codec::decode(Encoding::Bincode, pub_in)?;
+    // one partition is set up as None, like the server does
+    let partitions = if proofs.len() > 1 {
+        Some(proofs.len())
+    } else {
+        None
+    };
+    let pub_params = tasks::get_pub_params::<Tree>(post_config, partitions)?;
+    let vk = get_post_verifying_key::<Tree>(post_config)?;
+    let proof = MultiProof::new(proofs, &vk);
+    let requirements = ChallengeRequirements {
+        minimum_challenge_count: post_config.challenge_count * post_config.sector_count,
+    };
+    FallbackPoStCompound::verify(&*pub_params, &pub_in, &proof, &requirements)
+}
+
+/// generate_window_post of filecoin-proofs with the snark proved by a server: the vanilla
+/// proofs are generated here, the task goes to the first free server of `endpoints` and
+/// fails over to the next when a server fails it. When none is free, the servers are tried
+/// again a few times before giving up. The proof is verified before it is returned.
+pub async fn remote_generate_window_post<Tree: 'static + MerkleTreeTrait>(
+    endpoints: &[&'static str],
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+) -> Result<SnarkProof> {
+    if post_config.typ != PoStType::Window {
+        return Err(
+            Error::InvalidParameters("post config is not of a window PoSt".to_string()).into(),
+        );
+    }
+    // reading the trees and hashing the challenges takes minutes for a large partition,
+    // which would stall the runtime of the caller
+    let remote = remote_post(randomness, replicas, prover_id)?;
+    let config = post_config.clone();
+    let (pub_inputs, vanilla_proofs) = tokio::task::spawn_blocking(move || {
+        let partitions = get_partitions_for_window_post(remote.replicas.len(), &config);
+        let pub_params = tasks::get_pub_params::<Tree>(&config, partitions)?;
+        remote::vanilla_proofs::<Tree>(&remote, &config, &pub_params, partitions)
+    })
+    .await??;
+    let task_id = Uuid::new_v4().to_string();
+    let pool = ClientPool::new(endpoints, REMOTE_POST_CONNECT_TIME_OUT)?;
+    let mut round = 1;
+    let proofs = loop {
+        pool.probe().await;
+        match pool
+            .prove_window_post(
+                &task_id,
+                post_config,
+                &pub_inputs,
+                &vanilla_proofs,
+                replicas.len(),
+                REMOTE_POST_POLL_INTERVAL,
+            )
+            .await
+        {
+            Ok(proofs) => break proofs,
+            Err(e)
+                if round < REMOTE_POST_ROUNDS
+                    && e.downcast_ref::<Error>()
+                        .map_or(false, |e| matches!(e, Error::NoUsefulPostServer(..))) =>
+            {
+                warn!("{}, try again in {:?}", e, REMOTE_POST_RETRY_INTERVAL);
+                round += 1;
+                tokio::time::sleep(REMOTE_POST_RETRY_INTERVAL).await;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    let proof = encode_snark_proof(&proofs)?;
+    // checked before it leaves the miner, as it would be on chain
+    if !verify_result(post_config, &pub_inputs, &proof)? {
+        return Err(Error::ProofNotVerified(task_id).into());
+    }
+    info!(
+        "window PoSt of {} sectors proved as task {}",
+        replicas.len(),
+        task_id
+    );
+    Ok(proof)
+}
+
+/// The replicas as a full PoSt task, to generate their vanilla proofs like a server does
+fn remote_post<Tree: 'static + MerkleTreeTrait>(
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+) -> Result<RemotePoSt> {
+    let replicas = replicas
+        .iter()
+        .map(|(sector_id, r)| {
+            Ok(RemoteReplica {
+                sector_id: u64::from(*sector_id),
+                replica_path: r.replica_path().to_path_buf(),
+                cache_dir: r.cache_dir_path().to_path_buf(),
+                comm_r: remote::bytes32("comm_r", &r.safe_comm_r()?.into_bytes())?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RemotePoSt {
+        randomness: *randomness,
+        prover_id,
+        replicas,
+    })
 }
@@ -0,0 +1,194 @@
+use crate::error::{Error, Result};
+use crate::remote;
+use crate::snark_proof_grpc;
+use crate::snark_proof_grpc::PayloadEncoding;
+use filecoin_proofs::constants::{POREP_PARTITIONS, WINDOW_POST_SECTOR_COUNT};
+use filecoin_proofs::{PoRepConfig, PoRepProofPartitions, PoStConfig, PoStType, SectorSize};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use storage_proofs_core::api_version::ApiVersion;
+use strum_macros::{Display, EnumString};
+
+/// Encodings accepted for the serialized payloads of a task
+#[derive(Debug, PartialEq, Clone, Copy, EnumString, Display)]
+pub enum Encoding {
+    #[strum(to_string = "json")]
+    Json,
+    #[strum(to_string = "bincode")]
+    Bincode,
+    #[strum(to_string = "cbor")]
+    Cbor,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Json
+    }
+}
+
+impl Encoding {
+    /// Map the wire value, unknown values come from clients newer than this server
+    pub fn from_wire(v: i32) -> Result<Self> {
+        match PayloadEncoding::from_i32(v) {
+            Some(PayloadEncoding::Json) => Ok(Encoding::Json),
+            Some(PayloadEncoding::Bincode) => Ok(Encoding::Bincode),
+            Some(PayloadEncoding::Cbor) => Ok(Encoding::Cbor),
+            None => Err(anyhow::Error::from(Error::InvalidParameters(format!(
+                "unsupported payload encoding: {}",
+                v
+            )))),
+        }
+    }
+
+    pub fn to_wire(&self) -> PayloadEncoding {
+        match self {
+            Encoding::Json => PayloadEncoding::Json,
+            Encoding::Bincode => PayloadEncoding::Bincode,
+            Encoding::Cbor => PayloadEncoding::Cbor,
+        }
+    }
+}
+
+/// All encodings this server can decode, advertised to clients
+pub fn supported_encodings() -> Vec<Encoding> {
+    vec![Encoding::Json, Encoding::Bincode, Encoding::Cbor]
+}
+
+/// Most compact of the encodings a server accepts, json when it accepts none we know
+pub fn preferred_encoding(accepted: &[Encoding]) -> Encoding {
+    [Encoding::Bincode, Encoding::Cbor]
+        .iter()
+        .find(|e| accepted.contains(e))
+        .copied()
+        .unwrap_or(Encoding::Json)
+}
+
+pub fn encode<T: Serialize>(encoding: Encoding, value: &T) -> Result<Vec<u8>> {
+    let bytes = match encoding {
+        Encoding::Json => serde_json::to_vec(value)?,
+        Encoding::Bincode => bincode::serialize(value)?,
+        Encoding::Cbor => serde_cbor::to_vec(value)?,
+    };
+    Ok(bytes)
+}
+
+pub fn decode<T: DeserializeOwned>(encoding: Encoding, bytes: &[u8]) -> Result<T> {
+    let value = match encoding {
+        Encoding::Json => serde_json::from_slice(bytes)?,
+        Encoding::Bincode => bincode::deserialize(bytes)?,
+        Encoding::Cbor => serde_cbor::from_slice(bytes)?,
+    };
+    Ok(value)
+}
+
+pub fn post_config_to_proto(post_config: &PoStConfig) -> snark_proof_grpc::PoStConfig {
+    snark_proof_grpc::PoStConfig {
+        sector_size: u64::from(post_config.sector_size),
+        challenge_count: post_config.challenge_count as u64,
+        sector_count: post_config.sector_count as u64,
+        typ: match post_config.typ {
+            PoStType::Window => snark_proof_grpc::PoStType::Window as i32,
+            PoStType::Winning => snark_proof_grpc::PoStType::Winning as i32,
+        },
+        priority: post_config.priority,
+        api_version: api_version_to_proto(post_config.api_version),
+    }
+}
+
+pub fn post_config_from_proto(post_config: &snark_proof_grpc::PoStConfig) -> Result<PoStConfig> {
+    if post_config.sector_size == 0
+        || post_config.sector_count == 0
+        || post_config.challenge_count == 0
+    {
+        return Err(anyhow::Error::from(Error::InvalidParameters(format!(
+            "post config with empty field: {:?}",
+            post_config
+        ))));
+    }
+    check_post_sector_size(post_config.sector_size)?;
+    let typ = match snark_proof_grpc::PoStType::from_i32(post_config.typ) {
+        Some(snark_proof_grpc::PoStType::Window) => PoStType::Window,
+        Some(snark_proof_grpc::PoStType::Winning) => PoStType::Winning,
+        None => {
+            return Err(anyhow::Error::from(Error::InvalidParameters(format!(
+                "unknown post type: {}",
+                post_config.typ
+            ))))
+        }
+    };
+    let api_version = api_version_from_proto(post_config.api_version)?;
+    Ok(PoStConfig {
+        sector_size: SectorSize(post_config.sector_size),
+        challenge_count: post_config.challenge_count as usize,
+        sector_count: post_config.sector_count as usize,
+        typ,
+        priority: post_config.priority,
+        api_version,
+    })
+}
+
+/// The prover has a tree shape for these sector sizes only, and panics on others
+pub fn check_post_sector_size(sector_size: u64) -> Result<()> {
+    if !WINDOW_POST_SECTOR_COUNT
+        .read()
+        .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+        .contains_key(&sector_size)
+    {
+        return Err(anyhow::Error::from(Error::InvalidParameters(format!(
+            "unsupported sector size: {}",
+            sector_size
+        ))));
+    }
+    Ok(())
+}
+
+fn api_version_to_proto(api_version: ApiVersion) -> i32 {
+    match api_version {
+        ApiVersion::V1_0_0 => snark_proof_grpc::ApiVersion::V100 as i32,
+        ApiVersion::V1_1_0 => snark_proof_grpc::ApiVersion::V110 as i32,
+    }
+}
+
+fn api_version_from_proto(v: i32) -> Result<ApiVersion> {
+    match snark_proof_grpc::ApiVersion::from_i32(v) {
+        Some(snark_proof_grpc::ApiVersion::V100) => Ok(ApiVersion::V1_0_0),
+        Some(snark_proof_grpc::ApiVersion::V110) => Ok(ApiVersion::V1_1_0),
+        None => Err(anyhow::Error::from(Error::InvalidParameters(format!(
+            "unknown api version: {}",
+            v
+        )))),
+    }
+}
+
+pub fn porep_config_to_proto(porep_config: &PoRepConfig) -> snark_proof_grpc::PoRepConfig {
+    snark_proof_grpc::PoRepConfig {
+        sector_size: u64::from(porep_config.sector_size),
+        porep_id: porep_config.porep_id.to_vec(),
+        api_version: api_version_to_proto(porep_config.api_version),
+    }
+}
+
+/// The partitions are not sent, they follow from the sector size as on chain
+pub fn porep_config_from_proto(
+    porep_config: &snark_proof_grpc::PoRepConfig,
+) -> Result<PoRepConfig> {
+    let partitions = match POREP_PARTITIONS
+        .read()
+        .expect("POREP_PARTITIONS poisoned")
+        .get(&porep_config.sector_size)
+    {
+        Some(p) => *p,
+        None => {
+            return Err(anyhow::Error::from(Error::InvalidParameters(format!(
+                "unsupported sector size: {}",
+                porep_config.sector_size
+            ))))
+        }
+    };
+    Ok(PoRepConfig {
+        sector_size: SectorSize(porep_config.sector_size),
+        partitions: PoRepProofPartitions(partitions),
+        porep_id: remote::bytes32("porep_id", &porep_config.porep_id)?,
+        api_version: api_version_from_proto(porep_config.api_version)?,
+    })
+}
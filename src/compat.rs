@@ -0,0 +1,56 @@
+use crate::codec::{self, Encoding};
+use crate::error::Result;
+use filecoin_proofs::PoStConfig;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+// what clients built against proofs releases before api_version existed implicitly used
+const LEGACY_API_VERSION: &str = "V1_0_0";
+
+/// Decode a PoStConfig, also accepting the json written by older proofs releases
+/// which did not have the `api_version` and `priority` fields yet.
+pub fn decode_post_config(encoding: Encoding, bytes: &[u8]) -> Result<PoStConfig> {
+    if encoding != Encoding::Json {
+        return codec::decode(encoding, bytes);
+    }
+    let mut value: Value = serde_json::from_slice(bytes)?;
+    if let Value::Object(fields) = &mut value {
+        if !fields.contains_key("api_version") {
+            warn!(
+                "post config without api_version, assume {}",
+                LEGACY_API_VERSION
+            );
+            fields.insert(
+                "api_version".to_string(),
+                Value::String(LEGACY_API_VERSION.to_string()),
+            );
+        }
+        if !fields.contains_key("priority") {
+            fields.insert("priority".to_string(), Value::Bool(false));
+        }
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Decode the per partition vanilla proofs. Older clients proving a single partition
+/// sent the partition proof itself instead of a list with one element.
+pub fn decode_vanilla_proofs<T: DeserializeOwned>(
+    encoding: Encoding,
+    bytes: &[u8],
+) -> Result<Vec<T>> {
+    if encoding != Encoding::Json {
+        return codec::decode(encoding, bytes);
+    }
+    let value: Value = serde_json::from_slice(bytes)?;
+    match value {
+        Value::Array(_) => Ok(serde_json::from_value(value)?),
+        Value::Object(_) => {
+            warn!("vanilla proof is a single partition proof, decode it in legacy format");
+            Ok(vec![serde_json::from_value(value)?])
+        }
+        _ => Err(anyhow::Error::msg(
+            "vanilla proof is neither a list nor an object",
+        )),
+    }
+}
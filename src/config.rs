@@ -0,0 +1,205 @@
+//! Settings of the server binary read from a TOML file, e.g.
+//!
+//! ```toml
+//! port = 50051
+//! log_level = "info"
+//! gpu = "0"
+//!
+//! [timeouts]
+//! server_lock_time_out = 60
+//! server_task_get_back_time_out = 60
+//!
+//! [params]
+//! cache = ["/var/tmp/filecoin-proof-parameters"]
+//! preload = [34359738368]
+//!
+//! [tls]
+//! cert = "/etc/wps/server.pem"
+//! key = "/etc/wps/server.key"
+//! ```
+//!
+//! Every setting may be overridden by its `WPS_` environment variable, and the flags of
+//! `run` override both. Durations are in seconds.
+
+use crate::error::Result;
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: Option<u16>,
+    // e.g. info or debug, as RUST_LOG
+    pub log_level: Option<String>,
+    // devices the server proves on, as CUDA_VISIBLE_DEVICES
+    pub gpu: Option<String>,
+    pub timeouts: TimeoutsConfig,
+    pub params: ParamsConfig,
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub server_lock_time_out: Option<u64>,
+    pub server_task_get_back_time_out: Option<u64>,
+    pub server_exit_time_out_after_task_done: Option<u64>,
+    // 0 for no limit
+    pub prove_time_out: Option<u64>,
+    pub grace_period: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParamsConfig {
+    // groth parameter dirs, the first one holding parameters is used
+    pub cache: Vec<PathBuf>,
+    // parameters.json to check the parameter files against on start
+    pub manifest: Option<PathBuf>,
+    // sector sizes to load the window PoSt parameters of on start
+    pub preload: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TimeoutsConfig {
+    pub fn server_lock_time_out(&self) -> Option<Duration> {
+        self.server_lock_time_out.map(Duration::from_secs)
+    }
+
+    pub fn server_task_get_back_time_out(&self) -> Option<Duration> {
+        self.server_task_get_back_time_out.map(Duration::from_secs)
+    }
+
+    pub fn server_exit_time_out_after_task_done(&self) -> Option<Duration> {
+        self.server_exit_time_out_after_task_done
+            .map(Duration::from_secs)
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data =
+            fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+        Self::parse(&data).with_context(|| format!("bad config file {:?}", path))
+    }
+
+    pub fn parse(data: &str) -> Result<Self> {
+        Ok(toml::from_str(data)?)
+    }
+
+    /// Override the settings of the file with the `WPS_` environment variables set
+    pub fn apply_env(&mut self) -> Result<()> {
+        self.apply_vars(|name| env::var_os(name))
+    }
+
+    /// apply_env with the variables looked up in `var`
+    pub fn apply_vars<F>(&mut self, var: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let string = |name: &str| -> Result<Option<String>> {
+            var(name)
+                .map(|v| {
+                    v.into_string()
+                        .map_err(|v| anyhow!("{} is not unicode: {:?}", name, v))
+                })
+                .transpose()
+        };
+        let number = |name: &str| -> Result<Option<u64>> { parse_var(name, string(name)?) };
+
+        if let Some(p) = parse_var("WPS_PORT", string("WPS_PORT")?)? {
+            self.port = Some(p);
+        }
+        if let Some(l) = string("WPS_LOG_LEVEL")? {
+            self.log_level = Some(l);
+        }
+        if let Some(g) = string("WPS_GPU")? {
+            self.gpu = Some(g);
+        }
+
+        let t = &mut self.timeouts;
+        for (name, field) in [
+            ("WPS_SERVER_LOCK_TIME_OUT", &mut t.server_lock_time_out),
+            (
+                "WPS_SERVER_TASK_GET_BACK_TIME_OUT",
+                &mut t.server_task_get_back_time_out,
+            ),
+            (
+                "WPS_SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE",
+                &mut t.server_exit_time_out_after_task_done,
+            ),
+            ("WPS_PROVE_TIME_OUT", &mut t.prove_time_out),
+            ("WPS_GRACE_PERIOD", &mut t.grace_period),
+        ] {
+            if let Some(secs) = number(name)? {
+                *field = Some(secs);
+            }
+        }
+
+        if let Some(dirs) = var("WPS_PARAM_CACHE") {
+            self.params.cache = env::split_paths(&dirs).collect();
+        }
+        if let Some(m) = var("WPS_PARAM_MANIFEST") {
+            self.params.manifest = Some(PathBuf::from(m));
+        }
+        if let Some(sizes) = string("WPS_PRELOAD_PARAMS")? {
+            self.params.preload = sizes
+                .split(',')
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| {
+                    s.trim()
+                        .parse::<u64>()
+                        .with_context(|| format!("bad WPS_PRELOAD_PARAMS: {}", s))
+                })
+                .collect::<Result<_>>()?;
+        }
+
+        match (var("WPS_TLS_CERT"), var("WPS_TLS_KEY")) {
+            (Some(cert), Some(key)) => {
+                let client_ca = self.tls.take().and_then(|t| t.client_ca);
+                self.tls = Some(TlsConfig {
+                    cert: PathBuf::from(cert),
+                    key: PathBuf::from(key),
+                    client_ca,
+                });
+            }
+            (None, None) => {}
+            _ => return Err(anyhow!("WPS_TLS_CERT and WPS_TLS_KEY must be set together")),
+        }
+        if let Some(ca) = var("WPS_TLS_CLIENT_CA") {
+            match self.tls.as_mut() {
+                Some(t) => t.client_ca = Some(PathBuf::from(ca)),
+                None => return Err(anyhow!("WPS_TLS_CLIENT_CA needs a TLS certificate")),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_var<T, S>(name: &str, value: Option<S>) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+    S: AsRef<str>,
+{
+    value
+        .map(|v| {
+            v.as_ref()
+                .parse::<T>()
+                .with_context(|| format!("bad {}: {}", name, v.as_ref()))
+        })
+        .transpose()
+}
@@ -0,0 +1,137 @@
+use crate::utils;
+use lazy_static::lazy_static;
+use log::{error, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::SystemTime;
+
+pub const PHASE_IDLE: &str = "idle";
+pub const PHASE_PROVING: &str = "proving";
+
+lazy_static! {
+    // task and phase the worker is in, for the crash report
+    static ref CURRENT_TASK: Mutex<(String, &'static str)> =
+        Mutex::new((String::default(), PHASE_IDLE));
+}
+
+thread_local! {
+    // set while the thread runs code whose panics are caught and turned into an error
+    static RECOVERING: Cell<bool> = Cell::new(false);
+}
+
+/// What was going on when the process panicked
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CrashReport {
+    // unix time in milliseconds
+    pub crashed_at_ms: u64,
+    pub version: String,
+    pub thread: String,
+    pub message: String,
+    pub location: String,
+    pub task_id: String,
+    pub phase: String,
+    pub device_id: String,
+    pub backtrace: String,
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "panicked at {} on thread {} while {} task {:?} on device {:?}: {}",
+            self.location, self.thread, self.phase, self.task_id, self.device_id, self.message
+        )
+    }
+}
+
+/// Record the task the worker moved on to, it ends up in the crash report
+pub fn set_current_task(task_id: &str, phase: &'static str) {
+    *CURRENT_TASK.lock() = (task_id.to_string(), phase);
+}
+
+/// Write a crash report to `path` on panic, then run the hook which was installed before
+pub fn install_panic_hook(path: PathBuf) {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // the panic fails a task or a call, the process goes on and did not crash
+        if RECOVERING.with(|r| r.get()) {
+            previous(info);
+            return;
+        }
+        // never block in the hook, the panicking thread may be the one holding the lock
+        let (task_id, phase) = match CURRENT_TASK.try_lock() {
+            Some(current) => (current.0.clone(), current.1.to_string()),
+            None => (String::default(), "unknown".to_string()),
+        };
+        let message = panic_message(info.payload());
+        let report = CrashReport {
+            crashed_at_ms: utils::unix_millis(Some(SystemTime::now())),
+            version: utils::version().to_string(),
+            thread: thread::current().name().unwrap_or("unnamed").to_string(),
+            message,
+            location: info.location().map(|l| l.to_string()).unwrap_or_default(),
+            task_id,
+            phase,
+            device_id: utils::gpu_device_id(),
+            backtrace: Backtrace::force_capture().to_string(),
+        };
+        match serde_json::to_vec_pretty(&report) {
+            Ok(b) => {
+                if let Err(e) = fs::write(&path, b) {
+                    error!("failed to write crash report to {:?}: {}", path, e);
+                }
+            }
+            Err(e) => error!("failed to encode crash report: {}", e),
+        }
+        previous(info);
+    }));
+}
+
+/// Run `f`, catching a panic of it instead of unwinding further. No crash report is
+/// written for it, the caller turns it into an error and the process goes on.
+pub fn recover<F: FnOnce() -> T, T>(f: F) -> thread::Result<T> {
+    let recovering = RECOVERING.with(|r| r.replace(true));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    RECOVERING.with(|r| r.set(recovering));
+    result
+}
+
+/// Message a panic was raised with
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(s) => s.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(s) => s.clone(),
+            None => "Box<dyn Any>".to_string(),
+        },
+    }
+}
+
+/// Report left by the previous run, if it crashed. The report is moved aside,
+/// so the run after this one does not report it again.
+pub fn take_previous_crash(path: &Path) -> Option<CrashReport> {
+    let b = fs::read(path).ok()?;
+    let mut seen = path.as_os_str().to_owned();
+    seen.push(".reported");
+    if let Err(e) = fs::rename(path, &seen) {
+        warn!("failed to move crash report {:?} aside: {}", path, e);
+    }
+    match serde_json::from_slice(&b) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            warn!("unreadable crash report {:?}: {}", path, e);
+            Some(CrashReport {
+                message: "unreadable crash report".to_string(),
+                ..Default::default()
+            })
+        }
+    }
+}
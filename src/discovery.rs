@@ -0,0 +1,104 @@
+//! Announcing servers on the LAN over mDNS, and finding them, so small operators with
+//! all their prover boxes on one LAN need no endpoint lists
+
+use crate::error::Result;
+use crate::utils;
+use anyhow::Context;
+use log::{info, warn};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashMap;
+use std::net::{IpAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+pub const SERVICE_TYPE: &str = "_wdpost-snark._tcp.local.";
+
+// TXT keys of the announcement
+const TXT_TLS: &str = "tls";
+const TXT_VERSION: &str = "version";
+
+/// Announcement of a server, withdrawn when dropped
+pub struct Announcement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Announcement {
+    /// Announce the server listening on `port` at the address of the host on the LAN
+    pub fn start(port: u16, tls: bool) -> Result<Self> {
+        let ip = lan_ip()?;
+        let instance = format!("wdpost-{}-{}", ip.to_string().replace('.', "-"), port);
+        let mut properties = HashMap::new();
+        properties.insert(TXT_TLS.to_string(), if tls { "1" } else { "0" }.to_string());
+        properties.insert(TXT_VERSION.to_string(), utils::version().to_string());
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance,
+            &format!("{}.local.", instance),
+            &ip.to_string(),
+            port,
+            Some(properties),
+        )
+        .map_err(|e| anyhow::Error::msg(e.to_string()))?;
+        let fullname = service.get_fullname().to_string();
+        let daemon = ServiceDaemon::new().map_err(|e| anyhow::Error::msg(e.to_string()))?;
+        daemon
+            .register(service)
+            .map_err(|e| anyhow::Error::msg(e.to_string()))?;
+        info!("announced as {} at {}:{} over mDNS", fullname, ip, port);
+        Ok(Announcement { daemon, fullname })
+    }
+}
+
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            warn!("failed to withdraw mDNS announcement: {}", e);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Address a client connects to, https for servers announcing TLS
+pub fn endpoint(ip: IpAddr, port: u16, tls: bool) -> String {
+    let scheme = if tls { "https" } else { "http" };
+    match ip {
+        IpAddr::V4(ip) => format!("{}://{}:{}", scheme, ip, port),
+        IpAddr::V6(ip) => format!("{}://[{}]:{}", scheme, ip, port),
+    }
+}
+
+/// Addresses of the servers answering on the LAN within `wait`, sorted. Blocks, run it
+/// on a blocking thread from async code.
+pub fn discover(wait: Duration) -> Result<Vec<String>> {
+    let daemon = ServiceDaemon::new().map_err(|e| anyhow::Error::msg(e.to_string()))?;
+    let events = daemon
+        .browse(SERVICE_TYPE)
+        .map_err(|e| anyhow::Error::msg(e.to_string()))?;
+    let deadline = Instant::now() + wait;
+    let mut found = vec![];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        let info = match events.recv_timeout(left) {
+            Ok(ServiceEvent::ServiceResolved(info)) => info,
+            Ok(_) => continue,
+            Err(_) => break,
+        };
+        let tls = info.get_properties().get(TXT_TLS).map(|v| v.as_str()) == Some("1");
+        for ip in info.get_addresses() {
+            found.push(endpoint(IpAddr::V4(*ip), info.get_port(), tls));
+        }
+    }
+    let _ = daemon.stop_browse(SERVICE_TYPE);
+    let _ = daemon.shutdown();
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+// address of the interface the host reaches the mDNS group on, no packet is sent
+fn lan_ip() -> Result<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket
+        .connect("224.0.0.251:5353")
+        .with_context(|| "no route to the LAN for mDNS")?;
+    Ok(socket.local_addr()?.ip())
+}
@@ -0,0 +1,287 @@
+use crate::c2::C2Task;
+use crate::codec::{self, Encoding};
+use crate::error::Result;
+use crate::pub_inputs::RawPublicInputs;
+use crate::remote;
+use crate::remote::RemotePoSt;
+use crate::server::ServerInfo;
+use crate::snark_proof_grpc::PoRepConfig;
+use crate::tasks::TaskInfo;
+use crate::utils;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DUMP_MAX_BYTES_DEFAULT: u64 = 512 << 20;
+pub const DUMP_KEEP_DEFAULT: usize = 20;
+
+pub const DUMP_META_FILE: &str = "task.json";
+pub const DUMP_POST_CONFIG_FILE: &str = "post_config.json";
+pub const DUMP_VANILLA_PROOF_FILE: &str = "vanilla_proof";
+pub const DUMP_PUB_IN_FILE: &str = "pub_in";
+
+/// Where and how much of failed tasks to keep
+#[derive(Debug, Clone)]
+pub struct DumpConfig {
+    pub dir: PathBuf,
+    // tasks with larger payloads are not dumped
+    pub max_bytes: u64,
+    // oldest dumps are removed beyond this count
+    pub keep: usize,
+}
+
+impl DumpConfig {
+    pub fn new(dir: PathBuf) -> Self {
+        DumpConfig {
+            dir,
+            max_bytes: DUMP_MAX_BYTES_DEFAULT,
+            keep: DUMP_KEEP_DEFAULT,
+        }
+    }
+}
+
+/// Server settings at the time a task failed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerSnapshot {
+    pub version: String,
+    pub lock_time_out_ms: u64,
+    pub lock_time_out_max_ms: u64,
+    pub task_get_back_time_out_ms: u64,
+    pub exit_time_out_after_task_done_ms: u64,
+    pub queued_tasks: usize,
+}
+
+impl ServerSnapshot {
+    pub fn new(si: &ServerInfo) -> Self {
+        ServerSnapshot {
+            version: utils::version().to_string(),
+            lock_time_out_ms: si.server_lock_time_out.as_millis() as u64,
+            lock_time_out_max_ms: si.server_lock_time_out_max.as_millis() as u64,
+            task_get_back_time_out_ms: si.server_task_get_back_time_out.as_millis() as u64,
+            exit_time_out_after_task_done_ms: si.server_exit_time_out_after_task_done.as_millis()
+                as u64,
+            queued_tasks: si.task_queue.len(),
+        }
+    }
+}
+
+/// Everything of a failed task except the payloads, which are written to files of their own
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DumpMeta {
+    pub task_id: String,
+    pub replicas_len: usize,
+    pub priority: bool,
+    pub client_id: String,
+    pub payload_encoding: String,
+    pub error: String,
+    pub failed_at: u64,
+    pub server: ServerSnapshot,
+    // dumps written before traces were kept do not have it
+    #[serde(default)]
+    pub trace_id: String,
+    // the vanilla proofs of tasks which sent a path are not copied into the dump
+    #[serde(default)]
+    pub vanilla_proof_path: Option<PathBuf>,
+    // replicas of full PoSt tasks, which have no payloads
+    #[serde(default)]
+    pub remote: Option<RemotePoSt>,
+    // partitions the client asked for
+    #[serde(default)]
+    pub partitions: Option<usize>,
+    // k of the public inputs of a task proving one partition of a larger sector set
+    #[serde(default)]
+    pub partition_index: Option<usize>,
+    // public inputs sent as their parts, the pub_in file is empty then
+    #[serde(default)]
+    pub raw_pub_in: Option<RawPublicInputs>,
+    #[serde(default)]
+    pub max_threads: usize,
+    #[serde(default)]
+    pub verify_proof: bool,
+    // seal commit phase2 tasks, their phase1 output is the vanilla_proof file
+    #[serde(default)]
+    pub c2: Option<DumpC2>,
+}
+
+/// C2Task of a dump, with the PoRep config as sent by the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DumpC2 {
+    pub sector_size: u64,
+    pub porep_id: Vec<u8>,
+    pub api_version: i32,
+    pub prover_id: Vec<u8>,
+    pub sector_id: u64,
+}
+
+impl DumpC2 {
+    pub fn new(c2: &C2Task) -> Self {
+        let p = codec::porep_config_to_proto(&c2.porep_config);
+        DumpC2 {
+            sector_size: p.sector_size,
+            porep_id: p.porep_id,
+            api_version: p.api_version,
+            prover_id: c2.prover_id.to_vec(),
+            sector_id: c2.sector_id,
+        }
+    }
+
+    pub fn task(&self) -> Result<C2Task> {
+        let porep_config = codec::porep_config_from_proto(&PoRepConfig {
+            sector_size: self.sector_size,
+            porep_id: self.porep_id.clone(),
+            api_version: self.api_version,
+        })?;
+        Ok(C2Task {
+            porep_config,
+            prover_id: remote::bytes32("prover_id", &self.prover_id)?,
+            sector_id: self.sector_id,
+        })
+    }
+}
+
+/// Write the payloads of a failed task under a directory of its own inside the dump dir,
+/// return the directory or None when the task is too large to dump
+pub fn dump_failed_task(
+    config: &DumpConfig,
+    task_info: &TaskInfo,
+    error: &str,
+    server: ServerSnapshot,
+) -> Result<Option<PathBuf>> {
+    let size = (task_info.vanilla_proof.len() + task_info.pub_in.len()) as u64;
+    if size > config.max_bytes {
+        warn!(
+            "payload of task {} has {} bytes, more than dump limit {}, skip dump",
+            task_info.task_id, size, config.max_bytes
+        );
+        return Ok(None);
+    }
+    let failed_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let dir = config
+        .dir
+        .join(format!("{}-{}", failed_at, sanitize(&task_info.task_id)));
+    write_task(&dir, task_info, error, failed_at, server)?;
+    info!("task {} dumped to {:?}", task_info.task_id, dir);
+
+    prune_dumps(&config.dir, config.keep)?;
+    Ok(Some(dir))
+}
+
+/// Write the task into `dir` in the layout `load_dump` reads
+pub fn write_task(
+    dir: &Path,
+    task_info: &TaskInfo,
+    error: &str,
+    failed_at: u64,
+    server: ServerSnapshot,
+) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let meta = DumpMeta {
+        task_id: task_info.task_id.clone(),
+        replicas_len: task_info.replicas_len,
+        priority: task_info.priority,
+        client_id: task_info.client_id.clone(),
+        payload_encoding: task_info.payload_encoding.to_string(),
+        error: error.to_string(),
+        failed_at,
+        server,
+        trace_id: task_info.trace_id().to_string(),
+        vanilla_proof_path: task_info.vanilla_proof_path.clone(),
+        remote: task_info.remote.clone(),
+        partitions: task_info.partitions,
+        partition_index: task_info.partition_index,
+        raw_pub_in: task_info.raw_pub_in.clone(),
+        max_threads: task_info.max_threads,
+        verify_proof: task_info.verify_proof,
+        c2: task_info.c2.as_ref().map(DumpC2::new),
+    };
+    fs::write(dir.join(DUMP_META_FILE), serde_json::to_vec_pretty(&meta)?)?;
+    if let Some(p) = &task_info.post_config {
+        fs::write(
+            dir.join(DUMP_POST_CONFIG_FILE),
+            serde_json::to_vec_pretty(p)?,
+        )?;
+    }
+    fs::write(dir.join(DUMP_VANILLA_PROOF_FILE), &task_info.vanilla_proof)?;
+    fs::write(dir.join(DUMP_PUB_IN_FILE), &task_info.pub_in)?;
+    Ok(())
+}
+
+/// Remove the oldest dumps so at most `keep` are left
+pub fn prune_dumps(dir: &Path, keep: usize) -> Result<()> {
+    let mut dumps = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.path().join(DUMP_META_FILE).exists() {
+            dumps.push(entry.path());
+        }
+    }
+    if dumps.len() <= keep {
+        return Ok(());
+    }
+    // dump names start with the unix time they were written at
+    dumps.sort_by_key(|p| dump_time(p));
+    let remove = dumps.len() - keep;
+    for p in dumps.into_iter().take(remove) {
+        if let Err(e) = fs::remove_dir_all(&p) {
+            warn!("failed to remove old dump {:?} with error: {}", p, e);
+        }
+    }
+    Ok(())
+}
+
+fn dump_time(p: &Path) -> u64 {
+    p.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.split('-').next())
+        .and_then(|t| t.parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+// task ids come from clients, keep them from escaping the dump dir
+pub(crate) fn sanitize(task_id: &str) -> String {
+    task_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Read back the task written by `dump_failed_task` or `write_task`
+pub fn load_dump(dir: &Path) -> Result<(DumpMeta, TaskInfo)> {
+    let meta: DumpMeta = serde_json::from_slice(&fs::read(dir.join(DUMP_META_FILE))?)?;
+    let post_config = match fs::read(dir.join(DUMP_POST_CONFIG_FILE)) {
+        Ok(b) => Some(serde_json::from_slice(&b)?),
+        Err(_) => None,
+    };
+    let vanilla_proof = fs::read(dir.join(DUMP_VANILLA_PROOF_FILE))?;
+    let pub_in = fs::read(dir.join(DUMP_PUB_IN_FILE))?;
+    let task_info = TaskInfo {
+        task_id: meta.task_id.clone(),
+        vanilla_proof_bytes: vanilla_proof.len(),
+        pub_in_bytes: pub_in.len(),
+        vanilla_proof,
+        pub_in,
+        post_config,
+        replicas_len: meta.replicas_len,
+        priority: meta.priority,
+        client_id: meta.client_id.clone(),
+        payload_encoding: meta.payload_encoding.parse::<Encoding>()?,
+        vanilla_proof_path: meta.vanilla_proof_path.clone(),
+        remote: meta.remote.clone(),
+        partitions: meta.partitions,
+        partition_index: meta.partition_index,
+        raw_pub_in: meta.raw_pub_in.clone(),
+        max_threads: meta.max_threads,
+        verify_proof: meta.verify_proof,
+        c2: meta.c2.as_ref().map(|c| c.task()).transpose()?,
+        ..Default::default()
+    };
+    Ok((meta, task_info))
+}
@@ -1,4 +1,8 @@
+use bellperson::SynthesisError;
+use serde::{Deserialize, Serialize};
 use std::any::Any;
+use std::collections::VecDeque;
+use std::time::SystemTime;
 
 pub use anyhow::Result;
 
@@ -9,6 +13,10 @@ pub enum Error {
     Unclassified(String),
     #[error("Invalid parameters file: {}", _0)]
     InvalidParameters(String),
+    #[error("invalid field {}: {}", _0, _1)]
+    InvalidField(String, String),
+    #[error("task id {} is used by another client", _0)]
+    TaskIdInUse(String),
     #[error("no task running on this server")]
     NoTaskRunningOnSever,
     #[error("Task is still running, not completed")]
@@ -17,6 +25,23 @@ pub enum Error {
     TaskFailedWithError(String),
     #[error("new client failed with error: {}", _0)]
     NewClientFailed(String),
+    #[error("invalid proof: {}", _0)]
+    InvalidProof(String),
+    #[error("proof of task {} does not verify", _0)]
+    ProofNotVerified(String),
+    #[error("task {} was cancelled", _0)]
+    TaskCancelled(String),
+    #[error(
+        "task {} yielded the gpu to a winning PoSt after {} partitions",
+        _0,
+        _1
+    )]
+    TaskPreempted(String, usize),
+    #[error("no server could prove task {}: {}", _0, _1)]
+    NoUsefulPostServer(String, String),
+    // failed in the child process it was proved in, classified there
+    #[error("{}", _0)]
+    ChildTaskFailed(String, FailureKind),
 }
 
 impl From<Box<dyn Any + Send>> for Error {
@@ -24,3 +49,115 @@ impl From<Box<dyn Any + Send>> for Error {
         Error::Unclassified(format!("{:?}", dbg!(inner)))
     }
 }
+
+/// Whether sending a failed task again, here or to another server, can succeed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FailureKind {
+    // the server or its GPU had a problem, the same payload may prove fine on a retry
+    Retryable,
+    // the payload itself is wrong, resending it will fail again
+    Permanent,
+    Unknown,
+}
+
+impl Default for FailureKind {
+    fn default() -> Self {
+        FailureKind::Unknown
+    }
+}
+
+/// Classify the error a task failed with
+pub fn classify_failure(e: &anyhow::Error) -> FailureKind {
+    for cause in e.chain() {
+        if let Some(Error::InvalidParameters(_)) | Some(Error::InvalidField(..)) =
+            cause.downcast_ref::<Error>()
+        {
+            return FailureKind::Permanent;
+        }
+        if let Some(Error::ChildTaskFailed(_, kind)) = cause.downcast_ref::<Error>() {
+            return *kind;
+        }
+        // a flaky gpu, or vanilla proofs which do not satisfy the circuit, proving again tells
+        if let Some(Error::ProofNotVerified(_)) = cause.downcast_ref::<Error>() {
+            return FailureKind::Retryable;
+        }
+        // payloads which do not decode
+        if cause.is::<serde_json::Error>()
+            || cause.is::<bincode::Error>()
+            || cause.is::<serde_cbor::Error>()
+        {
+            return FailureKind::Permanent;
+        }
+        if let Some(s) = cause.downcast_ref::<SynthesisError>() {
+            return match s {
+                // vanilla proofs which do not satisfy the circuit
+                SynthesisError::Unsatisfiable
+                | SynthesisError::AssignmentMissing
+                | SynthesisError::DivisionByZero => FailureKind::Permanent,
+                // io and device errors
+                _ => FailureKind::Retryable,
+            };
+        }
+        if cause.is::<std::io::Error>() {
+            return FailureKind::Retryable;
+        }
+    }
+    FailureKind::Unknown
+}
+
+/// Whether the task stopped to let a winning PoSt prove, it is not a failure
+pub fn is_preempted(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<Error>(), Some(Error::TaskPreempted(..)))
+}
+
+/// A task failure kept for the status
+#[derive(Debug, Clone)]
+pub struct TaskError {
+    pub task_id: String,
+    pub error: String,
+    pub kind: FailureKind,
+    pub failed_at: SystemTime,
+}
+
+/// The last task errors, the oldest one is dropped once full
+#[derive(Debug, Default)]
+pub struct RecentErrors {
+    errors: VecDeque<TaskError>,
+    capacity: usize,
+    consecutive_failures: u32,
+}
+
+impl RecentErrors {
+    pub fn new(capacity: usize) -> Self {
+        RecentErrors {
+            errors: VecDeque::with_capacity(capacity),
+            capacity,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn push(&mut self, error: TaskError) {
+        self.consecutive_failures += 1;
+        if self.capacity == 0 {
+            return;
+        }
+        if self.errors.len() >= self.capacity {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(error);
+    }
+
+    /// A task succeeded, the run of failures is over
+    pub fn succeeded(&mut self) {
+        self.consecutive_failures = 0;
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Errors oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &TaskError> {
+        self.errors.iter()
+    }
+}
@@ -0,0 +1,176 @@
+use bytes::Bytes;
+use prost::Message;
+use prost_types::Any;
+use std::time::Duration;
+use tonic::{Code, Status};
+
+// google.rpc error detail messages, see google/rpc/status.proto and google/rpc/error_details.proto
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+const RETRY_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.RetryInfo";
+const DEBUG_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.DebugInfo";
+
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<Any>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FieldViolation {
+    #[prost(string, tag = "1")]
+    field: String,
+    #[prost(string, tag = "2")]
+    description: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    field_violations: Vec<FieldViolation>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<prost_types::Duration>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct DebugInfo {
+    #[prost(string, repeated, tag = "1")]
+    stack_entries: Vec<String>,
+    #[prost(string, tag = "2")]
+    detail: String,
+}
+
+/// Structured details attached to an error status, readable by any client
+/// which understands google.rpc.Status
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorDetails {
+    // (field, description) of request fields which were rejected
+    pub field_violations: Vec<(String, String)>,
+    // how long to wait before sending the request again
+    pub retry_delay: Option<Duration>,
+    // causes of the error, outermost first
+    pub debug_stack: Vec<String>,
+}
+
+impl ErrorDetails {
+    pub fn field_violation(field: &str, description: &str) -> Self {
+        ErrorDetails {
+            field_violations: vec![(field.to_string(), description.to_string())],
+            ..Default::default()
+        }
+    }
+
+    pub fn retry_after(delay: Duration) -> Self {
+        ErrorDetails {
+            retry_delay: Some(delay),
+            ..Default::default()
+        }
+    }
+
+    pub fn debug(stack: Vec<String>) -> Self {
+        ErrorDetails {
+            debug_stack: stack,
+            ..Default::default()
+        }
+    }
+
+    fn to_any(&self) -> Vec<Any> {
+        let mut details = vec![];
+        if !self.field_violations.is_empty() {
+            let bad_request = BadRequest {
+                field_violations: self
+                    .field_violations
+                    .iter()
+                    .map(|(field, description)| FieldViolation {
+                        field: field.clone(),
+                        description: description.clone(),
+                    })
+                    .collect(),
+            };
+            details.push(Any {
+                type_url: BAD_REQUEST_TYPE_URL.to_string(),
+                value: bad_request.encode_to_vec(),
+            });
+        }
+        if let Some(d) = self.retry_delay {
+            let retry_info = RetryInfo {
+                retry_delay: Some(prost_types::Duration {
+                    seconds: d.as_secs() as i64,
+                    nanos: d.subsec_nanos() as i32,
+                }),
+            };
+            details.push(Any {
+                type_url: RETRY_INFO_TYPE_URL.to_string(),
+                value: retry_info.encode_to_vec(),
+            });
+        }
+        if !self.debug_stack.is_empty() {
+            let debug_info = DebugInfo {
+                stack_entries: self.debug_stack.clone(),
+                detail: String::default(),
+            };
+            details.push(Any {
+                type_url: DEBUG_INFO_TYPE_URL.to_string(),
+                value: debug_info.encode_to_vec(),
+            });
+        }
+        details
+    }
+}
+
+/// Build a status carrying the details as an encoded google.rpc.Status
+pub fn status_with_details(
+    code: Code,
+    message: impl Into<String>,
+    details: ErrorDetails,
+) -> Status {
+    let message = message.into();
+    let rpc_status = RpcStatus {
+        code: code as i32,
+        message: message.clone(),
+        details: details.to_any(),
+    };
+    Status::with_details(code, message, Bytes::from(rpc_status.encode_to_vec()))
+}
+
+/// Read the details of a status, details of unknown types are skipped
+pub fn error_details(status: &Status) -> anyhow::Result<ErrorDetails> {
+    let mut details = ErrorDetails::default();
+    if status.details().is_empty() {
+        return Ok(details);
+    }
+    let rpc_status = RpcStatus::decode(status.details())?;
+    for any in rpc_status.details {
+        match any.type_url.as_str() {
+            BAD_REQUEST_TYPE_URL => {
+                let bad_request = BadRequest::decode(any.value.as_slice())?;
+                details.field_violations.extend(
+                    bad_request
+                        .field_violations
+                        .into_iter()
+                        .map(|v| (v.field, v.description)),
+                );
+            }
+            RETRY_INFO_TYPE_URL => {
+                let retry_info = RetryInfo::decode(any.value.as_slice())?;
+                details.retry_delay = retry_info.retry_delay.map(|d| {
+                    Duration::from_secs(d.seconds.max(0) as u64)
+                        + Duration::from_nanos(d.nanos.max(0) as u64)
+                });
+            }
+            DEBUG_INFO_TYPE_URL => {
+                let debug_info = DebugInfo::decode(any.value.as_slice())?;
+                details.debug_stack = debug_info.stack_entries;
+            }
+            _ => {}
+        }
+    }
+    Ok(details)
+}
@@ -0,0 +1,86 @@
+use crate::audit::AuditRecord;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+// proving times kept per sector size and partition count, older ones are dropped so
+// the estimate follows driver and hardware changes
+const SAMPLES_KEPT: usize = 20;
+
+/// Proving times of the tasks this server finished, per sector size and partition count
+#[derive(Debug, Clone, Default)]
+pub struct ProvingTimes {
+    samples: HashMap<(u64, usize), VecDeque<Duration>>,
+}
+
+/// Expected proving time of a task and how many finished tasks it is based on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub prove: Duration,
+    pub samples: usize,
+    // taken from tasks of another partition count, scaled per partition
+    pub scaled: bool,
+}
+
+impl ProvingTimes {
+    /// Seed from the audit records of earlier runs, tasks which failed or were proved
+    /// on the cpu say nothing about proving on this hardware
+    pub fn from_audit_records(records: &[AuditRecord]) -> Self {
+        let mut times = ProvingTimes::default();
+        let mut records: Vec<&AuditRecord> = records
+            .iter()
+            .filter(|r| r.error.is_empty() && !r.degraded)
+            .collect();
+        records.sort_by_key(|r| r.finished_at_ms);
+        for r in records {
+            if let Some(c) = &r.post_config {
+                times.record(
+                    u64::from(c.sector_size),
+                    r.partitions,
+                    Duration::from_millis(r.decode_ms + r.load_params_ms + r.prove_ms),
+                );
+            }
+        }
+        times
+    }
+
+    pub fn record(&mut self, sector_size: u64, partitions: usize, took: Duration) {
+        let samples = self
+            .samples
+            .entry((sector_size, partitions.max(1)))
+            .or_insert_with(VecDeque::new);
+        if samples.len() == SAMPLES_KEPT {
+            samples.pop_front();
+        }
+        samples.push_back(took);
+    }
+
+    /// Median of the tasks of the sector size and partition count. Without any, the
+    /// partition count of the sector size with the most tasks is scaled, the partitions
+    /// are proved one after another. None when no task of the sector size was proved.
+    pub fn estimate(&self, sector_size: u64, partitions: usize) -> Option<Estimate> {
+        let partitions = partitions.max(1);
+        if let Some(samples) = self.samples.get(&(sector_size, partitions)) {
+            return Some(Estimate {
+                prove: median(samples),
+                samples: samples.len(),
+                scaled: false,
+            });
+        }
+        let ((_, known_partitions), samples) = self
+            .samples
+            .iter()
+            .filter(|((s, _), _)| *s == sector_size)
+            .max_by_key(|((_, p), samples)| (samples.len(), *p))?;
+        Some(Estimate {
+            prove: median(samples) / *known_partitions as u32 * partitions as u32,
+            samples: samples.len(),
+            scaled: true,
+        })
+    }
+}
+
+fn median(samples: &VecDeque<Duration>) -> Duration {
+    let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+    sorted.sort();
+    sorted[sorted.len() / 2]
+}
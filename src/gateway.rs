@@ -0,0 +1,1141 @@
+//! Gateway in front of several servers. `Router` picks the backend of each task, and
+//! `Gateway` serves the SnarkTaskService of the servers with it, see the wps-gateway binary.
+
+use crate::auth::{BearerToken, Principal};
+use crate::codec;
+use crate::error::Result;
+use crate::error_details::{status_with_details, ErrorDetails};
+use crate::server::{
+    collect_task_frames, ok_response, SERVER_LOCK_WAIT_TIME_OUT_DEFAULT, TASK_PAYLOAD_MAX_LEN,
+};
+use crate::snark_proof_grpc::snark_task_service_client::SnarkTaskServiceClient;
+use crate::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use crate::snark_proof_grpc::{
+    self, AckResultRequest, BaseResponse, BatchReport, C2TaskRequest, CancelTaskRequest,
+    Capabilities, DumpServerStateRequest, EstimateTaskRequest, ExportTaskReportRequest,
+    GetBatchReportRequest, GetCapabilitiesRequest, GetTaskResultRequest, GetTaskResultResponse,
+    GetWorkerStatusRequest, LockServerWaitRequest, ManageParamCacheRequest, ParamCacheState,
+    PartitionProof, ServerStateDump, ServerTimeouts, SetServerTimeoutsRequest, SnarkTaskFrame,
+    SnarkTaskRequestParams, SubmitBatchRequest, SubmitBatchResponse, TaskEstimate, TaskEvent,
+    TaskReport, TaskResultChunk, UnlockServerRequest, WatchTaskRequest, WorkerStatus,
+};
+use crate::status::ServerStatus;
+use anyhow::Context;
+use futures::future::join_all;
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Response, Status, Streaming};
+
+pub const GATEWAY_HEARTBEAT_INTERVAL_DEFAULT: Duration = Duration::from_secs(5);
+pub const GATEWAY_CALL_TIME_OUT_DEFAULT: Duration = Duration::from_secs(30);
+// how often a waiting lock request checks the backends again
+const LOCK_WAIT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// When a backend is quarantined, i.e. taken out of rotation until a probe task succeeds
+#[derive(Debug, Clone, Copy)]
+pub struct HealthPolicy {
+    // task outcomes the failure rate is computed over
+    pub window: usize,
+    // no verdict on fewer outcomes than this
+    pub min_samples: usize,
+    pub max_failure_rate: f64,
+    pub max_heartbeat_latency: Duration,
+    // a backend not heard from for this long is taken for dead
+    pub heartbeat_time_out: Duration,
+    // a quarantined backend which answers its heartbeats is sent a task as its probe
+    // this long after it was quarantined or its last probe failed
+    pub probe_interval: Duration,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        HealthPolicy {
+            window: 10,
+            min_samples: 3,
+            max_failure_rate: 0.5,
+            max_heartbeat_latency: Duration::from_secs(5),
+            heartbeat_time_out: Duration::from_secs(30),
+            probe_interval: Duration::from_secs(300),
+        }
+    }
+}
+
+/// How the backends are shared, e.g. tenant A may use at most 2 of 4 backends and
+/// 1 is kept for priority tasks
+#[derive(Debug, Clone, Default)]
+pub struct CapacityPolicy {
+    // tenant to the backends it may have tasks on at once, tenants not listed have no limit
+    pub tenant_limits: HashMap<String, usize>,
+    // backends only priority tasks may take, the last ones available
+    pub reserved_for_priority: usize,
+}
+
+/// What the capacity policy is applied to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskClass {
+    pub tenant: String,
+    pub priority: bool,
+}
+
+/// Capacity as advertised to clients
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capacity {
+    pub backends: usize,
+    // healthy and not busy
+    pub available: usize,
+    pub reserved_for_priority: usize,
+    // tenants with a limit to their backends in use and the limit
+    pub tenants: BTreeMap<String, (usize, usize)>,
+}
+
+/// A prover server tasks are dispatched to
+#[derive(Debug, Clone)]
+pub struct Backend {
+    pub addr: String,
+    // has a task or is locked, as last seen
+    pub busy: bool,
+    // false while quarantined
+    pub healthy: bool,
+    // constraints per second of its hardware profile, 0 when not calibrated
+    pub speed: f64,
+    // whether the last tasks succeeded, oldest first
+    outcomes: VecDeque<bool>,
+    last_heartbeat: Instant,
+    // when a quarantined backend gets its next probe task
+    next_probe: Option<Instant>,
+}
+
+impl Backend {
+    pub fn new(addr: String) -> Self {
+        Backend {
+            addr,
+            busy: false,
+            healthy: true,
+            speed: 0.0,
+            outcomes: VecDeque::new(),
+            last_heartbeat: Instant::now(),
+            next_probe: None,
+        }
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            return 0.0;
+        }
+        let failures = self.outcomes.iter().filter(|ok| !**ok).count();
+        failures as f64 / self.outcomes.len() as f64
+    }
+
+    fn available(&self) -> bool {
+        self.healthy && !self.busy
+    }
+
+    fn quarantine(&mut self, probe_interval: Duration) {
+        self.healthy = false;
+        self.next_probe = Some(Instant::now() + probe_interval);
+    }
+}
+
+/// A task sent to a backend and not finished yet
+#[derive(Debug, Clone)]
+pub struct Dispatch {
+    pub miner_id: String,
+    pub class: TaskClass,
+    pub backend: usize,
+    // backends the task was sent to, this one included
+    pub attempts: usize,
+}
+
+/// A task moved off a dead backend, to tell its client about
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub task_id: String,
+    pub from: usize,
+    // None when no backend is available, the task has to wait for one
+    pub to: Option<usize>,
+}
+
+/// Picks the backend for the tasks of each miner. A miner sticks to the backend it was
+/// sent to before, which has its parameters warm and a known latency, and only falls
+/// back to another one while that backend is busy or unhealthy.
+#[derive(Debug, Default)]
+pub struct Router {
+    backends: Vec<Backend>,
+    // miner id to the index of its backend
+    affinity: HashMap<String, usize>,
+    health: HealthPolicy,
+    capacity: CapacityPolicy,
+    // task id to where it runs
+    dispatched: HashMap<String, Dispatch>,
+}
+
+impl Router {
+    pub fn new(addrs: Vec<String>) -> Self {
+        Self::with_health_policy(addrs, HealthPolicy::default())
+    }
+
+    pub fn with_health_policy(addrs: Vec<String>, health: HealthPolicy) -> Self {
+        Router {
+            backends: addrs.into_iter().map(Backend::new).collect(),
+            affinity: HashMap::new(),
+            health,
+            capacity: CapacityPolicy::default(),
+            dispatched: HashMap::new(),
+        }
+    }
+
+    pub fn set_capacity_policy(&mut self, capacity: CapacityPolicy) {
+        self.capacity = capacity;
+    }
+
+    pub fn capacity(&self) -> Capacity {
+        Capacity {
+            backends: self.backends.len(),
+            available: self.available(),
+            reserved_for_priority: self.capacity.reserved_for_priority,
+            tenants: self
+                .capacity
+                .tenant_limits
+                .iter()
+                .map(|(t, &limit)| (t.clone(), (self.tenant_usage(t), limit)))
+                .collect(),
+        }
+    }
+
+    fn available(&self) -> usize {
+        self.backends.iter().filter(|b| b.available()).count()
+    }
+
+    fn tenant_usage(&self, tenant: &str) -> usize {
+        self.dispatched
+            .values()
+            .filter(|d| d.class.tenant == tenant)
+            .count()
+    }
+
+    fn within_tenant_limit(&self, tenant: &str) -> bool {
+        match self.capacity.tenant_limits.get(tenant) {
+            Some(&limit) => self.tenant_usage(tenant) < limit,
+            None => true,
+        }
+    }
+
+    pub fn backends(&self) -> &[Backend] {
+        &self.backends
+    }
+
+    pub fn set_busy(&mut self, backend: usize, busy: bool) {
+        if let Some(b) = self.backends.get_mut(backend) {
+            b.busy = busy;
+        }
+    }
+
+    pub fn set_healthy(&mut self, backend: usize, healthy: bool) {
+        let probe_interval = self.health.probe_interval;
+        if let Some(b) = self.backends.get_mut(backend) {
+            if healthy {
+                b.healthy = true;
+                b.next_probe = None;
+            } else if b.healthy {
+                b.quarantine(probe_interval);
+            }
+        }
+    }
+
+    /// Constraints per second the backend advertises in its capabilities. Faster
+    /// backends get more miners sticking to them.
+    pub fn set_speed(&mut self, backend: usize, constraints_per_sec: f64) {
+        if let Some(b) = self.backends.get_mut(backend) {
+            b.speed = constraints_per_sec.max(0.0);
+        }
+    }
+
+    // how much faster than the others the backend is, backends without a profile are
+    // taken for as fast as the calibrated ones on average
+    fn weight(&self, backend: usize) -> f64 {
+        let known: Vec<f64> = self
+            .backends
+            .iter()
+            .map(|b| b.speed)
+            .filter(|s| *s > 0.0)
+            .collect();
+        if known.is_empty() {
+            return 1.0;
+        }
+        match self.backends[backend].speed {
+            s if s > 0.0 => s,
+            _ => known.iter().sum::<f64>() / known.len() as f64,
+        }
+    }
+
+    /// Outcome of a task on the backend. A success on a quarantined backend is the
+    /// probe which brings it back, too many failures put it into quarantine.
+    pub fn record_task(&mut self, backend: usize, ok: bool) {
+        let policy = self.health;
+        let b = match self.backends.get_mut(backend) {
+            Some(b) => b,
+            None => return,
+        };
+        if !b.healthy {
+            if ok {
+                info!("backend {} passed its probe, back in rotation", b.addr);
+                b.healthy = true;
+                b.next_probe = None;
+                b.outcomes.clear();
+            } else {
+                b.next_probe = Some(Instant::now() + policy.probe_interval);
+            }
+            return;
+        }
+        b.outcomes.push_back(ok);
+        while b.outcomes.len() > policy.window {
+            b.outcomes.pop_front();
+        }
+        if b.outcomes.len() >= policy.min_samples && b.failure_rate() > policy.max_failure_rate {
+            warn!(
+                "backend {} failed {:.0}% of its last {} tasks, quarantine it",
+                b.addr,
+                b.failure_rate() * 100.0,
+                b.outcomes.len()
+            );
+            b.quarantine(policy.probe_interval);
+        }
+    }
+
+    /// Round trip of a status call to the backend, slow backends are quarantined
+    pub fn record_heartbeat(&mut self, backend: usize, latency: Duration) {
+        let policy = self.health;
+        if let Some(b) = self.backends.get_mut(backend) {
+            b.last_heartbeat = Instant::now();
+            if b.healthy && latency > policy.max_heartbeat_latency {
+                warn!(
+                    "backend {} answered in {:?}, quarantine it",
+                    b.addr, latency
+                );
+                b.quarantine(policy.probe_interval);
+            }
+        }
+    }
+
+    /// Quarantined backends, which need a probe task to be re-admitted
+    pub fn quarantined(&self) -> Vec<usize> {
+        (0..self.backends.len())
+            .filter(|&b| !self.backends[b].healthy)
+            .collect()
+    }
+
+    // quarantined backend due for a probe task, which answers its heartbeats
+    fn probe_due(&self, now: Instant) -> Option<usize> {
+        let time_out = self.health.heartbeat_time_out;
+        (0..self.backends.len()).find(|&b| {
+            let backend = &self.backends[b];
+            !backend.healthy
+                && !backend.busy
+                && backend.next_probe.map_or(false, |t| t <= now)
+                && now.saturating_duration_since(backend.last_heartbeat) <= time_out
+        })
+    }
+
+    /// Pick a backend for the task and remember it runs there
+    pub fn dispatch(&mut self, task_id: &str, miner_id: &str) -> Option<usize> {
+        self.dispatch_as(task_id, miner_id, TaskClass::default())
+    }
+
+    /// Like dispatch, within what the capacity policy leaves to the class of the task
+    pub fn dispatch_as(
+        &mut self,
+        task_id: &str,
+        miner_id: &str,
+        class: TaskClass,
+    ) -> Option<usize> {
+        let backend = self.pick_as(miner_id, &class)?;
+        self.backends[backend].busy = true;
+        self.dispatched.insert(
+            task_id.to_string(),
+            Dispatch {
+                miner_id: miner_id.to_string(),
+                class,
+                backend,
+                attempts: 1,
+            },
+        );
+        Some(backend)
+    }
+
+    pub fn dispatched(&self, task_id: &str) -> Option<&Dispatch> {
+        self.dispatched.get(task_id)
+    }
+
+    /// The task is done with, its backend is free again
+    pub fn complete(&mut self, task_id: &str, ok: bool) {
+        if let Some(d) = self.dispatched.remove(task_id) {
+            self.set_busy(d.backend, false);
+            self.record_task(d.backend, ok);
+        }
+    }
+
+    /// The task was given up without an outcome, e.g. cancelled by its client, its
+    /// backend is free again
+    pub fn release(&mut self, task_id: &str) {
+        if let Some(d) = self.dispatched.remove(task_id) {
+            self.set_busy(d.backend, false);
+        }
+    }
+
+    /// Take backends which missed their heartbeats out of rotation and move their
+    /// tasks to other backends. The submitting clients should be told about the
+    /// migrations rather than see the transport errors of the dead backend.
+    pub fn reap_dead(&mut self, now: Instant) -> Vec<Migration> {
+        let time_out = self.health.heartbeat_time_out;
+        let dead: Vec<usize> = (0..self.backends.len())
+            .filter(|&b| now.saturating_duration_since(self.backends[b].last_heartbeat) > time_out)
+            .collect();
+        let probe_interval = self.health.probe_interval;
+        for &b in &dead {
+            if self.backends[b].healthy {
+                warn!(
+                    "backend {} missed its heartbeats, take it for dead",
+                    self.backends[b].addr
+                );
+                self.backends[b].quarantine(probe_interval);
+            }
+            self.backends[b].busy = false;
+        }
+        let mut stranded: Vec<String> = self
+            .dispatched
+            .iter()
+            .filter(|(_, d)| dead.contains(&d.backend))
+            .map(|(t, _)| t.clone())
+            .collect();
+        stranded.sort();
+        let mut migrations = vec![];
+        for task_id in stranded {
+            // out of the way, so it does not count against the limit of its tenant
+            let mut d = self.dispatched.remove(&task_id).unwrap();
+            let from = d.backend;
+            let to = self.pick_as(&d.miner_id, &d.class);
+            if let Some(b) = to {
+                d.backend = b;
+                d.attempts += 1;
+                self.backends[b].busy = true;
+                info!("task {} moved from backend {} to {}", task_id, from, b);
+            }
+            self.dispatched.insert(task_id.clone(), d);
+            migrations.push(Migration { task_id, from, to });
+        }
+        migrations
+    }
+
+    /// Backend the miner sticks to, if any
+    pub fn affinity(&self, miner_id: &str) -> Option<usize> {
+        self.affinity.get(miner_id).copied()
+    }
+
+    /// Backend for the next task of the miner, None when all are busy or unhealthy
+    pub fn pick(&mut self, miner_id: &str) -> Option<usize> {
+        self.pick_as(miner_id, &TaskClass::default())
+    }
+
+    /// Whether the capacity policy leaves a backend to a task of the class now
+    pub fn has_capacity(&self, class: &TaskClass) -> bool {
+        if !self.within_tenant_limit(&class.tenant) {
+            return false;
+        }
+        let reserved = if class.priority {
+            0
+        } else {
+            self.capacity.reserved_for_priority
+        };
+        self.available() > reserved
+    }
+
+    /// Like pick, None also when the capacity policy leaves nothing to the class of the task.
+    /// A quarantined backend due for its probe takes the next task which is not a priority
+    /// one, without the miner sticking to it.
+    pub fn pick_as(&mut self, miner_id: &str, class: &TaskClass) -> Option<usize> {
+        if !self.within_tenant_limit(&class.tenant) {
+            return None;
+        }
+        if !class.priority {
+            let now = Instant::now();
+            if let Some(b) = self.probe_due(now) {
+                info!(
+                    "backend {} takes a task as its probe",
+                    self.backends[b].addr
+                );
+                self.backends[b].next_probe = Some(now + self.health.probe_interval);
+                return Some(b);
+            }
+        }
+        if !self.has_capacity(class) {
+            return None;
+        }
+        let sticky = self.affinity(miner_id);
+        if let Some(b) = sticky {
+            if self.backends[b].available() {
+                return Some(b);
+            }
+        }
+        // of the others, the one the fewest miners stick to for its speed
+        let load = |b: usize| {
+            let miners = self.affinity.values().filter(|&&a| a == b).count();
+            (miners + 1) as f64 / self.weight(b)
+        };
+        let picked = (0..self.backends.len())
+            .filter(|&b| self.backends[b].available())
+            .min_by(|&a, &b| load(a).partial_cmp(&load(b)).unwrap())?;
+        match sticky {
+            // busy is over soon, the miner goes back once it is
+            Some(b) if self.backends[b].healthy => {}
+            _ => {
+                self.affinity.insert(miner_id.to_string(), picked);
+            }
+        }
+        Some(picked)
+    }
+}
+
+/// Settings of a gateway, see `Gateway::new`
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    pub backends: Vec<String>,
+    pub health: HealthPolicy,
+    pub capacity: CapacityPolicy,
+    // how often every backend is asked for its status
+    pub heartbeat_interval: Duration,
+    // time out of each call to a backend
+    pub call_time_out: Duration,
+    // sent to the backends with every call
+    pub token: BearerToken,
+}
+
+impl GatewayConfig {
+    pub fn new(backends: Vec<String>) -> Self {
+        GatewayConfig {
+            backends,
+            health: HealthPolicy::default(),
+            capacity: CapacityPolicy::default(),
+            heartbeat_interval: GATEWAY_HEARTBEAT_INTERVAL_DEFAULT,
+            call_time_out: GATEWAY_CALL_TIME_OUT_DEFAULT,
+            token: BearerToken::default(),
+        }
+    }
+}
+
+type BackendClient = SnarkTaskServiceClient<InterceptedService<Channel, BearerToken>>;
+
+// a task sent through the gateway and not acked yet, kept to send it again when its
+// backend dies
+#[derive(Debug, Clone)]
+struct RoutedTask {
+    params: SnarkTaskRequestParams,
+    // backend the task was moved off, told to the client with its results
+    migrated_from: Option<String>,
+    // its backend died and no other one was free for it yet
+    stranded: bool,
+    // why the task could not be sent again after its backend died
+    lost: Option<String>,
+}
+
+/// SnarkTaskService in front of several servers. A task goes to the backend the `Router`
+/// picks for its miner, the `client_id` of the task, and the calls about it to the backend
+/// it was sent to. Tasks of a backend which stops answering its heartbeats are sent to
+/// another one, and their clients find the dead backend in `migrated_from` of the results.
+///
+/// Locks are not held on a backend: a lock granted by the gateway tells a backend is free
+/// now, the task takes it when sent. Calls about a backend rather than a task, e.g. the
+/// admin calls, C2 and batches, are not routed and go to the backends themselves.
+#[derive(Clone)]
+pub struct Gateway {
+    router: Arc<Mutex<Router>>,
+    addrs: Vec<String>,
+    clients: Vec<BackendClient>,
+    tasks: Arc<Mutex<HashMap<String, RoutedTask>>>,
+    heartbeat_interval: Duration,
+}
+
+impl Gateway {
+    /// Backends are connected to on their first call, so one which is down now is only
+    /// taken out of rotation by its heartbeats
+    pub fn new(config: GatewayConfig) -> Result<Self> {
+        let mut clients = vec![];
+        for addr in &config.backends {
+            let channel = Endpoint::from_shared(addr.clone())
+                .with_context(|| format!("bad backend address {}", addr))?
+                .timeout(config.call_time_out)
+                .connect_lazy()?;
+            clients.push(SnarkTaskServiceClient::with_interceptor(
+                channel,
+                config.token.clone(),
+            ));
+        }
+        let mut router = Router::with_health_policy(config.backends.clone(), config.health);
+        router.set_capacity_policy(config.capacity);
+        Ok(Gateway {
+            router: Arc::new(Mutex::new(router)),
+            addrs: config.backends,
+            clients,
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            heartbeat_interval: config.heartbeat_interval,
+        })
+    }
+
+    pub fn capacity(&self) -> Capacity {
+        self.router.lock().capacity()
+    }
+
+    /// Backend the task was sent to, None when it is not routed by this gateway
+    pub fn backend_of(&self, task_id: &str) -> Option<String> {
+        let backend = self.router.lock().dispatched(task_id).map(|d| d.backend)?;
+        Some(self.addrs[backend].clone())
+    }
+
+    fn client(&self, backend: usize) -> BackendClient {
+        self.clients[backend].clone()
+    }
+
+    /// Heartbeat the backends every heartbeat interval in the background.
+    /// Needs a tokio runtime.
+    pub fn spawn_heartbeats(&self) -> JoinHandle<()> {
+        let gateway = self.clone();
+        tokio::spawn(async move {
+            loop {
+                gateway.heartbeat().await;
+                tokio::time::sleep(gateway.heartbeat_interval).await;
+            }
+        })
+    }
+
+    /// Ask every backend for its status, then move the tasks of those which missed
+    /// their heartbeats for too long to other backends
+    pub async fn heartbeat(&self) {
+        let calls = (0..self.clients.len()).map(|b| {
+            let mut client = self.client(b);
+            async move {
+                let started = Instant::now();
+                let res = client
+                    .get_worker_status(GetWorkerStatusRequest::default())
+                    .await;
+                (b, started.elapsed(), res)
+            }
+        });
+        for (b, latency, res) in join_all(calls).await {
+            match res {
+                Ok(res) => {
+                    let busy = res.into_inner().status != ServerStatus::Free.to_string();
+                    let mut router = self.router.lock();
+                    router.record_heartbeat(b, latency);
+                    router.set_busy(b, busy);
+                }
+                Err(e) => debug!("backend {} missed its heartbeat: {}", self.addrs[b], e),
+            }
+        }
+        let migrations = self.router.lock().reap_dead(Instant::now());
+        for m in migrations {
+            self.migrate(m).await;
+        }
+    }
+
+    // send a task of a dead backend to the one the router moved it to
+    async fn migrate(&self, m: Migration) {
+        let from = self.addrs[m.from].clone();
+        let params = {
+            let mut tasks = self.tasks.lock();
+            let t = match tasks.get_mut(&m.task_id) {
+                Some(t) => t,
+                None => return,
+            };
+            t.migrated_from = Some(from.clone());
+            t.stranded = m.to.is_none();
+            t.params.clone()
+        };
+        let to = match m.to {
+            Some(b) => b,
+            None => {
+                warn!(
+                    "backend {} of task {} died, no other backend is free for it",
+                    from, m.task_id
+                );
+                return;
+            }
+        };
+        if let Err(e) = self.client(to).do_snark_task(params).await {
+            warn!(
+                "task {} moved off {} was refused by {}: {}",
+                m.task_id, from, self.addrs[to], e
+            );
+            self.router.lock().release(&m.task_id);
+            if let Some(t) = self.tasks.lock().get_mut(&m.task_id) {
+                t.lost = Some(format!(
+                    "backend {} of task {} died and {} refused it: {}",
+                    from,
+                    m.task_id,
+                    self.addrs[to],
+                    e.message()
+                ));
+            }
+        }
+    }
+
+    // pick the backend of the task and send it there
+    async fn route_task(
+        &self,
+        params: SnarkTaskRequestParams,
+        tenant: String,
+    ) -> std::result::Result<BaseResponse, Status> {
+        let task_id = params.task_id.clone();
+        if self.tasks.lock().contains_key(&task_id) {
+            return Err(Status::already_exists(format!(
+                "task {} was sent already",
+                task_id
+            )));
+        }
+        let class = TaskClass {
+            tenant,
+            priority: is_priority(&params),
+        };
+        let backend = self
+            .router
+            .lock()
+            .dispatch_as(&task_id, &params.client_id, class);
+        let backend = match backend {
+            Some(b) => b,
+            None => {
+                return Err(Status::resource_exhausted(format!(
+                    "no backend is free for task {}",
+                    task_id
+                )))
+            }
+        };
+        match self.client(backend).do_snark_task(params.clone()).await {
+            Ok(res) => {
+                info!("task {} sent to backend {}", task_id, self.addrs[backend]);
+                self.tasks.lock().insert(
+                    task_id,
+                    RoutedTask {
+                        params,
+                        migrated_from: None,
+                        stranded: false,
+                        lost: None,
+                    },
+                );
+                Ok(res.into_inner())
+            }
+            Err(s) => {
+                self.router.lock().release(&task_id);
+                Err(s)
+            }
+        }
+    }
+
+    // backend to send the calls about the task to
+    fn task_backend(&self, task_id: &str) -> std::result::Result<usize, Status> {
+        {
+            let mut tasks = self.tasks.lock();
+            if let Some(t) = tasks.get(task_id) {
+                if let Some(e) = t.lost.clone() {
+                    tasks.remove(task_id);
+                    return Err(Status::unavailable(e));
+                }
+                if t.stranded {
+                    return Err(status_with_details(
+                        Code::Unavailable,
+                        format!(
+                            "backend {} of task {} died, the task waits for another one",
+                            t.migrated_from.clone().unwrap_or_default(),
+                            task_id
+                        ),
+                        ErrorDetails::retry_after(self.heartbeat_interval),
+                    ));
+                }
+            }
+        }
+        match self.router.lock().dispatched(task_id) {
+            Some(d) => Ok(d.backend),
+            None => Err(Status::not_found(format!(
+                "task {} was not sent through this gateway",
+                task_id
+            ))),
+        }
+    }
+
+    // the task is done with on its backend
+    fn finish_task(&self, task_id: &str, outcome: Option<bool>) {
+        match outcome {
+            Some(ok) => self.router.lock().complete(task_id, ok),
+            None => self.router.lock().release(task_id),
+        }
+        self.tasks.lock().remove(task_id);
+    }
+
+    // a call about the task failed on its backend. Errors of the task itself end it, those
+    // of reaching the backend are left to the heartbeats.
+    fn task_call_failed(&self, task_id: &str, s: &Status) {
+        if s.code() == Code::Unavailable && s.details().is_empty() {
+            return;
+        }
+        match s.code() {
+            // retryable or unknown failures, the backend may be to blame
+            Code::Unavailable | Code::Aborted => self.finish_task(task_id, Some(false)),
+            _ => self.finish_task(task_id, None),
+        }
+    }
+
+    // what the lock calls answer, no backend is held for the task
+    fn lock_status(&self, class: &TaskClass) -> WorkerStatus {
+        let status = if self.router.lock().has_capacity(class) {
+            ServerStatus::Free
+        } else {
+            ServerStatus::Working
+        };
+        WorkerStatus {
+            status: status.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+fn is_priority(params: &SnarkTaskRequestParams) -> bool {
+    params.priority
+        || params.post_config.as_ref().map_or(false, |c| {
+            c.priority || c.typ == snark_proof_grpc::PoStType::Winning as i32
+        })
+}
+
+// tasks are counted against the limit of the authenticated caller, or of the client id
+// of the task when the gateway does not authenticate
+fn tenant<T>(request: &Request<T>) -> String {
+    request
+        .extensions()
+        .get::<Principal>()
+        .map(|p| p.subject.clone())
+        .unwrap_or_default()
+}
+
+// calls about a backend rather than a task
+fn not_routed(rpc: &str) -> Status {
+    Status::unimplemented(format!(
+        "{} is not routed by the gateway, call a backend",
+        rpc
+    ))
+}
+
+// the streams of tonic clients are not Sync, as the streams of a service have to be
+fn forward_stream<T: Send + 'static>(
+    mut stream: Streaming<T>,
+) -> UnboundedReceiverStream<std::result::Result<T, Status>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let item = match stream.message().await {
+                Ok(Some(m)) => Ok(m),
+                Ok(None) => break,
+                Err(s) => Err(s),
+            };
+            let failed = item.is_err();
+            if tx.send(item).is_err() || failed {
+                break;
+            }
+        }
+    });
+    UnboundedReceiverStream::new(rx)
+}
+
+fn capacity_to_proto(c: &Capacity) -> snark_proof_grpc::GatewayCapacity {
+    snark_proof_grpc::GatewayCapacity {
+        backends: c.backends as u32,
+        available: c.available as u32,
+        reserved_for_priority: c.reserved_for_priority as u32,
+        tenants: c
+            .tenants
+            .iter()
+            .map(|(t, &(in_use, limit))| snark_proof_grpc::TenantCapacity {
+                tenant: t.clone(),
+                in_use: in_use as u32,
+                limit: limit as u32,
+            })
+            .collect(),
+    }
+}
+
+#[tonic::async_trait]
+impl SnarkTaskService for Gateway {
+    async fn do_snark_task(
+        &self,
+        request: Request<SnarkTaskRequestParams>,
+    ) -> std::result::Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let tenant = tenant(&request);
+        let mut params = request.into_inner();
+        if params.client_id.is_empty() {
+            if let Some(addr) = remote_addr {
+                params.client_id = addr.ip().to_string();
+            }
+        }
+        let tenant = if tenant.is_empty() {
+            params.client_id.clone()
+        } else {
+            tenant
+        };
+        self.route_task(params, tenant).await.map(Response::new)
+    }
+
+    async fn do_snark_task_stream(
+        &self,
+        request: Request<Streaming<SnarkTaskFrame>>,
+    ) -> std::result::Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let tenant = tenant(&request);
+        let mut params = collect_task_frames(request.into_inner(), TASK_PAYLOAD_MAX_LEN).await?;
+        if params.client_id.is_empty() {
+            if let Some(addr) = remote_addr {
+                params.client_id = addr.ip().to_string();
+            }
+        }
+        let tenant = if tenant.is_empty() {
+            params.client_id.clone()
+        } else {
+            tenant
+        };
+        self.route_task(params, tenant).await.map(Response::new)
+    }
+
+    async fn do_c2_task(
+        &self,
+        _request: Request<C2TaskRequest>,
+    ) -> std::result::Result<Response<BaseResponse>, Status> {
+        Err(not_routed("DoC2Task"))
+    }
+
+    async fn lock_server_if_free(
+        &self,
+        request: Request<GetWorkerStatusRequest>,
+    ) -> std::result::Result<Response<WorkerStatus>, Status> {
+        let class = TaskClass {
+            tenant: tenant(&request),
+            priority: request.get_ref().priority,
+        };
+        Ok(Response::new(self.lock_status(&class)))
+    }
+
+    async fn get_worker_status(
+        &self,
+        request: Request<GetWorkerStatusRequest>,
+    ) -> std::result::Result<Response<WorkerStatus>, Status> {
+        let req = request.into_inner();
+        if req.task_id.is_empty() || self.router.lock().dispatched(&req.task_id).is_none() {
+            return Ok(Response::new(self.lock_status(&TaskClass::default())));
+        }
+        let backend = self.task_backend(&req.task_id)?;
+        self.client(backend).get_worker_status(req).await
+    }
+
+    async fn lock_server_wait(
+        &self,
+        request: Request<LockServerWaitRequest>,
+    ) -> std::result::Result<Response<WorkerStatus>, Status> {
+        let class = TaskClass {
+            tenant: tenant(&request),
+            priority: request.get_ref().priority,
+        };
+        let wait = match request.into_inner().wait_timeout_ms {
+            0 => SERVER_LOCK_WAIT_TIME_OUT_DEFAULT,
+            ms => Duration::from_millis(ms),
+        };
+        let deadline = Instant::now() + wait;
+        loop {
+            let status = self.lock_status(&class);
+            if status.status == ServerStatus::Free.to_string() || Instant::now() >= deadline {
+                return Ok(Response::new(status));
+            }
+            tokio::time::sleep(LOCK_WAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    async fn get_snark_task_result(
+        &self,
+        request: Request<GetTaskResultRequest>,
+    ) -> std::result::Result<Response<GetTaskResultResponse>, Status> {
+        let req = request.into_inner();
+        let task_id = req.task_id.clone();
+        let backend = self.task_backend(&task_id)?;
+        match self.client(backend).get_snark_task_result(req).await {
+            Ok(res) => {
+                let mut res = res.into_inner();
+                if let Some(t) = self.tasks.lock().get(&task_id) {
+                    res.migrated_from = t.migrated_from.clone().unwrap_or_default();
+                }
+                Ok(Response::new(res))
+            }
+            Err(s) => {
+                self.task_call_failed(&task_id, &s);
+                Err(s)
+            }
+        }
+    }
+
+    type DownloadTaskResultStream =
+        UnboundedReceiverStream<std::result::Result<TaskResultChunk, Status>>;
+
+    async fn download_task_result(
+        &self,
+        request: Request<GetTaskResultRequest>,
+    ) -> std::result::Result<Response<Self::DownloadTaskResultStream>, Status> {
+        let req = request.into_inner();
+        let task_id = req.task_id.clone();
+        let backend = self.task_backend(&task_id)?;
+        match self.client(backend).download_task_result(req).await {
+            Ok(res) => Ok(Response::new(forward_stream(res.into_inner()))),
+            Err(s) => {
+                self.task_call_failed(&task_id, &s);
+                Err(s)
+            }
+        }
+    }
+
+    async fn ack_result(
+        &self,
+        request: Request<AckResultRequest>,
+    ) -> std::result::Result<Response<BaseResponse>, Status> {
+        let req = request.into_inner();
+        let task_id = req.task_id.clone();
+        let backend = self.task_backend(&task_id)?;
+        match self.client(backend).ack_result(req).await {
+            Ok(res) => {
+                self.finish_task(&task_id, Some(true));
+                Ok(res)
+            }
+            Err(s) => {
+                self.task_call_failed(&task_id, &s);
+                Err(s)
+            }
+        }
+    }
+
+    type StreamTaskResultStream =
+        UnboundedReceiverStream<std::result::Result<PartitionProof, Status>>;
+
+    async fn stream_task_result(
+        &self,
+        request: Request<GetTaskResultRequest>,
+    ) -> std::result::Result<Response<Self::StreamTaskResultStream>, Status> {
+        let req = request.into_inner();
+        let backend = self.task_backend(&req.task_id)?;
+        let res = self.client(backend).stream_task_result(req).await?;
+        Ok(Response::new(forward_stream(res.into_inner())))
+    }
+
+    async fn unlock_server(
+        &self,
+        request: Request<UnlockServerRequest>,
+    ) -> std::result::Result<Response<BaseResponse>, Status> {
+        let req = request.into_inner();
+        let task_id = req.task_id.clone();
+        // locks are not held on a backend, only a task sent already has one to give up
+        if self.router.lock().dispatched(&task_id).is_none() {
+            return Ok(Response::new(ok_response(format!(
+                "task {} holds no backend",
+                task_id
+            ))));
+        }
+        let backend = self.task_backend(&task_id)?;
+        let res = self.client(backend).unlock_server(req).await?;
+        self.finish_task(&task_id, None);
+        Ok(res)
+    }
+
+    async fn cancel_task(
+        &self,
+        request: Request<CancelTaskRequest>,
+    ) -> std::result::Result<Response<BaseResponse>, Status> {
+        let req = request.into_inner();
+        let task_id = req.task_id.clone();
+        let backend = self.task_backend(&task_id)?;
+        let res = self.client(backend).cancel_task(req).await?;
+        self.finish_task(&task_id, None);
+        Ok(res)
+    }
+
+    type WatchTaskStream = UnboundedReceiverStream<std::result::Result<TaskEvent, Status>>;
+
+    async fn watch_task(
+        &self,
+        request: Request<WatchTaskRequest>,
+    ) -> std::result::Result<Response<Self::WatchTaskStream>, Status> {
+        let req = request.into_inner();
+        let backend = self.task_backend(&req.task_id)?;
+        let res = self.client(backend).watch_task(req).await?;
+        Ok(Response::new(forward_stream(res.into_inner())))
+    }
+
+    async fn get_capabilities(
+        &self,
+        _request: Request<GetCapabilitiesRequest>,
+    ) -> std::result::Result<Response<Capabilities>, Status> {
+        Ok(Response::new(Capabilities {
+            payload_encodings: codec::supported_encodings()
+                .iter()
+                .map(|e| e.to_wire() as i32)
+                .collect(),
+            gateway: Some(capacity_to_proto(&self.capacity())),
+            ..Default::default()
+        }))
+    }
+
+    async fn estimate_task(
+        &self,
+        _request: Request<EstimateTaskRequest>,
+    ) -> std::result::Result<Response<TaskEstimate>, Status> {
+        Err(not_routed("EstimateTask"))
+    }
+
+    async fn export_task_report(
+        &self,
+        _request: Request<ExportTaskReportRequest>,
+    ) -> std::result::Result<Response<TaskReport>, Status> {
+        Err(not_routed("ExportTaskReport"))
+    }
+
+    async fn submit_batch(
+        &self,
+        _request: Request<SubmitBatchRequest>,
+    ) -> std::result::Result<Response<SubmitBatchResponse>, Status> {
+        Err(not_routed("SubmitBatch"))
+    }
+
+    async fn get_batch_report(
+        &self,
+        _request: Request<GetBatchReportRequest>,
+    ) -> std::result::Result<Response<BatchReport>, Status> {
+        Err(not_routed("GetBatchReport"))
+    }
+
+    async fn dump_server_state(
+        &self,
+        _request: Request<DumpServerStateRequest>,
+    ) -> std::result::Result<Response<ServerStateDump>, Status> {
+        Err(not_routed("DumpServerState"))
+    }
+
+    async fn manage_param_cache(
+        &self,
+        _request: Request<ManageParamCacheRequest>,
+    ) -> std::result::Result<Response<ParamCacheState>, Status> {
+        Err(not_routed("ManageParamCache"))
+    }
+
+    async fn set_server_timeouts(
+        &self,
+        _request: Request<SetServerTimeoutsRequest>,
+    ) -> std::result::Result<Response<ServerTimeouts>, Status> {
+        Err(not_routed("SetServerTimeouts"))
+    }
+}
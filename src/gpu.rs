@@ -0,0 +1,179 @@
+//! GPUs of the host and the slots a server proves on. bellperson picks the devices of a
+//! proof itself and takes no device per proof, so a server seeing several GPUs proves a task
+//! per GPU at once in child processes, each pinned to its device by the env of the gpu
+//! backend. Every slot is locked on its own and takes the next task of the shared queue
+//! once free. A server seeing a single GPU proves in its own process, one task at a time.
+
+use crate::utils;
+use log::info;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GpuDevice {
+    pub name: String,
+    pub memory_bytes: u64,
+    pub unique_id: String,
+}
+
+/// Devices the gpu backend the server was built with finds, none for cpu-only builds
+#[cfg(any(feature = "cuda", feature = "opencl"))]
+pub fn devices() -> Vec<GpuDevice> {
+    rust_gpu_tools::Device::all()
+        .into_iter()
+        .map(|d| GpuDevice {
+            name: d.name(),
+            memory_bytes: d.memory(),
+            unique_id: d.unique_id().to_string(),
+        })
+        .collect()
+}
+
+#[cfg(not(any(feature = "cuda", feature = "opencl")))]
+pub fn devices() -> Vec<GpuDevice> {
+    vec![]
+}
+
+/// GPU backend bellperson proves with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Cuda,
+    OpenCl,
+}
+
+impl Backend {
+    /// Backend of this build, CUDA when built with both as bellperson prefers it
+    pub fn built() -> Option<Backend> {
+        if cfg!(feature = "cuda") {
+            Some(Backend::Cuda)
+        } else if cfg!(feature = "opencl") {
+            Some(Backend::OpenCl)
+        } else {
+            None
+        }
+    }
+
+    /// Env var pinning a process of this backend to some devices
+    pub fn select_env(self) -> &'static str {
+        match self {
+            Backend::Cuda => "CUDA_VISIBLE_DEVICES",
+            Backend::OpenCl => "GPU_DEVICE_ORDINAL",
+        }
+    }
+}
+
+// unique ids are uuids where the driver has them, else the PCI bus and device, e.g. 01:00
+fn is_uuid(unique_id: &str) -> bool {
+    unique_id.len() == 36 && unique_id.matches('-').count() == 4
+}
+
+/// What the select env of `backend` pins each of `devices` with, in their order. CUDA
+/// takes the uuid of a device. Ordinals are taken in PCI bus order, which CUDA only
+/// follows with `CUDA_DEVICE_ORDER=PCI_BUS_ID` and the OpenCL runtimes follow anyway;
+/// the order devices were enumerated in is not stable across drivers. None when a device
+/// can be told by neither.
+pub fn device_selectors(devices: &[GpuDevice], backend: Backend) -> Option<Vec<String>> {
+    let mut bus_ids = devices
+        .iter()
+        .filter(|d| !is_uuid(&d.unique_id))
+        .map(|d| d.unique_id.as_str())
+        .collect::<Vec<_>>();
+    bus_ids.sort_unstable();
+    devices
+        .iter()
+        .map(|d| match backend {
+            Backend::Cuda if is_uuid(&d.unique_id) => Some(format!("GPU-{}", d.unique_id)),
+            _ if is_uuid(&d.unique_id) => None,
+            _ => bus_ids
+                .iter()
+                .position(|id| *id == d.unique_id)
+                .map(|i| i.to_string()),
+        })
+        .collect()
+}
+
+/// Device a slot of the server proves its tasks on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SlotDevice {
+    // unique id of the device, or the devices the server is pinned to for the single slot
+    // of a server proving on all of them
+    pub device_id: String,
+    pub name: String,
+    // env pinning the child process a task of the slot is proved in to the device, empty
+    // when the slot proves in the server process on the devices bellperson picks
+    pub env: Vec<(String, String)>,
+}
+
+impl SlotDevice {
+    /// The single slot of a server proving in its own process, on the devices it is
+    /// pinned to if any
+    pub fn shared() -> Self {
+        SlotDevice {
+            device_id: utils::gpu_device_id(),
+            ..Default::default()
+        }
+    }
+
+    pub fn in_process(&self) -> bool {
+        self.env.is_empty()
+    }
+}
+
+/// Slots of a server seeing `devices`: one per device when there are several, each proving
+/// in a child process pinned to its device with the select env of `backend`. A server
+/// seeing one device, or which can not tell its devices apart, has a single slot proving
+/// in its own process. Ordinals count the devices of the host, so a server `pinned` to
+/// some of them only splits them by uuid.
+pub fn slot_devices(devices: &[GpuDevice], backend: Backend, pinned: &str) -> Vec<SlotDevice> {
+    let shared = vec![SlotDevice::shared()];
+    if devices.len() < 2 {
+        return shared;
+    }
+    let selectors = match device_selectors(devices, backend) {
+        Some(s) => s,
+        None => return shared,
+    };
+    let ordinals = selectors.iter().any(|s| !s.starts_with("GPU-"));
+    if ordinals && !pinned.is_empty() {
+        return shared;
+    }
+    devices
+        .iter()
+        .zip(selectors)
+        .map(|(d, selector)| {
+            let mut env = vec![(backend.select_env().to_string(), selector)];
+            if ordinals && backend == Backend::Cuda {
+                env.push(("CUDA_DEVICE_ORDER".to_string(), "PCI_BUS_ID".to_string()));
+            }
+            SlotDevice {
+                device_id: d.unique_id.clone(),
+                name: d.name.clone(),
+                env,
+            }
+        })
+        .collect()
+}
+
+/// Slots of this server, one per GPU it finds when it finds several
+pub fn detect_slots() -> Vec<SlotDevice> {
+    match Backend::built() {
+        Some(backend) => slot_devices(&devices(), backend, &utils::gpu_device_id()),
+        None => vec![SlotDevice::shared()],
+    }
+}
+
+/// Log the devices found and the slots proving on them
+pub fn log_devices(slots: &[SlotDevice]) {
+    for d in &devices() {
+        info!(
+            "gpu {} {} with {} MiB",
+            d.unique_id,
+            d.name,
+            d.memory_bytes >> 20
+        );
+    }
+    if slots.len() > 1 {
+        info!(
+            "{} slots prove a task each at once, one per gpu in a child process",
+            slots.len()
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::codec::{self, Encoding};
+use crate::compat;
+use crate::error::Result;
+use filecoin_hashers::Hasher;
+use filecoin_proofs::{get_partitions_for_window_post, with_shape, PoStConfig};
+use std::fmt;
+use storage_proofs_core::merkle::MerkleTreeTrait;
+use storage_proofs_post::fallback;
+
+/// What the payloads of a task contain, used to diagnose rejected or failed tasks
+#[derive(Debug, Default, Clone)]
+pub struct PayloadSummary {
+    pub sector_size: u64,
+    pub sector_count: usize,
+    pub challenge_count: usize,
+    pub replicas_len: Option<usize>,
+    // partitions the server derives from replicas_len and the post config
+    pub partitions: Option<usize>,
+    pub pub_in_bytes: usize,
+    pub pub_in_sectors: Option<usize>,
+    pub pub_in_k: Option<usize>,
+    pub vanilla_bytes: usize,
+    pub vanilla_partitions: Option<usize>,
+    pub vanilla_sectors: Option<usize>,
+    // most inclusion proofs found for one sector
+    pub vanilla_challenges: Option<usize>,
+}
+
+/// Decode the payloads of a task, empty payloads are skipped
+pub fn inspect(
+    post_config: &PoStConfig,
+    encoding: Encoding,
+    pub_in: &[u8],
+    vanilla_proof: &[u8],
+    replicas_len: Option<usize>,
+) -> Result<PayloadSummary> {
+    let summary = PayloadSummary {
+        sector_size: u64::from(post_config.sector_size),
+        sector_count: post_config.sector_count,
+        challenge_count: post_config.challenge_count,
+        replicas_len,
+        partitions: replicas_len.and_then(|r| get_partitions_for_window_post(r, post_config)),
+        pub_in_bytes: pub_in.len(),
+        vanilla_bytes: vanilla_proof.len(),
+        ..Default::default()
+    };
+    with_shape!(
+        u64::from(post_config.sector_size),
+        inspect_payload,
+        encoding,
+        pub_in,
+        vanilla_proof,
+        summary
+    )
+}
+
+fn inspect_payload<Tree: 'static + MerkleTreeTrait>(
+    encoding: Encoding,
+    pub_in: &[u8],
+    vanilla_proof: &[u8],
+    mut summary: PayloadSummary,
+) -> Result<PayloadSummary> {
+    if !pub_in.is_empty() {
+        let pub_inputs: fallback::PublicInputs<<Tree::Hasher as Hasher>::Domain> =
+            codec::decode(encoding, pub_in)?;
+        summary.pub_in_sectors = Some(pub_inputs.sectors.len());
+        summary.pub_in_k = pub_inputs.k;
+    }
+    if !vanilla_proof.is_empty() {
+        let proofs: Vec<fallback::Proof<Tree::Proof>> =
+            compat::decode_vanilla_proofs(encoding, vanilla_proof)?;
+        summary.vanilla_partitions = Some(proofs.len());
+        summary.vanilla_sectors = Some(proofs.iter().map(|p| p.sectors.len()).sum());
+        summary.vanilla_challenges = proofs
+            .iter()
+            .flat_map(|p| p.sectors.iter())
+            .map(|s| s.inclusion_proofs.len())
+            .max();
+    }
+    Ok(summary)
+}
+
+fn or_unknown(v: Option<usize>) -> String {
+    match v {
+        Some(v) => v.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+impl fmt::Display for PayloadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "sector size:             {}", self.sector_size)?;
+        writeln!(f, "sectors per partition:   {}", self.sector_count)?;
+        writeln!(f, "challenges per sector:   {}", self.challenge_count)?;
+        writeln!(
+            f,
+            "replicas_len:            {}",
+            or_unknown(self.replicas_len)
+        )?;
+        writeln!(
+            f,
+            "expected partitions:     {}",
+            or_unknown(self.partitions)
+        )?;
+        writeln!(f, "pub_in bytes:            {}", self.pub_in_bytes)?;
+        writeln!(
+            f,
+            "pub_in sectors:          {}",
+            or_unknown(self.pub_in_sectors)
+        )?;
+        writeln!(f, "pub_in k:                {}", or_unknown(self.pub_in_k))?;
+        writeln!(f, "vanilla proof bytes:     {}", self.vanilla_bytes)?;
+        writeln!(
+            f,
+            "vanilla partitions:      {}",
+            or_unknown(self.vanilla_partitions)
+        )?;
+        writeln!(
+            f,
+            "vanilla sectors:         {}",
+            or_unknown(self.vanilla_sectors)
+        )?;
+        write!(
+            f,
+            "vanilla challenges:      {}",
+            or_unknown(self.vanilla_challenges)
+        )
+    }
+}
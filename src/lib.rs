@@ -1,8 +1,50 @@
+//! Server proving the snark of window PoSt tasks for miners, and a client for it.
+//! Integrators should need no more than the `prelude`; modules hidden from the docs
+//! are internals of the server, kept public for its binaries and tests only.
+
+pub mod audit;
+pub mod auth;
+#[doc(hidden)]
+pub mod batch;
+#[doc(hidden)]
+pub mod c2;
+pub mod calibrate;
 pub mod client;
+pub mod codec;
+#[doc(hidden)]
+pub mod compat;
+pub mod config;
+#[doc(hidden)]
+pub mod crash;
+#[cfg(feature = "mdns")]
+pub mod discovery;
+pub mod dump;
 pub mod error;
+pub mod error_details;
+pub mod estimate;
+pub mod gateway;
+pub mod gpu;
+#[doc(hidden)]
+pub mod inspect;
+pub mod limits;
+#[doc(hidden)]
+pub mod params;
+pub mod pool;
+pub mod prelude;
+pub mod pub_inputs;
+pub mod remote;
+#[doc(hidden)]
+pub mod resources;
 pub mod run;
 pub mod server;
+#[doc(hidden)]
 pub mod snark_proof_grpc;
+pub mod state;
 pub mod status;
+#[doc(hidden)]
 pub mod tasks;
+#[doc(hidden)]
+pub mod trace;
+#[doc(hidden)]
 pub mod utils;
+pub mod vanilla;
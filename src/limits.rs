@@ -0,0 +1,163 @@
+use futures::Stream;
+use log::error;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Instant, Sleep};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+
+// what a semaphore standing for "no limit" starts with
+const UNLIMITED: usize = usize::MAX >> 4;
+
+/// Limits protecting the host from clients opening too many channels or requests, 0 means no limit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    pub max_connections: usize,
+    pub max_in_flight: usize,
+    pub max_in_flight_per_connection: usize,
+    // connections without any traffic for this long are closed, zero means never
+    pub idle_time_out: Duration,
+}
+
+impl ConnectionLimits {
+    /// Limit for a tower concurrency layer, which has no notion of unlimited
+    pub fn in_flight_limit(&self) -> usize {
+        if self.max_in_flight == 0 {
+            UNLIMITED
+        } else {
+            self.max_in_flight
+        }
+    }
+}
+
+/// An accepted connection, holding one of the connection slots until it is closed
+pub struct LimitedTcpStream {
+    inner: TcpStream,
+    _permit: OwnedSemaphorePermit,
+    idle_time_out: Duration,
+    idle: Option<Pin<Box<Sleep>>>,
+}
+
+impl LimitedTcpStream {
+    fn new(inner: TcpStream, permit: OwnedSemaphorePermit, idle_time_out: Duration) -> Self {
+        let idle = if idle_time_out.is_zero() {
+            None
+        } else {
+            Some(Box::pin(tokio::time::sleep(idle_time_out)))
+        };
+        LimitedTcpStream {
+            inner,
+            _permit: permit,
+            idle_time_out,
+            idle,
+        }
+    }
+
+    // traffic on the connection, it is not idle
+    fn touch(&mut self) {
+        let deadline = Instant::now() + self.idle_time_out;
+        if let Some(idle) = self.idle.as_mut() {
+            idle.as_mut().reset(deadline);
+        }
+    }
+
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> bool {
+        match self.idle.as_mut() {
+            Some(idle) => idle.as_mut().poll(cx).is_ready(),
+            None => false,
+        }
+    }
+}
+
+impl Connected for LimitedTcpStream {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.inner.connect_info()
+    }
+}
+
+impl AsyncRead for LimitedTcpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                if buf.filled().len() > filled {
+                    self.touch();
+                }
+                Poll::Ready(Ok(()))
+            }
+            // the server always waits for the next read, so this is where idle connections end
+            Poll::Pending if self.poll_idle(cx) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection idle for too long",
+            ))),
+            other => other,
+        }
+    }
+}
+
+impl AsyncWrite for LimitedTcpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            if n > 0 {
+                self.touch();
+            }
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Accept connections while fewer than `max_connections` are open.
+/// Clients beyond the limit wait in the listen backlog instead of taking file descriptors,
+/// and connections idle longer than `idle_time_out` are closed.
+pub fn limited_incoming(
+    listener: TcpListener,
+    limits: ConnectionLimits,
+) -> impl Stream<Item = io::Result<LimitedTcpStream>> {
+    let permits = Arc::new(Semaphore::new(if limits.max_connections == 0 {
+        UNLIMITED
+    } else {
+        limits.max_connections
+    }));
+    let idle_time_out = limits.idle_time_out;
+    futures::stream::unfold((listener, permits), move |(listener, permits)| async move {
+        let permit = permits.clone().acquire_owned().await.ok()?;
+        loop {
+            match listener.accept().await {
+                Ok((inner, _)) => {
+                    let stream = LimitedTcpStream::new(inner, permit, idle_time_out);
+                    return Some((Ok(stream), (listener, permits)));
+                }
+                // e.g. out of file descriptors, keep serving the connections we have
+                Err(e) => {
+                    error!("failed to accept connection with error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,248 @@
+use crate::calibrate;
+use crate::error::Result;
+use filecoin_proofs::caches::get_post_params;
+use filecoin_proofs::{with_shape, Bls12GrothParams, PoStConfig, PoStType};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use storage_proofs_core::merkle::MerkleTreeTrait;
+
+/// Env filecoin-proofs reads the groth parameter dir from
+pub const PARAMETER_CACHE_ENV: &str = "FIL_PROOFS_PARAMETER_CACHE";
+pub const PARAMETER_CACHE_DEFAULT: &str = "/var/tmp/filecoin-proof-parameters/";
+pub const PARAMS_EXTENSION: &str = "params";
+
+/// Entry of parameters.json, the manifest parameter files are published with
+#[derive(Debug, Clone, Deserialize)]
+pub struct ParameterData {
+    #[serde(default)]
+    pub cid: String,
+    // first 32 hex digits of the blake2b-512 of the file
+    pub digest: String,
+    #[serde(default)]
+    pub sector_size: u64,
+}
+
+pub type ParameterManifest = BTreeMap<String, ParameterData>;
+
+/// First of the dirs which holds groth parameters, the later ones are fallbacks
+/// for when a faster one is not mounted or not filled yet
+pub fn select_parameter_cache(dirs: &[PathBuf]) -> Option<PathBuf> {
+    for d in dirs {
+        if has_params(d) {
+            return Some(d.clone());
+        }
+        warn!("no groth parameters in {:?}, try the next parameter dir", d);
+    }
+    None
+}
+
+/// Point filecoin-proofs to the first dir with parameters. It reads the env once,
+/// so this has to run before the first proof.
+pub fn set_parameter_cache(dirs: &[PathBuf]) -> Result<PathBuf> {
+    match select_parameter_cache(dirs) {
+        Some(d) => {
+            info!("groth parameters are read from {:?}", d);
+            env::set_var(PARAMETER_CACHE_ENV, &d);
+            Ok(d)
+        }
+        None => Err(anyhow::anyhow!(
+            "none of the parameter dirs {:?} holds groth parameters",
+            dirs
+        )),
+    }
+}
+
+fn has_params(dir: &Path) -> bool {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .any(|e| e.path().extension().and_then(|x| x.to_str()) == Some(PARAMS_EXTENSION)),
+        Err(_) => false,
+    }
+}
+
+/// Dir filecoin-proofs reads the groth parameters from
+pub fn parameter_cache_dir() -> PathBuf {
+    match env::var(PARAMETER_CACHE_ENV) {
+        Ok(d) => PathBuf::from(d),
+        Err(_) => PathBuf::from(PARAMETER_CACHE_DEFAULT),
+    }
+}
+
+pub fn read_manifest(path: &Path) -> Result<ParameterManifest> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Check the parameter files in the dir against the manifest, return the names of
+/// those which do not match. Files not in the dir are skipped, they are only needed
+/// for sector sizes this server does not prove.
+pub fn verify_parameters(dir: &Path, manifest: &ParameterManifest) -> Result<Vec<String>> {
+    let mut corrupt = vec![];
+    for (name, data) in manifest {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        let digest = file_digest(&path)?;
+        if digest != data.digest {
+            warn!(
+                "parameter file {:?} has digest {}, expected {}",
+                path, digest, data.digest
+            );
+            corrupt.push(name.clone());
+        } else {
+            info!("parameter file {:?} verified", path);
+        }
+    }
+    Ok(corrupt)
+}
+
+/// Digest the way paramfetch computes it
+pub fn file_digest(path: &Path) -> Result<String> {
+    let mut state = blake2b_simd::State::new();
+    io::copy(&mut File::open(path)?, &mut state)?;
+    Ok(state.finalize().to_hex()[..32].to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct GrothParamsKey {
+    sector_size: u64,
+    typ: String,
+    challenge_count: usize,
+    sector_count: usize,
+}
+
+impl GrothParamsKey {
+    fn new(post_config: &PoStConfig) -> Self {
+        GrothParamsKey {
+            sector_size: u64::from(post_config.sector_size),
+            typ: format!("{:?}", post_config.typ),
+            challenge_count: post_config.challenge_count,
+            sector_count: post_config.sector_count,
+        }
+    }
+}
+
+/// Groth parameters kept in memory
+#[derive(Debug, Clone)]
+pub struct CachedParams {
+    pub sector_size: u64,
+    pub typ: PoStType,
+    pub loaded_at: SystemTime,
+    pub load_time: Duration,
+}
+
+struct CachedGrothParams {
+    params: Arc<Bls12GrothParams>,
+    info: CachedParams,
+}
+
+// empty while the parameters are loaded, or when loading them failed
+type GrothParamsCell = Arc<OnceCell<CachedGrothParams>>;
+
+lazy_static! {
+    static ref GROTH_PARAMS_CACHE: Mutex<HashMap<GrothParamsKey, GrothParamsCell>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Groth parameters of the post config, read from the parameter dir on first use and kept
+/// until evicted. filecoin-proofs reads them again for every proof unless
+/// FIL_PROOFS_USE_GROTH_PARAM_MEMORY_CACHE is set, and then never lets them go.
+pub fn groth_params<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+) -> Result<Arc<Bls12GrothParams>> {
+    let key = GrothParamsKey::new(post_config);
+    // the map is only locked to find the cell of the key: a task waits on the cell for a
+    // preload of the same parameters instead of reading them a second time, while other
+    // parameters are loaded, listed and evicted meanwhile
+    let cell = GROTH_PARAMS_CACHE
+        .lock()
+        .entry(key.clone())
+        .or_default()
+        .clone();
+    let cached = cell.get_or_try_init(|| -> Result<_> {
+        let start = Instant::now();
+        let params = get_post_params::<Tree>(post_config)?;
+        let load_time = start.elapsed();
+        info!("groth parameters of {:?} loaded in {:?}", key, load_time);
+        Ok(CachedGrothParams {
+            params,
+            info: CachedParams {
+                sector_size: u64::from(post_config.sector_size),
+                typ: post_config.typ,
+                loaded_at: SystemTime::now(),
+                load_time,
+            },
+        })
+    })?;
+    Ok(cached.params.clone())
+}
+
+/// Load the window PoSt parameters of the sector sizes before the first task needs them
+pub fn preload_params(sector_sizes: &[u64]) -> Result<()> {
+    for &sector_size in sector_sizes {
+        let post_config = calibrate::window_post_config(sector_size)?;
+        with_shape!(sector_size, preload_typed, &post_config)?;
+    }
+    Ok(())
+}
+
+fn preload_typed<Tree: 'static + MerkleTreeTrait>(post_config: &PoStConfig) -> Result<()> {
+    groth_params::<Tree>(post_config).map(drop)
+}
+
+/// The parameters in memory, by sector size
+pub fn cached_params() -> Vec<CachedParams> {
+    let mut cached = GROTH_PARAMS_CACHE
+        .lock()
+        .values()
+        .filter_map(|c| c.get())
+        .map(|c| c.info.clone())
+        .collect::<Vec<_>>();
+    cached.sort_by_key(|c| (c.sector_size, format!("{:?}", c.typ)));
+    cached
+}
+
+/// Drop the parameters of the sector size, or all of them. A task proving with them keeps
+/// its copy, the memory is freed once it is done.
+pub fn evict_params(sector_size: Option<u64>) -> Vec<CachedParams> {
+    let mut cache = GROTH_PARAMS_CACHE.lock();
+    let keys = cache
+        .keys()
+        .filter(|k| sector_size.map_or(true, |s| k.sector_size == s))
+        .cloned()
+        .collect::<Vec<_>>();
+    let evicted = keys
+        .iter()
+        .filter_map(|k| cache.remove(k))
+        // parameters still loading are read again by the next task
+        .filter_map(|c| c.get().map(|c| c.info.clone()))
+        .collect::<Vec<_>>();
+    if !evicted.is_empty() {
+        info!("groth parameters evicted: {:?}", evicted);
+    }
+    evicted
+}
+
+/// Read the parameters of the sector size, or of every size in memory, from the parameter
+/// dir again, e.g. after the files were replaced. Only window PoSt parameters are loaded
+/// ahead, winning PoSt ones come back with the next task.
+pub fn reload_params(sector_size: Option<u64>) -> Result<Vec<CachedParams>> {
+    let mut sector_sizes = evict_params(sector_size)
+        .iter()
+        .map(|c| c.sector_size)
+        .collect::<BTreeSet<_>>();
+    sector_sizes.extend(sector_size);
+    preload_params(&sector_sizes.into_iter().collect::<Vec<_>>())?;
+    Ok(cached_params())
+}
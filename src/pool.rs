@@ -0,0 +1,373 @@
+use crate::client::WindowPostClient;
+use crate::error::{Error, Result};
+use crate::snark_proof_grpc::WorkerStatus;
+use crate::status::ServerStatus;
+use bellperson::groth16;
+use blstrs::Bls12;
+use filecoin_proofs::{PoStConfig, PoStType};
+use futures::future::join_all;
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tonic::{Code, Status};
+
+/// How the endpoints of a pool are probed and scored
+#[derive(Debug, Clone, Copy)]
+pub struct ProbePolicy {
+    pub interval: Duration,
+    // probes failed in a row before an endpoint is taken out, so one lost probe does
+    // not drop a server
+    pub unhealthy_after: u32,
+    // weight of the newest round trip in the smoothed latency
+    pub latency_weight: f64,
+}
+
+impl Default for ProbePolicy {
+    fn default() -> Self {
+        ProbePolicy {
+            interval: Duration::from_secs(10),
+            unhealthy_after: 2,
+            latency_weight: 0.3,
+        }
+    }
+}
+
+/// Health of an endpoint as seen by its probes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EndpointHealth {
+    pub healthy: bool,
+    // smoothed round trip of the probes, None before the first answer
+    pub latency: Option<Duration>,
+    // probes failed in a row
+    pub failures: u32,
+    pub last_probe: Option<Instant>,
+}
+
+impl Default for EndpointHealth {
+    fn default() -> Self {
+        // endpoints are tried until a probe tells otherwise
+        EndpointHealth {
+            healthy: true,
+            latency: None,
+            failures: 0,
+            last_probe: None,
+        }
+    }
+}
+
+/// Health and latency scores of the endpoints of a pool
+#[derive(Debug, Clone, Default)]
+pub struct EndpointScores {
+    policy: ProbePolicy,
+    endpoints: Vec<EndpointHealth>,
+}
+
+impl EndpointScores {
+    pub fn new(endpoints: usize, policy: ProbePolicy) -> Self {
+        EndpointScores {
+            policy,
+            endpoints: vec![EndpointHealth::default(); endpoints],
+        }
+    }
+
+    /// Result of a probe, the round trip when it succeeded
+    pub fn record_probe(&mut self, endpoint: usize, round_trip: Option<Duration>) {
+        let policy = self.policy;
+        let e = match self.endpoints.get_mut(endpoint) {
+            Some(e) => e,
+            None => return,
+        };
+        e.last_probe = Some(Instant::now());
+        match round_trip {
+            Some(rtt) => {
+                // the round trips from before an endpoint went down say nothing of it now
+                e.latency = Some(match e.latency {
+                    Some(l) if e.healthy => {
+                        l.mul_f64(1.0 - policy.latency_weight) + rtt.mul_f64(policy.latency_weight)
+                    }
+                    _ => rtt,
+                });
+                e.failures = 0;
+                e.healthy = true;
+            }
+            None => {
+                e.failures += 1;
+                if e.failures >= policy.unhealthy_after {
+                    e.healthy = false;
+                }
+            }
+        }
+    }
+
+    pub fn health(&self) -> &[EndpointHealth] {
+        &self.endpoints
+    }
+
+    /// Healthy endpoints, the fastest first and those not probed yet last
+    pub fn ranked(&self) -> Vec<usize> {
+        let mut ranked: Vec<usize> = (0..self.endpoints.len())
+            .filter(|&i| self.endpoints[i].healthy)
+            .collect();
+        ranked.sort_by_key(|&i| match self.endpoints[i].latency {
+            Some(l) => (0, l),
+            None => (1, Duration::default()),
+        });
+        ranked
+    }
+}
+
+/// Clients of several servers, probed in the background so tasks are only sent to
+/// servers which are up, e.g. while GPU boxes reboot for driver updates
+#[derive(Debug)]
+pub struct ClientPool {
+    addrs: Vec<&'static str>,
+    clients: Vec<WindowPostClient>,
+    scores: Arc<Mutex<EndpointScores>>,
+    probes: Option<JoinHandle<()>>,
+}
+
+impl ClientPool {
+    pub fn new(addrs: &[&'static str], timeout: Duration) -> Result<Self> {
+        Self::with_policy(addrs, timeout, ProbePolicy::default())
+    }
+
+    pub fn with_policy(
+        addrs: &[&'static str],
+        timeout: Duration,
+        policy: ProbePolicy,
+    ) -> Result<Self> {
+        let clients = addrs
+            .iter()
+            .map(|&a| WindowPostClient::connect_lazy(a, timeout))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ClientPool {
+            addrs: addrs.to_vec(),
+            clients,
+            scores: Arc::new(Mutex::new(EndpointScores::new(addrs.len(), policy))),
+            probes: None,
+        })
+    }
+
+    /// Pool of the servers announcing themselves on the LAN within `wait`
+    #[cfg(feature = "mdns")]
+    pub async fn discover(wait: Duration, timeout: Duration) -> Result<Self> {
+        let found = tokio::task::spawn_blocking(move || crate::discovery::discover(wait)).await??;
+        info!("discovered {} servers over mDNS", found.len());
+        // pools live as long as the program, like the addresses given to new
+        let addrs: Vec<&'static str> = found
+            .into_iter()
+            .map(|a| &*Box::leak(a.into_boxed_str()))
+            .collect();
+        Self::new(&addrs, timeout)
+    }
+
+    /// Authenticate with the token on every endpoint
+    pub fn set_token(&mut self, token: &str) -> Result<()> {
+        for c in self.clients.iter_mut() {
+            c.set_token(token)?;
+        }
+        Ok(())
+    }
+
+    pub fn addrs(&self) -> &[&'static str] {
+        &self.addrs
+    }
+
+    pub fn client(&self, endpoint: usize) -> Option<WindowPostClient> {
+        self.clients.get(endpoint).cloned()
+    }
+
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.scores.lock().health().to_vec()
+    }
+
+    /// Probe every endpoint once, all at the same time
+    pub async fn probe(&self) {
+        probe_all(&self.addrs, &self.clients, &self.scores).await
+    }
+
+    /// Probe the endpoints every policy interval until the pool is dropped. Needs a
+    /// tokio runtime.
+    pub fn spawn_probes(&mut self) {
+        if self.probes.is_some() {
+            return;
+        }
+        let addrs = self.addrs.clone();
+        let clients = self.clients.clone();
+        let scores = self.scores.clone();
+        let interval = scores.lock().policy.interval;
+        self.probes = Some(tokio::spawn(async move {
+            loop {
+                probe_all(&addrs, &clients, &scores).await;
+                tokio::time::sleep(interval).await;
+            }
+        }));
+    }
+
+    /// Lock a server for the task, trying the healthy ones fastest first. The client
+    /// of the server is returned with its index, or None when all are taken.
+    pub async fn lock_any(
+        &self,
+        task_id: &str,
+        priority: bool,
+    ) -> Option<(usize, WindowPostClient)> {
+        self.lock_any_except(task_id, priority, &[]).await
+    }
+
+    async fn lock_any_except(
+        &self,
+        task_id: &str,
+        priority: bool,
+        tried: &[usize],
+    ) -> Option<(usize, WindowPostClient)> {
+        let ranked = self.scores.lock().ranked();
+        for endpoint in ranked.into_iter().filter(|e| !tried.contains(e)) {
+            let mut client = self.clients[endpoint].clone();
+            let started = Instant::now();
+            match client.lock(task_id, priority).await {
+                Ok(WorkerStatus { status, .. }) if status == ServerStatus::Free.to_string() => {
+                    self.record(endpoint, Some(started.elapsed()));
+                    return Some((endpoint, client));
+                }
+                Ok(s) => {
+                    self.record(endpoint, Some(started.elapsed()));
+                    debug!("server {} is {}", self.addrs[endpoint], s.status);
+                }
+                // a server reserved for priority tasks is up, it just does not take this one
+                Err(e) if is_code(&e, Code::ResourceExhausted) => {
+                    self.record(endpoint, Some(started.elapsed()));
+                    debug!("server {} refused the lock: {}", self.addrs[endpoint], e);
+                }
+                // a failed lock counts as a failed probe, the server may just have gone down
+                Err(e) => {
+                    warn!("failed to lock server {}: {}", self.addrs[endpoint], e);
+                    self.record(endpoint, None);
+                }
+            }
+        }
+        None
+    }
+
+    /// Prove a window post task on the first server which takes it. When sending the task or
+    /// getting its proof fails, the server is unlocked, counted as a failed probe, and the task
+    /// goes to the next free one; a task the server refuses as invalid is not sent again.
+    /// The proof is acked once it is back.
+    pub async fn prove_window_post<P: Serialize, V: Serialize>(
+        &self,
+        task_id: &str,
+        post_config: &PoStConfig,
+        pub_inputs: &P,
+        vanilla_proofs: &V,
+        replicas_len: usize,
+        poll_interval: Duration,
+    ) -> Result<Vec<groth16::Proof<Bls12>>> {
+        let mut tried = vec![];
+        let mut last_error = "no server was free".to_string();
+        let priority = post_config.priority || post_config.typ == PoStType::Winning;
+        while let Some((endpoint, mut client)) =
+            self.lock_any_except(task_id, priority, &tried).await
+        {
+            tried.push(endpoint);
+            let addr = self.addrs[endpoint];
+            info!("task {} locked server {}", task_id, addr);
+            let proved = match client
+                .submit_window_post(
+                    task_id,
+                    post_config,
+                    pub_inputs,
+                    vanilla_proofs,
+                    replicas_len,
+                )
+                .await
+            {
+                Ok(()) => {
+                    client
+                        .wait_proof(task_id, post_config, replicas_len, poll_interval)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            match proved {
+                // wait_proof acked it, the server is free again
+                Ok(proofs) => return Ok(proofs),
+                Err(e) if is_refused(&e) => return Err(e),
+                Err(e) => {
+                    warn!(
+                        "task {} failed on {}, try the next server: {}",
+                        task_id, addr, e
+                    );
+                    self.record(endpoint, None);
+                    // the task or its lock would otherwise hold the server until they time out
+                    let _ = client.cancel(task_id).await;
+                    let _ = client.unlock(task_id).await;
+                    last_error = format!("{}: {}", addr, e);
+                }
+            }
+        }
+        Err(Error::NoUsefulPostServer(task_id.to_string(), last_error).into())
+    }
+
+    fn record(&self, endpoint: usize, round_trip: Option<Duration>) {
+        self.scores.lock().record_probe(endpoint, round_trip);
+    }
+}
+
+impl Drop for ClientPool {
+    fn drop(&mut self) {
+        if let Some(p) = self.probes.take() {
+            p.abort();
+        }
+    }
+}
+
+// the payload is wrong, another server refuses it as well
+fn is_refused(e: &anyhow::Error) -> bool {
+    is_code(e, Code::InvalidArgument)
+}
+
+fn is_code(e: &anyhow::Error, code: Code) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<Status>()
+            .map_or(false, |s| s.code() == code)
+    })
+}
+
+async fn probe_all(
+    addrs: &[&'static str],
+    clients: &[WindowPostClient],
+    scores: &Mutex<EndpointScores>,
+) {
+    let results = join_all(clients.iter().map(|c| {
+        let mut c = c.clone();
+        async move {
+            let started = Instant::now();
+            if c.is_healthy().await {
+                Some(started.elapsed())
+            } else {
+                None
+            }
+        }
+    }))
+    .await;
+    let mut scores = scores.lock();
+    for (endpoint, round_trip) in results.into_iter().enumerate() {
+        let was_healthy = scores.health()[endpoint].healthy;
+        scores.record_probe(endpoint, round_trip);
+        let healthy = scores.health()[endpoint].healthy;
+        if was_healthy != healthy {
+            info!(
+                "server {} is {}",
+                addrs[endpoint],
+                if healthy {
+                    "healthy again"
+                } else {
+                    "unhealthy"
+                }
+            );
+        }
+    }
+}
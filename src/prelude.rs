@@ -0,0 +1,47 @@
+//! Types most integrators need, `use window_post_snark_server::prelude::*`.
+//! The generated gRPC types are re-exported here, so callers do not depend on
+//! the `snark_proof_grpc` paths, which follow the layout of the proto.
+
+pub use crate::auth::{AuthConfig, BearerToken, JwtConfig, JwtKey, Role};
+pub use crate::client::{
+    client_tls_config, new_channel, new_client, new_tls_channel, new_tls_client,
+    remote_generate_window_post, verify_result, BlockingWindowPostClient, HealthProbe,
+    WindowPostClient,
+};
+pub use crate::codec::Encoding;
+pub use crate::dump::DumpConfig;
+pub use crate::error::FailureKind;
+pub use crate::error_details::{error_details, ErrorDetails};
+pub use crate::gateway::{CapacityPolicy, Gateway, GatewayConfig, HealthPolicy, Router};
+pub use crate::limits::ConnectionLimits;
+pub use crate::pool::{ClientPool, EndpointHealth, ProbePolicy};
+pub use crate::pub_inputs::{RawPublicInputs, RawPublicSector};
+pub use crate::remote::{RemotePoSt, RemoteReplica};
+pub use crate::run::run;
+pub use crate::server::{
+    server_builder, snark_task_service, ServerBuilder, SnarkServer, WindowPostSnarkServer,
+};
+pub use crate::snark_proof_grpc::snark_task_service_client::SnarkTaskServiceClient;
+pub use crate::snark_proof_grpc::snark_task_service_server::{
+    SnarkTaskService, SnarkTaskServiceServer,
+};
+pub use crate::snark_proof_grpc::{
+    AckResultRequest, BaseResponse, BatchReport, BatchTaskAccepted, BatchTaskReport, BuildInfo,
+    C2TaskRequest, CachedParams, CancelTaskRequest, Capabilities, DumpServerStateRequest,
+    EstimateTaskRequest, ExportTaskReportRequest, GatewayCapacity, GetBatchReportRequest,
+    GetCapabilitiesRequest, GetTaskResultRequest, GetTaskResultResponse, GetWorkerStatusRequest,
+    LockServerWaitRequest, ManageParamCacheRequest, ParamCacheAction, ParamCacheState,
+    PartitionProof, ReplicaInfo, ReportFormat, SectorCommitment, ServerStateDump, ServerTimeouts,
+    SetServerTimeoutsRequest, SlotStatus, SnarkTaskFrame, SnarkTaskRequestParams,
+    SubmitBatchRequest, SubmitBatchResponse, TaskError, TaskEstimate, TaskEvent, TaskReport,
+    TaskResultChunk, TenantCapacity, UnlockServerRequest, WatchTaskRequest, WorkerStatus,
+};
+pub use crate::state::ServerState;
+pub use crate::status::{ServerStatus, TaskStatus};
+
+/// Generated types whose names clash with those of filecoin-proofs, e.g. `proto::PoStConfig`
+pub mod proto {
+    pub use crate::snark_proof_grpc::{
+        ApiVersion, PayloadEncoding, PoRepConfig, PoStConfig, PoStType, TaskTimings,
+    };
+}
@@ -0,0 +1,108 @@
+//! Public inputs of a task sent as their parts: the randomness, the prover id and the
+//! comm_r of each challenged sector. The server builds the fallback::PublicInputs of
+//! them in the domain of the sector size, so clients neither build nor encode them.
+
+use crate::error::Result;
+use crate::remote::bytes32;
+use crate::snark_proof_grpc::{SectorCommitment, SnarkTaskRequestParams};
+use blstrs::Scalar as Fr;
+use filecoin_hashers::Domain;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use storage_proofs_core::sector::SectorId;
+use storage_proofs_post::fallback;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RawPublicInputs {
+    pub randomness: [u8; 32],
+    pub prover_id: [u8; 32],
+    // in the order the vanilla proofs were generated for
+    pub sectors: Vec<RawPublicSector>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawPublicSector {
+    pub sector_id: u64,
+    pub comm_r: [u8; 32],
+}
+
+/// The randomness of a PoSt as a field element. The chain randomness is 32 random bytes,
+/// its top two bits are cleared as filecoin-ffi does before calling filecoin-proofs.
+pub fn field_randomness(mut randomness: [u8; 32]) -> [u8; 32] {
+    randomness[31] &= 0x3f;
+    randomness
+}
+
+impl RawPublicInputs {
+    /// Public inputs part of a request, None when it carries them encoded in pub_in
+    pub fn from_proto(params: &SnarkTaskRequestParams) -> Result<Option<Self>> {
+        if params.pub_sectors.is_empty() {
+            return Ok(None);
+        }
+        let sectors = params
+            .pub_sectors
+            .iter()
+            .map(|s| {
+                Ok(RawPublicSector {
+                    sector_id: s.sector_id,
+                    comm_r: bytes32("pub_sectors.comm_r", &s.comm_r)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(RawPublicInputs {
+            randomness: bytes32("randomness", &params.randomness)?,
+            prover_id: bytes32("prover_id", &params.prover_id)?,
+            sectors,
+        }))
+    }
+
+    pub fn sectors_to_proto(&self) -> Vec<SectorCommitment> {
+        self.sectors
+            .iter()
+            .map(|s| SectorCommitment {
+                sector_id: s.sector_id,
+                comm_r: s.comm_r.to_vec(),
+            })
+            .collect()
+    }
+
+    /// fallback::PublicInputs in the domain of the tree the task is proved with
+    pub fn public_inputs<D: Domain>(&self) -> Result<fallback::PublicInputs<D>> {
+        let sectors = self
+            .sectors
+            .iter()
+            .map(|s| {
+                // try_from_bytes takes any 32 bytes, a comm_r out of the field would only
+                // fail the proof
+                if bool::from(Fr::from_bytes_le(&s.comm_r).is_none()) {
+                    return Err(anyhow::anyhow!(
+                        "comm_r of sector {} is not a field element",
+                        s.sector_id
+                    ));
+                }
+                Ok(fallback::PublicSector {
+                    id: SectorId::from(s.sector_id),
+                    comm_r: D::try_from_bytes(&s.comm_r)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(fallback::PublicInputs {
+            randomness: D::try_from_bytes(&field_randomness(self.randomness))?,
+            prover_id: D::try_from_bytes(&self.prover_id)?,
+            sectors,
+            k: None,
+        })
+    }
+
+    /// sha256 over the parts as sent, in place of the digest of an encoded pub_in
+    pub fn digest(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.randomness);
+        hasher.update(&self.prover_id);
+        for s in &self.sectors {
+            hasher.update(&s.sector_id.to_le_bytes());
+            hasher.update(&s.comm_r);
+        }
+        hasher.finalize().to_vec()
+    }
+}
@@ -0,0 +1,165 @@
+use crate::error::{Error, Result};
+use crate::pub_inputs::field_randomness;
+use crate::snark_proof_grpc::{ReplicaInfo, SnarkTaskRequestParams};
+use anyhow::Context;
+use filecoin_hashers::{Domain, Hasher};
+use filecoin_proofs::{PoStConfig, PrivateReplicaInfo};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::path::PathBuf;
+use storage_proofs_core::{
+    compound_proof, merkle::MerkleTreeTrait, proof::ProofScheme, sector::SectorId,
+};
+use storage_proofs_post::fallback::{self, FallbackPoSt};
+
+/// Full PoSt task, the server reads the replicas over a shared mount and generates the
+/// vanilla proofs itself, so the miner host does no proving I/O
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RemotePoSt {
+    pub randomness: [u8; 32],
+    pub prover_id: [u8; 32],
+    pub replicas: Vec<RemoteReplica>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteReplica {
+    pub sector_id: u64,
+    pub replica_path: PathBuf,
+    pub cache_dir: PathBuf,
+    pub comm_r: [u8; 32],
+}
+
+impl RemotePoSt {
+    /// Full PoSt part of a request, None when it carries vanilla proofs
+    pub fn from_proto(params: &SnarkTaskRequestParams) -> Result<Option<Self>> {
+        if params.replicas.is_empty() {
+            return Ok(None);
+        }
+        let replicas = params
+            .replicas
+            .iter()
+            .map(|r| {
+                Ok(RemoteReplica {
+                    sector_id: r.sector_id,
+                    replica_path: PathBuf::from(&r.replica_path),
+                    cache_dir: PathBuf::from(&r.cache_dir),
+                    comm_r: bytes32("replicas.comm_r", &r.comm_r)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut remote = RemotePoSt {
+            randomness: bytes32("randomness", &params.randomness)?,
+            prover_id: bytes32("prover_id", &params.prover_id)?,
+            replicas,
+        };
+        remote.sort_replicas()?;
+        Ok(Some(remote))
+    }
+
+    /// Put the replicas in sector id order, as generate_window_post takes them from its
+    /// BTreeMap, and refuse a sector sent twice. The challenges of a sector depend on its
+    /// position, so the vanilla proofs of another order would not verify.
+    pub fn sort_replicas(&mut self) -> Result<()> {
+        self.replicas.sort_by_key(|r| r.sector_id);
+        if let Some(w) = self
+            .replicas
+            .windows(2)
+            .find(|w| w[0].sector_id == w[1].sector_id)
+        {
+            return Err(Error::InvalidField(
+                "replicas".to_string(),
+                format!("sector {} is sent twice", w[0].sector_id),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn replicas_to_proto(&self) -> Vec<ReplicaInfo> {
+        self.replicas
+            .iter()
+            .map(|r| ReplicaInfo {
+                sector_id: r.sector_id,
+                replica_path: r.replica_path.display().to_string(),
+                cache_dir: r.cache_dir.display().to_string(),
+                comm_r: r.comm_r.to_vec(),
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn bytes32(field: &str, bytes: &[u8]) -> Result<[u8; 32]> {
+    bytes.try_into().map_err(|_| {
+        anyhow::Error::from(Error::InvalidField(
+            field.to_string(),
+            format!("has {} bytes, expected 32", bytes.len()),
+        ))
+    })
+}
+
+type PublicInputs<Tree> =
+    fallback::PublicInputs<<<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain>;
+
+/// Public inputs and per partition vanilla proofs of a full PoSt task, read from its replicas
+pub fn vanilla_proofs<Tree: 'static + MerkleTreeTrait>(
+    remote: &RemotePoSt,
+    post_config: &PoStConfig,
+    pub_params: &compound_proof::PublicParams<'static, FallbackPoSt<'static, Tree>>,
+    partitions: Option<usize>,
+) -> Result<(PublicInputs<Tree>, Vec<fallback::Proof<Tree::Proof>>)> {
+    let mut remote = remote.clone();
+    remote.sort_replicas()?;
+    let remote = &remote;
+    let replicas = remote
+        .replicas
+        .iter()
+        .map(|r| {
+            PrivateReplicaInfo::<Tree>::new(r.replica_path.clone(), r.comm_r, r.cache_dir.clone())
+                .with_context(|| format!("failed to open replica of sector {}", r.sector_id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // reading the trees is what goes over the network mount
+    let trees = replicas
+        .iter()
+        .zip(remote.replicas.iter())
+        .map(|(replica, r)| {
+            replica
+                .merkle_tree(post_config.sector_size)
+                .with_context(|| format!("failed to read tree of sector {}", r.sector_id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut pub_sectors = Vec::with_capacity(replicas.len());
+    let mut priv_sectors = Vec::with_capacity(replicas.len());
+    for ((replica, r), tree) in replicas
+        .iter()
+        .zip(remote.replicas.iter())
+        .zip(trees.iter())
+    {
+        pub_sectors.push(fallback::PublicSector {
+            id: SectorId::from(r.sector_id),
+            comm_r: replica.safe_comm_r()?,
+        });
+        priv_sectors.push(fallback::PrivateSector {
+            tree,
+            comm_c: replica.safe_comm_c(),
+            comm_r_last: replica.safe_comm_r_last(),
+        });
+    }
+    let pub_inputs = fallback::PublicInputs {
+        randomness: Domain::try_from_bytes(&field_randomness(remote.randomness))?,
+        prover_id: Domain::try_from_bytes(&remote.prover_id)?,
+        sectors: pub_sectors,
+        k: None,
+    };
+    let priv_inputs = fallback::PrivateInputs::<Tree> {
+        sectors: &priv_sectors,
+    };
+    let proofs = FallbackPoSt::<Tree>::prove_all_partitions(
+        &pub_params.vanilla_params,
+        &pub_inputs,
+        &priv_inputs,
+        partitions.unwrap_or(1),
+    )?;
+    Ok((pub_inputs, proofs))
+}
@@ -0,0 +1,181 @@
+use crate::tasks::TaskInfo;
+use log::info;
+use std::env;
+use std::fs;
+use std::io;
+use std::thread;
+use tokio::runtime::{Builder, Runtime};
+
+const CGROUP_V2_MEMORY_MAX: &str = "/sys/fs/cgroup/memory.max";
+const CGROUP_V1_MEMORY_LIMIT: &str = "/sys/fs/cgroup/memory/memory.limit_in_bytes";
+const CGROUP_V2_CPU_MAX: &str = "/sys/fs/cgroup/cpu.max";
+const CGROUP_V1_CPU_QUOTA: &str = "/sys/fs/cgroup/cpu/cpu.cfs_quota_us";
+const CGROUP_V1_CPU_PERIOD: &str = "/sys/fs/cgroup/cpu/cpu.cfs_period_us";
+
+pub const RUNTIME_THREAD_NAME_DEFAULT: &str = "wps-runtime";
+
+const GIB: u64 = 1 << 30;
+// rough peak memory of proving one partition, groth parameters included
+const PARTITION_MEMORY_32G: u64 = 12 * GIB;
+const PARTITION_MEMORY_64G: u64 = 24 * GIB;
+const PARTITION_MEMORY_SMALL: u64 = GIB;
+// rough peak memory of seal commit phase2 of a sector, all partitions and groth parameters
+const C2_MEMORY_32G: u64 = 192 * GIB;
+const C2_MEMORY_64G: u64 = 384 * GIB;
+const C2_MEMORY_SMALL: u64 = 2 * GIB;
+
+/// Memory and CPUs the process may use, the container budget when running in one
+#[derive(Debug, Clone, Default)]
+pub struct Resources {
+    pub memory_bytes: Option<u64>,
+    pub cpus: usize,
+}
+
+impl Resources {
+    pub fn detect() -> Self {
+        let host_cpus = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let cpus = match cgroup_cpu_limit() {
+            Some(limit) => host_cpus.min(limit.ceil().max(1.0) as usize),
+            None => host_cpus,
+        };
+        let memory_bytes = match (cgroup_memory_limit(), host_memory()) {
+            (Some(c), Some(h)) => Some(c.min(h)),
+            (c, h) => c.or(h),
+        };
+        Resources { memory_bytes, cpus }
+    }
+}
+
+/// Memory limit of the cgroup, None when unlimited or not in a cgroup
+pub fn cgroup_memory_limit() -> Option<u64> {
+    if let Ok(s) = fs::read_to_string(CGROUP_V2_MEMORY_MAX) {
+        return s.trim().parse::<u64>().ok();
+    }
+    let limit = fs::read_to_string(CGROUP_V1_MEMORY_LIMIT)
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    // cgroup v1 reports a page aligned i64::MAX when unlimited
+    if limit >= (i64::MAX as u64) & !0xfff {
+        None
+    } else {
+        Some(limit)
+    }
+}
+
+/// CPU quota of the cgroup in cpus, None when unlimited or not in a cgroup
+pub fn cgroup_cpu_limit() -> Option<f64> {
+    let (quota, period) = match fs::read_to_string(CGROUP_V2_CPU_MAX) {
+        Ok(s) => {
+            let mut fields = s.split_whitespace();
+            let quota = fields.next()?.parse::<f64>().ok()?;
+            let period = fields.next()?.parse::<f64>().ok()?;
+            (quota, period)
+        }
+        Err(_) => {
+            let quota = fs::read_to_string(CGROUP_V1_CPU_QUOTA)
+                .ok()?
+                .trim()
+                .parse::<f64>()
+                .ok()?;
+            let period = fs::read_to_string(CGROUP_V1_CPU_PERIOD)
+                .ok()?
+                .trim()
+                .parse::<f64>()
+                .ok()?;
+            (quota, period)
+        }
+    };
+    if quota <= 0.0 || period <= 0.0 {
+        None
+    } else {
+        Some(quota / period)
+    }
+}
+
+fn host_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kib = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kib * 1024)
+}
+
+/// Threads of the tokio runtime serving the grpc calls, 0 keeps the tokio default
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    pub worker_threads: usize,
+    pub max_blocking_threads: usize,
+    pub thread_name: String,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            worker_threads: 0,
+            max_blocking_threads: 0,
+            thread_name: RUNTIME_THREAD_NAME_DEFAULT.to_string(),
+        }
+    }
+}
+
+impl RuntimeConfig {
+    pub fn build(&self) -> io::Result<Runtime> {
+        let mut builder = Builder::new_multi_thread();
+        builder.enable_all().thread_name(self.thread_name.clone());
+        if self.worker_threads != 0 {
+            builder.worker_threads(self.worker_threads);
+        }
+        if self.max_blocking_threads != 0 {
+            builder.max_blocking_threads(self.max_blocking_threads);
+        }
+        builder.build()
+    }
+}
+
+/// Threads left for proving once the runtime workers have their cpus, at least one
+pub fn proving_threads(resources: &Resources, runtime: &RuntimeConfig) -> usize {
+    resources.cpus.saturating_sub(runtime.worker_threads).max(1)
+}
+
+/// Size the proving thread pool to the cpus the container may use, unless set explicitly.
+/// With a fixed number of runtime workers their cpus are kept out of it,
+/// so synthesis can not starve the lock and status calls on small hosts.
+pub fn limit_proving_threads(resources: &Resources, runtime: &RuntimeConfig) {
+    if env::var("RAYON_NUM_THREADS").is_err() {
+        let threads = proving_threads(resources, runtime);
+        info!("limit proving threads to {}", threads);
+        env::set_var("RAYON_NUM_THREADS", threads.to_string());
+    }
+}
+
+/// Share of the multiexp bellperson runs on the cpu next to the gpu, between 0 and 1.
+/// bellperson reads it once, so it has to be set before the first proof.
+pub fn set_cpu_utilization(cpu_utilization: f32) {
+    info!("bellperson cpu utilization set to {}", cpu_utilization);
+    env::set_var("BELLMAN_CPU_UTILIZATION", cpu_utilization.to_string());
+}
+
+/// Rough peak memory needed to prove the task
+pub fn task_memory_estimate(task_info: &TaskInfo) -> u64 {
+    if let Some(c2) = &task_info.c2 {
+        return match u64::from(c2.porep_config.sector_size) {
+            s if s >= 64 * GIB => C2_MEMORY_64G,
+            s if s >= 32 * GIB => C2_MEMORY_32G,
+            _ => C2_MEMORY_SMALL,
+        };
+    }
+    let post_config = match &task_info.post_config {
+        Some(p) => p,
+        None => return 0,
+    };
+    let per_partition = match u64::from(post_config.sector_size) {
+        s if s >= 64 * GIB => PARTITION_MEMORY_64G,
+        s if s >= 32 * GIB => PARTITION_MEMORY_32G,
+        _ => PARTITION_MEMORY_SMALL,
+    };
+    let partitions = task_info.partitions(post_config).unwrap_or(1).max(1);
+    per_partition * partitions as u64
+}
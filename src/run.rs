@@ -1,26 +1,99 @@
+use crate::auth::AuthConfig;
+use crate::calibrate::HardwareProfile;
+use crate::config::ServerConfig;
+use crate::dump::DumpConfig;
+use crate::error::Result;
+use crate::estimate::ProvingTimes;
+use crate::limits::ConnectionLimits;
+use crate::resources::{self, Resources, RuntimeConfig};
 use crate::server::{
-    WindowPostSnarkServer, SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT,
-    SERVER_LOCK_TIME_OUT_DEFAULT, SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT,
+    WindowPostSnarkServer, SERVER_PORT_DEFAULT, SERVER_SHUTDOWN_GRACE_PERIOD_DEFAULT,
 };
-use crate::{server, tasks, utils};
+use crate::{audit, crash, gpu, params, tasks, utils};
 use anyhow::Context;
-use log::{debug, error, info};
+use futures::FutureExt;
+use log::{debug, error, info, warn};
 use signal_hook::consts::TERM_SIGNALS;
 use signal_hook::flag;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::sync::{mpsc, oneshot};
 
-pub fn run(
-    port: String,
-    server_lock_time_out: Duration,
-    server_task_get_back_time_out: Duration,
-    server_exit_time_out_after_task_done: Duration,
-) {
-    let rt = tokio::runtime::Runtime::new()
-        .with_context(|| "failed to build new runtime")
-        .unwrap();
+// audit records older than this are not used for estimates, e.g. of a replaced gpu
+const PROVING_TIMES_AGE_MS: u64 = 30 * 24 * 3600 * 1000;
+
+/// What `run` starts the server with, from the flags of `run`
+pub struct RunSettings {
+    // the config file and the `WPS_` variables with the flags applied over them
+    pub config: ServerConfig,
+    pub task_threads: usize,
+    pub cpu_utilization: Option<f32>,
+    pub limits: ConnectionLimits,
+    pub dump: Option<DumpConfig>,
+    pub auth: AuthConfig,
+    pub priority_only: bool,
+    pub audit_dir: Option<PathBuf>,
+    pub cpu_fallback: bool,
+    pub verify_proofs: bool,
+    pub preempt_window_post: bool,
+    pub payload_dirs: Vec<PathBuf>,
+    pub mdns: bool,
+    pub runtime: RuntimeConfig,
+    pub task_queue_capacity: usize,
+}
+
+pub fn run(settings: RunSettings) -> Result<()> {
+    let RunSettings {
+        config,
+        task_threads,
+        cpu_utilization,
+        limits,
+        dump,
+        auth,
+        priority_only,
+        audit_dir,
+        cpu_fallback,
+        verify_proofs,
+        preempt_window_post,
+        payload_dirs,
+        mdns,
+        runtime,
+        task_queue_capacity,
+    } = settings;
+    let ServerConfig {
+        port,
+        timeouts,
+        params: param_config,
+        tls,
+        ..
+    } = config;
+    let shutdown_grace_period = timeouts
+        .grace_period
+        .map_or(SERVER_SHUTDOWN_GRACE_PERIOD_DEFAULT, Duration::from_secs);
+    let prove_time_out = match timeouts.prove_time_out {
+        None | Some(0) => None,
+        Some(t) => Some(Duration::from_secs(t)),
+    };
+    // inside a container only its budget is ours, not what the host has
+    let res = Resources::detect();
+    info!("resources available: {:?}", res);
+    resources::limit_proving_threads(&res, &runtime);
+    if let Some(c) = cpu_utilization {
+        resources::set_cpu_utilization(c);
+    }
+    if !param_config.cache.is_empty() {
+        if let Err(e) = params::set_parameter_cache(&param_config.cache) {
+            warn!("{}, keep the default parameter dir", e);
+        }
+    }
+
+    info!("runtime: {:?}", runtime);
+    let rt = runtime
+        .build()
+        .with_context(|| "failed to build new runtime")?;
     // listening server exit signal
     let (server_exit_tx, server_exit_rx) = oneshot::channel::<String>();
     // listening task runner exit signal
@@ -28,59 +101,196 @@ pub fn run(
 
     let (run_task_tx, run_task_rx) = mpsc::unbounded_channel::<String>();
 
-    let sv = WindowPostSnarkServer::new(run_task_tx);
-
-    if server_lock_time_out != SERVER_LOCK_TIME_OUT_DEFAULT
-        && server_task_get_back_time_out != SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT
-        && server_exit_time_out_after_task_done != SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT
-    {
-        sv.set_time_out(
-            server_lock_time_out,
-            server_task_get_back_time_out,
-            server_exit_time_out_after_task_done,
-        )
-        .unwrap();
-    } else {
-        if server_lock_time_out != SERVER_LOCK_TIME_OUT_DEFAULT {
-            sv.set_server_lock_time_out(server_lock_time_out).unwrap();
+    let port = port.unwrap_or(SERVER_PORT_DEFAULT);
+    let mut builder = WindowPostSnarkServer::builder()
+        .port(port)
+        .limits(limits)
+        .auth(auth);
+    if let Some(t) = timeouts.server_lock_time_out() {
+        builder = builder.lock_time_out(t);
+    }
+    if let Some(t) = timeouts.server_task_get_back_time_out() {
+        builder = builder.task_get_back_time_out(t);
+    }
+    if let Some(t) = timeouts.server_exit_time_out_after_task_done() {
+        builder = builder.exit_time_out_after_task_done(t);
+    }
+    let tls_enabled = tls.is_some();
+    if let Some(t) = tls {
+        let read = |f: &PathBuf| fs::read(f).with_context(|| format!("failed to read {:?}", f));
+        builder = builder.tls(read(&t.cert)?, read(&t.key)?);
+        if let Some(ca) = t.client_ca {
+            info!(
+                "only clients with a certificate signed by {:?} are served",
+                ca
+            );
+            builder = builder.client_ca(read(&ca)?);
         }
-        if server_task_get_back_time_out != SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT {
-            sv.set_server_task_get_back_time_out(server_task_get_back_time_out)
-                .unwrap();
+    }
+    let snark_server = builder.build(run_task_tx)?;
+    let sv = snark_server.server();
+
+    if task_threads != 0 {
+        sv.set_task_threads(task_threads)?;
+    }
+    sv.set_task_queue_capacity(task_queue_capacity)?;
+    sv.set_runtime(runtime)?;
+    if let Some(m) = res.memory_bytes {
+        sv.set_memory_limit(m)?;
+    }
+    let crash_report = utils::crash_report_path();
+    if let Some(r) = crash::take_previous_crash(&crash_report) {
+        warn!("previous crash detected: {}", r);
+        sv.set_previous_crash(r.to_string())?;
+    }
+    crash::install_panic_hook(crash_report);
+
+    if let Some(d) = dump {
+        info!("payloads of failed tasks will be dumped to {:?}", d.dir);
+        sv.set_dump(d)?;
+    }
+
+    if priority_only {
+        info!("server is reserved for winning PoSt and priority tasks");
+        sv.set_priority_only(true)?;
+    }
+
+    // a truncated parameter file would only show as a failure in the middle of a task
+    if let Some(m) = param_config.manifest {
+        let dir = params::parameter_cache_dir();
+        let verified = params::read_manifest(&m)
+            .and_then(|manifest| params::verify_parameters(&dir, &manifest));
+        let unusable = match verified {
+            Ok(corrupt) if corrupt.is_empty() => {
+                info!("groth parameters in {:?} verified", dir);
+                None
+            }
+            Ok(corrupt) => Some(format!("corrupt parameter files: {}", corrupt.join(", "))),
+            Err(e) => Some(format!("failed to verify parameter files: {}", e)),
+        };
+        if let Some(e) = unusable {
+            error!("{}, server will stay Unknown", e);
+            sv.set_unusable(e)?;
+        }
+    }
+
+    // the first task of a sector size would otherwise wait minutes for its parameters
+    if !param_config.preload.is_empty() {
+        let preload = param_config.preload;
+        rt.spawn_blocking(move || {
+            info!("preload groth parameters of sector sizes {:?}", preload);
+            if let Err(e) = params::preload_params(&preload) {
+                error!("failed to preload groth parameters: {:?}", e);
+            }
+        });
+    }
+
+    if !utils::gpu_support() {
+        info!("built without a gpu backend, tasks are proved on the cpu");
+    } else {
+        let slots = gpu::detect_slots();
+        gpu::log_devices(&slots);
+        sv.set_slots(slots)?;
+    }
+    if cpu_fallback && utils::gpu_support() {
+        sv.set_cpu_fallback(true)?;
+    } else if cpu_fallback {
+        warn!("--cpu-fallback is ignored, this build proves on the cpu only");
+    }
+    if verify_proofs {
+        sv.set_verify_proofs(true)?;
+    }
+    if preempt_window_post {
+        sv.set_preempt_window_post(true)?;
+    }
+    if let Some(t) = prove_time_out {
+        sv.set_prove_time_out(t)?;
+    }
+    if !payload_dirs.is_empty() {
+        // paths sent by clients are compared to these after resolving links and ..
+        let payload_dirs = payload_dirs
+            .iter()
+            .filter_map(|d| match d.canonicalize() {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    warn!("skip payload dir {:?}: {}", d, e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        info!("vanilla proofs may be read from {:?}", payload_dirs);
+        sv.set_payload_dirs(payload_dirs)?;
+    }
+    if let Some(d) = audit_dir {
+        info!("audit records of finished tasks will be written to {:?}", d);
+        // estimates start from the tasks of earlier runs instead of from nothing
+        if d.is_dir() {
+            let since =
+                utils::unix_millis(Some(SystemTime::now())).saturating_sub(PROVING_TIMES_AGE_MS);
+            match audit::read_audit_records(&d, since, 0) {
+                Ok(records) => {
+                    sv.set_proving_times(ProvingTimes::from_audit_records(&records))?;
+                }
+                Err(e) => warn!("failed to read audit records for estimates: {}", e),
+            }
         }
-        if server_exit_time_out_after_task_done != SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT {
-            sv.set_server_exit_time_out_after_task_done(server_exit_time_out_after_task_done)
-                .unwrap();
+        sv.set_audit_dir(d)?;
+    }
+    let profile_path = utils::profile_path();
+    if profile_path.exists() {
+        match HardwareProfile::load(&profile_path) {
+            Ok(profile) => {
+                info!(
+                    "hardware profile {:?}: {:.0} constraints/s",
+                    profile_path, profile.constraints_per_sec
+                );
+                sv.set_hardware_profile(profile)?;
+            }
+            Err(e) => warn!("failed to load hardware profile: {:?}", e),
         }
-    };
+    }
 
     debug!("server_info:{:?}", sv.server_info);
 
     let sv_i = sv.server_info.clone();
 
-    let sv_handle = rt.spawn(server::run_server(server_exit_rx, sv, port));
+    let sv_handle = rt.spawn(snark_server.serve(server_exit_rx.map(drop)));
 
-    let task_handle = rt.spawn(tasks::run_task(task_exit_rx, run_task_rx, sv_i));
+    let task_handle = rt.spawn(tasks::run_task(task_exit_rx, run_task_rx, sv_i.clone()));
+    // withdrawn when dropped at the end of run
+    let _announcement = announce(mdns, port, tls_enabled);
+    if prove_time_out.is_some() {
+        rt.spawn(tasks::run_prove_watchdog(sv_i.clone()));
+    }
 
     // listen exit signal
     rt.block_on(listen_exit_signal());
 
+    // stop granting locks and accepting tasks, the running task may still finish
+    sv_i.lock().start_draining();
+
     // stop task
     match task_exit_tx.send("exit".to_string()) {
         Ok(_) => {}
         Err(e) => {
             error!("{}", e);
-            return;
+            return Ok(());
         }
     };
 
-    // wait task stop
+    // wait task stop, but not longer than the grace period
     rt.block_on(async {
-        match task_handle.await {
-            Ok(_) => {}
-            Err(e) => {
+        match tokio::time::timeout(shutdown_grace_period, task_handle).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
                 error!("{}", e)
             }
+            Err(_) => {
+                warn!(
+                    "task worker did not stop within {:?}, exit anyway",
+                    shutdown_grace_period
+                )
+            }
         }
     });
 
@@ -89,14 +299,17 @@ pub fn run(
         Ok(_) => {}
         Err(e) => {
             error!("{}", e);
-            return;
+            return Ok(());
         }
     };
 
     // wait server exit
     rt.block_on(async {
         match sv_handle.await {
-            Ok(_) => {}
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                error!("server failed with error: {:?}", e)
+            }
             Err(e) => {
                 error!("{}", e)
             }
@@ -106,7 +319,30 @@ pub fn run(
     // del file lock
     utils::del_file_lock();
     rt.shutdown_background();
-    info!("server main process exited")
+    info!("server main process exited");
+    Ok(())
+}
+
+#[cfg(feature = "mdns")]
+fn announce(mdns: bool, port: u16, tls: bool) -> Option<crate::discovery::Announcement> {
+    if !mdns {
+        return None;
+    }
+    match crate::discovery::Announcement::start(port, tls) {
+        Ok(a) => Some(a),
+        Err(e) => {
+            warn!("failed to announce the server over mDNS: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "mdns"))]
+fn announce(mdns: bool, _port: u16, _tls: bool) -> Option<()> {
+    if mdns {
+        warn!("server was built without the mdns feature, it is not announced");
+    }
+    None
 }
 
 async fn listen_exit_signal() {
@@ -1,356 +1,2803 @@
+use crate::audit;
+use crate::auth::{self, AuthConfig, Role};
+use crate::batch::{Batch, BatchOutcome, Batches};
+use crate::c2::set_c2_task_info;
+use crate::calibrate::HardwareProfile;
+use crate::codec;
+use crate::crash;
+use crate::dump::DumpConfig;
 use crate::error;
+use crate::error::{FailureKind, RecentErrors};
+use crate::error_details::{status_with_details, ErrorDetails};
+use crate::estimate::ProvingTimes;
+use crate::gpu::{self, SlotDevice};
+use crate::limits::{self, ConnectionLimits};
+use crate::params;
+use crate::resources::{self, RuntimeConfig};
+use crate::snark_proof_grpc;
+use crate::snark_proof_grpc::snark_task_frame::Frame;
 use crate::snark_proof_grpc::snark_task_service_server::{
     SnarkTaskService, SnarkTaskServiceServer,
 };
 use crate::snark_proof_grpc::{
-    BaseResponse, GetTaskResultRequest, GetTaskResultResponse, GetWorkerStatusRequest,
-    SnarkTaskRequestParams, UnlockServerRequest,
+    AckResultRequest, BaseResponse, BatchReport, BatchTaskAccepted, BatchTaskReport, C2TaskRequest,
+    CancelTaskRequest, Capabilities, DumpServerStateRequest, EstimateTaskRequest,
+    ExportTaskReportRequest, GetBatchReportRequest, GetCapabilitiesRequest, GetTaskResultRequest,
+    GetTaskResultResponse, GetWorkerStatusRequest, LockServerWaitRequest, ManageParamCacheRequest,
+    ParamCacheAction, ParamCacheState, PartitionProof, ReportFormat, ServerStateDump,
+    ServerTimeouts, SetServerTimeoutsRequest, SlotStatus, SnarkTaskFrame, SnarkTaskRequestParams,
+    SubmitBatchRequest, SubmitBatchResponse, TaskError, TaskEstimate, TaskEvent, TaskReport,
+    TaskResultChunk, TaskTimings, UnlockServerRequest, WatchTaskRequest, WorkerStatus,
 };
+use crate::state::ServerState;
 use crate::status::{ServerStatus, TaskStatus};
 use crate::tasks;
-use crate::tasks::{set_task_info, TaskInfo};
-use futures::FutureExt;
-use log::info;
+use crate::tasks::{set_task_info, CancelToken, TaskInfo};
+use crate::trace::TraceContext;
+use crate::utils;
+use anyhow::Context;
+use filecoin_proofs::PoStType;
+use futures::{Future, Stream, StreamExt};
+use log::{error, info};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::oneshot;
-use tonic::transport::Server;
-use tonic::{Request, Response, Status};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use tokio::task::spawn_blocking;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{
+    Certificate as TlsCertificate, Identity as TlsIdentity, Server, ServerTlsConfig,
+};
+use tonic::{Code, Request, Response, Status, Streaming};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use uuid::Uuid;
 
 pub const SERVER_LOCK_TIME_OUT_DEFAULT: Duration = Duration::from_secs(10);
 pub const SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT: Duration = Duration::from_secs(60);
 pub const SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT: Duration = Duration::from_secs(300);
+pub const SERVER_LOCK_TIME_OUT_MAX_DEFAULT: Duration = Duration::from_secs(300);
+pub const SERVER_LOCK_WAITER_TIME_OUT_DEFAULT: Duration = Duration::from_secs(30);
+pub const SERVER_LOCK_WAIT_TIME_OUT_DEFAULT: Duration = Duration::from_secs(60);
+pub const SERVER_LOCK_WAIT_TIME_OUT_MAX: Duration = Duration::from_secs(600);
+pub const SERVER_SHUTDOWN_GRACE_PERIOD_DEFAULT: Duration = Duration::from_secs(600);
+// shortest time out SetServerTimeouts takes, shorter ones expire before a client can act
+pub const SERVER_TIME_OUT_MIN: Duration = Duration::from_secs(1);
+pub const SERVER_TASK_ID_REUSE_TIME_OUT_DEFAULT: Duration = Duration::from_secs(3600);
+// task errors kept for the status
+pub const SERVER_RECENT_ERRORS_DEFAULT: usize = 16;
+// tasks waiting behind the running one, further ones are refused
+pub const SERVER_TASK_QUEUE_CAPACITY_DEFAULT: usize = 64;
+pub const SERVER_PORT_DEFAULT: u16 = 50051;
+// lock requests for this slot take any free one
+pub const SLOT_ANY: u32 = 0;
+// how soon the health service reports a change of the server state
+const HEALTH_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// how soon the server moves on from a finished batch task
+const BATCH_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// well below the 4MiB gRPC clients accept in one message by default
+pub const RESULT_CHUNK_LEN: usize = 1 << 20;
+// payloads of a task, sent in one message or in chunks; tonic does not bound the size
+// of a request, so the server does
+pub const TASK_PAYLOAD_MAX_LEN: usize = 2 << 30;
 
-#[derive(Debug)]
+type TaskWatcher = UnboundedSender<Result<TaskEvent, Status>>;
+type PartitionProofStream =
+    Pin<Box<dyn Stream<Item = Result<PartitionProof, Status>> + Send + Sync + 'static>>;
+type TaskResultChunkStream =
+    Pin<Box<dyn Stream<Item = Result<TaskResultChunk, Status>> + Send + Sync + 'static>>;
+
+#[derive(Debug, Clone)]
 pub struct WindowPostSnarkServer {
     pub server_info: Arc<Mutex<ServerInfo>>,
     task_run_tx: UnboundedSender<String>,
+    // that of the server info, to wait on without holding its lock
+    lock_released: Arc<Notify>,
+}
+
+/// A client waiting in line for the lock
+#[derive(Debug, Clone)]
+pub struct LockWaiter {
+    pub task_id: String,
+    // index of the slot it waits for, None for any
+    pub slot: Option<usize>,
+    pub lock_time_out: Duration,
+    pub last_seen: Instant,
 }
 
+/// A device the server proves one task at a time on, locked on its own. Slots are numbered
+/// from 1 on the wire and indexed from 0 in `ServerInfo::slots`.
 #[derive(Debug)]
-pub struct ServerInfo {
+pub struct Slot {
     pub task_info: tasks::TaskInfo,
     pub status: ServerStatus,
-    pub last_update_time: Instant,
+    // lock duration granted to the current lock holder
+    pub lock_time_out: Duration,
+    pub error: String,
+    // whether the last failed task may succeed when sent again
+    pub error_kind: FailureKind,
+    // causes of the last task error, outermost first
+    pub error_stack: Vec<String>,
+    // the task timed out but its prove has not returned yet
+    pub proving_hung: bool,
+    pub device: SlotDevice,
+}
+
+impl Slot {
+    pub fn new(device: SlotDevice) -> Self {
+        Slot {
+            task_info: tasks::TaskInfo::default(),
+            status: ServerStatus::default(),
+            lock_time_out: SERVER_LOCK_TIME_OUT_DEFAULT,
+            error: String::default(),
+            error_kind: FailureKind::default(),
+            error_stack: vec![],
+            proving_hung: false,
+            device,
+        }
+    }
+
+    /// Whether the slot holds the task, locked for it or proving it
+    pub fn holds(&self, task_id: &str) -> bool {
+        self.status != ServerStatus::Free
+            && self.status != ServerStatus::Unknown
+            && self.task_info.task_id == task_id
+    }
+
+    /// Whether the worker of the slot has yet to start proving its task
+    pub fn has_task_to_start(&self) -> bool {
+        self.status == ServerStatus::Working
+            && self.task_info.task_status == TaskStatus::Ready
+            && self.task_info.started_at.is_none()
+    }
+
+    /// Time until the lock of the slot expires
+    pub fn lock_remaining(&self, now: Instant) -> Duration {
+        let locked_for = self.task_info.timers.locked_for(now).unwrap_or_default();
+        self.lock_time_out
+            .checked_sub(locked_for)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerInfo {
+    // one per GPU the server proves on at once, see gpu::slot_devices
+    pub slots: Vec<Slot>,
     pub server_lock_time_out: Duration,
+    pub server_lock_time_out_max: Duration,
     pub server_task_get_back_time_out: Duration,
     pub server_exit_time_out_after_task_done: Duration,
-    pub error: String,
+    pub task_queue: tasks::TaskQueue,
+    pub task_watchers: HashMap<String, Vec<TaskWatcher>>,
+    pub lock_waiters: VecDeque<LockWaiter>,
+    // waiters which neither asked again nor watch their task within this time are forgotten
+    pub lock_waiter_time_out: Duration,
+    // payloads of failed tasks are dumped here when set
+    pub dump: Option<DumpConfig>,
+    // set on shutdown, no more locks are granted and no more tasks accepted
+    pub draining: bool,
+    // memory tasks may use, tasks estimated to need more are refused
+    pub memory_limit: Option<u64>,
+    // threads a task may prove with, 0 means no limit
+    pub task_threads: usize,
+    // the runtime serving the grpc calls, only reported as it is built before the server
+    pub runtime: RuntimeConfig,
+    pub task_owners: tasks::TaskOwners,
+    // a task id can be taken by another peer once unused for this long
+    pub task_id_reuse_time_out: Duration,
+    pub recent_errors: RecentErrors,
+    // the previous run panicked, see crash::CrashReport
+    pub previous_crash: String,
+    // credentials callers have to present, none by default
+    pub auth: AuthConfig,
+    // reserved for winning PoSt and priority tasks, idle rather than proving others
+    pub priority_only: bool,
+    // an audit record of every finished task is written here when set
+    pub audit_dir: Option<PathBuf>,
+    // prove tasks again on the cpu when proving on the gpu failed
+    pub cpu_fallback: bool,
+    // verify every proof before returning it, for gpus suspected to be flaky
+    pub verify_proofs: bool,
+    // a task proving for longer than this is failed, None means no limit
+    pub prove_time_out: Option<Duration>,
+    // canonical dirs on shared storage tasks may send the path of their vanilla proofs in
+    pub payload_dirs: Vec<PathBuf>,
+    // how long tasks took to prove here, for EstimateTask
+    pub proving_times: ProvingTimes,
+    // measured by calibrate, for sector sizes no task was proved of yet
+    pub hardware_profile: Option<HardwareProfile>,
+    // tasks submitted with SubmitBatch, and their outcomes
+    pub batches: Batches,
+    // window PoSt tasks are proved partition by partition and yield to winning PoSt
+    pub preempt_window_post: bool,
+    // when the server was created, for the uptime in WorkerStatus
+    pub up_since: Instant,
+    // woken whenever the server is released, for clients blocked in LockServerWait
+    pub lock_released: Arc<Notify>,
 }
 
 impl Default for ServerInfo {
     fn default() -> Self {
         ServerInfo {
-            task_info: tasks::TaskInfo::default(),
-            status: ServerStatus::default(),
-            last_update_time: Instant::now(),
+            slots: vec![Slot::new(SlotDevice::shared())],
             server_lock_time_out: SERVER_LOCK_TIME_OUT_DEFAULT,
+            server_lock_time_out_max: SERVER_LOCK_TIME_OUT_MAX_DEFAULT,
             server_task_get_back_time_out: SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT,
             server_exit_time_out_after_task_done: SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT,
-            error: String::default(),
+            task_queue: tasks::TaskQueue::with_capacity(SERVER_TASK_QUEUE_CAPACITY_DEFAULT),
+            task_watchers: HashMap::new(),
+            lock_waiters: VecDeque::new(),
+            lock_waiter_time_out: SERVER_LOCK_WAITER_TIME_OUT_DEFAULT,
+            dump: None,
+            draining: false,
+            memory_limit: None,
+            task_threads: 0,
+            runtime: RuntimeConfig::default(),
+            task_owners: tasks::TaskOwners::default(),
+            task_id_reuse_time_out: SERVER_TASK_ID_REUSE_TIME_OUT_DEFAULT,
+            recent_errors: RecentErrors::new(SERVER_RECENT_ERRORS_DEFAULT),
+            previous_crash: String::default(),
+            auth: AuthConfig::default(),
+            priority_only: false,
+            audit_dir: None,
+            cpu_fallback: false,
+            verify_proofs: false,
+            prove_time_out: None,
+            payload_dirs: vec![],
+            proving_times: ProvingTimes::default(),
+            hardware_profile: None,
+            batches: Batches::default(),
+            preempt_window_post: false,
+            up_since: Instant::now(),
+            lock_released: Arc::new(Notify::new()),
         }
     }
 }
 
-impl WindowPostSnarkServer {
-    pub fn new(task_run_tx: UnboundedSender<String>) -> Self {
-        WindowPostSnarkServer {
-            server_info: Arc::new(Mutex::new(ServerInfo::default())),
-            task_run_tx,
+impl ServerInfo {
+    /// Status of the server as a whole: Free while any slot is, else Locked while a lock
+    /// may still end soon, else Working while any slot proves, else Unknown
+    pub fn status(&self) -> ServerStatus {
+        for status in &[
+            ServerStatus::Free,
+            ServerStatus::Locked,
+            ServerStatus::Working,
+        ] {
+            if self.slots.iter().any(|s| &s.status == status) {
+                return status.clone();
+            }
         }
+        ServerStatus::Unknown
     }
 
-    pub fn set_time_out(
-        &self,
-        server_lock_time_out: Duration,
-        server_task_get_back_time_out: Duration,
-        server_exit_time_out_after_task_done: Duration,
-    ) -> anyhow::Result<()> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(anyhow::Error::msg(e.to_string()));
-            }
-        };
-        si.server_lock_time_out = server_lock_time_out;
-        si.server_task_get_back_time_out = server_task_get_back_time_out;
-        si.server_exit_time_out_after_task_done = server_exit_time_out_after_task_done;
-        Ok(())
+    /// Slot holding the task, locked for it or proving it
+    pub fn slot_of(&self, task_id: &str) -> Option<usize> {
+        self.slots.iter().position(|s| s.holds(task_id))
     }
 
-    pub fn set_server_lock_time_out(&self, time_out: Duration) -> anyhow::Result<()> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(anyhow::Error::msg(e.to_string()));
+    pub fn free_slot(&self) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|s| s.status == ServerStatus::Free)
+    }
+
+    /// Whether every slot waits for a timed out task to return, nothing can be proved then
+    pub fn is_stuck(&self) -> bool {
+        self.slots.iter().all(|s| s.proving_hung)
+    }
+
+    /// Ids of the tasks the slots prove, for messages
+    pub fn working_task_ids(&self) -> Vec<String> {
+        self.slots
+            .iter()
+            .filter(|s| s.status == ServerStatus::Working)
+            .map(|s| s.task_info.task_id.clone())
+            .collect()
+    }
+
+    /// Lock the slot for the task with a fresh task info
+    pub fn lock_for(&mut self, slot: usize, task_id: String, lock_time_out: Duration) {
+        let s = &mut self.slots[slot];
+        s.task_info.wipe();
+        s.task_info = TaskInfo::default();
+        s.status = ServerStatus::Locked;
+        s.task_info.task_id = task_id;
+        s.task_info.timers.locked_at = Some(Instant::now());
+        s.lock_time_out = lock_time_out;
+    }
+
+    /// Remember how long the task which just finished on the slot took to prove, for
+    /// EstimateTask
+    pub fn record_proving_time(&mut self, slot: usize) {
+        let t = &self.slots[slot].task_info;
+        // proofs made on the cpu say nothing of proving on the gpu
+        if t.degraded {
+            return;
+        }
+        if let Some(c) = &t.post_config {
+            self.proving_times.record(
+                u64::from(c.sector_size),
+                t.timings.partitions,
+                t.timings.decode + t.timings.load_params + t.timings.prove,
+            );
+        }
+    }
+
+    /// Proving time of a task from the tasks proved here, or else from the calibration
+    pub fn estimate_prove_time(&self, task_info: &TaskInfo) -> Option<Duration> {
+        let post_config = task_info.post_config.as_ref()?;
+        let partitions = task_info.partitions(post_config).unwrap_or(1);
+        let sector_size = u64::from(post_config.sector_size);
+        match self.proving_times.estimate(sector_size, partitions) {
+            Some(e) => Some(e.prove),
+            None => self
+                .hardware_profile
+                .as_ref()
+                .and_then(|p| p.estimate(sector_size, partitions)),
+        }
+    }
+
+    /// Tasks a task submitted now would wait behind, the running ones included when no
+    /// slot is free, and the estimated wait. Queued tasks go to the slot free first. The
+    /// flag is false when a task ahead can not be estimated, e.g. one only locked for, the
+    /// wait counts the others then.
+    pub fn queue_wait(&self, now: SystemTime) -> (usize, Duration, bool) {
+        let mut known = true;
+        // when each slot able to prove is free, from now
+        let mut free_in = vec![];
+        for s in &self.slots {
+            match s.status {
+                ServerStatus::Free => free_in.push(Duration::ZERO),
+                // the task locked for is not sent yet, its size is not known
+                ServerStatus::Locked => {
+                    known = false;
+                    free_in.push(Duration::ZERO);
+                }
+                ServerStatus::Working => {
+                    let t = &s.task_info;
+                    if t.task_status != TaskStatus::Ready {
+                        free_in.push(Duration::ZERO);
+                        continue;
+                    }
+                    match self.estimate_prove_time(t) {
+                        Some(d) => {
+                            let proving_for = t
+                                .started_at
+                                .and_then(|s| now.duration_since(s).ok())
+                                .unwrap_or_default();
+                            free_in.push(d.saturating_sub(proving_for));
+                        }
+                        None => {
+                            known = false;
+                            free_in.push(Duration::ZERO);
+                        }
+                    }
+                }
+                ServerStatus::Unknown => {}
+            }
+        }
+        let busy = self
+            .slots
+            .iter()
+            .filter(|s| s.status != ServerStatus::Free && s.status != ServerStatus::Unknown)
+            .count();
+        let mut tasks_ahead = self.task_queue.len();
+        if free_in.is_empty() {
+            return (tasks_ahead, Duration::ZERO, false);
+        }
+        if self.free_slot().is_none() {
+            tasks_ahead += busy;
+        }
+        for t in self.task_queue.iter() {
+            let i = free_in
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, d)| **d)
+                .map(|(i, _)| i)
+                .unwrap_or_default();
+            match self.estimate_prove_time(t) {
+                Some(d) => free_in[i] += d,
+                None => known = false,
             }
+        }
+        let wait = free_in.into_iter().min().unwrap_or_default();
+        (tasks_ahead, wait, known)
+    }
+
+    /// Keep the outcome of the task which just finished on the slot with its batch, if it
+    /// came in one
+    pub fn finish_batch_task(&mut self, slot: usize) {
+        let s = &self.slots[slot];
+        let batch_id = match &s.task_info.batch_id {
+            Some(b) => b.clone(),
+            None => return,
         };
-        si.server_lock_time_out = time_out;
-        Ok(())
+        let outcome = if s.task_info.task_status == TaskStatus::Done {
+            BatchOutcome::new(&s.task_info, "", FailureKind::default())
+        } else if s.task_info.task_status == TaskStatus::Cancelled {
+            BatchOutcome::rejected(TaskStatus::Cancelled, "task cancelled by its client")
+        } else {
+            BatchOutcome::new(&s.task_info, &s.error, s.error_kind)
+        };
+        let task_id = s.task_info.task_id.clone();
+        self.batches.finish(&batch_id, &task_id, outcome);
     }
 
-    pub fn set_server_task_get_back_time_out(&self, time_out: Duration) -> anyhow::Result<()> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(anyhow::Error::msg(e.to_string()));
-            }
+    /// Ask a running window PoSt task to stop at its next partition for the winning PoSt
+    /// just queued, if the server proves window PoSt preemptibly. Only tasks proved in the
+    /// process of the server are stopped between partitions.
+    pub fn preempt_for_winning(&mut self, winning_task_id: &str) {
+        if !self.preempt_window_post {
+            return;
+        }
+        let slot = self.slots.iter().position(|s| {
+            let t = &s.task_info;
+            s.device.in_process()
+                && s.status == ServerStatus::Working
+                && t.task_status == TaskStatus::Ready
+                && matches!(&t.post_config, Some(c) if c.typ == PoStType::Window)
+        });
+        let t = match slot {
+            Some(i) => &self.slots[i].task_info,
+            None => return,
         };
-        si.server_task_get_back_time_out = time_out;
-        Ok(())
+        if t.preempt.is_cancelled() {
+            return;
+        }
+        let task_id = t.task_id.clone();
+        info!(
+            "task {} yields to winning PoSt {} at its next partition",
+            task_id, winning_task_id
+        );
+        t.preempt.cancel();
+        self.notify_task_event(
+            &task_id,
+            &TaskStatus::Ready,
+            0,
+            &format!("yielding to winning PoSt {}", winning_task_id),
+        );
     }
 
-    pub fn set_server_exit_time_out_after_task_done(
-        &self,
-        time_out: Duration,
-    ) -> anyhow::Result<()> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(anyhow::Error::msg(e.to_string()));
+    /// The task running on the slot stopped between two partitions for a winning PoSt.
+    /// Run the winning PoSt and queue the task ahead of the normal tasks, it resumes from
+    /// its checkpoint. Without a winning PoSt queued any more, e.g. cancelled meanwhile,
+    /// the task runs on.
+    pub fn preempt_running_task(&mut self, slot: usize) {
+        let mut task_info = std::mem::take(&mut self.slots[slot].task_info);
+        if let Some(payload) = task_info.checkpoint.take_payload() {
+            task_info.set_payload(payload);
+        }
+        task_info.preempt = CancelToken::default();
+        task_info.started_at = None;
+        let winning = match self.task_queue.take_winning() {
+            Some(t) => t,
+            None => {
+                self.slots[slot].task_info = task_info;
+                return;
             }
         };
-        si.server_exit_time_out_after_task_done = time_out;
-        Ok(())
+        // another winning PoSt waits as well, yield to it right after this one
+        if self.task_queue.iter().any(|t| t.is_winning()) {
+            task_info.preempt.cancel();
+        }
+        let task_id = task_info.task_id.clone();
+        let winning_task_id = winning.task_id.clone();
+        let position = self.task_queue.resume(task_info);
+        info!(
+            "task {} preempted by winning PoSt {}, queued at position {}",
+            task_id, winning_task_id, position
+        );
+        self.slots[slot].task_info = winning;
+        self.notify_task_event(
+            &task_id,
+            &TaskStatus::Queued,
+            position,
+            &format!("preempted by winning PoSt {}", winning_task_id),
+        );
+        self.notify_task_event(&winning_task_id, &TaskStatus::Ready, 0, "dequeued to run");
+        self.notify_queue_positions("moved forward in queue");
     }
 
-    fn do_task(&self, task_params: &SnarkTaskRequestParams) -> Result<(), Status> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(Status::aborted(e.to_string()));
-            }
+    /// Start the head of the queue on the slot if nothing else holds it, return whether a
+    /// task was started. The worker has to be told then.
+    pub fn run_next_queued_task(&mut self, slot: usize) -> bool {
+        if self.slots[slot].status != ServerStatus::Free {
+            return false;
+        }
+        let task_info = match self.task_queue.pop() {
+            Some(t) => t,
+            None => return false,
         };
-        // Determine whether the request to execute the task came from the locked task
-        let task_id = task_params.task_id.clone();
-        if si.status == ServerStatus::Locked && si.task_info.task_id == task_id {
-            // set task info
-            let task_info = set_task_info(task_params);
-            // set server info
-            si.task_info = task_info;
-            si.status = ServerStatus::Working;
-            si.last_update_time = Instant::now();
-            match self.task_run_tx.send("ok".to_string()) {
-                Ok(_) => Ok(()),
-                Err(s) => Err(Status::cancelled(s.0)),
-            }
+        let task_id = task_info.task_id.clone();
+        info!("start to run queued task {} on slot {}", task_id, slot + 1);
+        self.slots[slot].task_info = task_info;
+        self.slots[slot].status = ServerStatus::Working;
+        self.notify_task_event(&task_id, &TaskStatus::Ready, 0, "dequeued to run");
+        self.notify_queue_positions("moved forward in queue");
+        true
+    }
+
+    /// The slot is free again: queued tasks run first, otherwise the longest waiting
+    /// client gets the lock. Returns whether a queued task was started, as
+    /// `run_next_queued_task` does.
+    pub fn release(&mut self, slot: usize) -> bool {
+        // the gpu is still busy with the timed out task, run nothing else on it
+        if self.slots[slot].proving_hung {
+            self.slots[slot].status = ServerStatus::Unknown;
+            return false;
+        }
+        self.slots[slot].status = ServerStatus::Free;
+        self.lock_released.notify_waiters();
+        if self.run_next_queued_task(slot) {
+            return true;
+        }
+        self.prune_lock_waiters();
+        let waiter = self
+            .lock_waiters
+            .iter()
+            .position(|w| w.slot.map_or(true, |s| s == slot))
+            .and_then(|i| self.lock_waiters.remove(i));
+        if let Some(waiter) = waiter {
+            info!(
+                "hand lock of slot {} over to waiting task: {}",
+                slot + 1,
+                waiter.task_id
+            );
+            self.lock_for(slot, waiter.task_id.clone(), waiter.lock_time_out);
+            self.notify_task_event(&waiter.task_id, &TaskStatus::None, 0, "lock granted");
+            self.notify_lock_wait_positions("moved forward in lock queue");
+        }
+        false
+    }
+
+    /// The task which timed out on the slot returned at last. Once its client fetched the
+    /// failure the slot waited Unknown for this, it is released now. Returns whether a
+    /// queued task was started.
+    pub fn finish_hung_task(&mut self, slot: usize) -> bool {
+        self.slots[slot].proving_hung = false;
+        if self.slots[slot].status == ServerStatus::Unknown {
+            return self.release(slot);
+        }
+        false
+    }
+
+    /// Whether the health service reports the server as serving: it takes tasks, or
+    /// will once a running one is done
+    pub fn is_serving(&self) -> bool {
+        !self.draining && self.status() != ServerStatus::Unknown
+    }
+
+    /// Lock duration to grant for a request, 0 means the default
+    pub fn lock_time_out_for(&self, requested: Duration) -> Duration {
+        if requested.is_zero() {
+            self.server_lock_time_out
         } else {
-            match si.status {
-                ServerStatus::Locked => Err(Status::cancelled(
-                    "server was locked by another task, can not be used now",
-                )),
-                ServerStatus::Free => Err(Status::cancelled(
-                    "server should be locked until task is executed",
-                )),
-                ServerStatus::Working => Err(Status::cancelled(
-                    "server is working on another task, can not be used now",
-                )),
-                ServerStatus::Unknown => {
-                    Err(Status::cancelled("server is Unknown, can not be used now"))
-                }
-            }
+            requested.min(self.server_lock_time_out_max)
         }
     }
 
-    fn lock_server_if_free(&self, task_id: String) -> Result<ServerStatus, Status> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => return Err(Status::aborted(e.to_string())),
-        };
-        match si.status {
-            ServerStatus::Free => {
-                si.task_info = TaskInfo::default();
-                // server will be locked by client with task_id here at first
-                si.status = ServerStatus::Locked;
-                si.task_info.task_id = task_id.clone();
-                si.last_update_time = Instant::now();
-                Ok(ServerStatus::Free)
-            }
-            ServerStatus::Locked => {
-                // if locked too long and still not received task from miner, unlock it
-                if Instant::now().duration_since(si.last_update_time) > si.server_lock_time_out {
-                    si.task_info = TaskInfo::default();
-                    si.status = ServerStatus::Locked;
-                    si.task_info.task_id = task_id.clone();
-                    si.last_update_time = Instant::now();
-                    Ok(ServerStatus::Free)
-                } else {
-                    Ok(ServerStatus::Locked)
-                }
+    /// Put the task in line for the lock of the slot, or of any slot when None, or refresh
+    /// it when already waiting. Returns its position starting from 1.
+    pub fn wait_for_lock(
+        &mut self,
+        task_id: String,
+        slot: Option<usize>,
+        lock_time_out: Duration,
+    ) -> usize {
+        self.prune_lock_waiters();
+        if let Some(index) = self.lock_waiters.iter().position(|w| w.task_id == task_id) {
+            let waiter = &mut self.lock_waiters[index];
+            waiter.slot = slot;
+            waiter.lock_time_out = lock_time_out;
+            waiter.last_seen = Instant::now();
+            return index + 1;
+        }
+        self.lock_waiters.push_back(LockWaiter {
+            task_id,
+            slot,
+            lock_time_out,
+            last_seen: Instant::now(),
+        });
+        self.lock_waiters.len()
+    }
+
+    /// Position of the task in the lock queue starting from 1
+    pub fn lock_wait_position(&self, task_id: &str) -> Option<usize> {
+        self.lock_waiters
+            .iter()
+            .position(|w| w.task_id == task_id)
+            .map(|i| i + 1)
+    }
+
+    /// Take the task out of the lock queue, return whether it was waiting
+    pub fn leave_lock_queue(&mut self, task_id: &str) -> bool {
+        match self.lock_wait_position(task_id) {
+            Some(position) => {
+                self.lock_waiters.remove(position - 1);
+                self.notify_lock_wait_positions("moved forward in lock queue");
+                true
             }
-            ServerStatus::Working => {
-                // if miner do not get result back in SERVER_TASK_GET_BACK_TIME_OUT after task done or failed, drop task
-                if (si.task_info.task_status == TaskStatus::Done
-                    && Instant::now().duration_since(si.last_update_time)
-                        >= si.server_task_get_back_time_out)
-                    || (si.task_info.task_status == TaskStatus::Failed
-                        && Instant::now().duration_since(si.last_update_time)
-                            >= si.server_task_get_back_time_out)
-                {
-                    si.task_info = TaskInfo::default();
-                    si.status = ServerStatus::Locked;
-                    si.task_info.task_id = task_id.clone();
-                    si.last_update_time = Instant::now();
-                    Ok(ServerStatus::Free)
-                } else {
-                    Ok(ServerStatus::Working)
-                }
+            None => false,
+        }
+    }
+
+    /// Forget batches complete for longer than the get back time out, whether or not
+    /// their report was asked for
+    pub fn prune_batches(&mut self, now: Instant) {
+        let time_out = self.server_task_get_back_time_out;
+        self.batches.prune(now, time_out);
+    }
+
+    /// Forget waiters which gave up, so they will not be handed a lock nobody uses
+    pub fn prune_lock_waiters(&mut self) {
+        let time_out = self.lock_waiter_time_out;
+        let watchers = &self.task_watchers;
+        self.lock_waiters.retain(|w| {
+            Instant::now().duration_since(w.last_seen) <= time_out
+                || watchers.contains_key(&w.task_id)
+        });
+    }
+
+    /// Send an event to every client watching the task, closed watch streams are dropped
+    pub fn notify_task_event(
+        &mut self,
+        task_id: &str,
+        task_status: &TaskStatus,
+        queue_position: usize,
+        msg: &str,
+    ) {
+        if let Some(watchers) = self.task_watchers.get_mut(task_id) {
+            let event = task_event(task_id, task_status, queue_position, msg);
+            watchers.retain(|w| w.send(Ok(event.clone())).is_ok());
+            if watchers.is_empty() {
+                self.task_watchers.remove(task_id);
             }
-            ServerStatus::Unknown => Ok(ServerStatus::Unknown),
         }
     }
 
-    fn get_task_result(&self, task_id: String) -> Result<Vec<u8>, Status> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(Status::aborted(e.to_string()));
+    /// Tell every queued task where it stands now
+    pub fn notify_queue_positions(&mut self, msg: &str) {
+        for (index, task_id) in self.task_queue.task_ids().into_iter().enumerate() {
+            self.notify_task_event(&task_id, &TaskStatus::Queued, index + 1, msg);
+        }
+    }
+
+    /// Tell every lock waiter where it stands now
+    pub fn notify_lock_wait_positions(&mut self, msg: &str) {
+        let task_ids: Vec<String> = self
+            .lock_waiters
+            .iter()
+            .map(|w| w.task_id.clone())
+            .collect();
+        for (index, task_id) in task_ids.into_iter().enumerate() {
+            self.notify_task_event(&task_id, &TaskStatus::None, index + 1, msg);
+        }
+    }
+
+    /// Stop taking work before shutdown. The running tasks may finish and be fetched,
+    /// queued tasks and lock waiters are told to go elsewhere. Draining lives in memory
+    /// only, a restarted server takes tasks again.
+    pub fn start_draining(&mut self) {
+        if self.draining {
+            return;
+        }
+        self.draining = true;
+        self.turn_away("server is shutting down");
+    }
+
+    // tell queued tasks and lock waiters to go elsewhere
+    fn turn_away(&mut self, reason: &str) {
+        while let Some(mut t) = self.task_queue.pop() {
+            t.wipe();
+            info!("drop queued task {}: {}", t.task_id, reason);
+            if let Some(batch_id) = &t.batch_id {
+                self.batches.finish(
+                    batch_id,
+                    &t.task_id,
+                    BatchOutcome::rejected(TaskStatus::Cancelled, reason),
+                );
             }
+            self.notify_task_event(&t.task_id, &TaskStatus::Cancelled, 0, reason);
+            self.close_task_watchers(&t.task_id);
+        }
+        while let Some(w) = self.lock_waiters.pop_front() {
+            self.notify_task_event(&w.task_id, &TaskStatus::None, 0, reason);
+            self.close_task_watchers(&w.task_id);
+        }
+    }
+
+    /// Fail the tasks which have been proving for longer than the prove time out. A
+    /// proving thread can not be stopped, so its slot turns Unknown once the client fetched
+    /// the failure and stays so until the proof returns. Queued tasks and lock waiters are
+    /// turned away once every slot is stuck. Return whether a task timed out.
+    pub fn check_prove_time_out(&mut self, now: SystemTime) -> bool {
+        let time_out = match self.prove_time_out {
+            Some(t) => t,
+            None => return false,
         };
+        let mut timed_out = false;
+        for slot in 0..self.slots.len() {
+            timed_out |= self.check_slot_prove_time_out(slot, now, time_out);
+        }
+        if timed_out && self.is_stuck() {
+            self.turn_away("server is stuck proving a task");
+        }
+        timed_out
+    }
 
-        if si.status == ServerStatus::Working {
-            if task_id != si.task_info.task_id {
-                Err(Status::invalid_argument(
-                    anyhow::Error::from(error::Error::InvalidParameters(format!(
-                        "current working task id is:{},but:{}",
-                        si.task_info.task_id, task_id
-                    )))
-                    .to_string(),
-                ))
-            } else {
-                if si.task_info.task_status == TaskStatus::Done {
-                    si.status = ServerStatus::Free;
-                    si.last_update_time = Instant::now();
-                    si.task_info.task_status = TaskStatus::Returned;
-                    Ok(si.task_info.result.clone())
-                } else if si.task_info.task_status == TaskStatus::Failed {
-                    si.status = ServerStatus::Free;
-                    si.last_update_time = Instant::now();
-                    Err(Status::aborted(
-                        anyhow::Error::from(error::Error::TaskFailedWithError(si.error.clone()))
-                            .to_string(),
-                    ))
-                } else {
-                    Ok(vec![])
-                }
-            }
-        } else {
-            Err(Status::cancelled(
-                anyhow::Error::from(error::Error::NoTaskRunningOnSever).to_string(),
-            ))
+    fn check_slot_prove_time_out(
+        &mut self,
+        slot: usize,
+        now: SystemTime,
+        time_out: Duration,
+    ) -> bool {
+        let s = &mut self.slots[slot];
+        if s.status != ServerStatus::Working
+            || s.proving_hung
+            || s.task_info.task_status != TaskStatus::Ready
+        {
+            return false;
+        }
+        let started_at = match s.task_info.started_at {
+            Some(t) => t,
+            None => return false,
+        };
+        if now.duration_since(started_at).unwrap_or_default() <= time_out {
+            return false;
         }
+        let task_id = s.task_info.task_id.clone();
+        let msg = format!("proving did not finish within {:?}", time_out);
+        error!("task {} {}, the gpu may be hung", task_id, msg);
+        s.proving_hung = true;
+        // stops it early if it is not in the prove call yet, kills it in a child process
+        s.task_info.cancel.cancel();
+        s.task_info.task_status = TaskStatus::TimedOut;
+        s.task_info.finished_at = Some(now);
+        s.task_info.checkpoint.fail(&msg);
+        s.error = msg.clone();
+        s.error_kind = FailureKind::Retryable;
+        s.error_stack = vec![msg.clone()];
+        s.task_info.timers.done_at = Some(Instant::now());
+        self.recent_errors.push(error::TaskError {
+            task_id: task_id.clone(),
+            error: msg.clone(),
+            kind: FailureKind::Retryable,
+            failed_at: now,
+        });
+        self.notify_task_event(&task_id, &TaskStatus::TimedOut, 0, &msg);
+        self.finish_batch_task(slot);
+        true
     }
 
-    fn unlock(&self, task_id: String) -> Result<(), Status> {
-        let mut si = match self.server_info.lock() {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(Status::aborted(e.to_string()));
-            }
+    /// State of the GPU slots, one per device found, each with its own status and the task
+    /// holding it. The queue is shared by the slots, each reports its whole length.
+    pub fn slot_statuses(&self) -> Vec<SlotStatus> {
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let task_id = match s.status {
+                    ServerStatus::Locked | ServerStatus::Working => s.task_info.task_id.clone(),
+                    _ => String::default(),
+                };
+                SlotStatus {
+                    slot: i as u32 + 1,
+                    device_id: s.device.device_id.clone(),
+                    task_id,
+                    task_status: s.task_info.task_status.to_string(),
+                    queue_len: self.task_queue.len() as u32,
+                    last_error: s.error.clone(),
+                    created_at_ms: utils::unix_millis(s.task_info.created_at),
+                    started_at_ms: utils::unix_millis(s.task_info.started_at),
+                    finished_at_ms: utils::unix_millis(s.task_info.finished_at),
+                    status: s.status.to_string(),
+                    device_name: s.device.name.clone(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn recent_task_errors(&self) -> Vec<TaskError> {
+        self.recent_errors
+            .iter()
+            .map(|e| TaskError {
+                task_id: e.task_id.clone(),
+                error: e.error.clone(),
+                failure_kind: format!("{:?}", e.kind),
+                failed_at_ms: utils::unix_millis(Some(e.failed_at)),
+            })
+            .collect()
+    }
+
+    /// Status of the server as seen by the caller of `task_id`, changing nothing: an
+    /// expired lock is reported until a call reclaims it.
+    pub fn worker_status(&self, task_id: &str) -> WorkerStatus {
+        let now = Instant::now();
+        let status = self.status();
+        let held = self.slot_of(task_id);
+        // tell the caller who holds the lock and for how long, so it can back off
+        // accordingly: its own lock, or the one expiring first while no slot is free
+        let locked = match held {
+            Some(i) if self.slots[i].status == ServerStatus::Locked => Some(i),
+            Some(_) => None,
+            None if status == ServerStatus::Locked => self
+                .slots
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.status == ServerStatus::Locked)
+                .min_by_key(|(_, s)| s.lock_remaining(now))
+                .map(|(i, _)| i),
+            None => None,
         };
-        if si.status == ServerStatus::Free {
-            Err(Status::cancelled("server is already Free"))
+        let (lock_task_id, lock_remaining) = match locked {
+            Some(i) => (
+                self.slots[i].task_info.task_id.clone(),
+                self.slots[i].lock_remaining(now),
+            ),
+            None => (String::default(), Duration::default()),
+        };
+        // the holder of a slot goes first
+        let (tasks_ahead, wait, wait_known) = if held.is_some() {
+            (0, Duration::ZERO, true)
         } else {
-            if si.status == ServerStatus::Locked {
-                if task_id == si.task_info.task_id {
-                    si.status = ServerStatus::default();
-                    si.task_info = TaskInfo::default();
-                    si.last_update_time = Instant::now();
-                    Ok(())
-                } else {
-                    Err(Status::invalid_argument(format!(
-                        "can not be unlocked by another task ,which is locked by task_id:{},but {}",
-                        si.task_info.task_id, task_id
-                    )))
-                }
-            } else {
-                Err(Status::cancelled(
-                    "this operation just used to unlock a server in status Locked",
-                ))
-            }
+            self.queue_wait(SystemTime::now())
+        };
+        WorkerStatus {
+            status: status.to_string(),
+            lock_task_id,
+            lock_remaining_ms: lock_remaining.as_millis() as u64,
+            lock_queue_position: self.lock_wait_position(task_id).unwrap_or_default() as u32,
+            slots: self.slot_statuses(),
+            recent_errors: self.recent_task_errors(),
+            consecutive_failures: self.recent_errors.consecutive_failures(),
+            previous_crash: self.previous_crash.clone(),
+            slot: held.map_or(0, |i| i as u32 + 1),
+            build: Some(utils::build_info()),
+            tasks_ahead: tasks_ahead as u32,
+            wait_ms: wait.as_millis() as u64,
+            wait_known,
+            uptime_ms: self.up_since.elapsed().as_millis() as u64,
+            gpu_count: gpu::devices().len() as u32,
         }
     }
-}
 
-#[tonic::async_trait]
-impl SnarkTaskService for WindowPostSnarkServer {
-    async fn do_snark_task(
-        &self,
-        request: Request<SnarkTaskRequestParams>,
-    ) -> Result<Response<BaseResponse>, Status> {
-        // get all params
-        let params_all = request.into_inner();
-        match self.do_task(&params_all) {
-            Ok(_) => Ok({
-                Response::new(BaseResponse {
-                    msg: "ok".to_string(),
-                })
-            }),
-            Err(e) => Err(e),
+    pub fn server_timeouts(&self) -> ServerTimeouts {
+        ServerTimeouts {
+            server_lock_time_out_ms: self.server_lock_time_out.as_millis() as u64,
+            server_lock_time_out_max_ms: self.server_lock_time_out_max.as_millis() as u64,
+            server_task_get_back_time_out_ms: self.server_task_get_back_time_out.as_millis() as u64,
+            server_exit_time_out_after_task_done_ms: self
+                .server_exit_time_out_after_task_done
+                .as_millis() as u64,
         }
     }
 
-    async fn lock_server_if_free(
-        &self,
-        request: Request<GetWorkerStatusRequest>,
-    ) -> Result<Response<BaseResponse>, Status> {
-        match self.lock_server_if_free(request.into_inner().task_id) {
-            Ok(s) => Ok(Response::new(BaseResponse { msg: s.to_string() })),
-            Err(e) => Err(e),
+    /// The worker of the slot returned from a task its client cancelled, whatever it
+    /// proved is dropped. The server moves on from it on the next call or tick of the
+    /// server.
+    pub fn finish_cancelled_task(&mut self, slot: usize) {
+        let t = &mut self.slots[slot].task_info;
+        let task_id = t.task_id.clone();
+        let msg = "task cancelled by its client";
+        t.task_status = TaskStatus::Cancelled;
+        t.finished_at = Some(SystemTime::now());
+        t.timers.done_at = Some(Instant::now());
+        t.checkpoint.fail(msg);
+        t.wipe();
+        self.notify_task_event(&task_id, &TaskStatus::Cancelled, 0, msg);
+        self.finish_batch_task(slot);
+    }
+
+    /// End the watch streams of a task which will not change any more
+    pub fn close_task_watchers(&mut self, task_id: &str) {
+        self.task_watchers.remove(task_id);
+    }
+}
+
+fn cached_params_to_proto(c: &params::CachedParams) -> snark_proof_grpc::CachedParams {
+    snark_proof_grpc::CachedParams {
+        sector_size: c.sector_size,
+        typ: match c.typ {
+            PoStType::Window => snark_proof_grpc::PoStType::Window as i32,
+            PoStType::Winning => snark_proof_grpc::PoStType::Winning as i32,
+        },
+        loaded_at_ms: utils::unix_millis(Some(c.loaded_at)),
+        load_ms: c.load_time.as_millis() as u64,
+    }
+}
+
+fn task_event(
+    task_id: &str,
+    task_status: &TaskStatus,
+    queue_position: usize,
+    msg: &str,
+) -> TaskEvent {
+    TaskEvent {
+        task_id: task_id.to_string(),
+        task_status: task_status.to_string(),
+        queue_position: queue_position as u32,
+        msg: msg.to_string(),
+        task_state: task_status.to_wire() as i32,
+    }
+}
+
+fn timings_to_proto(timings: &tasks::TaskTimings) -> TaskTimings {
+    TaskTimings {
+        partitions: timings.partitions as u32,
+        decode_ms: timings.decode.as_millis() as u64,
+        load_params_ms: timings.load_params.as_millis() as u64,
+        prove_ms: timings.prove.as_millis() as u64,
+        partition_prove_ms: timings
+            .partition_prove
+            .iter()
+            .map(|d| d.as_millis() as u64)
+            .collect(),
+    }
+}
+
+// where a task of a batch stands, finished ones from their outcome
+fn batch_task_report(si: &ServerInfo, batch: &Batch, task_id: &str) -> BatchTaskReport {
+    if let Some(o) = batch.outcome(task_id) {
+        return BatchTaskReport {
+            task_id: task_id.to_string(),
+            task_state: o.task_status.to_wire() as i32,
+            queue_position: 0,
+            result: o.proof.clone(),
+            pub_in_digest: o.pub_in_digest.clone(),
+            error: o.error.clone(),
+            failure_kind: if o.is_failed() {
+                format!("{:?}", o.error_kind)
+            } else {
+                String::new()
+            },
+            timings: Some(timings_to_proto(&o.timings)),
+            degraded: o.degraded,
+            finished_at_ms: utils::unix_millis(o.finished_at),
+        };
+    }
+    let (task_status, queue_position) = match si.task_queue.position(task_id) {
+        Some(p) => (TaskStatus::Queued, p),
+        None => match si.slots.iter().find(|s| s.task_info.task_id == task_id) {
+            Some(s) => (s.task_info.task_status.clone(), 0),
+            None => (TaskStatus::None, 0),
+        },
+    };
+    BatchTaskReport {
+        task_id: task_id.to_string(),
+        task_state: task_status.to_wire() as i32,
+        queue_position: queue_position as u32,
+        ..Default::default()
+    }
+}
+
+// calls carrying a trace context are logged with it, to find them by the client's trace id
+fn log_call(rpc: &str, task_id: &str, trace: &Option<TraceContext>) {
+    if let Some(t) = trace {
+        info!("{} of task {} in trace {}", rpc, task_id, t.trace_id);
+    }
+}
+
+/// Index of the slot a lock request is for, None for any free slot
+fn requested_slot(si: &ServerInfo, slot: u32) -> Result<Option<usize>, Status> {
+    match slot {
+        SLOT_ANY => Ok(None),
+        s if s as usize <= si.slots.len() => Ok(Some(s as usize - 1)),
+        s => Err(Status::out_of_range(format!(
+            "no slot {}, this server has {} slots",
+            s,
+            si.slots.len()
+        ))),
+    }
+}
+
+/// What GetSnarkTaskResult reports about a task
+#[derive(Debug, Default)]
+pub struct TaskResult {
+    pub task_status: TaskStatus,
+    pub proof: Vec<u8>,
+    // digest of the public inputs the proof is for
+    pub pub_in_digest: Vec<u8>,
+    pub created_at: Option<SystemTime>,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+    pub timings: tasks::TaskTimings,
+    pub degraded: bool,
+}
+
+impl TaskResult {
+    fn pending(task_status: TaskStatus) -> Self {
+        TaskResult {
+            task_status,
+            ..Default::default()
         }
     }
+}
 
-    async fn get_snark_task_result(
-        &self,
-        request: Request<GetTaskResultRequest>,
-    ) -> Result<Response<GetTaskResultResponse>, Status> {
-        match self.get_task_result(request.into_inner().task_id) {
-            Ok(v) => {
-                if v.len() > 0 {
-                    Ok(Response::new(GetTaskResultResponse {
-                        msg: "ok".to_string(),
-                        result: v,
-                    }))
-                } else {
-                    Ok(Response::new(GetTaskResultResponse {
-                        msg: TaskStatus::Working.to_string(),
-                        result: v,
-                    }))
+/// Status a failed task is reported with, clients retry on unavailable but not on invalid_argument
+pub fn task_failure_status(kind: FailureKind, msg: String, details: ErrorDetails) -> Status {
+    let code = match kind {
+        FailureKind::Retryable => Code::Unavailable,
+        FailureKind::Permanent => Code::InvalidArgument,
+        FailureKind::Unknown => Code::Aborted,
+    };
+    status_with_details(code, msg, details)
+}
+
+/// Response of a call which succeeded, with the version of this server
+pub fn ok_response(detail: impl Into<String>) -> BaseResponse {
+    BaseResponse {
+        msg: "ok".to_string(),
+        code: Code::Ok as i32,
+        server_version: utils::version().to_string(),
+        detail: detail.into(),
+        queue_position: 0,
+    }
+}
+
+// tasks which can not be taken as sent, with the offending field for the client to fix
+fn invalid_task_status(e: anyhow::Error) -> Status {
+    let details = match e.downcast_ref::<error::Error>() {
+        Some(error::Error::InvalidField(field, reason)) => {
+            ErrorDetails::field_violation(field, reason)
+        }
+        _ => ErrorDetails::default(),
+    };
+    status_with_details(Code::InvalidArgument, e.to_string(), details)
+}
+
+// a --priority-only server refuses other tasks and their locks
+fn not_priority_status(task_id: &str) -> Status {
+    Status::resource_exhausted(format!(
+        "server is reserved for winning PoSt and priority tasks, task {} is neither",
+        task_id
+    ))
+}
+
+/// Reassemble a task sent with DoSnarkTaskStream, its params come first and the chunks of
+/// its payloads are appended in the order they arrive
+pub async fn collect_task_frames(
+    mut frames: impl Stream<Item = Result<SnarkTaskFrame, Status>> + Unpin,
+    max_len: usize,
+) -> Result<SnarkTaskRequestParams, Status> {
+    let mut params: Option<SnarkTaskRequestParams> = None;
+    while let Some(frame) = frames.next().await {
+        if let Some(p) = &params {
+            // refuse as soon as the payloads outgrow the limit, not once all are buffered
+            check_payload_len(p, max_len)?;
+        }
+        match (frame?.frame, params.as_mut()) {
+            (Some(Frame::Params(p)), None) => {
+                if !p.vanilla_proof.is_empty() || !p.pub_in.is_empty() {
+                    return Err(invalid_task_status(tasks::invalid_field(
+                        "params",
+                        anyhow::Error::msg("carries payloads, stream them in chunks"),
+                    )));
                 }
+                params = Some(p);
             }
-            Err(e) => Err(e),
+            (Some(Frame::Params(_)), Some(_)) => {
+                return Err(Status::invalid_argument("params sent twice"))
+            }
+            (Some(Frame::VanillaProofChunk(c)), Some(p)) => p.vanilla_proof.extend_from_slice(&c),
+            (Some(Frame::PubInChunk(c)), Some(p)) => p.pub_in.extend_from_slice(&c),
+            (Some(_), None) => return Err(Status::invalid_argument("chunk sent before params")),
+            (None, _) => return Err(Status::invalid_argument("empty frame")),
         }
     }
+    let params = params.ok_or_else(|| Status::invalid_argument("stream ended without params"))?;
+    check_payload_len(&params, max_len)?;
+    Ok(params)
+}
 
-    async fn unlock_server(
-        &self,
-        request: Request<UnlockServerRequest>,
-    ) -> Result<Response<BaseResponse>, Status> {
-        match self.unlock(request.into_inner().task_id) {
-            Ok(_) => Ok(Response::new(BaseResponse {
-                msg: "ok".to_string(),
-            })),
-            Err(e) => Err(e),
-        }
+// decode a task off the runtime threads, a panic decoding it fails the call and is no crash;
+// its paths are resolved there as well, a stalled mount must not hold up the server info
+async fn decode_task<F>(f: F, payload_dirs: Vec<PathBuf>) -> Result<TaskInfo, Status>
+where
+    F: FnOnce() -> anyhow::Result<TaskInfo> + Send + 'static,
+{
+    let decode = move || {
+        let mut task_info = f()?;
+        task_info.resolve_payload_paths(&payload_dirs)?;
+        Ok(task_info)
+    };
+    match spawn_blocking(move || crash::recover(decode)).await {
+        Ok(Ok(Ok(t))) => Ok(t),
+        Ok(Ok(Err(e))) => Err(invalid_task_status(e)),
+        Ok(Err(e)) => Err(Status::internal(format!(
+            "decoding the task panicked: {}",
+            crash::panic_message(&*e)
+        ))),
+        Err(e) => Err(Status::internal(e.to_string())),
     }
 }
 
-pub async fn run_server(
-    srv_exit_rx: oneshot::Receiver<String>,
-    srv: WindowPostSnarkServer,
-    port: String,
-) {
-    let mut addr_s = "0.0.0.0:".to_string();
-    addr_s += &port;
-    let addr = addr_s.parse::<SocketAddr>().unwrap();
-    info!("Server listening on {}", addr);
-    Server::builder()
-        .accept_http1(true)
-        .add_service(SnarkTaskServiceServer::new(srv))
-        .serve_with_shutdown(addr, srv_exit_rx.map(drop))
-        .await
-        .unwrap();
-    info!("server stop listen")
+fn check_payload_len(params: &SnarkTaskRequestParams, max_len: usize) -> Result<(), Status> {
+    let len = params.vanilla_proof.len() + params.pub_in.len();
+    if len > max_len {
+        return Err(Status::resource_exhausted(format!(
+            "payloads of task {} exceed {} bytes",
+            params.task_id, max_len
+        )));
+    }
+    Ok(())
+}
+
+// a C2 task carries its phase 1 output where other tasks carry their payloads
+fn check_c2_payload_len(req: &C2TaskRequest, max_len: usize) -> Result<(), Status> {
+    if req.commit_phase1_output.len() > max_len {
+        return Err(Status::invalid_argument(format!(
+            "commit_phase1_output of task {} exceeds {} bytes",
+            req.task_id, max_len
+        )));
+    }
+    Ok(())
+}
+
+// slot proving the task, the error names the tasks the server proves otherwise
+fn working_slot(si: &ServerInfo, task_id: &str) -> Result<usize, Status> {
+    if let Some(slot) = si
+        .slot_of(task_id)
+        .filter(|i| si.slots[*i].status == ServerStatus::Working)
+    {
+        return Ok(slot);
+    }
+    let working = si.working_task_ids();
+    if working.is_empty() {
+        return Err(Status::failed_precondition(
+            anyhow::Error::from(error::Error::NoTaskRunningOnSever).to_string(),
+        ));
+    }
+    Err(Status::invalid_argument(
+        anyhow::Error::from(error::Error::InvalidParameters(format!(
+            "current working task id is:{},but:{}",
+            working.join(","),
+            task_id
+        )))
+        .to_string(),
+    ))
+}
+
+impl WindowPostSnarkServer {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn new(task_run_tx: UnboundedSender<String>) -> Self {
+        let server_info = ServerInfo::default();
+        let lock_released = server_info.lock_released.clone();
+        WindowPostSnarkServer {
+            server_info: Arc::new(Mutex::new(server_info)),
+            task_run_tx,
+            lock_released,
+        }
+    }
+
+    pub fn set_time_out(
+        &self,
+        server_lock_time_out: Duration,
+        server_task_get_back_time_out: Duration,
+        server_exit_time_out_after_task_done: Duration,
+    ) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.server_lock_time_out = server_lock_time_out;
+        si.server_task_get_back_time_out = server_task_get_back_time_out;
+        si.server_exit_time_out_after_task_done = server_exit_time_out_after_task_done;
+        Ok(())
+    }
+
+    pub fn set_server_lock_time_out(&self, time_out: Duration) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.server_lock_time_out = time_out;
+        Ok(())
+    }
+
+    pub fn set_server_lock_time_out_max(&self, time_out: Duration) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.server_lock_time_out_max = time_out;
+        Ok(())
+    }
+
+    pub fn set_server_task_get_back_time_out(&self, time_out: Duration) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.server_task_get_back_time_out = time_out;
+        Ok(())
+    }
+
+    pub fn set_server_exit_time_out_after_task_done(
+        &self,
+        time_out: Duration,
+    ) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.server_exit_time_out_after_task_done = time_out;
+        Ok(())
+    }
+
+    pub fn set_dump(&self, dump: DumpConfig) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.dump = Some(dump);
+        Ok(())
+    }
+
+    pub fn set_task_threads(&self, task_threads: usize) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.task_threads = task_threads;
+        Ok(())
+    }
+
+    pub fn set_runtime(&self, runtime: RuntimeConfig) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.runtime = runtime;
+        Ok(())
+    }
+
+    pub fn set_previous_crash(&self, previous_crash: String) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.previous_crash = previous_crash;
+        Ok(())
+    }
+
+    pub fn set_auth(&self, auth: AuthConfig) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.auth = auth;
+        Ok(())
+    }
+
+    pub fn set_priority_only(&self, priority_only: bool) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.priority_only = priority_only;
+        Ok(())
+    }
+
+    pub fn set_audit_dir(&self, audit_dir: PathBuf) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.audit_dir = Some(audit_dir);
+        Ok(())
+    }
+
+    pub fn set_proving_times(&self, proving_times: ProvingTimes) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.proving_times = proving_times;
+        Ok(())
+    }
+
+    pub fn set_hardware_profile(&self, hardware_profile: HardwareProfile) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.hardware_profile = Some(hardware_profile);
+        Ok(())
+    }
+
+    /// Keep the server Unknown, e.g. when its parameters are corrupt, so no client locks it
+    pub fn set_unusable(&self, error: String) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        for s in si.slots.iter_mut() {
+            s.status = ServerStatus::Unknown;
+            s.error = error.clone();
+        }
+        Ok(())
+    }
+
+    pub fn set_cpu_fallback(&self, cpu_fallback: bool) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.cpu_fallback = cpu_fallback;
+        Ok(())
+    }
+
+    pub fn set_preempt_window_post(&self, preempt_window_post: bool) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.preempt_window_post = preempt_window_post;
+        Ok(())
+    }
+
+    pub fn set_verify_proofs(&self, verify_proofs: bool) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.verify_proofs = verify_proofs;
+        Ok(())
+    }
+
+    pub fn set_prove_time_out(&self, time_out: Duration) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.prove_time_out = Some(time_out);
+        Ok(())
+    }
+
+    pub fn set_payload_dirs(&self, payload_dirs: Vec<PathBuf>) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.payload_dirs = payload_dirs;
+        Ok(())
+    }
+
+    /// Tasks which may wait behind the running one, 0 means no limit
+    pub fn set_task_queue_capacity(&self, capacity: usize) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.task_queue.set_capacity(capacity);
+        Ok(())
+    }
+
+    pub fn set_memory_limit(&self, memory_limit: u64) -> anyhow::Result<()> {
+        let mut si = self.server_info.lock();
+        si.memory_limit = Some(memory_limit);
+        Ok(())
+    }
+
+    /// Prove on these slots, see `gpu::detect_slots`. Set before the server takes tasks.
+    pub fn set_slots(&self, devices: Vec<SlotDevice>) -> anyhow::Result<()> {
+        if devices.is_empty() {
+            return Err(anyhow::anyhow!("a server needs a slot to prove on"));
+        }
+        let mut si = self.server_info.lock();
+        if si.slots.iter().any(|s| s.status != ServerStatus::Free) {
+            return Err(anyhow::anyhow!(
+                "slots can not change while the server is used"
+            ));
+        }
+        si.slots = devices.into_iter().map(Slot::new).collect();
+        Ok(())
+    }
+
+    // refuse requests for a task id which another peer is using, requests without a known peer are not checked
+    fn claim_task_id(&self, task_id: &str, remote_addr: Option<SocketAddr>) -> Result<(), Status> {
+        let peer = match remote_addr {
+            Some(addr) => addr.ip().to_string(),
+            None => return Ok(()),
+        };
+        let mut si = self.server_info.lock();
+        let time_out = si.task_id_reuse_time_out;
+        match si.task_owners.claim(task_id, &peer, time_out) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                info!("peer {} refused for task {}: {}", peer, task_id, e);
+                Err(Status::permission_denied(e.to_string()))
+            }
+        }
+    }
+
+    // what became of the task when it was accepted, and its position in the queue,
+    // 0 when it was started
+    fn do_task(
+        &self,
+        mut task_info: TaskInfo,
+        trace: Option<TraceContext>,
+    ) -> Result<(String, usize), Status> {
+        task_info.trace = trace;
+        let mut si = self.server_info.lock();
+        if si.draining {
+            return Err(Status::unavailable("server is shutting down"));
+        }
+        if si.is_stuck() {
+            return Err(Status::unavailable("server is stuck proving a task"));
+        }
+        if si.priority_only && !task_info.is_priority() {
+            return Err(not_priority_status(&task_info.task_id));
+        }
+        if let Some(limit) = si.memory_limit {
+            let needed = resources::task_memory_estimate(&task_info);
+            if needed > limit {
+                return Err(Status::resource_exhausted(format!(
+                    "task {} needs about {} bytes of memory, but only {} are available",
+                    task_info.task_id, needed, limit
+                )));
+            }
+        }
+        // Determine whether the request to execute the task came from the task a slot is
+        // locked for, a free slot takes the task right away without being locked first
+        let task_id = task_info.task_id.clone();
+        let locked_slot = si
+            .slot_of(&task_id)
+            .filter(|i| si.slots[*i].status == ServerStatus::Locked);
+        if let Some(slot) = locked_slot.or_else(|| si.free_slot()) {
+            // set server info
+            si.slots[slot].task_info = task_info;
+            si.slots[slot].status = ServerStatus::Working;
+            match self.task_run_tx.send("ok".to_string()) {
+                Ok(_) => Ok((format!("task {} started on slot {}", task_id, slot + 1), 0)),
+                Err(s) => Err(Status::unavailable(s.0)),
+            }
+        } else {
+            match si.status() {
+                ServerStatus::Locked | ServerStatus::Working => {
+                    // every slot is busy with another task, queue this one behind them
+                    if si.slot_of(&task_id).is_some() {
+                        return Err(Status::already_exists(format!(
+                            "task {} is already running",
+                            task_id
+                        )));
+                    }
+                    if si.task_queue.contains(&task_id) {
+                        return Err(Status::already_exists(format!(
+                            "task {} is already queued",
+                            task_id
+                        )));
+                    }
+                    if si.task_queue.is_full() {
+                        return Err(Status::resource_exhausted(format!(
+                            "task queue is full with {} tasks, try another server",
+                            si.task_queue.len()
+                        )));
+                    }
+                    // a client waiting for the lock does not need it any more once its task is queued
+                    si.leave_lock_queue(&task_id);
+                    let winning = task_info.is_winning();
+                    let (position, displaced) = si.task_queue.push(task_info);
+                    info!("task {} queued at position {}", task_id, position);
+                    for id in displaced {
+                        let p = si.task_queue.position(&id).unwrap_or_default();
+                        si.notify_task_event(
+                            &id,
+                            &TaskStatus::Queued,
+                            p,
+                            &format!("moved back by priority task {}", task_id),
+                        );
+                    }
+                    if winning {
+                        si.preempt_for_winning(&task_id);
+                    }
+                    Ok((
+                        format!("task {} queued at position {}", task_id, position),
+                        position,
+                    ))
+                }
+                // a free slot started the task above
+                ServerStatus::Free | ServerStatus::Unknown => Err(Status::unavailable(
+                    "server is Unknown, can not be used now",
+                )),
+            }
+        }
+    }
+
+    fn lock_server_if_free(
+        &self,
+        task_id: String,
+        lock_duration: Duration,
+        slot: u32,
+        priority: bool,
+    ) -> Result<WorkerStatus, Status> {
+        let mut si = self.server_info.lock();
+        let slot = requested_slot(&si, slot)?;
+        if si.is_stuck() {
+            return Err(Status::unavailable("server is stuck proving a task"));
+        }
+        // refuse before the task takes the lock or a place in the lock queue
+        if si.priority_only && !priority {
+            return Err(not_priority_status(&task_id));
+        }
+        let lock_time_out = si.lock_time_out_for(lock_duration);
+        let status = self.try_lock(&mut si, task_id.clone(), slot, lock_time_out)?;
+        Ok(WorkerStatus {
+            status: status.to_string(),
+            ..si.worker_status(&task_id)
+        })
+    }
+
+    async fn lock_server_wait(
+        &self,
+        task_id: String,
+        lock_duration: Duration,
+        wait_time_out: Duration,
+        slot: u32,
+        priority: bool,
+    ) -> Result<WorkerStatus, Status> {
+        let deadline = Instant::now() + wait_time_out;
+        loop {
+            // subscribe before trying, so a release in between is not missed
+            let released = self.lock_released.notified();
+            let status =
+                self.lock_server_if_free(task_id.clone(), lock_duration, slot, priority)?;
+            if status.status == ServerStatus::Free.to_string() {
+                return Ok(status);
+            }
+            if status.status == ServerStatus::Unknown.to_string() {
+                return Err(Status::unavailable(
+                    "server is Unknown, can not be used now",
+                ));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                self.server_info.lock().leave_lock_queue(&task_id);
+                // the lock may be free once the current holder's lease ran out
+                return Err(status_with_details(
+                    Code::DeadlineExceeded,
+                    format!(
+                        "lock was not granted to task {} within {:?}",
+                        task_id, wait_time_out
+                    ),
+                    ErrorDetails::retry_after(Duration::from_millis(status.lock_remaining_ms)),
+                ));
+            }
+            // wake up at least every second, which also keeps our place in the lock queue
+            let _ =
+                tokio::time::timeout((deadline - now).min(Duration::from_secs(1)), released).await;
+        }
+    }
+
+    // lock the slot, or any free slot when None, for the task
+    fn try_lock(
+        &self,
+        si: &mut ServerInfo,
+        task_id: String,
+        slot: Option<usize>,
+        lock_time_out: Duration,
+    ) -> Result<ServerStatus, Status> {
+        if si.draining {
+            return Err(Status::unavailable("server is shutting down"));
+        }
+        // take the slots back from holders which did not make it in time
+        self.reclaim_if_expired(si)?;
+        match si.slot_of(&task_id) {
+            // the lock was handed over from the lock queue, or is asked for again by its holder
+            Some(i) if si.slots[i].status == ServerStatus::Locked => return Ok(ServerStatus::Free),
+            Some(_) => return Ok(ServerStatus::Working),
+            None => {}
+        }
+        let free = match slot {
+            Some(i) => Some(i).filter(|i| si.slots[*i].status == ServerStatus::Free),
+            None => si.free_slot(),
+        };
+        if let Some(free) = free {
+            // the slot will be locked by client with task_id here at first
+            si.lock_for(free, task_id, lock_time_out);
+            return Ok(ServerStatus::Free);
+        }
+        let status = match slot {
+            Some(i) => si.slots[i].status.clone(),
+            None => si.status(),
+        };
+        match status {
+            ServerStatus::Locked | ServerStatus::Working => {
+                if !si.task_queue.contains(&task_id) {
+                    // wait in line instead of making the client retry blindly
+                    let position = si.wait_for_lock(task_id.clone(), slot, lock_time_out);
+                    info!("task {} waits for lock at position {}", task_id, position);
+                }
+                Ok(status)
+            }
+            _ => Ok(ServerStatus::Unknown),
+        }
+    }
+
+    fn reclaim_if_expired(&self, si: &mut ServerInfo) -> Result<(), Status> {
+        for slot in 0..si.slots.len() {
+            self.reclaim_slot_if_expired(si, slot)?;
+        }
+        Ok(())
+    }
+
+    fn reclaim_slot_if_expired(&self, si: &mut ServerInfo, slot: usize) -> Result<(), Status> {
+        let get_back_time_out = si.server_task_get_back_time_out;
+        let s = &mut si.slots[slot];
+        let timers = s.task_info.timers;
+        let now = Instant::now();
+        match s.status {
+            // if locked too long and still not received task from miner, unlock it
+            ServerStatus::Locked
+                if timers
+                    .locked_for(now)
+                    .map_or(false, |t| t > s.lock_time_out) =>
+            {
+                info!("lock of task {} expired", s.task_info.task_id);
+                self.release(si, slot)
+            }
+            // set by the worker once it returned, its client does not want the result
+            ServerStatus::Working if s.task_info.task_status == TaskStatus::Cancelled => {
+                let task_id = s.task_info.task_id.clone();
+                info!("task {} cancelled, move on", task_id);
+                si.close_task_watchers(&task_id);
+                self.release(si, slot)
+            }
+            // the outcome of a batch task is kept with its batch, nothing to wait for
+            ServerStatus::Working
+                if s.task_info.batch_id.is_some()
+                    && (s.task_info.task_status == TaskStatus::Done
+                        || s.task_info.task_status.is_failed()) =>
+            {
+                let task_id = s.task_info.task_id.clone();
+                info!("task {} of a batch finished, move on", task_id);
+                s.task_info.wipe();
+                si.close_task_watchers(&task_id);
+                self.release(si, slot)
+            }
+            // if miner do not get result back and ack it in SERVER_TASK_GET_BACK_TIME_OUT after task done or failed, drop task
+            ServerStatus::Working
+                if (s.task_info.task_status == TaskStatus::Done
+                    || s.task_info.task_status == TaskStatus::Returned
+                    || s.task_info.task_status.is_failed())
+                    && timers
+                        .done_for(now)
+                        .map_or(false, |t| t >= get_back_time_out) =>
+            {
+                let expired_task_id = s.task_info.task_id.clone();
+                info!(
+                    "result of task {} was not got back in time",
+                    expired_task_id
+                );
+                s.task_info.wipe();
+                si.close_task_watchers(&expired_task_id);
+                self.release(si, slot)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // the slot is free again, tell the worker when a queued task was started on it
+    fn release(&self, si: &mut ServerInfo, slot: usize) -> Result<(), Status> {
+        if si.release(slot) {
+            if let Err(s) = self.task_run_tx.send("ok".to_string()) {
+                return Err(Status::unavailable(s.0));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_task_result(&self, task_id: String) -> Result<TaskResult, Status> {
+        let mut si = self.server_info.lock();
+
+        if si.task_queue.contains(&task_id) {
+            return Ok(TaskResult::pending(TaskStatus::Queued));
+        }
+
+        let slot = working_slot(&si, &task_id)?;
+        let t = &mut si.slots[slot].task_info;
+        if t.task_status == TaskStatus::Done || t.task_status == TaskStatus::Returned {
+            if t.task_status == TaskStatus::Done {
+                t.task_status = TaskStatus::Returned;
+                si.notify_task_event(&task_id, &TaskStatus::Returned, 0, "result returned");
+            }
+            // the result is kept until it is acked, a client which crashed before
+            // persisting it can fetch it again
+            let t = &si.slots[slot].task_info;
+            Ok(TaskResult {
+                task_status: TaskStatus::Returned,
+                proof: t.result.clone(),
+                pub_in_digest: t.pub_in_digest.clone(),
+                created_at: t.created_at,
+                started_at: t.started_at,
+                finished_at: t.finished_at,
+                timings: t.timings.clone(),
+                degraded: t.degraded,
+            })
+        } else if t.task_status.is_failed() {
+            t.wipe();
+            si.close_task_watchers(&task_id);
+            let s = &si.slots[slot];
+            let err = task_failure_status(
+                s.error_kind,
+                anyhow::Error::from(error::Error::TaskFailedWithError(s.error.clone())).to_string(),
+                ErrorDetails::debug(s.error_stack.clone()),
+            );
+            self.release(&mut si, slot)?;
+            Err(err)
+        } else {
+            Ok(TaskResult {
+                created_at: t.created_at,
+                started_at: t.started_at,
+                ..TaskResult::pending(TaskStatus::Working)
+            })
+        }
+    }
+
+    // the client has persisted the result, drop it and free its slot
+    fn ack_result(&self, task_id: String) -> Result<(), Status> {
+        let mut si = self.server_info.lock();
+        let slot = working_slot(&si, &task_id)?;
+        match si.slots[slot].task_info.task_status {
+            TaskStatus::Done | TaskStatus::Returned => {
+                si.notify_task_event(&task_id, &TaskStatus::Returned, 0, "result acked");
+                si.close_task_watchers(&task_id);
+                si.slots[slot].task_info.wipe();
+                // release may already start the next task
+                self.release(&mut si, slot)
+            }
+            _ => Err(Status::failed_precondition(format!(
+                "task {} has no result to ack, it is {}",
+                task_id, si.slots[slot].task_info.task_status
+            ))),
+        }
+    }
+
+    // drop a queued task, or stop the running one at its next checkpoint
+    fn cancel_task(&self, task_id: &str) -> Result<String, Status> {
+        let mut si = self.server_info.lock();
+        if let Some(mut t) = si.task_queue.remove(task_id) {
+            // a preempted task keeps its payloads with its checkpoint, dropping them wipes them
+            drop(t.checkpoint.take_payload());
+            t.wipe();
+            let msg = "task cancelled by its client";
+            t.checkpoint.fail(msg);
+            if let Some(batch_id) = &t.batch_id {
+                si.batches.finish(
+                    batch_id,
+                    task_id,
+                    BatchOutcome::rejected(TaskStatus::Cancelled, msg),
+                );
+            }
+            info!("queued task {} cancelled", task_id);
+            si.notify_task_event(task_id, &TaskStatus::Cancelled, 0, msg);
+            si.close_task_watchers(task_id);
+            si.notify_queue_positions("moved forward in queue");
+            return Ok(format!("task {} removed from the queue", task_id));
+        }
+        let slot = match si
+            .slot_of(task_id)
+            .filter(|i| si.slots[*i].status == ServerStatus::Working)
+        {
+            Some(i) => i,
+            None => {
+                return Err(Status::not_found(format!(
+                    "task {} is neither running nor queued on this server",
+                    task_id
+                )))
+            }
+        };
+        let t = &si.slots[slot].task_info;
+        match t.task_status {
+            TaskStatus::Ready | TaskStatus::Working | TaskStatus::Queued => {
+                if !t.cancel.is_cancelled() {
+                    info!("task {} cancelled, stop it at its next checkpoint", task_id);
+                    t.cancel.cancel();
+                    let task_status = t.task_status.clone();
+                    si.notify_task_event(task_id, &task_status, 0, "cancelling");
+                }
+                Ok(format!(
+                    "task {} stops at its next checkpoint, the server moves on then",
+                    task_id
+                ))
+            }
+            TaskStatus::Cancelled => Ok(format!("task {} is cancelled", task_id)),
+            _ => Err(Status::failed_precondition(format!(
+                "task {} is {} already, get its result or ack it instead",
+                task_id, t.task_status
+            ))),
+        }
+    }
+
+    fn unlock(&self, task_id: String) -> Result<(), Status> {
+        let mut si = self.server_info.lock();
+        // a waiting client which gives up just leaves the lock queue
+        if si.leave_lock_queue(&task_id) {
+            return Ok(());
+        }
+        match si.slot_of(&task_id) {
+            Some(slot) if si.slots[slot].status == ServerStatus::Locked => {
+                si.slots[slot].task_info = TaskInfo::default();
+                self.release(&mut si, slot)
+            }
+            Some(_) => Err(Status::failed_precondition(
+                "this operation just used to unlock a server in status Locked",
+            )),
+            None => {
+                let locked: Vec<String> = si
+                    .slots
+                    .iter()
+                    .filter(|s| s.status == ServerStatus::Locked)
+                    .map(|s| s.task_info.task_id.clone())
+                    .collect();
+                if !locked.is_empty() {
+                    Err(Status::invalid_argument(format!(
+                        "can not be unlocked by another task ,which is locked by task_id:{},but {}",
+                        locked.join(","),
+                        task_id
+                    )))
+                } else if si.status() == ServerStatus::Free {
+                    Err(Status::failed_precondition("server is already Free"))
+                } else {
+                    Err(Status::failed_precondition(
+                        "this operation just used to unlock a server in status Locked",
+                    ))
+                }
+            }
+        }
+    }
+
+    // take a task sent with DoSnarkTask or DoSnarkTaskStream
+    async fn take_snark_task(
+        &self,
+        mut params: SnarkTaskRequestParams,
+        remote_addr: Option<SocketAddr>,
+        trace: Option<TraceContext>,
+    ) -> Result<BaseResponse, Status> {
+        check_payload_len(&params, TASK_PAYLOAD_MAX_LEN)?;
+        self.claim_task_id(&params.task_id, remote_addr)?;
+        // clients which do not identify themselves are told apart by address
+        if params.client_id.is_empty() {
+            if let Some(addr) = remote_addr {
+                params.client_id = addr.ip().to_string();
+            }
+        }
+        // payloads which can not even be parsed are rejected before touching the server
+        // state; decoding and hashing hundreds of megabytes would hold up a runtime thread
+        let payload_dirs = self.server_info.lock().payload_dirs.clone();
+        let task_info = decode_task(move || set_task_info(params), payload_dirs).await?;
+        let (detail, position) = self.do_task(task_info, trace)?;
+        Ok(BaseResponse {
+            queue_position: position as u32,
+            ..ok_response(detail)
+        })
+    }
+
+    // take a task of a batch like DoSnarkTask does
+    async fn submit_batch_task(
+        &self,
+        mut params: SnarkTaskRequestParams,
+        batch_id: &str,
+        remote_addr: Option<SocketAddr>,
+        trace: Option<TraceContext>,
+    ) -> Result<String, Status> {
+        check_payload_len(&params, TASK_PAYLOAD_MAX_LEN)?;
+        self.claim_task_id(&params.task_id, remote_addr)?;
+        if params.client_id.is_empty() {
+            if let Some(addr) = remote_addr {
+                params.client_id = addr.ip().to_string();
+            }
+        }
+        let payload_dirs = self.server_info.lock().payload_dirs.clone();
+        let mut task_info = decode_task(move || set_task_info(params), payload_dirs).await?;
+        task_info.batch_id = Some(batch_id.to_string());
+        // a server done with a task only frees up on the next call, take it back first
+        self.reclaim_if_expired(&mut self.server_info.lock())?;
+        self.do_task(task_info, trace).map(|(detail, _)| detail)
+    }
+
+    // the result of a task as GetSnarkTaskResult returns it
+    fn task_result_response(&self, task_id: String) -> Result<GetTaskResultResponse, Status> {
+        let r = self.get_task_result(task_id)?;
+        let msg = if r.task_status == TaskStatus::Returned {
+            "ok".to_string()
+        } else {
+            r.task_status.to_string()
+        };
+        Ok(GetTaskResultResponse {
+            msg,
+            result: r.proof,
+            pub_in_digest: r.pub_in_digest,
+            created_at_ms: utils::unix_millis(r.created_at),
+            started_at_ms: utils::unix_millis(r.started_at),
+            finished_at_ms: utils::unix_millis(r.finished_at),
+            timings: Some(timings_to_proto(&r.timings)),
+            degraded: r.degraded,
+            task_state: r.task_status.to_wire() as i32,
+            migrated_from: String::new(),
+        })
+    }
+
+    fn batch_report(
+        &self,
+        batch_id: &str,
+        remote_addr: Option<SocketAddr>,
+    ) -> Result<BatchReport, Status> {
+        let mut si = self.server_info.lock();
+        self.reclaim_if_expired(&mut si)?;
+        si.prune_batches(Instant::now());
+        let time_out = si.server_task_get_back_time_out;
+        let si = &*si;
+        let batch = match si.batches.get(batch_id) {
+            Some(b) => b,
+            None => {
+                return Err(Status::not_found(format!(
+                    "no batch {}, it may have been complete for longer than {:?}",
+                    batch_id, time_out
+                )))
+            }
+        };
+        let peer = remote_addr.map_or_else(String::new, |a| a.ip().to_string());
+        if !batch.is_readable_by(&peer) {
+            info!("peer {} refused for batch {}", peer, batch_id);
+            return Err(Status::permission_denied(format!(
+                "batch {} was submitted by another client",
+                batch_id
+            )));
+        }
+        let tasks: Vec<BatchTaskReport> = batch
+            .task_ids()
+            .iter()
+            .map(|task_id| batch_task_report(si, batch, task_id))
+            .collect();
+        Ok(BatchReport {
+            batch_id: batch_id.to_string(),
+            complete: batch.is_complete(),
+            done: batch
+                .task_ids()
+                .iter()
+                .filter_map(|t| batch.outcome(t))
+                .filter(|o| !o.is_failed())
+                .count() as u32,
+            failed: batch
+                .task_ids()
+                .iter()
+                .filter_map(|t| batch.outcome(t))
+                .filter(|o| o.is_failed())
+                .count() as u32,
+            tasks,
+        })
+    }
+
+    /// Proofs of the partitions of a running or queued task as they are proved, those of a
+    /// done task all at once
+    fn add_partition_watcher(&self, task_id: String) -> Result<PartitionProofStream, Status> {
+        let si = self.server_info.lock();
+        let held = si
+            .slots
+            .iter()
+            .find(|s| s.status != ServerStatus::Free && s.task_info.task_id == task_id);
+        let t = if let Some(s) = held {
+            &s.task_info
+        } else if let Some(t) = si.task_queue.get(&task_id) {
+            t
+        } else {
+            return Err(Status::not_found(format!(
+                "task {} is neither running nor queued on this server",
+                task_id
+            )));
+        };
+        if t.task_status.is_failed() {
+            return Err(Status::failed_precondition(format!(
+                "task {} failed, get its result for the error",
+                task_id
+            )));
+        }
+        let partitions = t
+            .post_config
+            .as_ref()
+            .and_then(|c| t.partitions(c))
+            .unwrap_or(1) as u32;
+        let rx = t.checkpoint.watch();
+        if t.task_status == TaskStatus::Done || t.task_status == TaskStatus::Returned {
+            t.checkpoint.finish(&t.result);
+        }
+        let stream = UnboundedReceiverStream::new(rx).map(move |event| match event {
+            Ok((partition, proof)) => Ok(PartitionProof {
+                task_id: task_id.clone(),
+                partition: partition as u32,
+                partitions,
+                proof,
+            }),
+            Err(e) => Err(Status::aborted(e)),
+        });
+        Ok(Box::pin(stream))
+    }
+
+    fn add_task_watcher(
+        &self,
+        task_id: String,
+    ) -> Result<UnboundedReceiver<Result<TaskEvent, Status>>, Status> {
+        let mut si = self.server_info.lock();
+        let (tx, rx) = mpsc::unbounded_channel();
+        // the first event on the stream is where the task stands right now
+        let event = if let Some(position) = si.task_queue.position(&task_id) {
+            task_event(&task_id, &TaskStatus::Queued, position, "queued")
+        } else if let Some(position) = si.lock_wait_position(&task_id) {
+            task_event(&task_id, &TaskStatus::None, position, "waiting for lock")
+        } else if let Some(s) = si
+            .slots
+            .iter()
+            .find(|s| s.status != ServerStatus::Free && s.task_info.task_id == task_id)
+        {
+            task_event(&task_id, &s.task_info.task_status, 0, &s.status.to_string())
+        } else {
+            return Err(Status::not_found(format!(
+                "task {} is neither running nor waiting on this server",
+                task_id
+            )));
+        };
+        // receiver is held by us, can not be closed yet
+        let _ = tx.send(Ok(event));
+        si.task_watchers
+            .entry(task_id)
+            .or_insert_with(Vec::new)
+            .push(tx);
+        Ok(rx)
+    }
+}
+
+#[tonic::async_trait]
+impl SnarkTaskService for WindowPostSnarkServer {
+    async fn do_snark_task(
+        &self,
+        request: Request<SnarkTaskRequestParams>,
+    ) -> Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let params = request.into_inner();
+        log_call("do_snark_task", &params.task_id, &trace);
+        self.take_snark_task(params, remote_addr, trace)
+            .await
+            .map(Response::new)
+    }
+
+    async fn do_snark_task_stream(
+        &self,
+        request: Request<Streaming<SnarkTaskFrame>>,
+    ) -> Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let params = collect_task_frames(request.into_inner(), TASK_PAYLOAD_MAX_LEN).await?;
+        log_call("do_snark_task_stream", &params.task_id, &trace);
+        self.take_snark_task(params, remote_addr, trace)
+            .await
+            .map(Response::new)
+    }
+
+    async fn do_c2_task(
+        &self,
+        request: Request<C2TaskRequest>,
+    ) -> Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let mut req = request.into_inner();
+        log_call("do_c2_task", &req.task_id, &trace);
+        check_c2_payload_len(&req, TASK_PAYLOAD_MAX_LEN)?;
+        self.claim_task_id(&req.task_id, remote_addr)?;
+        if req.client_id.is_empty() {
+            if let Some(addr) = remote_addr {
+                req.client_id = addr.ip().to_string();
+            }
+        }
+        let payload_dirs = self.server_info.lock().payload_dirs.clone();
+        let task_info = decode_task(move || set_c2_task_info(&req), payload_dirs).await?;
+        let (detail, position) = self.do_task(task_info, trace)?;
+        Ok(Response::new(BaseResponse {
+            queue_position: position as u32,
+            ..ok_response(detail)
+        }))
+    }
+
+    async fn lock_server_if_free(
+        &self,
+        request: Request<GetWorkerStatusRequest>,
+    ) -> Result<Response<WorkerStatus>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let req = request.into_inner();
+        log_call("lock_server_if_free", &req.task_id, &trace);
+        self.claim_task_id(&req.task_id, remote_addr)?;
+        match self.lock_server_if_free(
+            req.task_id,
+            Duration::from_millis(req.lock_duration_ms),
+            req.slot,
+            req.priority,
+        ) {
+            Ok(s) => Ok(Response::new(s)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_worker_status(
+        &self,
+        request: Request<GetWorkerStatusRequest>,
+    ) -> Result<Response<WorkerStatus>, Status> {
+        let trace = TraceContext::from_metadata(request.metadata());
+        let req = request.into_inner();
+        log_call("get_worker_status", &req.task_id, &trace);
+        let si = self.server_info.lock();
+        Ok(Response::new(si.worker_status(&req.task_id)))
+    }
+
+    async fn lock_server_wait(
+        &self,
+        request: Request<LockServerWaitRequest>,
+    ) -> Result<Response<WorkerStatus>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let req = request.into_inner();
+        log_call("lock_server_wait", &req.task_id, &trace);
+        self.claim_task_id(&req.task_id, remote_addr)?;
+        let wait_time_out = if req.wait_timeout_ms == 0 {
+            SERVER_LOCK_WAIT_TIME_OUT_DEFAULT
+        } else {
+            Duration::from_millis(req.wait_timeout_ms).min(SERVER_LOCK_WAIT_TIME_OUT_MAX)
+        };
+        match self
+            .lock_server_wait(
+                req.task_id,
+                Duration::from_millis(req.lock_duration_ms),
+                wait_time_out,
+                req.slot,
+                req.priority,
+            )
+            .await
+        {
+            Ok(s) => Ok(Response::new(s)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_snark_task_result(
+        &self,
+        request: Request<GetTaskResultRequest>,
+    ) -> Result<Response<GetTaskResultResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let task_id = request.into_inner().task_id;
+        log_call("get_snark_task_result", &task_id, &trace);
+        self.claim_task_id(&task_id, remote_addr)?;
+        self.task_result_response(task_id).map(Response::new)
+    }
+
+    type DownloadTaskResultStream = TaskResultChunkStream;
+
+    async fn download_task_result(
+        &self,
+        request: Request<GetTaskResultRequest>,
+    ) -> Result<Response<Self::DownloadTaskResultStream>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let task_id = request.into_inner().task_id;
+        log_call("download_task_result", &task_id, &trace);
+        self.claim_task_id(&task_id, remote_addr)?;
+        let mut header = self.task_result_response(task_id)?;
+        let result = std::mem::take(&mut header.result);
+        let mut chunks = vec![TaskResultChunk {
+            header: Some(header),
+            chunk: vec![],
+        }];
+        chunks.extend(result.chunks(RESULT_CHUNK_LEN).map(|c| TaskResultChunk {
+            header: None,
+            chunk: c.to_vec(),
+        }));
+        Ok(Response::new(Box::pin(futures::stream::iter(
+            chunks.into_iter().map(Ok),
+        ))))
+    }
+
+    type StreamTaskResultStream = PartitionProofStream;
+
+    async fn stream_task_result(
+        &self,
+        request: Request<GetTaskResultRequest>,
+    ) -> Result<Response<Self::StreamTaskResultStream>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let task_id = request.into_inner().task_id;
+        log_call("stream_task_result", &task_id, &trace);
+        self.claim_task_id(&task_id, remote_addr)?;
+        self.add_partition_watcher(task_id).map(Response::new)
+    }
+
+    async fn unlock_server(
+        &self,
+        request: Request<UnlockServerRequest>,
+    ) -> Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let task_id = request.into_inner().task_id;
+        log_call("unlock_server", &task_id, &trace);
+        self.claim_task_id(&task_id, remote_addr)?;
+        match self.unlock(task_id.clone()) {
+            Ok(_) => Ok(Response::new(ok_response(format!(
+                "server unlocked by task {}",
+                task_id
+            )))),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn cancel_task(
+        &self,
+        request: Request<CancelTaskRequest>,
+    ) -> Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let task_id = request.into_inner().task_id;
+        log_call("cancel_task", &task_id, &trace);
+        self.claim_task_id(&task_id, remote_addr)?;
+        self.cancel_task(&task_id)
+            .map(|msg| Response::new(ok_response(msg)))
+    }
+
+    async fn ack_result(
+        &self,
+        request: Request<AckResultRequest>,
+    ) -> Result<Response<BaseResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let task_id = request.into_inner().task_id;
+        log_call("ack_result", &task_id, &trace);
+        self.claim_task_id(&task_id, remote_addr)?;
+        match self.ack_result(task_id.clone()) {
+            Ok(_) => Ok(Response::new(ok_response(format!(
+                "result of task {} acked",
+                task_id
+            )))),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_capabilities(
+        &self,
+        _request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<Capabilities>, Status> {
+        let si = self.server_info.lock();
+        Ok(Response::new(Capabilities {
+            payload_encodings: codec::supported_encodings()
+                .iter()
+                .map(|e| e.to_wire() as i32)
+                .collect(),
+            priority_only: si.priority_only,
+            constraints_per_sec: si
+                .hardware_profile
+                .as_ref()
+                .map_or(0.0, |p| p.constraints_per_sec),
+            cpu_only: !utils::gpu_support(),
+            gpus: gpu::devices()
+                .into_iter()
+                .map(|d| snark_proof_grpc::GpuDevice {
+                    name: d.name,
+                    memory_bytes: d.memory_bytes,
+                    unique_id: d.unique_id,
+                })
+                .collect(),
+            gateway: None,
+        }))
+    }
+
+    async fn estimate_task(
+        &self,
+        request: Request<EstimateTaskRequest>,
+    ) -> Result<Response<TaskEstimate>, Status> {
+        let req = request.into_inner();
+        let post_config = match &req.post_config {
+            Some(p) => codec::post_config_from_proto(p)
+                .map_err(|e| invalid_task_status(tasks::invalid_field("post_config", e)))?,
+            None => {
+                return Err(invalid_task_status(tasks::invalid_field(
+                    "post_config",
+                    anyhow::Error::msg("not set"),
+                )))
+            }
+        };
+        let task_info = TaskInfo {
+            replicas_len: req.replicas_len as usize,
+            partitions: match req.partitions {
+                0 => None,
+                p => Some(p as usize),
+            },
+            ..Default::default()
+        };
+        let partitions = task_info.partitions(&post_config).unwrap_or(1);
+        let si = self.server_info.lock();
+        let (tasks_ahead, _, _) = si.queue_wait(SystemTime::now());
+        let sector_size = u64::from(post_config.sector_size);
+        let estimate = si.proving_times.estimate(sector_size, partitions);
+        // before the first task of the sector size, from the benchmark of the hardware
+        let calibrated = match estimate {
+            Some(_) => None,
+            None => si
+                .hardware_profile
+                .as_ref()
+                .and_then(|p| p.estimate(sector_size, partitions)),
+        };
+        Ok(Response::new(TaskEstimate {
+            known: estimate.is_some() || calibrated.is_some(),
+            prove_ms: estimate
+                .map(|e| e.prove)
+                .or(calibrated)
+                .map_or(0, |d| d.as_millis() as u64),
+            partitions: partitions as u32,
+            samples: estimate.map_or(0, |e| e.samples as u32),
+            scaled: estimate.map_or(false, |e| e.scaled),
+            tasks_ahead: tasks_ahead as u32,
+            calibrated: calibrated.is_some(),
+        }))
+    }
+
+    async fn export_task_report(
+        &self,
+        request: Request<ExportTaskReportRequest>,
+    ) -> Result<Response<TaskReport>, Status> {
+        auth::authorize(&request, Role::Admin)?;
+        let req = request.into_inner();
+        let audit_dir = self.server_info.lock().audit_dir.clone();
+        let dir = match audit_dir {
+            Some(d) => d,
+            None => {
+                return Err(Status::failed_precondition(
+                    "server keeps no task history, run it with --audit-dir",
+                ))
+            }
+        };
+        let (from_ms, to_ms) = (req.from_ms, req.to_ms);
+        let records = spawn_blocking(move || audit::read_audit_records(&dir, from_ms, to_ms))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let limit = match req.limit as usize {
+            0 => audit::REPORT_PAGE_LIMIT,
+            l => l.min(audit::REPORT_PAGE_LIMIT),
+        };
+        let (records, more) = audit::report_page(records, from_ms, &req.after_task_id, limit);
+        let content = match ReportFormat::from_i32(req.format) {
+            Some(ReportFormat::ReportJson) => {
+                serde_json::to_vec_pretty(&records).map_err(|e| Status::internal(e.to_string()))?
+            }
+            Some(ReportFormat::ReportCsv) => audit::report_csv(&records).into_bytes(),
+            None => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown report format {}",
+                    req.format
+                )))
+            }
+        };
+        info!(
+            "export report of {} tasks finished from {} to {}",
+            records.len(),
+            req.from_ms,
+            req.to_ms
+        );
+        let last = records.last().filter(|_| more);
+        Ok(Response::new(TaskReport {
+            content,
+            tasks: records.len() as u32,
+            more,
+            next_from_ms: last.map_or(0, |r| r.finished_at_ms),
+            next_after_task_id: last.map_or_else(String::new, |r| r.task_id.clone()),
+        }))
+    }
+
+    async fn submit_batch(
+        &self,
+        request: Request<SubmitBatchRequest>,
+    ) -> Result<Response<SubmitBatchResponse>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let tasks = request.into_inner().tasks;
+        if tasks.is_empty() {
+            return Err(Status::invalid_argument("batch has no tasks"));
+        }
+        let task_ids: Vec<String> = tasks.iter().map(|t| t.task_id.clone()).collect();
+        let mut unique = task_ids.clone();
+        unique.sort();
+        unique.dedup();
+        if unique.len() != task_ids.len() {
+            return Err(Status::invalid_argument(
+                "task ids of a batch have to be unique",
+            ));
+        }
+        let batch_id = Uuid::new_v4().to_string();
+        log_call("submit_batch", &batch_id, &trace);
+        let owner = remote_addr.map_or_else(String::new, |a| a.ip().to_string());
+        // known before any of its tasks can finish
+        self.server_info
+            .lock()
+            .batches
+            .insert(&batch_id, task_ids, &owner);
+        let mut accepted = vec![];
+        for params in tasks {
+            let task_id = params.task_id.clone();
+            match self
+                .submit_batch_task(params, &batch_id, remote_addr, trace.clone())
+                .await
+            {
+                Ok(msg) => accepted.push(BatchTaskAccepted {
+                    task_id,
+                    accepted: true,
+                    msg,
+                }),
+                Err(e) => {
+                    info!(
+                        "task {} of batch {} refused: {}",
+                        task_id,
+                        batch_id,
+                        e.message()
+                    );
+                    self.server_info.lock().batches.finish(
+                        &batch_id,
+                        &task_id,
+                        BatchOutcome::rejected(TaskStatus::Failed, e.message()),
+                    );
+                    accepted.push(BatchTaskAccepted {
+                        task_id,
+                        accepted: false,
+                        msg: e.message().to_string(),
+                    });
+                }
+            }
+        }
+        info!(
+            "batch {} of {} tasks submitted, {} accepted",
+            batch_id,
+            accepted.len(),
+            accepted.iter().filter(|t| t.accepted).count()
+        );
+        Ok(Response::new(SubmitBatchResponse {
+            batch_id,
+            tasks: accepted,
+        }))
+    }
+
+    async fn get_batch_report(
+        &self,
+        request: Request<GetBatchReportRequest>,
+    ) -> Result<Response<BatchReport>, Status> {
+        let remote_addr = request.remote_addr();
+        let batch_id = request.into_inner().batch_id;
+        self.batch_report(&batch_id, remote_addr).map(Response::new)
+    }
+
+    async fn dump_server_state(
+        &self,
+        request: Request<DumpServerStateRequest>,
+    ) -> Result<Response<ServerStateDump>, Status> {
+        auth::authorize(&request, Role::Admin)?;
+        let state = ServerState::new(&self.server_info.lock());
+        let json =
+            serde_json::to_string_pretty(&state).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(ServerStateDump { json }))
+    }
+
+    async fn manage_param_cache(
+        &self,
+        request: Request<ManageParamCacheRequest>,
+    ) -> Result<Response<ParamCacheState>, Status> {
+        auth::authorize(&request, Role::Admin)?;
+        let req = request.into_inner();
+        let action = ParamCacheAction::from_i32(req.action).ok_or_else(|| {
+            Status::invalid_argument(format!("unknown param cache action {}", req.action))
+        })?;
+        let sector_size = match req.sector_size {
+            0 => None,
+            s => Some(s),
+        };
+        info!("param cache {:?} of sector size {:?}", action, sector_size);
+        // loading takes minutes, and waits for a task loading the same parameters
+        let cached = spawn_blocking(move || match action {
+            ParamCacheAction::ParamsList => Ok(params::cached_params()),
+            ParamCacheAction::ParamsEvict => {
+                params::evict_params(sector_size);
+                Ok(params::cached_params())
+            }
+            ParamCacheAction::ParamsReload => params::reload_params(sector_size),
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| Status::failed_precondition(format!("{:?}", e)))?;
+        Ok(Response::new(ParamCacheState {
+            params: cached.iter().map(cached_params_to_proto).collect(),
+        }))
+    }
+
+    async fn set_server_timeouts(
+        &self,
+        request: Request<SetServerTimeoutsRequest>,
+    ) -> Result<Response<ServerTimeouts>, Status> {
+        auth::authorize(&request, Role::Admin)?;
+        let req = request.into_inner();
+        let fields = [
+            ("server_lock_time_out_ms", req.server_lock_time_out_ms),
+            (
+                "server_lock_time_out_max_ms",
+                req.server_lock_time_out_max_ms,
+            ),
+            (
+                "server_task_get_back_time_out_ms",
+                req.server_task_get_back_time_out_ms,
+            ),
+            (
+                "server_exit_time_out_after_task_done_ms",
+                req.server_exit_time_out_after_task_done_ms,
+            ),
+        ];
+        for (field, ms) in fields.iter() {
+            if *ms != 0 && Duration::from_millis(*ms) < SERVER_TIME_OUT_MIN {
+                return Err(invalid_task_status(tasks::invalid_field(
+                    field,
+                    anyhow::Error::msg(format!("shorter than {:?}", SERVER_TIME_OUT_MIN)),
+                )));
+            }
+        }
+        let keep = |ms: u64, current: Duration| match ms {
+            0 => current,
+            ms => Duration::from_millis(ms),
+        };
+        // checked and applied under one lock, so a refused request changes nothing
+        let mut si = self.server_info.lock();
+        let lock = keep(req.server_lock_time_out_ms, si.server_lock_time_out);
+        let lock_max = keep(req.server_lock_time_out_max_ms, si.server_lock_time_out_max);
+        if lock > lock_max {
+            let field = if req.server_lock_time_out_ms != 0 {
+                "server_lock_time_out_ms"
+            } else {
+                "server_lock_time_out_max_ms"
+            };
+            return Err(invalid_task_status(tasks::invalid_field(
+                field,
+                anyhow::Error::msg(format!(
+                    "lock time out {:?} is longer than the maximum {:?}",
+                    lock, lock_max
+                )),
+            )));
+        }
+        si.server_lock_time_out = lock;
+        si.server_lock_time_out_max = lock_max;
+        si.server_task_get_back_time_out = keep(
+            req.server_task_get_back_time_out_ms,
+            si.server_task_get_back_time_out,
+        );
+        si.server_exit_time_out_after_task_done = keep(
+            req.server_exit_time_out_after_task_done_ms,
+            si.server_exit_time_out_after_task_done,
+        );
+        let timeouts = si.server_timeouts();
+        drop(si);
+        info!("time outs set to {:?}", timeouts);
+        Ok(Response::new(timeouts))
+    }
+
+    type WatchTaskStream = UnboundedReceiverStream<Result<TaskEvent, Status>>;
+
+    async fn watch_task(
+        &self,
+        request: Request<WatchTaskRequest>,
+    ) -> Result<Response<Self::WatchTaskStream>, Status> {
+        let remote_addr = request.remote_addr();
+        let trace = TraceContext::from_metadata(request.metadata());
+        let task_id = request.into_inner().task_id;
+        log_call("watch_task", &task_id, &trace);
+        self.claim_task_id(&task_id, remote_addr)?;
+        match self.add_task_watcher(task_id) {
+            Ok(rx) => Ok(Response::new(UnboundedReceiverStream::new(rx))),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Builder with the connection limits applied, embedders may add layers of their own,
+/// e.g. logging or rate limits, with `.layer()` before adding `snark_task_service`
+pub fn server_builder(limits: &ConnectionLimits) -> Server<GlobalConcurrencyLimitLayer> {
+    let mut builder = Server::builder().accept_http1(true);
+    if limits.max_in_flight_per_connection != 0 {
+        builder = builder.concurrency_limit_per_connection(limits.max_in_flight_per_connection);
+    }
+    builder.layer(GlobalConcurrencyLimitLayer::new(limits.in_flight_limit()))
+}
+
+/// The gRPC service of the server, callers are authenticated as configured with `set_auth`
+pub fn snark_task_service(
+    srv: WindowPostSnarkServer,
+) -> InterceptedService<SnarkTaskServiceServer<WindowPostSnarkServer>, impl Interceptor + Clone> {
+    service_with_interceptor(srv, None)
+}
+
+// authentication runs first, so the interceptor of an embedder sees the principal
+fn service_with_interceptor(
+    srv: WindowPostSnarkServer,
+    interceptor: Option<ExtraInterceptor>,
+) -> InterceptedService<SnarkTaskServiceServer<WindowPostSnarkServer>, impl Interceptor + Clone> {
+    let auth = srv.server_info.lock().auth.clone();
+    if auth.is_enabled() {
+        info!("callers have to authenticate with {:?}", auth);
+    }
+    let mut authenticate = auth::interceptor(auth);
+    SnarkTaskServiceServer::with_interceptor(srv, move |request| {
+        let request = authenticate(request)?;
+        match &interceptor {
+            Some(f) => f(request),
+            None => Ok(request),
+        }
+    })
+}
+
+/// Interceptor of an embedder, run on every call after authentication
+pub type ExtraInterceptor =
+    Arc<dyn Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static>;
+
+/// Options of a server, see `WindowPostSnarkServer::builder`
+#[derive(Clone)]
+pub struct ServerBuilder {
+    addr: SocketAddr,
+    limits: ConnectionLimits,
+    tls: Option<ServerTlsConfig>,
+    client_ca: Option<TlsCertificate>,
+    lock_time_out: Option<Duration>,
+    task_get_back_time_out: Option<Duration>,
+    exit_time_out_after_task_done: Option<Duration>,
+    auth: AuthConfig,
+    interceptor: Option<ExtraInterceptor>,
+    slots: Option<Vec<SlotDevice>>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder {
+            addr: SocketAddr::from(([0, 0, 0, 0], SERVER_PORT_DEFAULT)),
+            limits: ConnectionLimits::default(),
+            tls: None,
+            client_ca: None,
+            lock_time_out: None,
+            task_get_back_time_out: None,
+            exit_time_out_after_task_done: None,
+            auth: AuthConfig::default(),
+            interceptor: None,
+            slots: None,
+        }
+    }
+}
+
+impl ServerBuilder {
+    pub fn addr(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// Listen on all interfaces on this port
+    pub fn port(mut self, port: u16) -> Self {
+        self.addr = SocketAddr::from(([0, 0, 0, 0], port));
+        self
+    }
+
+    pub fn limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Serve over TLS with this PEM encoded certificate chain and private key
+    pub fn tls(mut self, cert_pem: impl AsRef<[u8]>, key_pem: impl AsRef<[u8]>) -> Self {
+        self.tls = Some(ServerTlsConfig::new().identity(TlsIdentity::from_pem(cert_pem, key_pem)));
+        self
+    }
+
+    /// Only take calls of clients presenting a certificate this PEM encoded CA signed,
+    /// needs `tls`
+    pub fn client_ca(mut self, ca_pem: impl AsRef<[u8]>) -> Self {
+        self.client_ca = Some(TlsCertificate::from_pem(ca_pem));
+        self
+    }
+
+    pub fn lock_time_out(mut self, time_out: Duration) -> Self {
+        self.lock_time_out = Some(time_out);
+        self
+    }
+
+    pub fn task_get_back_time_out(mut self, time_out: Duration) -> Self {
+        self.task_get_back_time_out = Some(time_out);
+        self
+    }
+
+    pub fn exit_time_out_after_task_done(mut self, time_out: Duration) -> Self {
+        self.exit_time_out_after_task_done = Some(time_out);
+        self
+    }
+
+    pub fn auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Prove on these slots instead of a single one in the process of the server, see
+    /// `gpu::detect_slots`
+    pub fn slots(mut self, slots: Vec<SlotDevice>) -> Self {
+        self.slots = Some(slots);
+        self
+    }
+
+    pub fn interceptor<F>(mut self, f: F) -> Self
+    where
+        F: Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+    {
+        self.interceptor = Some(Arc::new(f));
+        self
+    }
+
+    /// Server sending the tasks it accepts to `task_run_tx`, for `tasks::run_task` to prove
+    pub fn build(self, task_run_tx: UnboundedSender<String>) -> anyhow::Result<SnarkServer> {
+        let tls = match (self.tls, self.client_ca) {
+            (Some(tls), Some(ca)) => Some(tls.client_ca_root(ca)),
+            (tls, None) => tls,
+            (None, Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "client certificates can only be checked when serving over tls"
+                ))
+            }
+        };
+        let srv = WindowPostSnarkServer::new(task_run_tx);
+        if let Some(t) = self.lock_time_out {
+            srv.set_server_lock_time_out(t)?;
+        }
+        if let Some(t) = self.task_get_back_time_out {
+            srv.set_server_task_get_back_time_out(t)?;
+        }
+        if let Some(t) = self.exit_time_out_after_task_done {
+            srv.set_server_exit_time_out_after_task_done(t)?;
+        }
+        srv.set_auth(self.auth)?;
+        if let Some(slots) = self.slots {
+            srv.set_slots(slots)?;
+        }
+        Ok(SnarkServer {
+            srv,
+            addr: self.addr,
+            limits: self.limits,
+            tls,
+            interceptor: self.interceptor,
+        })
+    }
+}
+
+/// Server built by ServerBuilder, not listening yet
+pub struct SnarkServer {
+    srv: WindowPostSnarkServer,
+    addr: SocketAddr,
+    limits: ConnectionLimits,
+    tls: Option<ServerTlsConfig>,
+    interceptor: Option<ExtraInterceptor>,
+}
+
+impl SnarkServer {
+    /// The server, to change further settings and to share its state with `tasks::run_task`
+    pub fn server(&self) -> &WindowPostSnarkServer {
+        &self.srv
+    }
+
+    /// Listen and serve until `shutdown` completes
+    pub async fn serve<F: Future<Output = ()>>(self, shutdown: F) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.addr)
+            .await
+            .with_context(|| format!("failed to listen on {}", self.addr))?;
+        info!(
+            "Server listening on {} with limits {:?}",
+            self.addr, self.limits
+        );
+        let mut builder = server_builder(&self.limits);
+        if let Some(tls) = self.tls {
+            info!("calls are served over tls");
+            builder = builder.tls_config(tls)?;
+        }
+        // grpc.health.v1 for probes of clients and load balancers, it needs no token
+        let (mut health, health_service) = tonic_health::server::health_reporter();
+        // finished batch tasks and cancelled tasks are cleared off the server, and batches
+        // nobody asks the report of anymore are dropped, without waiting for a call
+        let srv = self.srv.clone();
+        let batch_updates = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(BATCH_UPDATE_INTERVAL).await;
+                let mut si = srv.server_info.lock();
+                si.prune_batches(Instant::now());
+                let finished = si.slots.iter().any(|s| {
+                    s.task_info.batch_id.is_some()
+                        || s.task_info.task_status == TaskStatus::Cancelled
+                });
+                if !finished {
+                    continue;
+                }
+                if let Err(e) = srv.reclaim_if_expired(&mut si) {
+                    error!("failed to move on from a finished task: {}", e);
+                }
+            }
+        });
+        let server_info = self.srv.server_info.clone();
+        let health_updates = tokio::spawn(async move {
+            // the empty service is the process itself, alive as long as it answers
+            health
+                .set_service_status("", tonic_health::ServingStatus::Serving)
+                .await;
+            loop {
+                let serving = server_info.lock().is_serving();
+                if serving {
+                    health
+                        .set_serving::<SnarkTaskServiceServer<WindowPostSnarkServer>>()
+                        .await;
+                } else {
+                    health
+                        .set_not_serving::<SnarkTaskServiceServer<WindowPostSnarkServer>>()
+                        .await;
+                }
+                tokio::time::sleep(HEALTH_UPDATE_INTERVAL).await;
+            }
+        });
+        let served = builder
+            .add_service(health_service)
+            .add_service(service_with_interceptor(self.srv, self.interceptor))
+            .serve_with_incoming_shutdown(limits::limited_incoming(listener, self.limits), shutdown)
+            .await;
+        health_updates.abort();
+        batch_updates.abort();
+        served?;
+        info!("server stop listen");
+        Ok(())
+    }
 }
@@ -0,0 +1,247 @@
+//! The whole state of a server as JSON, for operators to attach to bug reports about
+//! stuck servers. Payloads and proofs are left out, only their sizes are kept.
+
+use crate::audit::to_hex;
+use crate::dump::ServerSnapshot;
+use crate::server::ServerInfo;
+use crate::tasks::TaskInfo;
+use crate::utils;
+use filecoin_proofs::PoStConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerState {
+    // unix ms the state was taken at
+    pub taken_at_ms: u64,
+    // of the server as a whole, see ServerInfo::status
+    pub status: String,
+    pub draining: bool,
+    pub previous_crash: String,
+    pub slots: Vec<SlotState>,
+    pub queue: Vec<TaskState>,
+    pub lock_waiters: Vec<LockWaiterState>,
+    // task id to the streams watching it
+    pub task_watchers: BTreeMap<String, usize>,
+    pub recent_errors: Vec<ErrorState>,
+    pub consecutive_failures: u32,
+    pub config: ConfigState,
+}
+
+/// A slot and the task holding it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SlotState {
+    // numbered from 1 as on the wire
+    pub slot: u32,
+    pub device_id: String,
+    pub device_name: String,
+    // proves in a child process pinned to its device
+    pub child: bool,
+    pub status: String,
+    pub error: String,
+    pub error_kind: String,
+    pub error_stack: Vec<String>,
+    pub proving_hung: bool,
+    // the task holding the slot, None while it is free
+    pub task: Option<TaskState>,
+}
+
+/// A task without its payloads
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TaskState {
+    pub task_id: String,
+    pub task_status: String,
+    pub client_id: String,
+    pub trace_id: String,
+    pub priority: bool,
+    pub post_config: Option<PoStConfig>,
+    pub replicas_len: usize,
+    pub partitions: Option<usize>,
+    pub payload_encoding: String,
+    pub vanilla_proof_bytes: usize,
+    pub vanilla_proof_path: Option<PathBuf>,
+    // full PoSt tasks only
+    pub remote_replicas: Option<usize>,
+    pub pub_in_bytes: usize,
+    pub pub_in_sha256: String,
+    pub result_bytes: usize,
+    pub max_threads: usize,
+    pub degraded: bool,
+    pub verify_proof: bool,
+    pub cancelled: bool,
+    pub created_at_ms: u64,
+    pub started_at_ms: u64,
+    pub finished_at_ms: u64,
+    // how long ago the lock time out and the get back time out started running
+    pub locked_for_ms: Option<u64>,
+    pub done_for_ms: Option<u64>,
+    pub decode_ms: u64,
+    pub load_params_ms: u64,
+    pub prove_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LockWaiterState {
+    pub task_id: String,
+    // the slot it waits for, numbered from 1, 0 for any
+    pub slot: u32,
+    pub lock_time_out_ms: u64,
+    pub last_seen_ms_ago: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ErrorState {
+    pub task_id: String,
+    pub error: String,
+    pub kind: String,
+    pub failed_at_ms: u64,
+}
+
+/// Settings the server runs with, secrets left out
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigState {
+    #[serde(flatten)]
+    pub server: ServerSnapshot,
+    pub lock_waiter_time_out_ms: u64,
+    pub task_id_reuse_time_out_ms: u64,
+    pub prove_time_out_ms: Option<u64>,
+    pub memory_limit: Option<u64>,
+    pub task_threads: usize,
+    pub runtime_worker_threads: usize,
+    pub runtime_max_blocking_threads: usize,
+    pub priority_only: bool,
+    pub cpu_fallback: bool,
+    pub verify_proofs: bool,
+    pub preempt_window_post: bool,
+    pub task_queue_capacity: usize,
+    pub auth: bool,
+    pub dump_dir: Option<PathBuf>,
+    pub audit_dir: Option<PathBuf>,
+    pub payload_dirs: Vec<PathBuf>,
+    pub constraints_per_sec: Option<f64>,
+}
+
+impl ServerState {
+    pub fn new(si: &ServerInfo) -> Self {
+        let now = Instant::now();
+        ServerState {
+            taken_at_ms: utils::unix_millis(Some(SystemTime::now())),
+            status: si.status().to_string(),
+            draining: si.draining,
+            previous_crash: si.previous_crash.clone(),
+            slots: si
+                .slots
+                .iter()
+                .enumerate()
+                .map(|(i, s)| SlotState {
+                    slot: i as u32 + 1,
+                    device_id: s.device.device_id.clone(),
+                    device_name: s.device.name.clone(),
+                    child: !s.device.in_process(),
+                    status: s.status.to_string(),
+                    error: s.error.clone(),
+                    error_kind: format!("{:?}", s.error_kind),
+                    error_stack: s.error_stack.clone(),
+                    proving_hung: s.proving_hung,
+                    task: if s.task_info.task_id.is_empty() {
+                        None
+                    } else {
+                        Some(TaskState::new(&s.task_info, now))
+                    },
+                })
+                .collect(),
+            queue: si
+                .task_queue
+                .iter()
+                .map(|t| TaskState::new(t, now))
+                .collect(),
+            lock_waiters: si
+                .lock_waiters
+                .iter()
+                .map(|w| LockWaiterState {
+                    task_id: w.task_id.clone(),
+                    slot: w.slot.map_or(0, |i| i as u32 + 1),
+                    lock_time_out_ms: millis(w.lock_time_out),
+                    last_seen_ms_ago: millis(now.saturating_duration_since(w.last_seen)),
+                })
+                .collect(),
+            task_watchers: si
+                .task_watchers
+                .iter()
+                .map(|(t, w)| (t.clone(), w.len()))
+                .collect(),
+            recent_errors: si
+                .recent_errors
+                .iter()
+                .map(|e| ErrorState {
+                    task_id: e.task_id.clone(),
+                    error: e.error.clone(),
+                    kind: format!("{:?}", e.kind),
+                    failed_at_ms: utils::unix_millis(Some(e.failed_at)),
+                })
+                .collect(),
+            consecutive_failures: si.recent_errors.consecutive_failures(),
+            config: ConfigState {
+                server: ServerSnapshot::new(si),
+                lock_waiter_time_out_ms: millis(si.lock_waiter_time_out),
+                task_id_reuse_time_out_ms: millis(si.task_id_reuse_time_out),
+                prove_time_out_ms: si.prove_time_out.map(millis),
+                memory_limit: si.memory_limit,
+                task_threads: si.task_threads,
+                runtime_worker_threads: si.runtime.worker_threads,
+                runtime_max_blocking_threads: si.runtime.max_blocking_threads,
+                priority_only: si.priority_only,
+                cpu_fallback: si.cpu_fallback,
+                verify_proofs: si.verify_proofs,
+                preempt_window_post: si.preempt_window_post,
+                task_queue_capacity: si.task_queue.capacity(),
+                auth: si.auth.is_enabled(),
+                dump_dir: si.dump.as_ref().map(|d| d.dir.clone()),
+                audit_dir: si.audit_dir.clone(),
+                payload_dirs: si.payload_dirs.clone(),
+                constraints_per_sec: si.hardware_profile.as_ref().map(|p| p.constraints_per_sec),
+            },
+        }
+    }
+}
+
+impl TaskState {
+    pub fn new(t: &TaskInfo, now: Instant) -> Self {
+        TaskState {
+            task_id: t.task_id.clone(),
+            task_status: t.task_status.to_string(),
+            client_id: t.client_id.clone(),
+            trace_id: t.trace_id().to_string(),
+            priority: t.priority,
+            post_config: t.post_config.clone(),
+            replicas_len: t.replicas_len,
+            partitions: t.partitions,
+            payload_encoding: t.payload_encoding.to_string(),
+            // the payloads of the running task are with the worker, not in t
+            vanilla_proof_bytes: t.vanilla_proof_bytes,
+            vanilla_proof_path: t.vanilla_proof_path.clone(),
+            remote_replicas: t.remote.as_ref().map(|r| r.replicas.len()),
+            pub_in_bytes: t.pub_in_bytes,
+            pub_in_sha256: to_hex(&t.pub_in_digest),
+            result_bytes: t.result.len(),
+            max_threads: t.max_threads,
+            degraded: t.degraded,
+            verify_proof: t.verify_proof,
+            cancelled: t.cancel.is_cancelled(),
+            created_at_ms: utils::unix_millis(t.created_at),
+            started_at_ms: utils::unix_millis(t.started_at),
+            finished_at_ms: utils::unix_millis(t.finished_at),
+            locked_for_ms: t.timers.locked_for(now).map(millis),
+            done_for_ms: t.timers.done_for(now).map(millis),
+            decode_ms: millis(t.timings.decode),
+            load_params_ms: millis(t.timings.load_params),
+            prove_ms: millis(t.timings.prove),
+        }
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
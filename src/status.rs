@@ -1,3 +1,4 @@
+use crate::snark_proof_grpc::TaskState;
 use strum_macros::{Display, EnumString};
 
 #[derive(Debug, PartialEq, Clone, EnumString, Display)]
@@ -18,12 +19,18 @@ impl Default for ServerStatus {
     }
 }
 
+/// Where a task stands. A submitted task is Ready, or Queued behind the running one
+/// and Ready once dequeued; it ends Done and then Returned, Failed, TimedOut when
+/// proving took longer than the prove time out, or Cancelled when it was dropped
+/// from the queue before it ran, e.g. on shutdown.
 #[derive(Debug, PartialEq, Clone, EnumString, Display)]
 pub enum TaskStatus {
     #[strum(to_string = "None")]
     None,
     #[strum(to_string = "Ready")]
     Ready,
+    #[strum(to_string = "Queued")]
+    Queued,
     #[strum(to_string = "Working")]
     Working,
     #[strum(to_string = "Done")]
@@ -32,6 +39,10 @@ pub enum TaskStatus {
     Returned,
     #[strum(to_string = "Failed")]
     Failed,
+    #[strum(to_string = "Cancelled")]
+    Cancelled,
+    #[strum(to_string = "TimedOut")]
+    TimedOut,
 }
 
 impl Default for TaskStatus {
@@ -39,3 +50,27 @@ impl Default for TaskStatus {
         TaskStatus::None
     }
 }
+
+impl TaskStatus {
+    /// Ended without a proof
+    pub fn is_failed(&self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Failed | TaskStatus::Cancelled | TaskStatus::TimedOut
+        )
+    }
+
+    pub fn to_wire(&self) -> TaskState {
+        match self {
+            TaskStatus::None => TaskState::TaskNone,
+            TaskStatus::Ready => TaskState::TaskReady,
+            TaskStatus::Queued => TaskState::TaskQueued,
+            TaskStatus::Working => TaskState::TaskWorking,
+            TaskStatus::Done => TaskState::TaskDone,
+            TaskStatus::Returned => TaskState::TaskReturned,
+            TaskStatus::Failed => TaskState::TaskFailed,
+            TaskStatus::Cancelled => TaskState::TaskCancelled,
+            TaskStatus::TimedOut => TaskState::TaskTimedOut,
+        }
+    }
+}
@@ -1,48 +1,764 @@
+use crate::audit::{self, AuditRecord};
+use crate::c2::{run_c2, C2Task};
+use crate::codec::{self, Encoding};
+use crate::compat;
+use crate::crash;
+use crate::dump::{self, ServerSnapshot};
+use crate::error::{self, FailureKind};
+use crate::gpu::SlotDevice;
+use crate::inspect;
+use crate::params;
+use crate::pub_inputs::RawPublicInputs;
+use crate::remote::{self, RemotePoSt};
 use crate::server::ServerInfo;
 use crate::snark_proof_grpc::SnarkTaskRequestParams;
 use crate::status::{ServerStatus, TaskStatus};
-use filecoin_proofs::caches::get_post_params;
-use filecoin_proofs::parameters::window_post_setup_params;
-use filecoin_proofs::{get_partitions_for_window_post, with_shape, PoStConfig};
+use crate::trace::TraceContext;
+use crate::vanilla;
+use anyhow::Context;
+use bellperson::groth16;
+use blstrs::Bls12;
+use filecoin_hashers::poseidon::PoseidonDomain;
+use filecoin_hashers::Hasher;
+use filecoin_proofs::parameters::{window_post_setup_params, winning_post_setup_params};
+use filecoin_proofs::{
+    get_partitions_for_window_post, with_shape, PoStConfig, PoStType, SINGLE_PARTITION_PROOF_LEN,
+};
+use futures::stream::{FuturesUnordered, StreamExt};
+use lazy_static::lazy_static;
 use log::{error, info, warn};
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use storage_proofs_core::{
     compound_proof, compound_proof::CompoundProof, error::Result, merkle::MerkleTreeTrait,
+    multi_proof::MultiProof,
+};
+use storage_proofs_post::fallback::{
+    self, ChallengeRequirements, FallbackPoSt, FallbackPoStCompound, PublicInputs,
 };
-use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCompound};
 use tokio::select;
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot;
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+const PROVE_WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+// name of the thread proving runs on, shows in crash reports
+pub const PROVE_THREAD_NAME: &str = "wps-prove";
+
+/// Env which keeps bellperson off the gpus
+pub const NO_GPU_ENV: &str = "BELLMAN_NO_GPU";
+/// Hidden subcommand of the server binary proving a task of `prove_in_child`
+pub const PROVE_CMD: &str = "prove-task";
+const CHILD_PROOF_FILE: &str = "proof";
+const CHILD_TIMINGS_FILE: &str = "timings.json";
+const CHILD_ERROR_FILE: &str = "error.json";
+// how often a child proving a task is checked for having exited or its task cancelled
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 #[derive(Default, Debug, Clone)]
 pub struct TaskInfo {
     pub task_id: String,
     pub vanilla_proof: Vec<u8>,
+    // read into vanilla_proof when the task starts proving, set instead of it
+    pub vanilla_proof_path: Option<PathBuf>,
+    // full PoSt tasks carry their replicas instead of vanilla proofs and public inputs
+    pub remote: Option<RemotePoSt>,
     pub pub_in: Vec<u8>,
-    pub post_config: Vec<u8>,
+    // set instead of pub_in, built into the public inputs when the task is proved
+    pub raw_pub_in: Option<RawPublicInputs>,
+    pub post_config: Option<PoStConfig>,
     pub replicas_len: usize,
+    // partitions the client asked for, derived from replicas_len when None. Of the whole
+    // sector set when the task is one partition of it.
+    pub partitions: Option<usize>,
+    // k of the public inputs, the task proves this one partition of a larger sector set
+    pub partition_index: Option<usize>,
     pub result: Vec<u8>,
     pub task_status: TaskStatus,
+    pub priority: bool,
+    pub client_id: String,
+    pub payload_encoding: Encoding,
+    // 0 means no limit
+    pub max_threads: usize,
+    // sha256 of pub_in as received, kept after the payloads are wiped
+    pub pub_in_digest: Vec<u8>,
+    // sha256 of vanilla_proof as received, for the audit record
+    pub vanilla_proof_digest: Vec<u8>,
+    // sizes of the payloads as received, kept while the worker holds the payloads
+    pub vanilla_proof_bytes: usize,
+    pub pub_in_bytes: usize,
+    // wall clock times, unlike Instant they mean something to clients and in logs
+    pub created_at: Option<SystemTime>,
+    pub started_at: Option<SystemTime>,
+    pub finished_at: Option<SystemTime>,
+    pub timers: TaskTimers,
+    pub timings: TaskTimings,
+    // trace context the task was submitted in
+    pub trace: Option<TraceContext>,
+    // proved on the cpu after proving on the gpu failed
+    pub degraded: bool,
+    // check the proof before returning it
+    pub verify_proof: bool,
+    // shared by the clones of the task, so the running one can be stopped
+    pub cancel: CancelToken,
+    // submitted in a batch, its outcome is kept with the batch instead of waiting to be fetched
+    pub batch_id: Option<String>,
+    // proved partition by partition, so a winning PoSt can take the gpu between two of them
+    pub preemptible: bool,
+    // set when a winning PoSt waits behind the task, shared like cancel
+    pub preempt: CancelToken,
+    // proved partition by partition, so each proof can be streamed as soon as it is done
+    pub stream_partitions: bool,
+    // partitions proved before the task was preempted, and the streams waiting for more
+    pub checkpoint: Checkpoint,
+    // seal commit phase2 of a sector instead of a PoSt, its phase1 output is the vanilla proof
+    pub c2: Option<C2Task>,
+}
+
+/// When the time outs of a task started running, each from its own event, so e.g.
+/// answering a status query never restarts the time a client has to fetch its result
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TaskTimers {
+    // the lock time out runs from here
+    pub locked_at: Option<Instant>,
+    // done or failed, the get back time out runs from here
+    pub done_at: Option<Instant>,
+}
+
+impl TaskTimers {
+    /// Time since the task was locked, None while it holds no lock
+    pub fn locked_for(&self, now: Instant) -> Option<Duration> {
+        self.locked_at.map(|t| now.saturating_duration_since(t))
+    }
+
+    /// Time since the task was done or failed, None while it is not
+    pub fn done_for(&self, now: Instant) -> Option<Duration> {
+        self.done_at.map(|t| now.saturating_duration_since(t))
+    }
+}
+
+/// Payloads of a task. The worker moves them out of the server state while the task
+/// proves, so the lock status and lock calls wait on is never held while hundreds of
+/// megabytes are copied or wiped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskPayload {
+    pub vanilla_proof: Vec<u8>,
+    pub pub_in: Vec<u8>,
 }
 
-pub fn set_task_info(snark_params: &SnarkTaskRequestParams) -> TaskInfo {
-    let task_info = TaskInfo {
-        task_id: snark_params.task_id.clone(),
-        vanilla_proof: snark_params.vanilla_proof.clone(),
-        pub_in: snark_params.pub_in.clone(),
-        post_config: snark_params.post_config.clone(),
-        replicas_len: snark_params.replicas_len as usize,
+impl Drop for TaskPayload {
+    fn drop(&mut self) {
+        self.vanilla_proof.zeroize();
+        self.pub_in.zeroize();
+    }
+}
+
+/// Stops a task at the next checkpoint between its proving phases. Unless the task is
+/// preemptible, bellperson proves all partitions in one call and there is no checkpoint
+/// between partitions.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Checkpoint, an error once cancelled
+    pub fn check(&self, task_id: &str) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(anyhow::Error::from(error::Error::TaskCancelled(
+                task_id.to_string(),
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What a window PoSt task preempted for a winning PoSt keeps until it runs again: the
+/// proofs of the partitions it finished and its payloads. Shared by the clones of the task.
+/// Streams of the task's partition proofs are fed from it as well.
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoint(Arc<Mutex<CheckpointState>>);
+
+#[derive(Debug, Default)]
+struct CheckpointState {
+    proofs: Vec<Vec<u8>>,
+    payload: Option<TaskPayload>,
+    watchers: Vec<PartitionWatcher>,
+}
+
+/// Index and groth proof of a partition, or why the task stopped before its last one
+pub type PartitionEvent = std::result::Result<(usize, Vec<u8>), String>;
+type PartitionWatcher = UnboundedSender<PartitionEvent>;
+
+impl Checkpoint {
+    /// Groth proofs of the partitions proved so far, in partition order
+    pub fn proofs(&self) -> Vec<Vec<u8>> {
+        self.0.lock().proofs.clone()
+    }
+
+    pub fn partitions_done(&self) -> usize {
+        self.0.lock().proofs.len()
+    }
+
+    pub fn push_proof(&self, proof: Vec<u8>) {
+        let mut state = self.0.lock();
+        let partition = state.proofs.len();
+        state
+            .watchers
+            .retain(|w| w.send(Ok((partition, proof.clone()))).is_ok());
+        state.proofs.push(proof);
+    }
+
+    /// The partitions proved so far right away, later ones as they are proved
+    pub fn watch(&self) -> UnboundedReceiver<PartitionEvent> {
+        let mut state = self.0.lock();
+        let (tx, rx) = unbounded_channel();
+        for (partition, proof) in state.proofs.iter().enumerate() {
+            // receiver is held by us, can not be closed yet
+            let _ = tx.send(Ok((partition, proof.clone())));
+        }
+        state.watchers.push(tx);
+        rx
+    }
+
+    /// Send the partitions of `proof` the watchers did not get yet, e.g. all of them for a
+    /// task proved in one call, and end their streams
+    pub fn finish(&self, proof: &[u8]) {
+        let mut state = self.0.lock();
+        let sent = state.proofs.len();
+        for (partition, p) in proof
+            .chunks(SINGLE_PARTITION_PROOF_LEN)
+            .enumerate()
+            .skip(sent)
+        {
+            state
+                .watchers
+                .retain(|w| w.send(Ok((partition, p.to_vec()))).is_ok());
+        }
+        state.watchers.clear();
+    }
+
+    /// End the streams of the watchers with the error of the task
+    pub fn fail(&self, msg: &str) {
+        let mut state = self.0.lock();
+        for w in state.watchers.drain(..) {
+            let _ = w.send(Err(msg.to_string()));
+        }
+    }
+
+    /// Keep the payloads while the task waits to resume
+    pub fn keep_payload(&self, payload: TaskPayload) {
+        self.0.lock().payload = Some(payload);
+    }
+
+    pub fn take_payload(&self) -> Option<TaskPayload> {
+        self.0.lock().payload.take()
+    }
+}
+
+impl TaskInfo {
+    /// Partitions the task is proved in, None for one as get_partitions_for_window_post
+    /// returns it, so the public params of both ways are shared
+    pub fn partitions(&self, post_config: &PoStConfig) -> Option<usize> {
+        if self.partition_index.is_some() {
+            return None;
+        }
+        match self.partitions {
+            Some(p) if p > 1 => Some(p),
+            Some(_) => None,
+            None => get_partitions_for_window_post(self.replicas_len, post_config),
+        }
+    }
+
+    /// Trace id of the submitting call, empty when it had none
+    pub fn trace_id(&self) -> &str {
+        match &self.trace {
+            Some(t) => &t.trace_id,
+            None => "",
+        }
+    }
+
+    pub fn is_winning(&self) -> bool {
+        matches!(
+            self.post_config,
+            Some(PoStConfig {
+                typ: PoStType::Winning,
+                ..
+            })
+        )
+    }
+
+    /// Winning PoSt proofs miss their block when they wait, they count as priority tasks
+    pub fn is_priority(&self) -> bool {
+        self.priority || self.is_winning()
+    }
+
+    /// Resolve the paths the task reads from, they have to lie in the payload dirs
+    pub fn resolve_payload_paths(&mut self, payload_dirs: &[PathBuf]) -> Result<()> {
+        if let Some(p) = &self.vanilla_proof_path {
+            self.vanilla_proof_path =
+                Some(check_payload_path("vanilla_proof_path", p, payload_dirs)?);
+        }
+        if let Some(remote) = &mut self.remote {
+            for r in remote.replicas.iter_mut() {
+                r.replica_path =
+                    check_payload_path("replicas.replica_path", &r.replica_path, payload_dirs)?;
+                r.cache_dir = check_payload_path("replicas.cache_dir", &r.cache_dir, payload_dirs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the payloads out, what is left is cheap to clone and to wipe
+    pub fn take_payload(&mut self) -> TaskPayload {
+        TaskPayload {
+            vanilla_proof: std::mem::take(&mut self.vanilla_proof),
+            pub_in: std::mem::take(&mut self.pub_in),
+        }
+    }
+
+    /// Put payloads taken out before back, the task's own are wiped
+    pub fn set_payload(&mut self, mut payload: TaskPayload) {
+        self.vanilla_proof.zeroize();
+        self.pub_in.zeroize();
+        self.vanilla_proof = std::mem::take(&mut payload.vanilla_proof);
+        self.pub_in = std::mem::take(&mut payload.pub_in);
+    }
+
+    /// Overwrite the payloads and the result, so they do not linger in memory once not needed
+    pub fn wipe(&mut self) {
+        self.vanilla_proof.zeroize();
+        self.pub_in.zeroize();
+        self.result.zeroize();
+    }
+}
+
+/// Task of the params, their payloads are moved into it rather than copied
+pub fn set_task_info(mut snark_params: SnarkTaskRequestParams) -> Result<TaskInfo> {
+    if snark_params.vanilla_proofs.is_some()
+        && !(snark_params.vanilla_proof.is_empty() && snark_params.vanilla_proof_path.is_empty())
+    {
+        return Err(invalid_field(
+            "vanilla_proofs",
+            anyhow::Error::msg("set together with vanilla_proof or vanilla_proof_path"),
+        ));
+    }
+    let vanilla_proof_path = if snark_params.vanilla_proof_path.is_empty() {
+        None
+    } else if !snark_params.vanilla_proof.is_empty() {
+        return Err(invalid_field(
+            "vanilla_proof_path",
+            anyhow::Error::msg("set together with vanilla_proof"),
+        ));
+    } else {
+        Some(PathBuf::from(&snark_params.vanilla_proof_path))
+    };
+    let remote = RemotePoSt::from_proto(&snark_params)?;
+    if remote.is_some()
+        && !(snark_params.vanilla_proof.is_empty()
+            && snark_params.vanilla_proof_path.is_empty()
+            && snark_params.vanilla_proofs.is_none()
+            && snark_params.pub_in.is_empty())
+    {
+        return Err(invalid_field(
+            "replicas",
+            anyhow::Error::msg("set together with vanilla proofs or public inputs"),
+        ));
+    }
+    let raw_pub_in = RawPublicInputs::from_proto(&snark_params)?;
+    if let Some(r) = &raw_pub_in {
+        if remote.is_some() || !snark_params.pub_in.is_empty() {
+            return Err(invalid_field(
+                "pub_sectors",
+                anyhow::Error::msg("set together with replicas or pub_in"),
+            ));
+        }
+        // comm_r has to be a field element, window and winning PoSt trees all hash with
+        // poseidon. The randomness is masked to one.
+        r.public_inputs::<PoseidonDomain>()
+            .map_err(|e| invalid_field("pub_sectors", e))?;
+    }
+    let payload_encoding = Encoding::from_wire(snark_params.payload_encoding)
+        .map_err(|e| invalid_field("payload_encoding", e))?;
+    let post_config = match &snark_params.post_config {
+        Some(p) => codec::post_config_from_proto(p).map_err(|e| invalid_field("post_config", e))?,
+        // clients built before PoStConfig became a message still send the encoded struct
+        None => compat::decode_post_config(payload_encoding, &snark_params.legacy_post_config)
+            .and_then(|p| codec::check_post_sector_size(u64::from(p.sector_size)).map(|_| p))
+            .map_err(|e| invalid_field("legacy_post_config", e))?,
+    };
+    if post_config.typ == PoStType::Winning && snark_params.partitions > 1 {
+        return Err(invalid_field(
+            "partitions",
+            anyhow::Error::msg("winning PoSt is proved in one partition"),
+        ));
+    }
+    let mut replicas_len = snark_params.replicas_len as usize;
+    if let Some(r) = &remote {
+        // the replicas are those challenged, unlike vanilla proofs they are read here
+        if post_config.typ != PoStType::Window {
+            return Err(invalid_field(
+                "replicas",
+                anyhow::Error::msg("full PoSt is only supported for window PoSt"),
+            ));
+        }
+        if replicas_len != 0 && replicas_len != r.replicas.len() {
+            return Err(invalid_field(
+                "replicas_len",
+                anyhow::Error::msg(format!("{} replicas were sent", r.replicas.len())),
+            ));
+        }
+        replicas_len = r.replicas.len();
+    }
+    if let Some(r) = &raw_pub_in {
+        if replicas_len != 0 && replicas_len != r.sectors.len() {
+            return Err(invalid_field(
+                "replicas_len",
+                anyhow::Error::msg(format!("{} pub_sectors were sent", r.sectors.len())),
+            ));
+        }
+        replicas_len = r.sectors.len();
+    }
+    let pub_in_digest = match &raw_pub_in {
+        Some(r) => r.digest(),
+        None => pub_in_digest(&snark_params.pub_in),
+    };
+    // not known before the file is read
+    let vanilla_proof_digest = if vanilla_proof_path.is_none() {
+        Sha256::digest(&snark_params.vanilla_proof).to_vec()
+    } else {
+        vec![]
+    };
+    let mut task_info = TaskInfo {
+        task_id: std::mem::take(&mut snark_params.task_id),
+        vanilla_proof_bytes: snark_params.vanilla_proof.len(),
+        pub_in_bytes: snark_params.pub_in.len(),
+        vanilla_proof: std::mem::take(&mut snark_params.vanilla_proof),
+        pub_in: std::mem::take(&mut snark_params.pub_in),
+        post_config: Some(post_config),
+        replicas_len,
+        partitions: match snark_params.partitions {
+            0 => None,
+            p => Some(p as usize),
+        },
+        // set from pub_in by check_replicas_len
+        partition_index: None,
         result: vec![],
         task_status: TaskStatus::Ready,
+        priority: snark_params.priority,
+        client_id: std::mem::take(&mut snark_params.client_id),
+        payload_encoding,
+        max_threads: snark_params.max_threads as usize,
+        pub_in_digest,
+        vanilla_proof_digest,
+        created_at: Some(SystemTime::now()),
+        started_at: None,
+        finished_at: None,
+        timers: TaskTimers::default(),
+        timings: TaskTimings::default(),
+        trace: None,
+        degraded: false,
+        verify_proof: false,
+        cancel: CancelToken::default(),
+        vanilla_proof_path,
+        remote,
+        raw_pub_in,
+        batch_id: None,
+        preemptible: false,
+        preempt: CancelToken::default(),
+        stream_partitions: snark_params.stream_partitions,
+        checkpoint: Checkpoint::default(),
+        c2: None,
     };
-    task_info
+    check_replicas_len(&mut task_info)?;
+    if let Some(v) = &snark_params.vanilla_proofs {
+        let post_config = get_post_config(&task_info)?;
+        let partitions = task_info.partitions(&post_config).unwrap_or(1);
+        // encoded like vanilla_proof, the prover takes it from there
+        let bytes = vanilla::check_vanilla_proofs(v, &post_config, partitions)
+            .and_then(|_| vanilla::encode_vanilla_proofs(v, &post_config, payload_encoding))
+            .map_err(|e| invalid_field("vanilla_proofs", e))?;
+        task_info.vanilla_proof_digest = Sha256::digest(&bytes).to_vec();
+        task_info.vanilla_proof_bytes = bytes.len();
+        task_info.vanilla_proof = bytes;
+    }
+    Ok(task_info)
+}
+
+/// Check replicas_len against the sectors in the public inputs of a window PoSt task.
+/// The partitions are derived from it, a wrong one proves fine but the proof fails
+/// verification on chain. Public inputs with k are of one partition of a larger sector set,
+/// as lotus sends one to each server, the task then proves that partition alone.
+pub fn check_replicas_len(task_info: &mut TaskInfo) -> Result<()> {
+    let post_config = get_post_config(task_info)?;
+    // full PoSt tasks derive it from their replicas
+    if post_config.typ != PoStType::Window
+        || task_info.remote.is_some()
+        || task_info.pub_in.is_empty()
+    {
+        return Ok(());
+    }
+    if task_info.replicas_len == 0 {
+        return Err(invalid_field(
+            "replicas_len",
+            anyhow::Error::msg("is 0 for a window PoSt task"),
+        ));
+    }
+    let summary = inspect::inspect(
+        &post_config,
+        task_info.payload_encoding,
+        &task_info.pub_in,
+        &[],
+        Some(task_info.replicas_len),
+    )
+    .map_err(|e| invalid_field("pub_in", e))?;
+    if summary.pub_in_sectors != Some(task_info.replicas_len) {
+        return Err(invalid_field(
+            "replicas_len",
+            anyhow::Error::msg(format!(
+                "is {}, but pub_in has {} sectors",
+                task_info.replicas_len,
+                summary.pub_in_sectors.unwrap_or_default()
+            )),
+        ));
+    }
+    task_info.partition_index = summary.pub_in_k;
+    let partitions = task_info.partitions(&post_config).unwrap_or(1);
+    if task_info.replicas_len > partitions * post_config.sector_count {
+        return Err(invalid_field(
+            "partitions",
+            anyhow::Error::msg(format!(
+                "{} partitions of {} sectors do not hold replicas_len {}",
+                partitions, post_config.sector_count, task_info.replicas_len
+            )),
+        ));
+    }
+    // partitions is of the whole sector set here, when the client sent it
+    if let (Some(k), Some(total)) = (task_info.partition_index, task_info.partitions) {
+        if k >= total {
+            return Err(invalid_field(
+                "pub_in",
+                anyhow::Error::msg(format!(
+                    "has k {}, but the sector set has {} partitions",
+                    k, total
+                )),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Digest of the serialized public inputs, for clients to check the proof is for what they sent
+pub fn pub_in_digest(pub_in: &[u8]) -> Vec<u8> {
+    Sha256::digest(pub_in).to_vec()
+}
+
+/// Resolve a payload path sent by a client, it has to lie in one of the payload dirs
+/// shared with the server, which are canonical
+pub fn check_payload_path(field: &str, path: &Path, payload_dirs: &[PathBuf]) -> Result<PathBuf> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| invalid_field(field, anyhow::Error::from(e)))?;
+    if payload_dirs.iter().any(|d| canonical.starts_with(d)) {
+        Ok(canonical)
+    } else {
+        Err(invalid_field(
+            field,
+            anyhow::Error::msg(format!("{:?} is not in a payload dir of this server", path)),
+        ))
+    }
+}
+
+/// Error refusing a field of a request, answered as INVALID_ARGUMENT naming the field
+pub fn invalid_field(field: &str, e: anyhow::Error) -> anyhow::Error {
+    anyhow::Error::from(error::Error::InvalidField(field.to_string(), e.to_string()))
+}
+
+/// Tasks waiting behind the running one, high priority tasks first.
+/// Within the same priority tasks are taken round-robin per client,
+/// so one client queueing many tasks can not starve the others.
+#[derive(Default, Debug)]
+pub struct TaskQueue {
+    tasks: VecDeque<TaskInfo>,
+    // tasks taken at most, 0 means no limit
+    capacity: usize,
+}
+
+impl TaskQueue {
+    pub fn with_capacity(capacity: usize) -> Self {
+        TaskQueue {
+            tasks: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// Whether a new task has to be refused. Preempted tasks are put back regardless.
+    pub fn is_full(&self) -> bool {
+        self.capacity != 0 && self.tasks.len() >= self.capacity
+    }
+
+    /// Queue a task and return its position (starting from 1) together with
+    /// the ids of the tasks it jumped ahead of, whose positions moved back by one.
+    pub fn push(&mut self, task: TaskInfo) -> (usize, Vec<String>) {
+        // the round of a task is how many tasks of the same client and priority wait before it
+        let round = self
+            .tasks
+            .iter()
+            .filter(|t| t.priority == task.priority && t.client_id == task.client_id)
+            .count();
+        let mut rounds: HashMap<&str, usize> = HashMap::new();
+        let mut index = 0;
+        for (i, t) in self.tasks.iter().enumerate() {
+            if t.priority != task.priority {
+                if t.priority {
+                    // priority tasks always stay ahead of normal tasks
+                    index = i + 1;
+                    continue;
+                } else {
+                    break;
+                }
+            }
+            let r = rounds.entry(t.client_id.as_str()).or_insert(0);
+            if *r <= round {
+                index = i + 1;
+            }
+            *r += 1;
+        }
+        let displaced = self
+            .tasks
+            .iter()
+            .skip(index)
+            .map(|t| t.task_id.clone())
+            .collect();
+        self.tasks.insert(index, task);
+        (index + 1, displaced)
+    }
+
+    pub fn pop(&mut self) -> Option<TaskInfo> {
+        self.tasks.pop_front()
+    }
+
+    /// Take a task out of the queue, e.g. cancelled by its client
+    pub fn remove(&mut self, task_id: &str) -> Option<TaskInfo> {
+        let i = self.tasks.iter().position(|t| t.task_id == task_id)?;
+        self.tasks.remove(i)
+    }
+
+    /// Put back a task preempted while running, ahead of all but the priority tasks.
+    /// Returns its position starting from 1.
+    pub fn resume(&mut self, task: TaskInfo) -> usize {
+        let index = self.tasks.iter().take_while(|t| t.is_priority()).count();
+        self.tasks.insert(index, task);
+        index + 1
+    }
+
+    /// Take the first winning PoSt task out of the queue
+    pub fn take_winning(&mut self) -> Option<TaskInfo> {
+        let i = self.tasks.iter().position(|t| t.is_winning())?;
+        self.tasks.remove(i)
+    }
+
+    /// Position of a queued task starting from 1
+    pub fn position(&self, task_id: &str) -> Option<usize> {
+        self.tasks
+            .iter()
+            .position(|t| t.task_id == task_id)
+            .map(|i| i + 1)
+    }
+
+    pub fn contains(&self, task_id: &str) -> bool {
+        self.position(task_id).is_some()
+    }
+
+    pub fn get(&self, task_id: &str) -> Option<&TaskInfo> {
+        self.tasks.iter().find(|t| t.task_id == task_id)
+    }
+
+    pub fn task_ids(&self) -> Vec<String> {
+        self.tasks.iter().map(|t| t.task_id.clone()).collect()
+    }
+
+    /// Queued tasks, next one first
+    pub fn iter(&self) -> impl Iterator<Item = &TaskInfo> {
+        self.tasks.iter()
+    }
+}
+
+/// The peer each recently used task id belongs to. Task ids are the only thing
+/// tying requests to a task, so another peer reusing one could take over the lock or the result.
+#[derive(Default, Debug)]
+pub struct TaskOwners {
+    owners: HashMap<String, (String, Instant)>,
+}
+
+impl TaskOwners {
+    /// Record that the peer uses the task id, fails when the id belongs to another peer.
+    /// Ids not used for `time_out` are free again.
+    pub fn claim(&mut self, task_id: &str, peer: &str, time_out: Duration) -> Result<()> {
+        let now = Instant::now();
+        self.owners
+            .retain(|_, (_, last_used)| now.duration_since(*last_used) <= time_out);
+        match self.owners.get_mut(task_id) {
+            Some((owner, _)) if owner != peer => Err(anyhow::Error::from(
+                error::Error::TaskIdInUse(task_id.to_string()),
+            )),
+            Some((_, last_used)) => {
+                *last_used = now;
+                Ok(())
+            }
+            None => {
+                self.owners
+                    .insert(task_id.to_string(), (peer.to_string(), now));
+                Ok(())
+            }
+        }
+    }
 }
 
-fn get_post_config(post_config_u8: &Vec<u8>) -> Result<PoStConfig> {
-    let post_config_v = serde_json::from_slice(post_config_u8)?;
-    let post_config = serde_json::from_value::<PoStConfig>(post_config_v)?;
-    Ok(post_config)
+fn get_post_config(task_info: &TaskInfo) -> Result<PoStConfig> {
+    match &task_info.post_config {
+        Some(p) => Ok(p.clone()),
+        None => Err(anyhow::Error::msg(format!(
+            "task {} has no post config",
+            task_info.task_id
+        ))),
+    }
 }
 
 pub async fn run_task(
@@ -52,78 +768,44 @@ pub async fn run_task(
 ) {
     info!("task worker run");
     let mission = async {
+        // a worker per slot, each proving the task set on its slot
+        let mut workers = FuturesUnordered::new();
+        let mut proving: Vec<usize> = vec![];
         loop {
-            match do_task_signal_rx.recv().await {
-                Some(value) => {
-                    if value == "ok".to_string() {
-                        let si1 = match srv_info.lock() {
-                            Ok(s) => s,
-                            Err(e) => {
-                                error!("get lock failed with error: {}", e);
-                                continue;
-                            }
-                        };
-
-                        info!("start to do task: {}", si1.task_info.task_id);
-                        let t = si1.task_info.clone();
-
-                        let post_config = get_post_config(&t.post_config);
-                        drop(si1);
-                        // run snark
-                        match post_config {
-                            Ok(p) => {
-                                let size = p.sector_size;
-                                let result = with_shape!(size.0, run_snark, t);
-
-                                let mut si2 = match srv_info.lock() {
-                                    Ok(s) => s,
-                                    Err(e) => {
-                                        error!("get lock failed with error: {}", e);
-                                        continue;
-                                    }
-                                };
-
-                                match result {
-                                    Ok(r) => {
-                                        info!("task {} done", si2.task_info.task_id);
-                                        si2.task_info.result = r;
-                                        si2.task_info.task_status = TaskStatus::Done;
-                                        si2.last_update_time = Instant::now();
-                                    }
-                                    Err(e) => {
-                                        error!(
-                                            "snark task {} failed with error: {}",
-                                            si2.task_info.task_id, e
-                                        );
-                                        si2.task_info.task_status = TaskStatus::Failed;
-                                        si2.error = e.to_string();
-                                        si2.last_update_time = Instant::now();
-                                    }
-                                }
-                                drop(si2)
-                            }
-                            Err(e) => {
-                                error!("parse post config with error:{}", e);
-                            }
-                        }
-                    } else {
-                        error!("wrong signal {:?}", value);
-                    }
+            // a task started on a slot a worker freed, e.g. the next queued one or the
+            // winning PoSt a task was preempted for, gets no signal
+            let to_start: Vec<usize> = {
+                let si = srv_info.lock();
+                (0..si.slots.len())
+                    .filter(|i| si.slots[*i].has_task_to_start() && !proving.contains(i))
+                    .collect()
+            };
+            for slot in to_start {
+                proving.push(slot);
+                workers.push(prove_slot_task(srv_info.clone(), slot));
+            }
+            select! {
+                signal = do_task_signal_rx.recv() => match signal {
+                    Some(value) if value == "ok" => {}
+                    Some(value) => error!("wrong signal {:?}", value),
+                    None => tokio::time::sleep(Duration::from_secs(2)).await,
+                },
+                Some(slot) = workers.next(), if !workers.is_empty() => {
+                    proving.retain(|s| *s != slot);
                 }
-                None => (),
             }
-            tokio::time::sleep(Duration::from_secs(2)).await;
         }
     };
 
+    tokio::pin!(mission);
     let is_exit_signal;
     select! {
         _ = exit_rx => {
-            info!("worker received an exit command,will exit after current task done");
+            info!("worker received an exit command,will exit after current tasks done");
             is_exit_signal = true;
             ()
         }
-        _ = mission => {
+        _ = &mut mission => {
             is_exit_signal = false;
             error!("task failed unexpected");
             ()
@@ -132,88 +814,780 @@ pub async fn run_task(
     if is_exit_signal {
         let exit_start_time = Instant::now();
         let (mut is_working_logged, mut is_done_logged) = (false, false);
-        loop {
-            let mut si = match srv_info.lock() {
-                Ok(s) => s,
-                Err(e) => {
-                    error!("{}", e);
-                    continue;
-                }
-            };
-            match si.task_info.task_status {
-                TaskStatus::None => {
-                    info!("no task running, will exit immediately");
-                    si.status = ServerStatus::Unknown;
-                    si.last_update_time = Instant::now();
-                    break;
-                }
-                TaskStatus::Ready => {
-                    info!("task is ready but not start running, will exit immediately");
-                    si.status = ServerStatus::Unknown;
-                    si.last_update_time = Instant::now();
-                    break;
-                }
-                TaskStatus::Working => {
-                    if !is_working_logged {
-                        is_working_logged = true;
-                        info!("task is running,will exit after task done and result returned");
+        let wait_task = async {
+            loop {
+                // the guard must be gone before sleeping, keep it in its own scope
+                let exit = {
+                    let mut si = srv_info.lock();
+                    let exit_time_out = si.server_exit_time_out_after_task_done;
+                    let mut exit = true;
+                    for s in &si.slots {
+                        exit &= match s.task_info.task_status {
+                            TaskStatus::None => true,
+                            // the worker marks the task started when it begins proving it
+                            TaskStatus::Ready | TaskStatus::Queued
+                                if s.task_info.started_at.is_none() =>
+                            {
+                                true
+                            }
+                            TaskStatus::Ready | TaskStatus::Queued | TaskStatus::Working => {
+                                if !is_working_logged {
+                                    is_working_logged = true;
+                                    info!(
+                                        "task is running,will exit after task done and result returned"
+                                    );
+                                }
+                                false
+                            }
+                            // the result stays until the miner acks it, wait for that
+                            TaskStatus::Done | TaskStatus::Returned => {
+                                // the miner gets the full time out from the later of the two
+                                let waiting_since = s
+                                    .task_info
+                                    .timers
+                                    .done_at
+                                    .map_or(exit_start_time, |t| t.max(exit_start_time));
+                                if Instant::now().duration_since(waiting_since) > exit_time_out {
+                                    warn!("worker has wait 5minute,force exited");
+                                    true
+                                } else {
+                                    if !is_done_logged {
+                                        is_done_logged = true;
+                                        info!("task is done,waiting for miner to get result back and ack it");
+                                    }
+                                    false
+                                }
+                            }
+                            TaskStatus::Failed | TaskStatus::Cancelled | TaskStatus::TimedOut => {
+                                true
+                            }
+                        };
                     }
-                    continue;
-                }
-                TaskStatus::Done => {
-                    if Instant::now().duration_since(exit_start_time)
-                        > si.server_exit_time_out_after_task_done
-                    {
-                        warn!("worker has wait 5minute,force exited");
-                        si.status = ServerStatus::Unknown;
-                        si.last_update_time = Instant::now();
-                        break;
-                    } else {
-                        if !is_done_logged {
-                            is_done_logged = true;
-                            info!("task is done,waiting for miner to get result back");
+                    if exit {
+                        info!("no task running, will exit immediately");
+                        for s in si.slots.iter_mut() {
+                            s.status = ServerStatus::Unknown;
                         }
-                        continue;
                     }
-                }
-                TaskStatus::Returned => {
-                    info!("task result was returned,will exit immediately");
-                    si.status = ServerStatus::Unknown;
-                    si.last_update_time = Instant::now();
+                    exit
+                };
+                if exit {
                     break;
                 }
-                TaskStatus::Failed => {
-                    si.status = ServerStatus::Unknown;
-                    si.last_update_time = Instant::now();
-                    break;
-                }
-            };
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        };
+        // the workers keep proving the running tasks and store their results meanwhile
+        select! {
+            _ = wait_task => {}
+            _ = mission => {}
         }
     }
     info!("task worker exited");
 }
 
-fn run_snark<Tree: 'static + MerkleTreeTrait>(task_info: TaskInfo) -> Result<Vec<u8>> {
-    let post_config_v = serde_json::from_slice(&task_info.post_config)?;
-    let post_config = serde_json::from_value::<PoStConfig>(post_config_v)?;
+// prove the task set on the slot and keep its outcome there, returns the slot once done
+async fn prove_slot_task(srv_info: Arc<Mutex<ServerInfo>>, slot: usize) -> usize {
+    // the guard must be gone before proving, keep it in its own scope
+    let (mut t, payload, device, cpu_fallback) = {
+        let mut si1 = srv_info.lock();
+        let max_threads = si1.task_threads;
+        let verify_proof = si1.verify_proofs;
+        let preempt_window_post = si1.preempt_window_post;
+        let cpu_fallback = si1.cpu_fallback;
+        let s = &mut si1.slots[slot];
+        info!(
+            "start to do task: {} on slot {} trace: {}",
+            s.task_info.task_id,
+            slot + 1,
+            s.task_info.trace_id()
+        );
+        s.task_info.started_at = Some(SystemTime::now());
+        // a task proved in a child process can not crash the server
+        if s.device.in_process() {
+            crash::set_current_task(&s.task_info.task_id, crash::PHASE_PROVING);
+        }
+        // the payloads go with the worker, the server state keeps the rest
+        let payload = s.task_info.take_payload();
+        let mut t = s.task_info.clone();
+        t.max_threads = task_threads(t.max_threads, max_threads);
+        t.verify_proof = verify_proof;
+        // a child process proves a task in one go, it can not stop between partitions
+        t.preemptible = preempt_window_post && s.device.in_process();
+        (t, payload, s.device.clone(), cpu_fallback)
+    };
+    let in_process = device.in_process();
+    let task_id = t.task_id.clone();
+
+    // C2 tasks have a PoRep config instead
+    let provable = match &t.c2 {
+        Some(_) => Ok(()),
+        None => get_post_config(&t).map(drop),
+    };
+    let set_idle = || {
+        if in_process {
+            crash::set_current_task(&task_id, crash::PHASE_IDLE);
+        }
+    };
+    t.set_payload(payload);
+    // proving consumes the task, a failed one leaves its payloads in the checkpoint
+    let checkpoint = t.checkpoint.clone();
+    // run snark
+    if let Err(e) = provable {
+        error!("get post config with error:{}", e);
+        t.wipe();
+        return slot;
+    }
+    // proving takes minutes of cpu and gpu, keep it off the runtime threads serving the
+    // status and lock calls
+    let result =
+        run_on_prove_thread(move || prove_task_with_fallback(t, cpu_fallback, &device)).await;
+    // only a preempted task needs its payloads kept, the others are dumped or dropped
+    let failed_payload = match &result {
+        Err(e) if !error::is_preempted(e) => checkpoint.take_payload(),
+        _ => None,
+    };
+
+    let mut failed_dump = None;
+    let mut si2 = srv_info.lock();
+    if si2.slots[slot].proving_hung {
+        // the task was failed to its client already
+        warn!(
+            "task {} returned after its prove time out, drop the result",
+            task_id
+        );
+        si2.finish_hung_task(slot);
+        drop(si2);
+        set_idle();
+        return slot;
+    }
+    let s = &si2.slots[slot];
+    if s.task_info.task_id == task_id && s.task_info.cancel.is_cancelled() {
+        info!("task {} was cancelled, drop what it proved", task_id);
+        si2.finish_cancelled_task(slot);
+        drop(si2);
+        set_idle();
+        return slot;
+    }
+    if let Err(e) = &result {
+        if error::is_preempted(e) {
+            info!("{}", e);
+            si2.preempt_running_task(slot);
+            drop(si2);
+            set_idle();
+            return slot;
+        }
+    }
+
+    match result {
+        Ok((r, timings, degraded)) => {
+            let t = &mut si2.slots[slot].task_info;
+            info!(
+                "task {} done trace: {} degraded: {}",
+                t.task_id,
+                t.trace_id(),
+                degraded
+            );
+            t.checkpoint.finish(&r);
+            t.result = r;
+            t.timings = timings;
+            t.degraded = degraded;
+            t.task_status = TaskStatus::Done;
+            t.finished_at = Some(SystemTime::now());
+            t.timers.done_at = Some(Instant::now());
+            si2.recent_errors.succeeded();
+            si2.record_proving_time(slot);
+            si2.notify_task_event(&task_id, &TaskStatus::Done, 0, "task done");
+        }
+        Err(e) => {
+            let s = &mut si2.slots[slot];
+            error!(
+                "snark task {} trace: {} failed with error: {}",
+                s.task_info.task_id,
+                s.task_info.trace_id(),
+                e
+            );
+            s.task_info.task_status = TaskStatus::Failed;
+            s.task_info.finished_at = Some(SystemTime::now());
+            s.task_info.checkpoint.fail(&e.to_string());
+            s.error = e.to_string();
+            s.error_kind = error::classify_failure(&e);
+            s.error_stack = e.chain().map(|c| c.to_string()).collect();
+            s.task_info.timers.done_at = Some(Instant::now());
+            let task_error = error::TaskError {
+                task_id: task_id.clone(),
+                error: s.error.clone(),
+                kind: s.error_kind,
+                failed_at: SystemTime::now(),
+            };
+            si2.recent_errors.push(task_error);
+            si2.notify_task_event(&task_id, &TaskStatus::Failed, 0, &e.to_string());
+            if let Some(d) = si2.dump.clone() {
+                failed_dump = Some((
+                    d,
+                    si2.slots[slot].task_info.clone(),
+                    e.to_string(),
+                    ServerSnapshot::new(&si2),
+                ));
+            }
+        }
+    }
+    si2.finish_batch_task(slot);
+    let audit = si2.audit_dir.clone().map(|d| {
+        let s = &si2.slots[slot];
+        let err = match s.task_info.task_status {
+            TaskStatus::Failed => s.error.as_str(),
+            _ => "",
+        };
+        (d, AuditRecord::new(&s.task_info, err))
+    });
+    drop(si2);
+    set_idle();
+    if let Some((d, record)) = audit {
+        if let Err(e) = audit::write_audit_record(&d, &record) {
+            error!(
+                "failed to write audit record of task {} with error: {}",
+                task_id, e
+            );
+        }
+    }
+    // write outside of the lock, payloads can be large
+    if let Some((d, mut t, err, snapshot)) = failed_dump {
+        if let Some(p) = failed_payload {
+            t.set_payload(p);
+        }
+        if let Err(e) = dump::dump_failed_task(&d, &t, &err, snapshot) {
+            error!("failed to dump task {} with error: {}", task_id, e);
+        }
+        t.wipe();
+    }
+    slot
+}
+
+/// Threads a task may use given what it asked for and what the server allows, 0 means no limit
+pub fn task_threads(requested: usize, server_max: usize) -> usize {
+    match (requested, server_max) {
+        (0, m) => m,
+        (r, 0) => r,
+        (r, m) => r.min(m),
+    }
+}
+
+/// Where the time of proving a task went. bellperson synthesizes the circuits and runs
+/// their FFTs and multiexps in one call without hooks in between, so `prove` covers both
+/// and the cpu and gpu time are not told apart. Tasks proved one partition at a time have
+/// the time of each partition as well.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaskTimings {
+    pub partitions: usize,
+    // deserializing the vanilla proofs and public inputs, or generating them from the
+    // replicas of full PoSt tasks
+    pub decode: Duration,
+    // public params and groth parameters, only slow when not cached yet
+    pub load_params: Duration,
+    // circuit synthesis on the cpu and proving on the gpu
+    pub prove: Duration,
+    // prove of each partition proved one at a time in this run, empty when all partitions
+    // were proved in one call
+    #[serde(default)]
+    pub partition_prove: Vec<Duration>,
+}
+
+pub fn prove_task(mut task_info: TaskInfo) -> Result<(Vec<u8>, TaskTimings)> {
+    let prove = |t: &TaskInfo| match &t.c2 {
+        Some(c) => with_shape!(u64::from(c.porep_config.sector_size), run_c2, t),
+        None => {
+            let post_config = get_post_config(t)?;
+            with_shape!(u64::from(post_config.sector_size), run_snark, t)
+        }
+    };
+    let result = if task_info.max_threads == 0 {
+        prove(&task_info)
+    } else {
+        // circuit synthesis runs on the current rayon pool, keep it to the task's share of the cpus
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(task_info.max_threads)
+            .build()?;
+        pool.install(|| prove(&task_info))
+    };
+    if result.is_err() {
+        // a preempted task resumes from its checkpoint and needs the payloads again, a
+        // failed one is dumped from there
+        let payload = task_info.take_payload();
+        task_info.checkpoint.keep_payload(payload);
+    }
+    task_info.wipe();
+    result
+}
+
+/// Run `f` on a thread of its own instead of the blocking pool of the runtime,
+/// which is capped and also serves the short blocking calls of the grpc handlers
+pub async fn run_on_prove_thread<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    thread::Builder::new()
+        .name(PROVE_THREAD_NAME.to_string())
+        .spawn(move || {
+            let result = crash::recover(f);
+            let _ = tx.send(result.unwrap_or_else(|e| {
+                Err(anyhow::Error::msg(format!(
+                    "proving panicked: {}",
+                    crash::panic_message(&*e)
+                )))
+            }));
+        })
+        .with_context(|| "failed to spawn the proving thread")?;
+    rx.await
+        .unwrap_or_else(|_| Err(anyhow::Error::msg("proving thread exited without a result")))
+}
+
+/// Fail tasks proving for longer than the prove time out of the server
+pub async fn run_prove_watchdog(srv_info: Arc<Mutex<ServerInfo>>) {
+    loop {
+        tokio::time::sleep(PROVE_WATCHDOG_INTERVAL).await;
+        srv_info.lock().check_prove_time_out(SystemTime::now());
+    }
+}
+
+/// Prove the task on the device of its slot, and when that fails on what may be the gpu,
+/// e.g. a driver crash, once more on the cpu if `cpu_fallback` allows. The flag returned
+/// tells whether the proof was made on the cpu.
+pub fn prove_task_with_fallback(
+    mut task_info: TaskInfo,
+    cpu_fallback: bool,
+    device: &SlotDevice,
+) -> Result<(Vec<u8>, TaskTimings, bool)> {
+    // the task without its payloads, a failed attempt leaves them in the checkpoint
+    let retry = if cpu_fallback {
+        let payload = task_info.take_payload();
+        let t = task_info.clone();
+        task_info.set_payload(payload);
+        Some(t)
+    } else {
+        None
+    };
+    let task_id = task_info.task_id.clone();
+    let e = match prove_on_device(task_info, device) {
+        Ok((proof, timings)) => return Ok((proof, timings, false)),
+        Err(e) => e,
+    };
+    let mut t = match retry {
+        Some(t) if error::classify_failure(&e) == FailureKind::Retryable => t,
+        _ => return Err(e),
+    };
+    if let Some(payload) = t.checkpoint.take_payload() {
+        t.set_payload(payload);
+    }
+    warn!(
+        "task {} failed with error: {}, prove it again on the cpu",
+        task_id, e
+    );
+    let (proof, timings) = prove_on_cpu(t).context(format!("gpu error before: {}", e))?;
+    Ok((proof, timings, true))
+}
+
+/// Prove the task in the server process, or in a child process pinned to the device of
+/// the slot
+pub fn prove_on_device(task_info: TaskInfo, device: &SlotDevice) -> Result<(Vec<u8>, TaskTimings)> {
+    if device.in_process() {
+        return prove_task(task_info);
+    }
+    let on = format!("on gpu {}", device.device_id);
+    prove_in_child(task_info, &device.env, &on)
+}
+
+/// Prove the task in a child process of the server binary with `BELLMAN_NO_GPU` set.
+/// bellperson reads it whenever it sets up a gpu kernel, and setting it in the server
+/// itself would race the other threads reading the environment and keep the next
+/// tasks off the gpu too.
+pub fn prove_on_cpu(task_info: TaskInfo) -> Result<(Vec<u8>, TaskTimings)> {
+    let env = [(NO_GPU_ENV.to_string(), "1".to_string())];
+    prove_in_child(task_info, &env, "on the cpu")
+}
 
-    let vanilla_params = window_post_setup_params(&post_config);
-    let partitions = get_partitions_for_window_post(task_info.replicas_len as usize, &post_config);
+/// Prove the task in a child process of the server binary with `env` set, `on` tells
+/// where in errors. The child is killed once the task is cancelled, e.g. by its client or
+/// the prove time out, so a hung device does not keep the slot.
+pub fn prove_in_child(
+    mut task_info: TaskInfo,
+    env: &[(String, String)],
+    on: &str,
+) -> Result<(Vec<u8>, TaskTimings)> {
+    let dir = env::temp_dir().join(format!(
+        "wps-prove-{}-{}",
+        dump::sanitize(&task_info.task_id),
+        Uuid::new_v4()
+    ));
+    let result = (|| {
+        dump::write_task(&dir, &task_info, "", 0, ServerSnapshot::default())?;
+        let mut child = Command::new(env::current_exe()?)
+            .arg(PROVE_CMD)
+            .arg(&dir)
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .spawn()
+            .with_context(|| format!("failed to start proving {}", on))?;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if task_info.cancel.is_cancelled() {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow::Error::from(error::Error::TaskCancelled(
+                    task_info.task_id.clone(),
+                )));
+            }
+            thread::sleep(CHILD_POLL_INTERVAL);
+        };
+        if !status.success() {
+            // the child classified what it failed with, as the server does for its own
+            if let Ok(e) = fs::read(dir.join(CHILD_ERROR_FILE)) {
+                let e: ChildError = serde_json::from_slice(&e)?;
+                return Err(anyhow::Error::from(error::Error::ChildTaskFailed(
+                    e.error, e.kind,
+                )));
+            }
+            return Err(anyhow::Error::msg(format!(
+                "proving {} exited with {}",
+                on, status
+            )));
+        }
+        let proof = fs::read(dir.join(CHILD_PROOF_FILE))?;
+        let timings = serde_json::from_slice(&fs::read(dir.join(CHILD_TIMINGS_FILE))?)?;
+        Ok((proof, timings))
+    })();
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        warn!("failed to remove {:?} with error: {}", dir, e);
+    }
+    if result.is_err() {
+        let payload = task_info.take_payload();
+        task_info.checkpoint.keep_payload(payload);
+    }
+    task_info.wipe();
+    result
+}
+
+// what a task failed with in a child process
+#[derive(Debug, Serialize, Deserialize)]
+struct ChildError {
+    error: String,
+    kind: FailureKind,
+}
+
+/// What the `prove-task` subcommand runs: prove the task `prove_in_child` wrote into `dir`
+/// and write the proof and timings next to it, or the error it failed with
+pub fn prove_task_in_dir(dir: &Path) -> Result<()> {
+    let (_, task_info) = dump::load_dump(dir)?;
+    let (proof, timings) = match prove_task(task_info) {
+        Ok(r) => r,
+        Err(e) => {
+            let failure = ChildError {
+                error: format!("{:#}", e),
+                kind: error::classify_failure(&e),
+            };
+            fs::write(dir.join(CHILD_ERROR_FILE), serde_json::to_vec(&failure)?)?;
+            return Err(e);
+        }
+    };
+    fs::write(dir.join(CHILD_TIMINGS_FILE), serde_json::to_vec(&timings)?)?;
+    fs::write(dir.join(CHILD_PROOF_FILE), proof)?;
+    Ok(())
+}
+
+// everything FallbackPoStCompound::setup depends on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PubParamsKey {
+    tree: TypeId,
+    sector_size: u64,
+    challenge_count: usize,
+    sector_count: usize,
+    partitions: Option<usize>,
+    // window and winning PoSt of one shape challenge differently
+    typ: String,
+    priority: bool,
+    api_version: String,
+}
+
+pub(crate) type PubParams<Tree> =
+    compound_proof::PublicParams<'static, FallbackPoSt<'static, Tree>>;
+
+lazy_static! {
+    static ref PUB_PARAMS_CACHE: Mutex<HashMap<PubParamsKey, Arc<dyn Any + Send + Sync>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Public params of the compound proof, set up once per configuration
+pub(crate) fn get_pub_params<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    partitions: Option<usize>,
+) -> Result<Arc<PubParams<Tree>>> {
+    let key = PubParamsKey {
+        tree: TypeId::of::<Tree>(),
+        sector_size: u64::from(post_config.sector_size),
+        challenge_count: post_config.challenge_count,
+        sector_count: post_config.sector_count,
+        partitions,
+        typ: format!("{:?}", post_config.typ),
+        priority: post_config.priority,
+        api_version: format!("{:?}", post_config.api_version),
+    };
+    let mut cache = PUB_PARAMS_CACHE.lock();
+    if let Some(cached) = cache.get(&key) {
+        if let Ok(p) = cached.clone().downcast::<PubParams<Tree>>() {
+            return Ok(p);
+        }
+    }
+    let vanilla_params = match post_config.typ {
+        PoStType::Window => window_post_setup_params(post_config),
+        // winning PoSt challenges all its sectors in one partition
+        PoStType::Winning => winning_post_setup_params(post_config)?,
+    };
     let setup_params = compound_proof::SetupParams {
         vanilla_params,
         partitions,
         priority: post_config.priority,
     };
-    let pub_params: compound_proof::PublicParams<'_, FallbackPoSt<'_, Tree>> =
-        FallbackPoStCompound::setup(&setup_params)?;
-    let vanilla_v = serde_json::from_slice(&task_info.vanilla_proof)?;
-    let pub_in_v = serde_json::from_slice(&task_info.pub_in)?;
-    let groth_params = get_post_params::<Tree>(&post_config)?;
-    let proof = FallbackPoStCompound::prove_with_vanilla_by_snark_server(
-        &pub_params,
+    let pub_params: Arc<PubParams<Tree>> = Arc::new(FallbackPoStCompound::setup(&setup_params)?);
+    info!("public params set up for {:?}", key);
+    cache.insert(key, pub_params.clone());
+    Ok(pub_params)
+}
+
+lazy_static! {
+    static ref PAYLOAD_BUFFERS: Mutex<HashMap<u64, Vec<u8>>> = Mutex::new(HashMap::new());
+}
+
+/// Buffer the payload files of a task are read into. It goes back to its sector size
+/// when dropped, so the next task of that size reads into the same allocation instead
+/// of growing a new one of hundreds of megabytes. It is the only scratch space kept
+/// between tasks, bellperson allocates the witnesses and FFT buffers of a proof itself.
+#[derive(Debug)]
+pub struct PayloadBuffer {
+    sector_size: u64,
+    buf: Vec<u8>,
+}
+
+impl PayloadBuffer {
+    /// The buffer left by the last task of the sector size, or a new one
+    pub fn take(sector_size: u64) -> Self {
+        let buf = PAYLOAD_BUFFERS
+            .lock()
+            .remove(&sector_size)
+            .unwrap_or_default();
+        PayloadBuffer { sector_size, buf }
+    }
+
+    /// Read the whole file, replacing what the buffer held
+    pub fn read_file(&mut self, path: &Path) -> Result<&[u8]> {
+        self.buf.zeroize();
+        let mut f = fs::File::open(path)?;
+        if let Ok(m) = f.metadata() {
+            self.buf.reserve(m.len() as usize);
+        }
+        f.read_to_end(&mut self.buf)?;
+        Ok(&self.buf)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+}
+
+impl Drop for PayloadBuffer {
+    fn drop(&mut self) {
+        // payloads are wiped like the task info, only the allocation is kept
+        self.buf.zeroize();
+        PAYLOAD_BUFFERS
+            .lock()
+            .insert(self.sector_size, std::mem::take(&mut self.buf));
+    }
+}
+
+fn run_snark<Tree: 'static + MerkleTreeTrait>(
+    task_info: &TaskInfo,
+) -> Result<(Vec<u8>, TaskTimings)> {
+    let post_config = get_post_config(task_info)?;
+    let mut timings = TaskTimings::default();
+
+    let partitions = task_info.partitions(&post_config);
+    timings.partitions = partitions.unwrap_or(1);
+    let start = Instant::now();
+    if let Some(remote) = &task_info.remote {
+        let pub_params = get_pub_params::<Tree>(&post_config, partitions)?;
+        let (pub_in_v, vanilla_v) =
+            remote::vanilla_proofs::<Tree>(remote, &post_config, &*pub_params, partitions)?;
+        timings.decode = start.elapsed();
+        info!(
+            "vanilla proofs of task {} generated from {} replicas in {:?}",
+            task_info.task_id,
+            remote.replicas.len(),
+            timings.decode
+        );
+        task_info.cancel.check(&task_info.task_id)?;
+        return prove_snark::<Tree>(
+            task_info,
+            &post_config,
+            partitions,
+            pub_in_v,
+            vanilla_v,
+            timings,
+        );
+    }
+    let vanilla_v = match &task_info.vanilla_proof_path {
+        Some(p) => {
+            let mut buf = PayloadBuffer::take(u64::from(post_config.sector_size));
+            let vanilla_proof = buf
+                .read_file(p)
+                .with_context(|| format!("failed to read vanilla proofs from {:?}", p))?;
+            compat::decode_vanilla_proofs(task_info.payload_encoding, vanilla_proof)?
+        }
+        None => {
+            compat::decode_vanilla_proofs(task_info.payload_encoding, &task_info.vanilla_proof)?
+        }
+    };
+    let pub_in_v = match &task_info.raw_pub_in {
+        Some(r) => r.public_inputs()?,
+        None => codec::decode(task_info.payload_encoding, &task_info.pub_in)?,
+    };
+    timings.decode = start.elapsed();
+    if post_config.typ == PoStType::Window && vanilla_v.len() != timings.partitions {
+        return Err(anyhow::Error::from(error::Error::InvalidParameters(
+            format!(
+                "vanilla proofs have {} partitions, the task has {}",
+                vanilla_v.len(),
+                timings.partitions
+            ),
+        )));
+    }
+    task_info.cancel.check(&task_info.task_id)?;
+    prove_snark::<Tree>(
+        task_info,
+        &post_config,
+        partitions,
         pub_in_v,
         vanilla_v,
-        &groth_params,
-    )?;
-    proof.to_vec()
+        timings,
+    )
+}
+
+fn prove_snark<Tree: 'static + MerkleTreeTrait>(
+    task_info: &TaskInfo,
+    post_config: &PoStConfig,
+    partitions: Option<usize>,
+    pub_in_v: PublicInputs<<Tree::Hasher as Hasher>::Domain>,
+    vanilla_v: Vec<fallback::Proof<Tree::Proof>>,
+    mut timings: TaskTimings,
+) -> Result<(Vec<u8>, TaskTimings)> {
+    let start = Instant::now();
+    let pub_params = get_pub_params::<Tree>(post_config, partitions)?;
+    let groth_params = params::groth_params::<Tree>(post_config)?;
+    timings.load_params = start.elapsed();
+    task_info.cancel.check(&task_info.task_id)?;
+
+    // proving consumes the public inputs, verifying needs them again
+    let pub_in_verify = if task_info.verify_proof {
+        Some(pub_in_v.clone())
+    } else {
+        None
+    };
+    let start = Instant::now();
+    let by_partition = task_info.preemptible || task_info.stream_partitions;
+    let proof = if by_partition && post_config.typ == PoStType::Window && vanilla_v.len() > 1 {
+        prove_partitions::<Tree>(
+            task_info,
+            &*pub_params,
+            &pub_in_v,
+            &vanilla_v,
+            &groth_params,
+            &mut timings.partition_prove,
+        )?
+    } else {
+        FallbackPoStCompound::prove_with_vanilla_by_snark_server(
+            &*pub_params,
+            pub_in_v,
+            vanilla_v,
+            &groth_params,
+        )?
+    };
+    timings.prove = start.elapsed();
+    task_info.cancel.check(&task_info.task_id)?;
+    if let Some(pub_in) = pub_in_verify {
+        let start = Instant::now();
+        let requirements = ChallengeRequirements {
+            minimum_challenge_count: post_config.challenge_count * post_config.sector_count,
+        };
+        if !FallbackPoStCompound::verify(&*pub_params, &pub_in, &proof, &requirements)? {
+            return Err(anyhow::Error::from(error::Error::ProofNotVerified(
+                task_info.task_id.clone(),
+            )));
+        }
+        info!(
+            "proof of task {} verified in {:?}",
+            task_info.task_id,
+            start.elapsed()
+        );
+    }
+    info!(
+        "task {} timings: {} partitions, decode {:?}, load params {:?}, prove {:?}, per partition {:?}",
+        task_info.task_id,
+        timings.partitions,
+        timings.decode,
+        timings.load_params,
+        timings.prove,
+        timings.partition_prove
+    );
+    Ok((proof.to_vec()?, timings))
+}
+
+/// Prove the partitions one at a time, starting after those in the checkpoint, and yield
+/// to a waiting winning PoSt between two of them. Proving them in one call shares the
+/// gpu setup, so only preemptible tasks and those streaming their partitions are proved
+/// this way.
+fn prove_partitions<'a, Tree: 'static + MerkleTreeTrait>(
+    task_info: &TaskInfo,
+    pub_params: &PubParams<Tree>,
+    pub_in: &PublicInputs<<Tree::Hasher as Hasher>::Domain>,
+    vanilla_v: &[fallback::Proof<Tree::Proof>],
+    groth_params: &'a groth16::MappedParameters<Bls12>,
+    partition_prove: &mut Vec<Duration>,
+) -> Result<MultiProof<'a>> {
+    let mut groth_proofs = Vec::with_capacity(vanilla_v.len());
+    for p in task_info.checkpoint.proofs() {
+        groth_proofs.push(groth16::Proof::<Bls12>::read(&p[..])?);
+    }
+    if !groth_proofs.is_empty() {
+        info!(
+            "task {} resumes after {} of {} partitions",
+            task_info.task_id,
+            groth_proofs.len(),
+            vanilla_v.len()
+        );
+    }
+    for (k, vanilla_proof) in vanilla_v.iter().enumerate().skip(groth_proofs.len()) {
+        if task_info.preempt.is_cancelled() {
+            return Err(anyhow::Error::from(error::Error::TaskPreempted(
+                task_info.task_id.clone(),
+                k,
+            )));
+        }
+        task_info.cancel.check(&task_info.task_id)?;
+        let start = Instant::now();
+        let circuit = FallbackPoStCompound::<Tree>::circuit(
+            pub_in,
+            Default::default(),
+            vanilla_proof,
+            &pub_params.vanilla_params,
+            Some(k),
+        )?;
+        let proof = groth16::create_random_proof(circuit, groth_params, &mut OsRng)?;
+        partition_prove.push(start.elapsed());
+        let mut bytes = vec![];
+        proof.write(&mut bytes)?;
+        task_info.checkpoint.push_proof(bytes);
+        groth_proofs.push(proof);
+    }
+    Ok(MultiProof::new(groth_proofs, &groth_params.pvk))
 }
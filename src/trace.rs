@@ -0,0 +1,75 @@
+use std::fmt;
+use tonic::metadata::MetadataMap;
+
+/// Metadata key clients send their trace context in
+pub const TRACEPARENT_KEY: &str = "traceparent";
+
+/// W3C trace context of a request, see https://www.w3.org/TR/trace-context/#traceparent-header
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceContext {
+    pub version: u8,
+    // 32 lowercase hex digits
+    pub trace_id: String,
+    // 16 lowercase hex digits, the span of the caller
+    pub parent_id: String,
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Parse a traceparent value, None when it is malformed, which the spec says to ignore
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let fields: Vec<&str> = traceparent.trim().split('-').collect();
+        if fields.len() < 4 {
+            return None;
+        }
+        let version = parse_hex_byte(fields[0])?;
+        // version 00 has exactly these fields, later versions may append more
+        if version == 0xff || (version == 0 && fields.len() != 4) {
+            return None;
+        }
+        let (trace_id, parent_id) = (fields[1], fields[2]);
+        if !is_hex_id(trace_id, 32) || !is_hex_id(parent_id, 16) {
+            return None;
+        }
+        Some(TraceContext {
+            version,
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+            flags: parse_hex_byte(fields[3])?,
+        })
+    }
+
+    pub fn from_metadata(metadata: &MetadataMap) -> Option<Self> {
+        Self::parse(metadata.get(TRACEPARENT_KEY)?.to_str().ok()?)
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}-{}-{}-{:02x}",
+            self.version, self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+fn parse_hex_byte(s: &str) -> Option<u8> {
+    if s.len() != 2 || !s.bytes().all(is_lower_hex) {
+        return None;
+    }
+    u8::from_str_radix(s, 16).ok()
+}
+
+// ids of all zeros are invalid
+fn is_hex_id(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(is_lower_hex) && s.bytes().any(|b| b != b'0')
+}
+
+fn is_lower_hex(b: u8) -> bool {
+    b.is_ascii_digit() || (b'a'..=b'f').contains(&b)
+}
@@ -1,9 +1,11 @@
+use crate::snark_proof_grpc::BuildInfo;
 use clap::crate_version;
-use log::{error, info};
+use log::{error, info, warn};
 use std::env;
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{
     fs::{remove_file, write},
     process,
@@ -28,14 +30,134 @@ pub fn author() -> &'static str {
 
 pub fn version() -> &'static str {
     if let Ok(c) = env::var("PROJECT_VERSION") {
-        Box::leak(format!("{}+git.{}", crate_version!(), c).into_boxed_str())
+        Box::leak(format!("{}+git.{}", crate_version!(), c.trim()).into_boxed_str())
     } else {
-        Box::leak(format!("{}+git.{}", crate_version!(), "".to_string()).into_boxed_str())
+        Box::leak(format!("{}+git.{}", crate_version!(), git_commit()).into_boxed_str())
     }
 }
 
+/// Commit the server was built from, empty when built outside a checkout
+pub fn git_commit() -> &'static str {
+    env!("WPS_GIT_COMMIT")
+}
+
+/// Build of this server, for fleet operators to check rollouts against
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: version().to_string(),
+        git_commit: git_commit().to_string(),
+        build_date: env!("WPS_BUILD_DATE").to_string(),
+        features: env!("WPS_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_string())
+            .collect(),
+    }
+}
+
+/// Whether the server was built with a gpu backend, without one it proves on the cpu only
+pub fn gpu_support() -> bool {
+    cfg!(any(feature = "cuda", feature = "opencl"))
+}
+
+/// GPU this process was pinned to, empty when it may use any
+pub fn gpu_device_id() -> String {
+    env::var("CUDA_VISIBLE_DEVICES")
+        .or_else(|_| env::var("GPU_DEVICE_ORDINAL"))
+        .unwrap_or_default()
+}
+
+/// Milliseconds since the unix epoch, 0 for unset or pre-epoch times
+pub fn unix_millis(t: Option<SystemTime>) -> u64 {
+    t.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 pub fn lock_file_path() -> PathBuf {
-    dirs::home_dir().unwrap().join(".fil_wdpost_server.lock")
+    lock_file_paths().remove(0)
+}
+
+/// One lock file per GPU this process was pinned to, so servers on other GPUs of the
+/// host can run next to it. A single lock file for the host when not pinned.
+pub fn lock_file_paths() -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap();
+    let paths: Vec<PathBuf> = gpu_device_id()
+        .split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            let d = d.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+            home.join(format!(".fil_wdpost_server.gpu-{}.lock", d))
+        })
+        .collect();
+    if paths.is_empty() {
+        vec![home.join(".fil_wdpost_server.lock")]
+    } else {
+        paths
+    }
+}
+
+/// Write our pid into the lock files, failing when a running server holds any of them
+pub fn acquire_file_lock() -> Result<(), anyhow::Error> {
+    let mut taken = vec![];
+    for lock_path in lock_file_paths() {
+        match take_lock_file(&lock_path) {
+            Ok(_) => taken.push(lock_path),
+            Err(e) => {
+                for p in taken {
+                    let _ = remove_file(p);
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn take_lock_file(lock_path: &Path) -> Result<(), anyhow::Error> {
+    // a stale lock file is removed once, then taken like a fresh one
+    for _ in 0..2 {
+        // create_new, so two servers starting at once can not both take it
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(lock_path)
+        {
+            Ok(mut f) => {
+                f.write_all(process::id().to_string().as_bytes())?;
+                return Ok(());
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let pid = read_pid(lock_path.to_str().unwrap().to_string());
+                if pid != 0 && is_process_running(pid) {
+                    return Err(anyhow::Error::msg(format!(
+                        "another server is running with pid {}, lock file: {:?}",
+                        pid, lock_path
+                    )));
+                }
+                warn!("remove stale lock file {:?} of pid {}", lock_path, pid);
+                remove_file(lock_path)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(anyhow::Error::msg(format!(
+        "failed to take lock file {:?}",
+        lock_path
+    )))
+}
+
+/// Where `calibrate` keeps the hardware profile, one per GPU set like the lock files
+pub fn profile_path() -> PathBuf {
+    let mut lock_path = lock_file_path();
+    lock_path.set_extension("profile.json");
+    lock_path
+}
+
+/// Where a panic leaves its crash report for the next start
+pub fn crash_report_path() -> PathBuf {
+    dirs::home_dir().unwrap().join(".fil_wdpost_server.crash")
 }
 
 pub fn is_file_lock_exist() -> bool {
@@ -59,35 +181,40 @@ pub fn check_process_is_running_by_pid() -> Option<u32> {
     if pid == 0 {
         None
     } else {
-        let pid_str = pid.to_string();
-        let args = vec!["-p", &pid_str, "-o", "pid="];
-        let ps_cmd_out = process::Command::new("ps")
-            .args(args)
-            .output()
-            .expect("failed to execute ps -p");
-        if ps_cmd_out.status.success() {
-            if String::from_utf8(ps_cmd_out.stdout)
-                .unwrap()
-                .contains(&pid_str.to_string())
-            {
-                Some(pid)
-            } else {
-                None
-            }
+        if is_process_running(pid) {
+            Some(pid)
         } else {
             None
         }
     }
 }
 
+fn is_process_running(pid: u32) -> bool {
+    let pid_str = pid.to_string();
+    let args = vec!["-p", &pid_str, "-o", "pid="];
+    let ps_cmd_out = process::Command::new("ps")
+        .args(args)
+        .output()
+        .expect("failed to execute ps -p");
+    ps_cmd_out.status.success()
+        && String::from_utf8(ps_cmd_out.stdout)
+            .unwrap()
+            .contains(&pid_str.to_string())
+}
+
+/// Remove the lock files, unless another server took them over
 pub fn del_file_lock() {
-    let lock_path = lock_file_path();
-    match remove_file(lock_path) {
-        Ok(_) => {}
-        Err(e) => {
-            error!("{}", e);
+    for lock_path in lock_file_paths() {
+        if read_pid(lock_path.to_str().unwrap().to_string()) != process::id() {
+            continue;
         }
-    };
+        match remove_file(lock_path) {
+            Ok(_) => {}
+            Err(e) => {
+                error!("{}", e);
+            }
+        };
+    }
 }
 
 pub fn read_pid(path: String) -> u32 {
@@ -98,9 +225,8 @@ pub fn read_pid(path: String) -> u32 {
             buf_reader
                 .read_to_string(&mut contents)
                 .expect("read pid failed");
-            contents
-                .parse::<u32>()
-                .expect("parse pid error from pid file")
+            // a server which died while writing it leaves an empty file
+            contents.trim().parse::<u32>().unwrap_or(0)
         }
         Err(_) => 0,
     }
@@ -0,0 +1,138 @@
+//! Vanilla proofs of a task sent as their parts: per partition the comm_c, comm_r_last and
+//! inclusion proofs of each sector. Their shape is checked against the post config when the
+//! task is submitted, only the inclusion proofs, whose shape depends on the tree, are encoded.
+
+use crate::codec::{self, Encoding};
+use crate::error::Result;
+use crate::snark_proof_grpc::{PartitionVanillaProof, SectorVanillaProof, VanillaProofs};
+use filecoin_hashers::{Domain, Hasher};
+use filecoin_proofs::{with_shape, PoStConfig};
+use storage_proofs_core::merkle::MerkleTreeTrait;
+use storage_proofs_post::fallback;
+
+/// Check the partitions, sectors and challenges of the proofs, the last partition is
+/// padded to the sectors of a partition as filecoin-proofs does
+pub fn check_vanilla_proofs(
+    proofs: &VanillaProofs,
+    post_config: &PoStConfig,
+    partitions: usize,
+) -> Result<()> {
+    if proofs.partitions.len() != partitions {
+        return Err(anyhow::Error::msg(format!(
+            "have {} partitions, the task has {}",
+            proofs.partitions.len(),
+            partitions
+        )));
+    }
+    for (k, p) in proofs.partitions.iter().enumerate() {
+        if p.sectors.len() != post_config.sector_count {
+            return Err(anyhow::Error::msg(format!(
+                "partition {} has {} sectors, expected {}",
+                k,
+                p.sectors.len(),
+                post_config.sector_count
+            )));
+        }
+        for (i, s) in p.sectors.iter().enumerate() {
+            if s.comm_c.len() != 32 || s.comm_r_last.len() != 32 {
+                return Err(anyhow::Error::msg(format!(
+                    "sector {} of partition {} has a commitment which is not 32 bytes",
+                    i, k
+                )));
+            }
+            if s.inclusion_proofs.len() != post_config.challenge_count {
+                return Err(anyhow::Error::msg(format!(
+                    "sector {} of partition {} has {} inclusion proofs, expected {}",
+                    i,
+                    k,
+                    s.inclusion_proofs.len(),
+                    post_config.challenge_count
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The fallback::Proof of each partition, the inclusion proofs decoded with `encoding`
+pub fn vanilla_proofs_from_proto<Tree: 'static + MerkleTreeTrait>(
+    proofs: &VanillaProofs,
+    encoding: Encoding,
+) -> Result<Vec<fallback::Proof<Tree::Proof>>> {
+    proofs
+        .partitions
+        .iter()
+        .map(|p| {
+            let sectors = p
+                .sectors
+                .iter()
+                .map(|s| {
+                    Ok(fallback::SectorProof {
+                        inclusion_proofs: s
+                            .inclusion_proofs
+                            .iter()
+                            .map(|proof| codec::decode(encoding, proof))
+                            .collect::<Result<Vec<_>>>()?,
+                        comm_c: <Tree::Hasher as Hasher>::Domain::try_from_bytes(&s.comm_c)?,
+                        comm_r_last: <Tree::Hasher as Hasher>::Domain::try_from_bytes(
+                            &s.comm_r_last,
+                        )?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(fallback::Proof { sectors })
+        })
+        .collect()
+}
+
+pub fn vanilla_proofs_to_proto<Tree: 'static + MerkleTreeTrait>(
+    proofs: &[fallback::Proof<Tree::Proof>],
+    encoding: Encoding,
+) -> Result<VanillaProofs> {
+    let partitions = proofs
+        .iter()
+        .map(|p| {
+            let sectors = p
+                .sectors
+                .iter()
+                .map(|s| {
+                    Ok(SectorVanillaProof {
+                        comm_c: s.comm_c.into_bytes(),
+                        comm_r_last: s.comm_r_last.into_bytes(),
+                        inclusion_proofs: s
+                            .inclusion_proofs
+                            .iter()
+                            .map(|proof| codec::encode(encoding, proof))
+                            .collect::<Result<Vec<_>>>()?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(PartitionVanillaProof { sectors })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(VanillaProofs { partitions })
+}
+
+/// The proofs encoded as clients send vanilla_proof, so tasks sent either way are proved,
+/// dumped and wiped alike. Fails on commitments which are not field elements and inclusion
+/// proofs which do not decode for the tree of the sector size.
+pub fn encode_vanilla_proofs(
+    proofs: &VanillaProofs,
+    post_config: &PoStConfig,
+    encoding: Encoding,
+) -> Result<Vec<u8>> {
+    with_shape!(
+        u64::from(post_config.sector_size),
+        encode_typed,
+        proofs,
+        encoding
+    )
+}
+
+fn encode_typed<Tree: 'static + MerkleTreeTrait>(
+    proofs: &VanillaProofs,
+    encoding: Encoding,
+) -> Result<Vec<u8>> {
+    let proofs = vanilla_proofs_from_proto::<Tree>(proofs, encoding)?;
+    codec::encode(encoding, &proofs)
+}
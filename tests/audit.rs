@@ -0,0 +1,119 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use window_post_snark_server::audit::{
+    read_audit_records, report_csv, report_page, write_audit_record, AuditRecord, REPORT_CSV_HEADER,
+};
+use window_post_snark_server::status::TaskStatus;
+use window_post_snark_server::tasks::{pub_in_digest, TaskInfo, TaskTimings};
+
+fn finished_task() -> TaskInfo {
+    TaskInfo {
+        task_id: "../t1".to_string(),
+        client_id: "tenant-a".to_string(),
+        result: vec![1, 2, 3],
+        task_status: TaskStatus::Done,
+        pub_in_digest: pub_in_digest(b"pub_in"),
+        finished_at: Some(UNIX_EPOCH + Duration::from_millis(1_650_000_000_000)),
+        timings: TaskTimings {
+            partitions: 2,
+            prove: Duration::from_secs(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_audit_record() {
+    let record = AuditRecord::new(&finished_task(), "");
+    assert_eq!(record.tenant, "tenant-a");
+    assert_eq!(record.outcome, "Done");
+    assert_eq!(record.pub_in_sha256.len(), 64);
+    assert_eq!(record.proof_sha256.len(), 64);
+    assert_eq!(record.prove_ms, 3000);
+    assert_eq!(record.finished_at_ms, 1_650_000_000_000);
+
+    let mut failed = finished_task();
+    failed.result = vec![];
+    failed.task_status = TaskStatus::Failed;
+    failed.started_at = Some(SystemTime::now());
+    let record = AuditRecord::new(&failed, "out of memory");
+    assert_eq!(record.outcome, "Failed");
+    assert_eq!(record.proof_sha256, "");
+    assert_eq!(record.error, "out of memory");
+}
+
+#[test]
+fn test_write_audit_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let record = AuditRecord::new(&finished_task(), "");
+    let path = write_audit_record(dir.path(), &record).unwrap();
+    // the task id can not escape the audit dir
+    assert_eq!(path.parent().unwrap(), dir.path());
+    assert_eq!(
+        path.file_name().unwrap().to_str().unwrap(),
+        "1650000000000-___t1.json"
+    );
+    let read: AuditRecord = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(read.task_id, "../t1");
+    assert_eq!(read.proof_sha256, record.proof_sha256);
+}
+
+#[test]
+fn test_read_audit_records() {
+    let dir = tempfile::tempdir().unwrap();
+    for (task_id, finished_at_ms) in [("t2", 2000), ("t1", 1000), ("t3", 3000)].iter() {
+        let record = AuditRecord {
+            task_id: task_id.to_string(),
+            finished_at_ms: *finished_at_ms,
+            ..Default::default()
+        };
+        write_audit_record(dir.path(), &record).unwrap();
+    }
+    std::fs::write(dir.path().join("broken.json"), b"{").unwrap();
+    let ids = |from_ms, to_ms| -> Vec<String> {
+        read_audit_records(dir.path(), from_ms, to_ms)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.task_id)
+            .collect()
+    };
+    assert_eq!(ids(0, 0), vec!["t1", "t2", "t3"]);
+    assert_eq!(ids(1000, 3000), vec!["t1", "t2"]);
+    assert_eq!(ids(2500, 0), vec!["t3"]);
+}
+
+#[test]
+fn test_report_page() {
+    let records: Vec<AuditRecord> = [("t1", 1000), ("t2", 2000), ("t3", 2000), ("t4", 3000)]
+        .iter()
+        .map(|(task_id, finished_at_ms)| AuditRecord {
+            task_id: task_id.to_string(),
+            finished_at_ms: *finished_at_ms,
+            ..Default::default()
+        })
+        .collect();
+    let ids =
+        |page: Vec<AuditRecord>| -> Vec<String> { page.into_iter().map(|r| r.task_id).collect() };
+    let (page, more) = report_page(records.clone(), 0, "", 2);
+    assert_eq!(ids(page), vec!["t1", "t2"]);
+    assert!(more);
+    // the next page goes on after t2, though t3 finished in the same millisecond
+    let (page, more) = report_page(records.clone(), 2000, "t2", 2);
+    assert_eq!(ids(page), vec!["t3", "t4"]);
+    assert!(!more);
+    let (page, more) = report_page(records, 3000, "t4", 2);
+    assert!(page.is_empty());
+    assert!(!more);
+}
+
+#[test]
+fn test_report_csv() {
+    let mut record = AuditRecord::new(&finished_task(), "");
+    record.error = "bad \"proof\", again".to_string();
+    let csv = report_csv(&[record]);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], REPORT_CSV_HEADER);
+    assert!(lines[1].starts_with("../t1,tenant-a,"));
+    assert!(lines[1].contains(",\"bad \"\"proof\"\", again\","));
+}
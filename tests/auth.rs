@@ -0,0 +1,210 @@
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::json;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Request};
+use window_post_snark_server::auth::{
+    authorize, read_tokens, AuthConfig, BearerToken, JwtConfig, JwtKey, Principal, Role,
+};
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::DumpServerStateRequest;
+
+const SECRET: &[u8] = b"not so secret";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn jwt(exp: u64, iss: &str) -> String {
+    encode(
+        &Header::default(),
+        &json!({"sub": "miner-1", "exp": exp, "iss": iss}),
+        &EncodingKey::from_secret(SECRET),
+    )
+    .unwrap()
+}
+
+fn admin_jwt(exp: u64) -> String {
+    encode(
+        &Header::default(),
+        &json!({"sub": "ops", "exp": exp, "iss": "lotus", "role": "admin"}),
+        &EncodingKey::from_secret(SECRET),
+    )
+    .unwrap()
+}
+
+fn metadata(token: &str) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    BearerToken::new(token).unwrap().apply(&mut metadata);
+    metadata
+}
+
+fn jwt_config() -> AuthConfig {
+    AuthConfig {
+        tokens: vec![],
+        admin_tokens: vec![],
+        jwt: Some(
+            JwtConfig::new(
+                JwtKey::Hmac(SECRET.to_vec()),
+                Some("lotus".to_string()),
+                Duration::from_secs(60),
+            )
+            .unwrap(),
+        ),
+    }
+}
+
+#[test]
+fn test_auth_disabled() {
+    let auth = AuthConfig::default();
+    assert!(!auth.is_enabled());
+    assert_eq!(auth.authenticate(&MetadataMap::new()).unwrap(), None);
+}
+
+#[test]
+fn test_static_token() {
+    let auth = AuthConfig {
+        tokens: vec!["t1".to_string(), "t2".to_string()],
+        admin_tokens: vec![],
+        jwt: None,
+    };
+    assert!(auth.authenticate(&metadata("t2")).unwrap().is_some());
+    assert_eq!(
+        auth.authenticate(&metadata("t3")).unwrap_err().code(),
+        Code::Unauthenticated
+    );
+    assert_eq!(
+        auth.authenticate(&MetadataMap::new()).unwrap_err().code(),
+        Code::Unauthenticated
+    );
+    // secrets stay out of the logs
+    assert!(!format!("{:?}", auth).contains("t1"));
+}
+
+#[test]
+fn test_jwt() {
+    let auth = jwt_config();
+    let principal = auth
+        .authenticate(&metadata(&jwt(now() + 600, "lotus")))
+        .unwrap();
+    assert_eq!(
+        principal,
+        Some(Principal {
+            subject: "miner-1".to_string(),
+            role: Role::Submitter,
+        })
+    );
+    let err = auth
+        .authenticate(&metadata(&jwt(now() + 600, "someone")))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+}
+
+#[test]
+fn test_bad_jwt_key() {
+    // found when the config is made, not on the first call
+    let err = JwtConfig::new(
+        JwtKey::RsaPem(b"not a pem".to_vec()),
+        None,
+        Duration::from_secs(60),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("bad jwt public key"));
+}
+
+#[test]
+fn test_jwt_expiry() {
+    let auth = jwt_config();
+    // within the clock skew
+    assert!(auth
+        .authenticate(&metadata(&jwt(now() - 10, "lotus")))
+        .is_ok());
+    let err = auth
+        .authenticate(&metadata(&jwt(now() - 120, "lotus")))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    assert_eq!(err.message(), "token expired");
+}
+
+#[test]
+fn test_read_tokens() {
+    let mut f = tempfile::NamedTempFile::new().unwrap();
+    writeln!(f, "# miners\nt1\n\n  t2  ").unwrap();
+    assert_eq!(read_tokens(f.path()).unwrap(), vec!["t1", "t2"]);
+}
+
+#[test]
+fn test_roles() {
+    let mut auth = jwt_config();
+    auth.tokens = vec!["tenant".to_string()];
+    auth.admin_tokens = vec!["operator".to_string()];
+    let role = |token: &str| auth.authenticate(&metadata(token)).unwrap().unwrap().role;
+    assert_eq!(role("tenant"), Role::Submitter);
+    assert_eq!(role("operator"), Role::Admin);
+    assert_eq!(role(&jwt(now() + 600, "lotus")), Role::Submitter);
+    assert_eq!(role(&admin_jwt(now() + 600)), Role::Admin);
+}
+
+#[test]
+fn test_authorize() {
+    let request = |role: Role| {
+        let mut r = Request::new(());
+        r.extensions_mut().insert(Principal {
+            subject: String::new(),
+            role,
+        });
+        r
+    };
+    assert!(authorize(&request(Role::Submitter), Role::Submitter).is_ok());
+    assert!(authorize(&request(Role::Admin), Role::Submitter).is_ok());
+    assert!(authorize(&request(Role::Admin), Role::Admin).is_ok());
+    assert_eq!(
+        authorize(&request(Role::Submitter), Role::Admin)
+            .unwrap_err()
+            .code(),
+        Code::PermissionDenied
+    );
+    // no principal when auth is not enabled
+    assert!(authorize(&Request::new(()), Role::Admin).is_ok());
+}
+
+// what the interceptor makes of a call with the token of a submitter
+fn submitter_request<T>(auth: &AuthConfig, message: T) -> Request<T> {
+    let mut r = Request::new(message);
+    BearerToken::new("tenant").unwrap().apply(r.metadata_mut());
+    let principal = auth.authenticate(r.metadata()).unwrap().unwrap();
+    r.extensions_mut().insert(principal);
+    r
+}
+
+#[test]
+fn test_admin_calls_refuse_submitters() {
+    let auth = AuthConfig {
+        tokens: vec!["tenant".to_string()],
+        admin_tokens: vec!["operator".to_string()],
+        jwt: None,
+    };
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let results = [
+        rt.block_on(srv.export_task_report(submitter_request(&auth, Default::default())))
+            .map(drop),
+        rt.block_on(srv.dump_server_state(submitter_request(&auth, DumpServerStateRequest {})))
+            .map(drop),
+        rt.block_on(srv.manage_param_cache(submitter_request(&auth, Default::default())))
+            .map(drop),
+        rt.block_on(srv.set_server_timeouts(submitter_request(&auth, Default::default())))
+            .map(drop),
+    ];
+    for res in results {
+        assert_eq!(res.unwrap_err().code(), Code::PermissionDenied);
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request};
+use window_post_snark_server::batch::{BatchOutcome, Batches};
+use window_post_snark_server::server::{WindowPostSnarkServer, TASK_PAYLOAD_MAX_LEN};
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    BatchReport, GetBatchReportRequest, SnarkTaskRequestParams, SubmitBatchRequest, TaskState,
+};
+use window_post_snark_server::status::{ServerStatus, TaskStatus};
+
+mod common;
+
+use common::params;
+
+fn submit(rt: &Runtime, srv: &WindowPostSnarkServer, tasks: Vec<SnarkTaskRequestParams>) -> String {
+    let res = rt
+        .block_on(srv.submit_batch(Request::new(SubmitBatchRequest { tasks })))
+        .unwrap()
+        .into_inner();
+    res.batch_id
+}
+
+fn report(rt: &Runtime, srv: &WindowPostSnarkServer, batch_id: &str) -> BatchReport {
+    rt.block_on(srv.get_batch_report(Request::new(GetBatchReportRequest {
+        batch_id: batch_id.to_string(),
+    })))
+    .unwrap()
+    .into_inner()
+}
+
+// what the worker does when the running task is proved
+fn finish(srv: &WindowPostSnarkServer, ok: bool) {
+    let mut si = srv.server_info.lock();
+    if ok {
+        si.slots[0].task_info.result =
+            format!("proof of {}", si.slots[0].task_info.task_id).into_bytes();
+        si.slots[0].task_info.task_status = TaskStatus::Done;
+    } else {
+        si.slots[0].task_info.task_status = TaskStatus::Failed;
+        si.slots[0].error = "gpu failed".to_string();
+    }
+    si.slots[0].task_info.timers.done_at = Some(Instant::now());
+    si.finish_batch_task(0);
+}
+
+#[test]
+fn test_batch_runs_without_fetch_and_ack() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let batch_id = submit(&rt, &srv, vec![params("t1"), params("t2"), params("t3")]);
+
+    let r = report(&rt, &srv, &batch_id);
+    assert_eq!(r.tasks.len(), 3);
+    assert_eq!(r.tasks[1].task_state, TaskState::TaskQueued as i32);
+    assert_eq!(r.tasks[1].queue_position, 1);
+    assert_eq!(r.tasks[2].queue_position, 2);
+    assert_eq!(srv.server_info.lock().slots[0].task_info.task_id, "t1");
+
+    finish(&srv, true);
+    // the report moves the server on to the next task
+    let r = report(&rt, &srv, &batch_id);
+    assert_eq!(r.tasks[0].task_state, TaskState::TaskDone as i32);
+    assert_eq!(r.tasks[0].result, b"proof of t1".to_vec());
+    assert_eq!(r.done, 1);
+    assert_eq!(srv.server_info.lock().slots[0].task_info.task_id, "t2");
+
+    finish(&srv, false);
+    let r = report(&rt, &srv, &batch_id);
+    assert_eq!(r.tasks[1].task_state, TaskState::TaskFailed as i32);
+    assert_eq!(r.tasks[1].error, "gpu failed");
+    assert!(!r.complete);
+
+    finish(&srv, true);
+    let r = report(&rt, &srv, &batch_id);
+    assert!(r.complete);
+    assert_eq!((r.done, r.failed), (2, 1));
+    assert_eq!(srv.server_info.lock().slots[0].status, ServerStatus::Free);
+}
+
+#[test]
+fn test_batch_refused_tasks() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let mut bad = params("bad");
+    bad.post_config = None;
+    let res = rt
+        .block_on(srv.submit_batch(Request::new(SubmitBatchRequest {
+            tasks: vec![bad, params("good")],
+        })))
+        .unwrap()
+        .into_inner();
+    assert!(!res.tasks[0].accepted);
+    assert!(res.tasks[1].accepted);
+    // the refused task does not keep the server from the next one
+    assert_eq!(srv.server_info.lock().slots[0].task_info.task_id, "good");
+    let r = report(&rt, &srv, &res.batch_id);
+    assert_eq!(r.failed, 1);
+
+    let err = rt
+        .block_on(srv.submit_batch(Request::new(SubmitBatchRequest {
+            tasks: vec![params("a"), params("a")],
+        })))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = rt
+        .block_on(srv.get_batch_report(Request::new(GetBatchReportRequest {
+            batch_id: "unknown".to_string(),
+        })))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[test]
+fn test_prune_complete_batches() {
+    let mut batches = Batches::default();
+    batches.insert("b1", vec!["t1".to_string()], "");
+    batches.insert("b2", vec!["t2".to_string()], "");
+    batches.finish(
+        "b1",
+        "t1",
+        BatchOutcome::rejected(TaskStatus::Failed, "refused"),
+    );
+    assert!(batches.get("b1").unwrap().is_complete());
+    batches.prune(
+        Instant::now() + Duration::from_secs(61),
+        Duration::from_secs(60),
+    );
+    assert!(batches.get("b1").is_none());
+    // still running
+    assert!(batches.get("b2").is_some());
+}
+
+#[test]
+fn test_batch_report_of_owner_only() {
+    let mut batches = Batches::default();
+    batches.insert("b1", vec!["t1".to_string()], "10.0.0.1");
+    let batch = batches.get("b1").unwrap();
+    assert!(batch.is_readable_by("10.0.0.1"));
+    assert!(!batch.is_readable_by("10.0.0.2"));
+    // calls without a known peer are not checked, as for the tasks
+    assert!(batch.is_readable_by(""));
+    batches.insert("b2", vec!["t2".to_string()], "");
+    assert!(batches.get("b2").unwrap().is_readable_by("10.0.0.2"));
+}
+
+#[test]
+fn test_batch_payload_limit() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let mut big = params("big");
+    big.vanilla_proof = vec![0; TASK_PAYLOAD_MAX_LEN + 1];
+    let res = rt
+        .block_on(srv.submit_batch(Request::new(SubmitBatchRequest {
+            tasks: vec![big, params("small")],
+        })))
+        .unwrap()
+        .into_inner();
+    // refused like a single task of that size, the others are taken
+    assert!(!res.tasks[0].accepted);
+    assert!(res.tasks[0].msg.contains("exceed"));
+    assert!(res.tasks[1].accepted);
+    assert_eq!(srv.server_info.lock().slots[0].task_info.task_id, "small");
+}
+
+#[test]
+fn test_server_prunes_unpolled_batches() {
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let mut si = srv.server_info.lock();
+    si.batches.insert("b1", vec!["t1".to_string()], "");
+    si.batches.finish(
+        "b1",
+        "t1",
+        BatchOutcome::rejected(TaskStatus::Failed, "refused"),
+    );
+    // what the server tick does, no report was asked for
+    si.prune_batches(Instant::now());
+    assert!(si.batches.get("b1").is_some());
+    let later = Instant::now() + si.server_task_get_back_time_out + Duration::from_secs(1);
+    si.prune_batches(later);
+    assert!(si.batches.is_empty());
+}
@@ -0,0 +1,63 @@
+use filecoin_proofs::{PoRepConfig, PoRepProofPartitions, SectorSize, SECTOR_SIZE_2_KIB};
+use storage_proofs_core::api_version::ApiVersion;
+use window_post_snark_server::c2::set_c2_task_info;
+use window_post_snark_server::codec::{porep_config_from_proto, porep_config_to_proto};
+use window_post_snark_server::snark_proof_grpc::C2TaskRequest;
+use window_post_snark_server::status::TaskStatus;
+
+fn porep_config() -> PoRepConfig {
+    PoRepConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        partitions: PoRepProofPartitions(1),
+        porep_id: [5; 32],
+        api_version: ApiVersion::V1_1_0,
+    }
+}
+
+fn request() -> C2TaskRequest {
+    C2TaskRequest {
+        task_id: "c2".to_string(),
+        commit_phase1_output: b"{}".to_vec(),
+        porep_config: Some(porep_config_to_proto(&porep_config())),
+        prover_id: vec![1; 32],
+        sector_id: 7,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_porep_config_proto_round_trip() {
+    let p = porep_config_from_proto(&porep_config_to_proto(&porep_config())).unwrap();
+    assert_eq!(p.sector_size, SectorSize(SECTOR_SIZE_2_KIB));
+    assert_eq!(usize::from(p.partitions), 1);
+    assert_eq!(p.porep_id, [5; 32]);
+    assert_eq!(p.api_version, ApiVersion::V1_1_0);
+
+    let mut proto = porep_config_to_proto(&porep_config());
+    proto.sector_size = 3;
+    assert!(porep_config_from_proto(&proto).is_err());
+    let mut proto = porep_config_to_proto(&porep_config());
+    proto.porep_id = vec![5; 31];
+    assert!(porep_config_from_proto(&proto).is_err());
+}
+
+#[test]
+fn test_set_c2_task_info() {
+    let t = set_c2_task_info(&request()).unwrap();
+    assert_eq!(t.task_status, TaskStatus::Ready);
+    assert_eq!(t.vanilla_proof, b"{}".to_vec());
+    assert!(t.post_config.is_none());
+    let c2 = t.c2.unwrap();
+    assert_eq!(c2.prover_id, [1; 32]);
+    assert_eq!(c2.sector_id, 7);
+
+    let mut req = request();
+    req.commit_phase1_output.clear();
+    assert!(set_c2_task_info(&req).is_err());
+    let mut req = request();
+    req.porep_config = None;
+    assert!(set_c2_task_info(&req).is_err());
+    let mut req = request();
+    req.prover_id = vec![1; 31];
+    assert!(set_c2_task_info(&req).is_err());
+}
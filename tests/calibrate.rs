@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::Request;
+use window_post_snark_server::calibrate::HardwareProfile;
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    self, EstimateTaskRequest, GetCapabilitiesRequest,
+};
+
+fn profile() -> HardwareProfile {
+    let mut partition_constraints = BTreeMap::new();
+    partition_constraints.insert(2048, 3_000_000);
+    HardwareProfile {
+        constraints: 1 << 18,
+        prove_ms: 1000,
+        constraints_per_sec: 1_000_000.0,
+        partition_constraints,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_profile_save_and_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("profile.json");
+    let p = profile();
+    p.save(&path).unwrap();
+    assert_eq!(HardwareProfile::load(&path).unwrap(), p);
+    assert!(HardwareProfile::load(&dir.path().join("missing.json")).is_err());
+}
+
+#[test]
+fn test_profile_estimate() {
+    let p = profile();
+    assert_eq!(p.estimate(2048, 1), Some(Duration::from_secs(3)));
+    // partitions are proved one after another
+    assert_eq!(p.estimate(2048, 2), Some(Duration::from_secs(6)));
+    assert_eq!(p.estimate(4096, 1), None);
+    let uncalibrated = HardwareProfile {
+        constraints_per_sec: 0.0,
+        ..p
+    };
+    assert_eq!(uncalibrated.estimate(2048, 1), None);
+}
+
+#[test]
+fn test_estimate_task_from_profile() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let request = Request::new(EstimateTaskRequest {
+        post_config: Some(snark_proof_grpc::PoStConfig {
+            sector_size: 2048,
+            challenge_count: 10,
+            sector_count: 2,
+            typ: snark_proof_grpc::PoStType::Window as i32,
+            priority: false,
+            api_version: snark_proof_grpc::ApiVersion::V110 as i32,
+        }),
+        replicas_len: 2,
+        partitions: 0,
+    });
+    srv.set_hardware_profile(profile()).unwrap();
+
+    let e = rt
+        .block_on(srv.estimate_task(request))
+        .unwrap()
+        .into_inner();
+    assert!(e.known);
+    assert!(e.calibrated);
+    assert_eq!(e.prove_ms, 3000);
+    assert_eq!(e.samples, 0);
+
+    let c = rt
+        .block_on(srv.get_capabilities(Request::new(GetCapabilitiesRequest {})))
+        .unwrap()
+        .into_inner();
+    assert_eq!(c.constraints_per_sec, 1_000_000.0);
+    assert_eq!(c.cpu_only, !cfg!(any(feature = "cuda", feature = "opencl")));
+}
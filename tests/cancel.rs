@@ -0,0 +1,68 @@
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request};
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    CancelTaskRequest, GetBatchReportRequest, SubmitBatchRequest, TaskState,
+};
+use window_post_snark_server::status::ServerStatus;
+
+mod common;
+
+use common::params;
+
+fn cancel_request(task_id: &str) -> Request<CancelTaskRequest> {
+    Request::new(CancelTaskRequest {
+        task_id: task_id.to_string(),
+    })
+}
+
+#[test]
+fn test_cancel_queued_and_running_tasks() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let batch_id = rt
+        .block_on(srv.submit_batch(Request::new(SubmitBatchRequest {
+            tasks: vec![params("t1"), params("t2"), params("t3")],
+        })))
+        .unwrap()
+        .into_inner()
+        .batch_id;
+
+    // a queued task just leaves the queue
+    rt.block_on(srv.cancel_task(cancel_request("t2"))).unwrap();
+    assert!(!srv.server_info.lock().task_queue.contains("t2"));
+    let err = rt
+        .block_on(srv.cancel_task(cancel_request("unknown")))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    // the running task only stops at its next checkpoint, the server stays busy until then
+    rt.block_on(srv.cancel_task(cancel_request("t1"))).unwrap();
+    {
+        let si = srv.server_info.lock();
+        assert_eq!(si.slots[0].task_info.task_id, "t1");
+        assert!(si.slots[0].task_info.cancel.is_cancelled());
+        assert_eq!(si.slots[0].status, ServerStatus::Working);
+    }
+    // cancelling again is fine
+    rt.block_on(srv.cancel_task(cancel_request("t1"))).unwrap();
+
+    // what the worker does when it returns from the cancelled task
+    srv.server_info.lock().finish_cancelled_task(0);
+    let report = rt
+        .block_on(srv.get_batch_report(Request::new(GetBatchReportRequest {
+            batch_id: batch_id.clone(),
+        })))
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.tasks[0].task_state, TaskState::TaskCancelled as i32);
+    assert_eq!(report.tasks[1].task_state, TaskState::TaskCancelled as i32);
+    // the report moved the server on to the last task
+    let si = srv.server_info.lock();
+    assert_eq!(si.slots[0].task_info.task_id, "t3");
+    assert_eq!(si.slots[0].status, ServerStatus::Working);
+    assert!(!si.slots[0].task_info.cancel.is_cancelled());
+}
@@ -2,29 +2,30 @@ use std::collections::BTreeMap;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::sync::Once;
-use storage_proofs_core::error::{Result,Error};
+use storage_proofs_core::error::Result;
 use filecoin_hashers::Hasher;
-use anyhow::{Context, ensure};
-use filecoin_proofs::{add_piece, as_safe_commitment, ChallengeSeed, clear_cache, Commitment, compute_comm_d, fauxrep_aux, generate_piece_commitment, get_partitions_for_window_post, get_seal_inputs, PaddedBytesAmount, PieceInfo, POREP_PARTITIONS, PoRepConfig, PoRepProofPartitions, PoStConfig, PoStType, PrivateReplicaInfo, ProverId, PublicReplicaInfo, seal_commit_phase1, seal_commit_phase2, seal_pre_commit_phase1, seal_pre_commit_phase2, SealCommitOutput, SealPreCommitOutput, SealPreCommitPhase1Output, SECTOR_SIZE_2_KIB, SectorShape2KiB, SectorSize, SnarkProof, TEST_SEED, UnpaddedByteIndex, UnpaddedBytesAmount, unseal_range, validate_cache_for_commit, validate_cache_for_precommit_phase2, verify_seal, verify_window_post, WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT};
+use anyhow::ensure;
+use filecoin_proofs::{add_piece, ChallengeSeed, clear_cache, Commitment, compute_comm_d, fauxrep_aux, generate_piece_commitment, get_seal_inputs, PaddedBytesAmount, PieceInfo, POREP_PARTITIONS, PoRepConfig, PoRepProofPartitions, PoStConfig, PoStType, PrivateReplicaInfo, ProverId, PublicReplicaInfo, seal_commit_phase1, seal_commit_phase2, seal_pre_commit_phase1, seal_pre_commit_phase2, SealCommitOutput, SealPreCommitOutput, SealPreCommitPhase1Output, SECTOR_SIZE_2_KIB, SectorShape2KiB, SectorSize, SnarkProof, TEST_SEED, UnpaddedByteIndex, UnpaddedBytesAmount, unseal_range, validate_cache_for_commit, validate_cache_for_precommit_phase2, verify_seal, verify_window_post, WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT};
 use storage_proofs_core::api_version::ApiVersion;
-use std::time::Duration;
 use rand_xorshift::XorShiftRng;
 use storage_proofs_core::merkle::MerkleTreeTrait;
 use storage_proofs_core::sector::SectorId;
 use storage_proofs_post::fallback;
-use tokio::runtime::Runtime;
-use tonic::Request;
 use blstrs::Scalar as Fr;
 use ff::Field;
-use filecoin_proofs::parameters::window_post_setup_params;
-use log::{error, info};
+use log::info;
 use rand::{random, Rng, SeedableRng};
-use storage_proofs_core::compound_proof;
-use storage_proofs_core::compound_proof::CompoundProof;
-use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCompound, PrivateSector, PublicSector};
-use uuid::Uuid;
-use window_post_snark_server::client::new_client;
-use window_post_snark_server::snark_proof_grpc::{GetTaskResultRequest, GetWorkerStatusRequest, SnarkTaskRequestParams};
+use tokio::runtime::Runtime;
+use window_post_snark_server::client::{decode_snark_proof, encode_snark_proof, remote_generate_window_post, verify_result};
+use bellperson::groth16;
+use group::prime::PrimeCurveAffine;
+use blstrs::{Bls12, G1Affine, G2Affine};
+use futures::FutureExt;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use window_post_snark_server::limits::limited_incoming;
+use window_post_snark_server::prelude::{proto, server_builder, snark_task_service, ConnectionLimits, ServerStatus, SnarkTaskRequestParams, TaskStatus, WindowPostClient, WindowPostSnarkServer};
 use tempfile::{tempdir, NamedTempFile, TempDir};
 
 const ARBITRARY_POREP_ID_V1_0_0: [u8; 32] = [127; 32];
@@ -429,158 +430,10 @@ fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
 ) -> Result<SnarkProof> {
     info!("generate_window_post:start");
-    ensure!(
-        post_config.typ == PoStType::Window,
-        "invalid post config type"
-    );
-
-    let randomness_safe = as_safe_commitment(randomness, "randomness")?;
-    let prover_id_safe = as_safe_commitment(&prover_id, "prover_id")?;
-
-    let vanilla_params = window_post_setup_params(post_config);
-    let partitions = get_partitions_for_window_post(replicas.len(), post_config);
-
-    let sector_count = vanilla_params.sector_count;
-    let setup_params = compound_proof::SetupParams {
-        vanilla_params,
-        partitions,
-        priority: post_config.priority,
-    };
-
-    let pub_params: compound_proof::PublicParams<'_, FallbackPoSt<'_, Tree>> =
-        FallbackPoStCompound::setup(&setup_params)?;
-    // let groth_params = get_post_params::<Tree>(post_config)?;
-
-    let trees: Vec<_> = replicas
-        .iter()
-        .map(|(sector_id, replica)| {
-            replica
-                .merkle_tree(post_config.sector_size)
-                .with_context(|| {
-                    format!("generate_window_post: merkle_tree failed: {:?}", sector_id)
-                })
-        })
-        .collect::<Result<_>>()?;
-
-    let mut pub_sectors = Vec::with_capacity(sector_count);
-    let mut priv_sectors = Vec::with_capacity(sector_count);
-
-    for ((sector_id, replica), tree) in replicas.iter().zip(trees.iter()) {
-        let comm_r = replica.safe_comm_r().with_context(|| {
-            format!("generate_window_post: safe_comm_r failed: {:?}", sector_id)
-        })?;
-        let comm_c = replica.safe_comm_c();
-        let comm_r_last = replica.safe_comm_r_last();
-
-        pub_sectors.push(PublicSector {
-            id: *sector_id,
-            comm_r,
-        });
-        priv_sectors.push(PrivateSector {
-            tree,
-            comm_c,
-            comm_r_last,
-        });
-    }
-
-    let pub_inputs = fallback::PublicInputs {
-        randomness: randomness_safe,
-        prover_id: prover_id_safe,
-        sectors: pub_sectors,
-        k: None,
-    };
-
-    let priv_inputs = fallback::PrivateInputs::<Tree> {
-        sectors: &priv_sectors,
-    };
-
-
-    // do vanilla
-    let va_proof = FallbackPoStCompound::prove_vanilla(&pub_params, &pub_inputs, &priv_inputs)?;
-
-    let rt = Runtime::new().unwrap();
-
-    let mut client = rt.block_on(async {
-        match new_client("http://127.0.0.1:50051", Duration::from_secs(10)).await {
-            Ok(c) => c,
-            Err(e) => {
-                panic!("{}", e)
-            }
-        }
-    });
-
-    let task_id = Uuid::new_v4();
-
-    // lock server
-    loop {
-        let req_lock_server = GetWorkerStatusRequest { task_id: task_id.clone().to_string() };
-
-        match rt.block_on(async { client.lock_server_if_free(Request::new(req_lock_server.clone())).await }) {
-            Ok(r) => {
-                println!("{}", r.into_inner().msg)
-            }
-            Err(s) => {
-                error!("{}",s.message());
-                rt.block_on(async {
-                    tokio::time::sleep(Duration::from_secs(2)).await
-                });
-                continue
-            }
-        }
-
-        // do task
-        let req_do_task = Request::new(SnarkTaskRequestParams {
-            task_id: task_id.clone().to_string(),
-            vanilla_proof: serde_json::to_vec(&va_proof)?,
-            pub_in: serde_json::to_vec(&pub_inputs)?,
-            post_config: serde_json::to_vec(&post_config)?,
-            replicas_len: replicas.len() as u32,
-        });
-
-        match rt.block_on(async { client.do_snark_task(req_do_task).await }) {
-            Ok(r) => {
-                println!("{}", r.into_inner().msg)
-            }
-            Err(s) => {
-                error!("{}", s.message());
-                rt.block_on(async {
-                    tokio::time::sleep(Duration::from_secs(2)).await
-                });
-                continue
-            }
-        }
-
-        // get result
-        let req_get_result = GetTaskResultRequest { task_id: task_id.clone().to_string() };
-
-        let result = match rt.block_on(async {
-            loop {
-                match client.get_snark_task_result(Request::new(req_get_result.clone())).await {
-                    Ok(res) => {
-                        let r = res.into_inner();
-                        if r.msg == "ok".to_string() {
-                            info!("generate_window_post:finish");
-                            return Ok(r.result)
-                        } else {
-                            tokio::time::sleep(Duration::from_secs(2)).await;
-                            continue;
-                        }
-                    }
-                    Err(s) => {
-                        return Err(anyhow::Error::from(Error::Unclassified(s.message().to_string())))
-                    }
-                }
-            }
-        }) {
-            Ok(r) => {
-                Ok(r)
-            }
-            Err(s) => {
-                Err(s)
-            }
-        };
-        return result
-    };
+    let rt = Runtime::new()?;
+    let proof = rt.block_on(remote_generate_window_post(&["http://127.0.0.1:50051"], post_config, randomness, replicas, prover_id))?;
+    info!("generate_window_post:finish");
+    Ok(proof)
 }
 
 
@@ -625,3 +478,97 @@ fn test_window_post_two_partitions_matching_2kib_base_8() -> Result<()> {
     )
 }
 
+
+#[test]
+fn test_decode_snark_proof_length() {
+    assert!(decode_snark_proof(&[0u8; 100], 1).is_err());
+    assert!(decode_snark_proof(&[], 0).unwrap().is_empty());
+    assert_eq!(encode_snark_proof(&[]).unwrap(), Vec::<u8>::new());
+}
+
+#[test]
+fn test_verify_result_rejects_truncated_proof() {
+    let config = PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        sector_count: 2,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    };
+    let pub_inputs = fallback::PublicInputs::<<<SectorShape2KiB as MerkleTreeTrait>::Hasher as Hasher>::Domain> {
+        randomness: Default::default(),
+        prover_id: Default::default(),
+        sectors: vec![],
+        k: None,
+    };
+    assert!(verify_result(&config, &pub_inputs, &[0u8; 100]).is_err());
+}
+
+#[test]
+fn test_get_proof_acks_result() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: &'static str = Box::leak(format!("http://{}", listener.local_addr().unwrap()).into_boxed_str());
+        let (task_run_tx, _task_run_rx) = mpsc::unbounded_channel();
+        let srv = WindowPostSnarkServer::new(task_run_tx);
+        let limits = ConnectionLimits::default();
+        let (exit_tx, exit_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            server_builder(&limits)
+                .add_service(snark_task_service(srv.clone()))
+                .serve_with_incoming_shutdown(limited_incoming(listener, limits), exit_rx.map(drop)),
+        );
+
+        let mut client = WindowPostClient::connect(addr, Duration::from_secs(5)).await.unwrap();
+        let post_config = PoStConfig {
+            sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+            sector_count: 2,
+            challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+            typ: PoStType::Window,
+            priority: false,
+            api_version: ApiVersion::V1_1_0,
+        };
+        client.lock("t1", false).await.unwrap();
+        client
+            .raw()
+            .do_snark_task(SnarkTaskRequestParams {
+                task_id: "t1".to_string(),
+                replicas_len: 1,
+                post_config: Some(proto::PoStConfig {
+                    sector_size: SECTOR_SIZE_2_KIB,
+                    challenge_count: WINDOW_POST_CHALLENGE_COUNT as u64,
+                    sector_count: 2,
+                    typ: proto::PoStType::Window as i32,
+                    priority: false,
+                    api_version: proto::ApiVersion::V110 as i32,
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(client.get_proof("t1", &post_config, 1).await.unwrap().is_none());
+
+        // what the worker does once the task is proved
+        let proof = groth16::Proof::<Bls12> {
+            a: G1Affine::generator(),
+            b: G2Affine::generator(),
+            c: G1Affine::generator(),
+        };
+        {
+            let mut si = srv.server_info.lock();
+            si.slots[0].task_info.result = encode_snark_proof(&[proof]).unwrap();
+            si.slots[0].task_info.task_status = TaskStatus::Done;
+            si.slots[0].task_info.timers.done_at = Some(Instant::now());
+        }
+        let proofs = client.get_proof("t1", &post_config, 1).await.unwrap().unwrap();
+        assert_eq!(proofs.len(), 1);
+        // the proof was acked with it, the server takes the next task right away
+        assert_eq!(srv.server_info.lock().slots[0].status, ServerStatus::Free);
+        assert_eq!(client.lock("t2", false).await.unwrap().status, ServerStatus::Free.to_string());
+
+        exit_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    });
+}
@@ -0,0 +1,76 @@
+use filecoin_proofs::{
+    PoStConfig, PoStType, SectorSize, SECTOR_SIZE_2_KIB, WINDOW_POST_CHALLENGE_COUNT,
+};
+use std::str::FromStr;
+use storage_proofs_core::api_version::ApiVersion;
+use window_post_snark_server::codec::{
+    decode, encode, post_config_from_proto, post_config_to_proto, preferred_encoding,
+    supported_encodings, Encoding,
+};
+use window_post_snark_server::compat::decode_post_config;
+
+#[test]
+fn test_codec_round_trip() {
+    let value: Vec<(u64, String)> = vec![(1, "a".to_string()), (2, "b".to_string())];
+    for encoding in supported_encodings() {
+        let bytes = encode(encoding, &value).unwrap();
+        let decoded: Vec<(u64, String)> = decode(encoding, &bytes).unwrap();
+        assert_eq!(value, decoded);
+    }
+}
+
+#[test]
+fn test_encoding_wire_value() {
+    assert_eq!(Encoding::from_wire(0).unwrap(), Encoding::Json);
+    assert_eq!(Encoding::from_wire(1).unwrap(), Encoding::Bincode);
+    assert!(Encoding::from_wire(42).is_err());
+    assert_eq!(Encoding::from_str("cbor").unwrap(), Encoding::Cbor);
+}
+
+#[test]
+fn test_preferred_encoding() {
+    assert_eq!(
+        preferred_encoding(&supported_encodings()),
+        Encoding::Bincode
+    );
+    assert_eq!(
+        preferred_encoding(&[Encoding::Json, Encoding::Cbor]),
+        Encoding::Cbor
+    );
+    assert_eq!(preferred_encoding(&[]), Encoding::Json);
+}
+
+#[test]
+fn test_post_config_proto_round_trip() {
+    let post_config = PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        sector_count: 2,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    };
+    let decoded = post_config_from_proto(&post_config_to_proto(&post_config)).unwrap();
+    assert_eq!(u64::from(decoded.sector_size), SECTOR_SIZE_2_KIB);
+    assert_eq!(decoded.challenge_count, WINDOW_POST_CHALLENGE_COUNT);
+    assert_eq!(decoded.sector_count, 2);
+    assert_eq!(decoded.typ, PoStType::Window);
+    assert_eq!(decoded.api_version, ApiVersion::V1_1_0);
+
+    let mut empty = post_config_to_proto(&post_config);
+    empty.sector_count = 0;
+    assert!(post_config_from_proto(&empty).is_err());
+
+    let mut unsupported = post_config_to_proto(&post_config);
+    unsupported.sector_size = 3000;
+    assert!(post_config_from_proto(&unsupported).is_err());
+}
+
+#[test]
+fn test_legacy_post_config() {
+    let legacy = br#"{"sector_size":2048,"challenge_count":10,"sector_count":2,"typ":"Window"}"#;
+    let post_config = decode_post_config(Encoding::Json, legacy).unwrap();
+    assert_eq!(post_config.api_version, ApiVersion::V1_0_0);
+    assert!(!post_config.priority);
+    assert_eq!(post_config.sector_count, 2);
+}
@@ -0,0 +1,20 @@
+use window_post_snark_server::snark_proof_grpc::{
+    ApiVersion, PoStConfig, PoStType, SnarkTaskRequestParams,
+};
+
+/// Params of a window PoSt task of one 2KiB replica, the payloads are left for the test
+pub fn params(task_id: &str) -> SnarkTaskRequestParams {
+    SnarkTaskRequestParams {
+        task_id: task_id.to_string(),
+        replicas_len: 1,
+        post_config: Some(PoStConfig {
+            sector_size: 2048,
+            challenge_count: 10,
+            sector_count: 2,
+            typ: PoStType::Window as i32,
+            priority: false,
+            api_version: ApiVersion::V110 as i32,
+        }),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+use window_post_snark_server::config::{ServerConfig, TlsConfig};
+
+const CONFIG: &str = r#"
+port = 50052
+log_level = "debug"
+gpu = "1"
+
+[timeouts]
+server_lock_time_out = 30
+prove_time_out = 1800
+
+[params]
+cache = ["/data/params", "/var/tmp/filecoin-proof-parameters"]
+preload = [34359738368]
+
+[tls]
+cert = "/etc/wps/server.pem"
+key = "/etc/wps/server.key"
+"#;
+
+fn vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> {
+    let vars = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), OsString::from(v)))
+        .collect::<HashMap<_, _>>();
+    move |name| vars.get(name).cloned()
+}
+
+#[test]
+fn test_parse_config() {
+    let config = ServerConfig::parse(CONFIG).unwrap();
+    assert_eq!(config.port, Some(50052));
+    assert_eq!(config.log_level.as_deref(), Some("debug"));
+    assert_eq!(config.gpu.as_deref(), Some("1"));
+    assert_eq!(
+        config.timeouts.server_lock_time_out(),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(config.timeouts.server_task_get_back_time_out(), None);
+    assert_eq!(config.timeouts.prove_time_out, Some(1800));
+    assert_eq!(config.params.cache.len(), 2);
+    assert_eq!(config.params.preload, vec![34359738368]);
+    assert_eq!(
+        config.tls,
+        Some(TlsConfig {
+            cert: PathBuf::from("/etc/wps/server.pem"),
+            key: PathBuf::from("/etc/wps/server.key"),
+            client_ca: None,
+        })
+    );
+
+    assert_eq!(ServerConfig::parse("").unwrap(), ServerConfig::default());
+    // typos are not silently ignored
+    assert!(ServerConfig::parse("prot = 50051").is_err());
+    assert!(ServerConfig::parse("[timeouts]\nserver_lock_timeout = 1").is_err());
+}
+
+#[test]
+fn test_env_overrides_config() {
+    let mut config = ServerConfig::parse(CONFIG).unwrap();
+    config
+        .apply_vars(vars(&[
+            ("WPS_PORT", "50053"),
+            ("WPS_SERVER_TASK_GET_BACK_TIME_OUT", "120"),
+            ("WPS_PRELOAD_PARAMS", "2048, 536870912"),
+            ("WPS_TLS_CLIENT_CA", "/etc/wps/ca.pem"),
+        ]))
+        .unwrap();
+    assert_eq!(config.port, Some(50053));
+    assert_eq!(config.timeouts.server_lock_time_out, Some(30));
+    assert_eq!(config.timeouts.server_task_get_back_time_out, Some(120));
+    assert_eq!(config.params.preload, vec![2048, 536870912]);
+    assert_eq!(
+        config.tls.unwrap().client_ca,
+        Some(PathBuf::from("/etc/wps/ca.pem"))
+    );
+
+    let mut config = ServerConfig::default();
+    assert!(config.apply_vars(vars(&[("WPS_PORT", "port")])).is_err());
+    assert!(config
+        .apply_vars(vars(&[("WPS_TLS_CERT", "/etc/wps/server.pem")]))
+        .is_err());
+    assert!(config
+        .apply_vars(vars(&[("WPS_TLS_CLIENT_CA", "/etc/wps/ca.pem")]))
+        .is_err());
+}
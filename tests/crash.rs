@@ -0,0 +1,38 @@
+use std::fs;
+use tempfile::tempdir;
+use window_post_snark_server::crash::{install_panic_hook, recover, take_previous_crash, CrashReport};
+
+#[test]
+fn test_take_previous_crash() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("crash");
+    assert!(take_previous_crash(&path).is_none());
+
+    let report = CrashReport {
+        task_id: "a".to_string(),
+        phase: "proving".to_string(),
+        message: "out of gpu memory".to_string(),
+        ..Default::default()
+    };
+    fs::write(&path, serde_json::to_vec(&report).unwrap()).unwrap();
+    let taken = take_previous_crash(&path).unwrap();
+    assert_eq!(taken.task_id, "a");
+    assert_eq!(taken.message, "out of gpu memory");
+    // reported once only
+    assert!(take_previous_crash(&path).is_none());
+    assert!(dir.path().join("crash.reported").exists());
+}
+
+#[test]
+fn test_recovered_panic_is_no_crash() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("crash");
+    install_panic_hook(path.clone());
+    // caught and turned into an error, the process goes on
+    assert!(recover(|| panic!("bad task")).is_err());
+    assert!(!path.exists());
+    assert_eq!(recover(|| 1).unwrap(), 1);
+    // a panic nothing catches is reported
+    assert!(std::thread::spawn(|| panic!("worker bug")).join().is_err());
+    assert_eq!(take_previous_crash(&path).unwrap().message, "worker bug");
+}
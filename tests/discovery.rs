@@ -0,0 +1,15 @@
+#![cfg(feature = "mdns")]
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use window_post_snark_server::discovery::endpoint;
+
+#[test]
+fn test_endpoint() {
+    let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+    assert_eq!(endpoint(ip, 50051, false), "http://192.168.1.20:50051");
+    assert_eq!(endpoint(ip, 50051, true), "https://192.168.1.20:50051");
+    assert_eq!(
+        endpoint(IpAddr::V6(Ipv6Addr::LOCALHOST), 50051, false),
+        "http://[::1]:50051"
+    );
+}
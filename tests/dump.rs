@@ -0,0 +1,115 @@
+use filecoin_proofs::{PoRepConfig, PoRepProofPartitions, SectorSize, SECTOR_SIZE_2_KIB};
+use std::fs;
+use storage_proofs_core::api_version::ApiVersion;
+use tempfile::tempdir;
+use window_post_snark_server::c2::C2Task;
+use window_post_snark_server::dump::{
+    dump_failed_task, load_dump, prune_dumps, write_task, DumpConfig, DumpMeta, ServerSnapshot,
+    DUMP_META_FILE, DUMP_PUB_IN_FILE, DUMP_VANILLA_PROOF_FILE,
+};
+use window_post_snark_server::tasks::TaskInfo;
+
+fn task(task_id: &str, payload_len: usize) -> TaskInfo {
+    TaskInfo {
+        task_id: task_id.to_string(),
+        vanilla_proof: vec![1; payload_len],
+        pub_in: vec![2; payload_len],
+        replicas_len: 3,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_dump_failed_task() {
+    let dir = tempdir().unwrap();
+    let config = DumpConfig::new(dir.path().to_path_buf());
+    let dumped = dump_failed_task(
+        &config,
+        &task("../a", 4),
+        "bad proof",
+        ServerSnapshot::default(),
+    )
+    .unwrap()
+    .unwrap();
+    // the task id can not move the dump out of the dump dir
+    assert_eq!(dumped.parent().unwrap(), dir.path());
+    assert_eq!(
+        fs::read(dumped.join(DUMP_VANILLA_PROOF_FILE)).unwrap(),
+        vec![1; 4]
+    );
+    assert_eq!(fs::read(dumped.join(DUMP_PUB_IN_FILE)).unwrap(), vec![2; 4]);
+    let meta: DumpMeta =
+        serde_json::from_slice(&fs::read(dumped.join(DUMP_META_FILE)).unwrap()).unwrap();
+    assert_eq!(meta.task_id, "../a");
+    assert_eq!(meta.replicas_len, 3);
+    assert_eq!(meta.payload_encoding, "json");
+    assert_eq!(meta.error, "bad proof");
+}
+
+#[test]
+fn test_dump_size_cap() {
+    let dir = tempdir().unwrap();
+    let mut config = DumpConfig::new(dir.path().to_path_buf());
+    config.max_bytes = 10;
+    let dumped = dump_failed_task(&config, &task("a", 8), "", ServerSnapshot::default()).unwrap();
+    assert!(dumped.is_none());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_prune_dumps() {
+    let dir = tempdir().unwrap();
+    for name in &["3-c", "1-a", "2-b"] {
+        let d = dir.path().join(name);
+        fs::create_dir(&d).unwrap();
+        fs::write(d.join(DUMP_META_FILE), "{}").unwrap();
+    }
+    prune_dumps(dir.path(), 2).unwrap();
+    assert!(!dir.path().join("1-a").exists());
+    assert!(dir.path().join("2-b").exists());
+    assert!(dir.path().join("3-c").exists());
+}
+
+#[test]
+fn test_load_dump() {
+    let dir = tempdir().unwrap();
+    let config = DumpConfig::new(dir.path().to_path_buf());
+    let dumped = dump_failed_task(&config, &task("a", 4), "", ServerSnapshot::default())
+        .unwrap()
+        .unwrap();
+    let (meta, task_info) = load_dump(&dumped).unwrap();
+    assert_eq!(meta.task_id, "a");
+    assert_eq!(task_info.task_id, "a");
+    assert_eq!(task_info.vanilla_proof, vec![1; 4]);
+    assert_eq!(task_info.pub_in, vec![2; 4]);
+    assert_eq!(task_info.replicas_len, 3);
+    assert!(task_info.post_config.is_none());
+}
+
+#[test]
+fn test_write_c2_task() {
+    // the cpu fallback proves c2 tasks from the same files
+    let dir = tempdir().unwrap();
+    let mut t = task("c2", 4);
+    t.max_threads = 2;
+    t.verify_proof = true;
+    t.c2 = Some(C2Task {
+        porep_config: PoRepConfig {
+            sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+            partitions: PoRepProofPartitions(1),
+            porep_id: [5; 32],
+            api_version: ApiVersion::V1_1_0,
+        },
+        prover_id: [1; 32],
+        sector_id: 7,
+    });
+    write_task(dir.path(), &t, "", 0, ServerSnapshot::default()).unwrap();
+    let (_, task_info) = load_dump(dir.path()).unwrap();
+    assert_eq!(task_info.max_threads, 2);
+    assert!(task_info.verify_proof);
+    let c2 = task_info.c2.unwrap();
+    assert_eq!(c2.porep_config.sector_size, SectorSize(SECTOR_SIZE_2_KIB));
+    assert_eq!(c2.porep_config.porep_id, [5; 32]);
+    assert_eq!(c2.prover_id, [1; 32]);
+    assert_eq!(c2.sector_id, 7);
+}
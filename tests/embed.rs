@@ -0,0 +1,125 @@
+use futures::FutureExt;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+use tonic_health::proto::health_check_response::ServingStatus;
+use tonic_health::proto::health_client::HealthClient;
+use tonic_health::proto::HealthCheckRequest;
+use window_post_snark_server::limits::limited_incoming;
+use window_post_snark_server::prelude::*;
+
+// middleware of an embedder, refusing calls without a tenant
+fn require_tenant(request: Request<()>) -> Result<Request<()>, Status> {
+    match request.metadata().get("x-tenant") {
+        Some(_) => Ok(request),
+        None => Err(Status::permission_denied("no tenant")),
+    }
+}
+
+#[test]
+fn test_embedder_layer() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (task_run_tx, _task_run_rx) = mpsc::unbounded_channel();
+        let srv = WindowPostSnarkServer::new(task_run_tx);
+        let limits = ConnectionLimits::default();
+        let (exit_tx, exit_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            server_builder(&limits)
+                .layer(tonic::service::interceptor(require_tenant))
+                .add_service(snark_task_service(srv))
+                .serve_with_incoming_shutdown(
+                    limited_incoming(listener, limits),
+                    exit_rx.map(drop),
+                ),
+        );
+
+        let mut client = SnarkTaskServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let err = client
+            .get_capabilities(GetCapabilitiesRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::PermissionDenied);
+        let mut request = Request::new(GetCapabilitiesRequest {});
+        request
+            .metadata_mut()
+            .insert("x-tenant", "miner-1".parse().unwrap());
+        assert!(client.get_capabilities(request).await.is_ok());
+
+        exit_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    });
+}
+
+#[test]
+fn test_builder() {
+    let (task_run_tx, _task_run_rx) = mpsc::unbounded_channel();
+    let built = WindowPostSnarkServer::builder()
+        .port(50052)
+        .lock_time_out(Duration::from_secs(30))
+        .build(task_run_tx.clone())
+        .unwrap();
+    let si = built.server().server_info.lock();
+    assert_eq!(si.server_lock_time_out, Duration::from_secs(30));
+    drop(si);
+    // client certificates are a part of tls
+    assert!(WindowPostSnarkServer::builder()
+        .client_ca(b"ca")
+        .build(task_run_tx)
+        .is_err());
+}
+
+#[test]
+fn test_health() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (task_run_tx, _task_run_rx) = mpsc::unbounded_channel();
+        let built = WindowPostSnarkServer::builder()
+            .addr(([127, 0, 0, 1], 50053).into())
+            .build(task_run_tx)
+            .unwrap();
+        let server_info = built.server().server_info.clone();
+        let (exit_tx, exit_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(built.serve(exit_rx.map(drop)));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let mut client =
+            WindowPostClient::connect("http://127.0.0.1:50053", Duration::from_secs(5))
+                .await
+                .unwrap();
+        assert!(client.is_healthy().await);
+        let probe = client.spawn_health_probe(Duration::from_millis(100));
+        // a draining server answers, but takes no more tasks
+        server_info.lock().start_draining();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!client.is_healthy().await);
+        assert!(!probe.is_healthy());
+        // the process itself is still alive, only not ready for tasks
+        let channel = Channel::from_static("http://127.0.0.1:50053")
+            .connect()
+            .await
+            .unwrap();
+        let mut health = HealthClient::new(channel);
+        let status = |service: &str| HealthCheckRequest {
+            service: service.to_string(),
+        };
+        let live = health.check(status("")).await.unwrap().into_inner();
+        assert_eq!(live.status, ServingStatus::Serving as i32);
+        let ready = health
+            .check(status("snark_proof_grpc.SnarkTaskService"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(ready.status, ServingStatus::NotServing as i32);
+
+        exit_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    });
+}
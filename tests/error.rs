@@ -0,0 +1,91 @@
+use std::time::SystemTime;
+use tonic::Code;
+use window_post_snark_server::error::{
+    classify_failure, Error, FailureKind, RecentErrors, TaskError,
+};
+use window_post_snark_server::error_details::{error_details, status_with_details, ErrorDetails};
+use window_post_snark_server::server::task_failure_status;
+
+#[test]
+fn test_classify_failure() {
+    let e = serde_json::from_slice::<Vec<u8>>(b"{").unwrap_err();
+    assert_eq!(
+        classify_failure(&anyhow::Error::from(e)),
+        FailureKind::Permanent
+    );
+    let e = Error::InvalidParameters("bad".to_string());
+    assert_eq!(
+        classify_failure(&anyhow::Error::from(e)),
+        FailureKind::Permanent
+    );
+    let e = std::io::Error::new(std::io::ErrorKind::Other, "device lost");
+    assert_eq!(
+        classify_failure(&anyhow::Error::from(e).context("prove partition")),
+        FailureKind::Retryable
+    );
+    let e = Error::ProofNotVerified("t1".to_string());
+    assert_eq!(
+        classify_failure(&anyhow::Error::from(e)),
+        FailureKind::Retryable
+    );
+    assert_eq!(
+        classify_failure(&anyhow::Error::msg("something")),
+        FailureKind::Unknown
+    );
+}
+
+#[test]
+fn test_task_failure_status() {
+    let msg = String::from("failed");
+    assert_eq!(
+        task_failure_status(FailureKind::Retryable, msg.clone(), ErrorDetails::default()).code(),
+        Code::Unavailable
+    );
+    assert_eq!(
+        task_failure_status(FailureKind::Permanent, msg.clone(), ErrorDetails::default()).code(),
+        Code::InvalidArgument
+    );
+    assert_eq!(
+        task_failure_status(FailureKind::Unknown, msg, ErrorDetails::default()).code(),
+        Code::Aborted
+    );
+}
+
+#[test]
+fn test_error_details_round_trip() {
+    let details = ErrorDetails {
+        field_violations: vec![("post_config".to_string(), "zero sector size".to_string())],
+        retry_delay: Some(std::time::Duration::from_millis(1500)),
+        debug_stack: vec!["prove failed".to_string(), "device lost".to_string()],
+    };
+    let status = status_with_details(Code::InvalidArgument, "bad task", details.clone());
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "bad task");
+    assert_eq!(error_details(&status).unwrap(), details);
+    // statuses without details read as empty details
+    let status = tonic::Status::unavailable("down");
+    assert_eq!(error_details(&status).unwrap(), ErrorDetails::default());
+}
+
+fn task_error(task_id: &str) -> TaskError {
+    TaskError {
+        task_id: task_id.to_string(),
+        error: "gpu fell off the bus".to_string(),
+        kind: FailureKind::Retryable,
+        failed_at: SystemTime::now(),
+    }
+}
+
+#[test]
+fn test_recent_errors() {
+    let mut errors = RecentErrors::new(2);
+    errors.push(task_error("a"));
+    errors.push(task_error("b"));
+    errors.push(task_error("c"));
+    let ids: Vec<&str> = errors.iter().map(|e| e.task_id.as_str()).collect();
+    assert_eq!(ids, vec!["b", "c"]);
+    assert_eq!(errors.consecutive_failures(), 3);
+    errors.succeeded();
+    assert_eq!(errors.consecutive_failures(), 0);
+    assert_eq!(errors.iter().count(), 2);
+}
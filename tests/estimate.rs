@@ -0,0 +1,204 @@
+use filecoin_proofs::{PoStConfig, PoStType, SectorSize};
+use std::time::{Duration, SystemTime};
+use storage_proofs_core::api_version::ApiVersion;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::Request;
+use window_post_snark_server::audit::AuditRecord;
+use window_post_snark_server::estimate::ProvingTimes;
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    self, EstimateTaskRequest, GetWorkerStatusRequest,
+};
+use window_post_snark_server::status::{ServerStatus, TaskStatus};
+use window_post_snark_server::tasks::TaskInfo;
+
+const GIB_32: u64 = 32 << 30;
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+#[test]
+fn test_estimate_median() {
+    let mut times = ProvingTimes::default();
+    assert_eq!(times.estimate(GIB_32, 1), None);
+    // the first task also loaded the params
+    for s in [300, 100, 110, 90, 105].iter() {
+        times.record(GIB_32, 1, secs(*s));
+    }
+    let e = times.estimate(GIB_32, 1).unwrap();
+    assert_eq!(e.prove, secs(105));
+    assert_eq!(e.samples, 5);
+    assert!(!e.scaled);
+    assert_eq!(times.estimate(GIB_32 * 2, 1), None);
+}
+
+#[test]
+fn test_estimate_scaled_per_partition() {
+    let mut times = ProvingTimes::default();
+    times.record(GIB_32, 2, secs(200));
+    times.record(GIB_32, 2, secs(200));
+    times.record(GIB_32, 3, secs(330));
+    let e = times.estimate(GIB_32, 5).unwrap();
+    // from the partition count with the most tasks
+    assert_eq!(e.prove, secs(500));
+    assert_eq!(e.samples, 2);
+    assert!(e.scaled);
+}
+
+#[test]
+fn test_estimate_keeps_recent_samples() {
+    let mut times = ProvingTimes::default();
+    for _ in 0..20 {
+        times.record(GIB_32, 1, secs(100));
+    }
+    // e.g. a faster gpu was put in
+    for _ in 0..11 {
+        times.record(GIB_32, 1, secs(50));
+    }
+    let e = times.estimate(GIB_32, 1).unwrap();
+    assert_eq!(e.samples, 20);
+    assert_eq!(e.prove, secs(50));
+}
+
+fn record(error: &str, degraded: bool, prove_ms: u64) -> AuditRecord {
+    AuditRecord {
+        post_config: Some(PoStConfig {
+            sector_size: SectorSize(GIB_32),
+            challenge_count: 10,
+            sector_count: 2349,
+            typ: PoStType::Window,
+            priority: false,
+            api_version: ApiVersion::V1_1_0,
+        }),
+        partitions: 1,
+        decode_ms: 1000,
+        load_params_ms: 1000,
+        prove_ms,
+        error: error.to_string(),
+        degraded,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_proving_times_from_audit_records() {
+    let times = ProvingTimes::from_audit_records(&[
+        record("", false, 98_000),
+        record("gpu failed", false, 1_000),
+        record("", true, 900_000),
+    ]);
+    let e = times.estimate(GIB_32, 1).unwrap();
+    assert_eq!(e.samples, 1);
+    assert_eq!(e.prove, secs(100));
+}
+
+#[test]
+fn test_estimate_task() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let request = |post_config| {
+        Request::new(EstimateTaskRequest {
+            post_config,
+            replicas_len: 2,
+            partitions: 0,
+        })
+    };
+    let post_config = Some(snark_proof_grpc::PoStConfig {
+        sector_size: 2048,
+        challenge_count: 10,
+        sector_count: 2,
+        typ: snark_proof_grpc::PoStType::Window as i32,
+        priority: false,
+        api_version: snark_proof_grpc::ApiVersion::V110 as i32,
+    });
+
+    let e = rt
+        .block_on(srv.estimate_task(request(post_config.clone())))
+        .unwrap()
+        .into_inner();
+    assert!(!e.known);
+    assert_eq!(e.partitions, 1);
+    assert_eq!(e.tasks_ahead, 0);
+
+    let mut times = ProvingTimes::default();
+    times.record(2048, 1, secs(3));
+    srv.set_proving_times(times).unwrap();
+    let e = rt
+        .block_on(srv.estimate_task(request(post_config)))
+        .unwrap()
+        .into_inner();
+    assert!(e.known);
+    assert_eq!(e.prove_ms, 3000);
+    assert_eq!(e.samples, 1);
+
+    let err = rt.block_on(srv.estimate_task(request(None))).unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+}
+
+#[test]
+fn test_queue_wait_in_lock_response() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let mut times = ProvingTimes::default();
+    times.record(2048, 1, secs(100));
+    srv.set_proving_times(times).unwrap();
+    let lock = |task_id: &str| {
+        rt.block_on(SnarkTaskService::lock_server_if_free(
+            &srv,
+            Request::new(GetWorkerStatusRequest {
+                task_id: task_id.to_string(),
+                ..Default::default()
+            }),
+        ))
+        .unwrap()
+        .into_inner()
+    };
+    let res = lock("a");
+    assert_eq!(res.tasks_ahead, 0);
+    assert!(res.wait_known);
+    // a task behind the one locked for waits an unknown time, nothing was submitted yet
+    let res = lock("b");
+    assert_eq!(res.tasks_ahead, 1);
+    assert_eq!(res.wait_ms, 0);
+    assert!(!res.wait_known);
+
+    let task = |task_id: &str| TaskInfo {
+        task_id: task_id.to_string(),
+        post_config: Some(PoStConfig {
+            sector_size: SectorSize(2048),
+            challenge_count: 10,
+            sector_count: 2,
+            typ: PoStType::Window,
+            priority: false,
+            api_version: ApiVersion::V1_1_0,
+        }),
+        replicas_len: 2,
+        task_status: TaskStatus::Ready,
+        ..Default::default()
+    };
+    {
+        let mut si = srv.server_info.lock();
+        si.slots[0].status = ServerStatus::Working;
+        si.slots[0].task_info = task("a");
+        si.slots[0].task_info.started_at = Some(SystemTime::now() - secs(40));
+        si.task_queue.push(task("q"));
+    }
+    let res = lock("b");
+    assert_eq!(res.tasks_ahead, 2);
+    // what is left of the running task and the whole queued one
+    assert!(res.wait_ms > 159_000 && res.wait_ms <= 160_000);
+    assert!(res.wait_known);
+
+    srv.server_info.lock().task_queue.push(TaskInfo {
+        task_id: "unknown".to_string(),
+        ..Default::default()
+    });
+    let res = lock("b");
+    assert_eq!(res.tasks_ahead, 3);
+    assert!(!res.wait_known);
+}
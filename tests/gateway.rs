@@ -0,0 +1,398 @@
+use futures::FutureExt;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use window_post_snark_server::gateway::{
+    CapacityPolicy, Gateway, GatewayConfig, HealthPolicy, Migration, Router, TaskClass,
+};
+use window_post_snark_server::limits::limited_incoming;
+use window_post_snark_server::prelude::*;
+
+mod common;
+
+fn router() -> Router {
+    Router::new(vec![
+        "http://a:50051".to_string(),
+        "http://b:50051".to_string(),
+    ])
+}
+
+#[test]
+fn test_sticky_backend() {
+    let mut r = router();
+    let first = r.pick("f01").unwrap();
+    // the other miner is spread to the other backend
+    assert_ne!(r.pick("f02").unwrap(), first);
+    assert_eq!(r.pick("f01"), Some(first));
+}
+
+#[test]
+fn test_faster_backend_takes_more_miners() {
+    let mut r = router();
+    r.set_speed(0, 2_000_000.0);
+    r.set_speed(1, 1_000_000.0);
+    assert_eq!(r.pick("f01"), Some(0));
+    assert_eq!(r.pick("f02"), Some(0));
+    assert_eq!(r.pick("f03"), Some(1));
+}
+
+#[test]
+fn test_fall_back_while_busy() {
+    let mut r = router();
+    let first = r.pick("f01").unwrap();
+    r.set_busy(first, true);
+    assert_ne!(r.pick("f01").unwrap(), first);
+    r.set_busy(first, false);
+    assert_eq!(r.pick("f01"), Some(first));
+}
+
+#[test]
+fn test_move_off_unhealthy() {
+    let mut r = router();
+    let first = r.pick("f01").unwrap();
+    r.set_healthy(first, false);
+    let second = r.pick("f01").unwrap();
+    assert_ne!(second, first);
+    r.set_healthy(first, true);
+    assert_eq!(r.pick("f01"), Some(second));
+}
+
+#[test]
+fn test_all_busy() {
+    let mut r = router();
+    r.set_busy(0, true);
+    r.set_busy(1, true);
+    assert_eq!(r.pick("f01"), None);
+}
+
+#[test]
+fn test_quarantine_on_failures() {
+    let mut r = router();
+    r.record_task(0, true);
+    r.record_task(0, false);
+    assert!(r.backends()[0].healthy);
+    r.record_task(0, false);
+    assert!(!r.backends()[0].healthy);
+    assert_eq!(r.quarantined(), vec![0]);
+    assert_eq!(r.pick("f01"), Some(1));
+
+    // a failed probe keeps it out, a successful one brings it back
+    r.record_task(0, false);
+    assert!(!r.backends()[0].healthy);
+    r.record_task(0, true);
+    assert!(r.backends()[0].healthy);
+    assert_eq!(r.backends()[0].failure_rate(), 0.0);
+}
+
+#[test]
+fn test_quarantine_on_slow_heartbeat() {
+    let mut r = Router::with_health_policy(
+        vec!["http://a:50051".to_string()],
+        HealthPolicy {
+            max_heartbeat_latency: Duration::from_millis(100),
+            ..Default::default()
+        },
+    );
+    r.record_heartbeat(0, Duration::from_millis(50));
+    assert!(r.backends()[0].healthy);
+    r.record_heartbeat(0, Duration::from_millis(500));
+    assert!(!r.backends()[0].healthy);
+    assert_eq!(r.pick("f01"), None);
+}
+
+#[test]
+fn test_dispatch_and_complete() {
+    let mut r = router();
+    let b = r.dispatch("t1", "f01").unwrap();
+    assert!(r.backends()[b].busy);
+    assert_eq!(r.dispatched("t1").unwrap().backend, b);
+    r.complete("t1", true);
+    assert!(!r.backends()[b].busy);
+    assert!(r.dispatched("t1").is_none());
+}
+
+#[test]
+fn test_requeue_from_dead_backend() {
+    let mut r = Router::with_health_policy(
+        vec!["http://a:50051".to_string(), "http://b:50051".to_string()],
+        HealthPolicy {
+            heartbeat_time_out: Duration::from_millis(50),
+            ..Default::default()
+        },
+    );
+    let from = r.dispatch("t1", "f01").unwrap();
+    let to = 1 - from;
+    thread::sleep(Duration::from_millis(100));
+    // only the other backend still answers
+    r.record_heartbeat(to, Duration::from_millis(10));
+    let migrations = r.reap_dead(Instant::now());
+    assert!(!r.backends()[from].healthy);
+    assert_eq!(
+        migrations,
+        vec![Migration {
+            task_id: "t1".to_string(),
+            from,
+            to: Some(to),
+        }]
+    );
+    let d = r.dispatched("t1").unwrap();
+    assert_eq!(d.backend, to);
+    assert_eq!(d.attempts, 2);
+}
+
+fn tenant(tenant: &str, priority: bool) -> TaskClass {
+    TaskClass {
+        tenant: tenant.to_string(),
+        priority,
+    }
+}
+
+#[test]
+fn test_reserved_for_priority() {
+    let mut r = Router::new((0..3).map(|i| format!("http://{}:50051", i)).collect());
+    r.set_capacity_policy(CapacityPolicy {
+        reserved_for_priority: 1,
+        ..Default::default()
+    });
+    assert!(r.dispatch("t1", "f01").is_some());
+    assert!(r.dispatch("t2", "f02").is_some());
+    // the last backend is kept for priority tasks
+    assert_eq!(r.dispatch("t3", "f03"), None);
+    assert!(r.dispatch_as("t3", "f03", tenant("", true)).is_some());
+}
+
+#[test]
+fn test_tenant_limit() {
+    let mut r = Router::new((0..4).map(|i| format!("http://{}:50051", i)).collect());
+    let mut policy = CapacityPolicy::default();
+    policy.tenant_limits.insert("a".to_string(), 2);
+    r.set_capacity_policy(policy);
+    assert!(r.dispatch_as("t1", "f01", tenant("a", false)).is_some());
+    assert!(r.dispatch_as("t2", "f01", tenant("a", false)).is_some());
+    assert_eq!(r.dispatch_as("t3", "f01", tenant("a", false)), None);
+    assert!(r.dispatch_as("t3", "f02", tenant("b", false)).is_some());
+    let capacity = r.capacity();
+    assert_eq!(capacity.backends, 4);
+    assert_eq!(capacity.available, 1);
+    assert_eq!(capacity.tenants["a"], (2, 2));
+    r.complete("t1", true);
+    assert!(r.dispatch_as("t4", "f01", tenant("a", false)).is_some());
+}
+
+#[test]
+fn test_probe_after_interval() {
+    let mut r = Router::with_health_policy(
+        vec!["http://a:50051".to_string(), "http://b:50051".to_string()],
+        HealthPolicy {
+            probe_interval: Duration::from_millis(50),
+            ..Default::default()
+        },
+    );
+    r.set_healthy(0, false);
+    assert_eq!(r.pick("f01"), Some(1));
+    thread::sleep(Duration::from_millis(100));
+    r.record_heartbeat(0, Duration::from_millis(10));
+    // priority tasks are not sent to a probe
+    assert_eq!(r.pick_as("f01", &tenant("", true)), Some(1));
+    let b = r.dispatch("t1", "f02").unwrap();
+    assert_eq!(b, 0);
+    // the probe does not make the miner stick to the backend
+    assert_ne!(r.affinity("f02"), Some(0));
+    r.complete("t1", true);
+    assert!(r.backends()[0].healthy);
+}
+
+// a server behind the gateway, serving until dropped or killed
+struct TestBackend {
+    addr: String,
+    srv: WindowPostSnarkServer,
+    exit_tx: Option<oneshot::Sender<()>>,
+    served: JoinHandle<()>,
+    _task_run_rx: mpsc::UnboundedReceiver<String>,
+}
+
+impl TestBackend {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let (task_run_tx, task_run_rx) = mpsc::unbounded_channel();
+        let srv = WindowPostSnarkServer::new(task_run_tx);
+        let limits = ConnectionLimits::default();
+        let (exit_tx, exit_rx) = oneshot::channel::<()>();
+        let served = tokio::spawn(
+            server_builder(&limits)
+                .add_service(snark_task_service(srv.clone()))
+                .serve_with_incoming_shutdown(limited_incoming(listener, limits), exit_rx.map(drop))
+                .map(drop),
+        );
+        TestBackend {
+            addr,
+            srv,
+            exit_tx: Some(exit_tx),
+            served,
+            _task_run_rx: task_run_rx,
+        }
+    }
+
+    // what the worker does once the task is proved
+    fn finish(&self, task_id: &str) {
+        let mut si = self.srv.server_info.lock();
+        assert_eq!(si.slots[0].task_info.task_id, task_id);
+        si.slots[0].task_info.result = b"proof".to_vec();
+        si.slots[0].task_info.task_status = TaskStatus::Done;
+        si.slots[0].task_info.timers.done_at = Some(Instant::now());
+    }
+
+    fn task_id(&self) -> String {
+        self.srv.server_info.lock().slots[0]
+            .task_info
+            .task_id
+            .clone()
+    }
+
+    async fn kill(mut self) {
+        self.exit_tx.take().unwrap().send(()).unwrap();
+        self.served.abort();
+    }
+}
+
+fn task(task_id: &str, miner: &str) -> SnarkTaskRequestParams {
+    SnarkTaskRequestParams {
+        client_id: miner.to_string(),
+        ..common::params(task_id)
+    }
+}
+
+fn result_request(task_id: &str) -> GetTaskResultRequest {
+    GetTaskResultRequest {
+        task_id: task_id.to_string(),
+    }
+}
+
+#[test]
+fn test_gateway_end_to_end() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let backends = vec![TestBackend::start().await, TestBackend::start().await];
+        let mut config = GatewayConfig::new(backends.iter().map(|b| b.addr.clone()).collect());
+        config.health.heartbeat_time_out = Duration::from_millis(300);
+        let gateway = Gateway::new(config).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limits = ConnectionLimits::default();
+        let (exit_tx, exit_rx) = oneshot::channel::<()>();
+        let served = tokio::spawn(
+            server_builder(&limits)
+                .add_service(SnarkTaskServiceServer::new(gateway.clone()))
+                .serve_with_incoming_shutdown(
+                    limited_incoming(listener, limits),
+                    exit_rx.map(drop),
+                ),
+        );
+        let mut client = SnarkTaskServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+
+        // the protocol of a miner, through the gateway
+        let status = client
+            .lock_server_if_free(GetWorkerStatusRequest {
+                task_id: "t1".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.status, ServerStatus::Free.to_string());
+        client.do_snark_task(task("t1", "f01")).await.unwrap();
+        let first = gateway.backend_of("t1").unwrap();
+        let first = backends.iter().position(|b| b.addr == first).unwrap();
+        assert_eq!(backends[first].task_id(), "t1");
+        assert_eq!(
+            client
+                .do_snark_task(task("t1", "f01"))
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::AlreadyExists
+        );
+        backends[first].finish("t1");
+        let res = client
+            .get_snark_task_result(result_request("t1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(res.msg, "ok");
+        assert_eq!(res.result, b"proof".to_vec());
+        assert!(res.migrated_from.is_empty());
+        client
+            .ack_result(AckResultRequest {
+                task_id: "t1".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(gateway.backend_of("t1"), None);
+
+        // the miner sticks to its backend
+        client.do_snark_task(task("t2", "f01")).await.unwrap();
+        assert_eq!(gateway.backend_of("t2").unwrap(), backends[first].addr);
+        backends[first].finish("t2");
+        client
+            .get_snark_task_result(result_request("t2"))
+            .await
+            .unwrap();
+        client
+            .ack_result(AckResultRequest {
+                task_id: "t2".to_string(),
+            })
+            .await
+            .unwrap();
+
+        // the task of a backend which dies is moved to the other one
+        client.do_snark_task(task("t3", "f02")).await.unwrap();
+        let dead = gateway.backend_of("t3").unwrap();
+        let dead = backends.iter().position(|b| b.addr == dead).unwrap();
+        let mut backends = backends;
+        let survivor = backends.remove(1 - dead);
+        let dead = backends.pop().unwrap();
+        let dead_addr = dead.addr.clone();
+        dead.kill().await;
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        gateway.heartbeat().await;
+        assert_eq!(gateway.backend_of("t3").unwrap(), survivor.addr);
+        assert_eq!(survivor.task_id(), "t3");
+        survivor.finish("t3");
+        let res = client
+            .get_snark_task_result(result_request("t3"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(res.msg, "ok");
+        assert_eq!(res.migrated_from, dead_addr);
+
+        let capabilities = client
+            .get_capabilities(GetCapabilitiesRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        let capacity = capabilities.gateway.unwrap();
+        assert_eq!(capacity.backends, 2);
+        // the dead backend is out, the other one still has t3
+        assert_eq!(capacity.available, 0);
+        // calls about a backend rather than a task go to the backends
+        assert_eq!(
+            client
+                .dump_server_state(DumpServerStateRequest::default())
+                .await
+                .unwrap_err()
+                .code(),
+            tonic::Code::Unimplemented
+        );
+
+        exit_tx.send(()).unwrap();
+        served.await.unwrap().unwrap();
+    });
+}
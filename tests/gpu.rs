@@ -0,0 +1,55 @@
+use window_post_snark_server::gpu::{device_selectors, slot_devices, Backend, GpuDevice};
+
+fn device(unique_id: &str) -> GpuDevice {
+    GpuDevice {
+        name: "GeForce RTX 3090".to_string(),
+        memory_bytes: 24 << 30,
+        unique_id: unique_id.to_string(),
+    }
+}
+
+#[test]
+fn test_slot_devices() {
+    let devices = [device("02:00"), device("01:00")];
+    let slots = slot_devices(&devices[..1], Backend::Cuda, "");
+    assert_eq!(slots.len(), 1);
+    assert!(slots[0].in_process());
+    // ordinals count the devices of the host, a pinned server sees only some of them
+    assert!(slot_devices(&devices, Backend::Cuda, "1")[0].in_process());
+    let slots = slot_devices(&devices, Backend::Cuda, "");
+    assert_eq!(slots.len(), 2);
+    // ordinals follow the bus ids, not the order the devices were found in
+    assert_eq!(slots[0].device_id, "02:00");
+    assert_eq!(
+        slots[0].env,
+        vec![
+            ("CUDA_VISIBLE_DEVICES".to_string(), "1".to_string()),
+            ("CUDA_DEVICE_ORDER".to_string(), "PCI_BUS_ID".to_string())
+        ]
+    );
+    let slots = slot_devices(&devices, Backend::OpenCl, "");
+    assert_eq!(
+        slots[1].env,
+        vec![("GPU_DEVICE_ORDINAL".to_string(), "0".to_string())]
+    );
+    // uuids tell the devices apart whatever the server is pinned to
+    let uuid = "a0e1b2c3-d4e5-f607-1829-3a4b5c6d7e8f";
+    let devices = [device(uuid), device(&uuid.replace('a', "b"))];
+    let slots = slot_devices(&devices, Backend::Cuda, "GPU-x");
+    assert_eq!(
+        slots[0].env,
+        vec![("CUDA_VISIBLE_DEVICES".to_string(), format!("GPU-{}", uuid))]
+    );
+}
+
+#[test]
+fn test_device_selectors() {
+    let uuid = "a0e1b2c3-d4e5-f607-1829-3a4b5c6d7e8f";
+    let devices = [device(uuid), device("03:00")];
+    assert_eq!(
+        device_selectors(&devices, Backend::Cuda).unwrap(),
+        vec![format!("GPU-{}", uuid), "0".to_string()]
+    );
+    // OpenCL only takes ordinals, a device without a bus id can not be told
+    assert_eq!(device_selectors(&devices, Backend::OpenCl), None);
+}
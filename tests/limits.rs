@@ -0,0 +1,67 @@
+use futures::StreamExt;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use window_post_snark_server::limits::{limited_incoming, ConnectionLimits};
+
+#[test]
+fn test_in_flight_limit() {
+    let limits = ConnectionLimits {
+        max_in_flight: 8,
+        ..Default::default()
+    };
+    assert_eq!(limits.in_flight_limit(), 8);
+    // no limit still has to be a usable semaphore size
+    assert!(ConnectionLimits::default().in_flight_limit() > 1 << 20);
+}
+
+#[test]
+fn test_limited_incoming() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limits = ConnectionLimits {
+            max_connections: 1,
+            ..Default::default()
+        };
+        let incoming = limited_incoming(listener, limits);
+        futures::pin_mut!(incoming);
+
+        let _c1 = TcpStream::connect(addr).await.unwrap();
+        let _c2 = TcpStream::connect(addr).await.unwrap();
+        let first = incoming.next().await.unwrap().unwrap();
+        // the second connection is not accepted while the first one is open
+        let second = tokio::time::timeout(Duration::from_millis(200), incoming.next()).await;
+        assert!(second.is_err());
+
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_secs(1), incoming.next()).await;
+        assert!(second.unwrap().unwrap().is_ok());
+    });
+}
+
+#[test]
+fn test_idle_time_out() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limits = ConnectionLimits {
+            idle_time_out: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let incoming = limited_incoming(listener, limits);
+        futures::pin_mut!(incoming);
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        let mut buf = [0u8; 4];
+        // traffic keeps the connection open
+        client.write_all(b"ping").await.unwrap();
+        conn.read_exact(&mut buf).await.unwrap();
+        let err = conn.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    });
+}
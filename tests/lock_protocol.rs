@@ -0,0 +1,214 @@
+use proptest::prelude::*;
+use std::collections::HashSet;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request, Status};
+use window_post_snark_server::error::FailureKind;
+use window_post_snark_server::server::{
+    WindowPostSnarkServer, SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT,
+};
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    AckResultRequest, GetTaskResultRequest, GetWorkerStatusRequest, UnlockServerRequest,
+};
+use window_post_snark_server::status::{ServerStatus, TaskStatus};
+
+mod common;
+
+use common::params;
+
+const TASKS: u8 = 3;
+
+/// What a client or the task worker does next
+#[derive(Debug, Clone)]
+enum Op {
+    Lock(u8),
+    Submit(u8),
+    Get(u8),
+    Ack(u8),
+    Unlock(u8),
+    // the worker finishes the running task, successfully or not
+    Finish(bool),
+    // every lock and every result runs out of time
+    Expire,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..TASKS).prop_map(Op::Lock),
+        (0..TASKS).prop_map(Op::Submit),
+        (0..TASKS).prop_map(Op::Get),
+        (0..TASKS).prop_map(Op::Ack),
+        (0..TASKS).prop_map(Op::Unlock),
+        any::<bool>().prop_map(Op::Finish),
+        Just(Op::Expire),
+    ]
+}
+
+fn task_id(t: u8) -> String {
+    format!("task-{}", t)
+}
+
+// the worker proves each task to a proof of its own, so a leaked one is told apart
+fn proof_of(task_id: &str) -> Vec<u8> {
+    format!("proof of {}", task_id).into_bytes()
+}
+
+// the server state behind the calls must never be broken, whatever the call returned
+fn check_call<T>(res: &Result<T, Status>) {
+    if let Err(s) = res {
+        assert_ne!(s.code(), Code::Aborted, "{}", s.message());
+        assert_ne!(s.code(), Code::Internal, "{}", s.message());
+    }
+}
+
+fn check_invariants(srv: &WindowPostSnarkServer) {
+    let si = srv.server_info.lock();
+    match si.slots[0].status {
+        ServerStatus::Locked => {
+            assert!(
+                !si.slots[0].task_info.task_id.is_empty(),
+                "locked without a task"
+            );
+        }
+        ServerStatus::Working => {
+            assert!(
+                !si.slots[0].task_info.task_id.is_empty(),
+                "working without a task"
+            );
+            assert_ne!(si.slots[0].task_info.task_status, TaskStatus::None);
+        }
+        _ => {}
+    }
+    if si.slots[0].status != ServerStatus::Free {
+        assert!(
+            !si.task_queue.contains(&si.slots[0].task_info.task_id),
+            "task {} runs and is queued",
+            si.slots[0].task_info.task_id
+        );
+    }
+    if si.slots[0].status == ServerStatus::Working
+        && (si.slots[0].task_info.task_status == TaskStatus::Done
+            || si.slots[0].task_info.task_status == TaskStatus::Returned)
+    {
+        assert_eq!(
+            si.slots[0].task_info.result,
+            proof_of(&si.slots[0].task_info.task_id)
+        );
+    }
+}
+
+fn run_ops(ops: Vec<Op>) {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let mut proved = HashSet::new();
+    for op in ops {
+        match op {
+            Op::Lock(t) => {
+                let res = rt.block_on(SnarkTaskService::lock_server_if_free(
+                    &srv,
+                    Request::new(GetWorkerStatusRequest {
+                        task_id: task_id(t),
+                        ..Default::default()
+                    }),
+                ));
+                check_call(&res);
+            }
+            Op::Submit(t) => {
+                let res = rt.block_on(srv.do_snark_task(Request::new(params(&task_id(t)))));
+                check_call(&res);
+            }
+            Op::Get(t) => {
+                let res = rt.block_on(srv.get_snark_task_result(Request::new(
+                    GetTaskResultRequest {
+                        task_id: task_id(t),
+                    },
+                )));
+                check_call(&res);
+                if let Ok(r) = res {
+                    let r = r.into_inner();
+                    if !r.result.is_empty() {
+                        assert_eq!(r.result, proof_of(&task_id(t)), "result leaked");
+                        assert!(proved.contains(&task_id(t)));
+                    }
+                }
+            }
+            Op::Ack(t) => {
+                let res = rt.block_on(SnarkTaskService::ack_result(
+                    &srv,
+                    Request::new(AckResultRequest {
+                        task_id: task_id(t),
+                    }),
+                ));
+                check_call(&res);
+            }
+            Op::Unlock(t) => {
+                let res = rt.block_on(srv.unlock_server(Request::new(UnlockServerRequest {
+                    task_id: task_id(t),
+                })));
+                check_call(&res);
+            }
+            Op::Finish(ok) => {
+                let mut si = srv.server_info.lock();
+                if si.slots[0].status == ServerStatus::Working
+                    && si.slots[0].task_info.task_status == TaskStatus::Ready
+                {
+                    let id = si.slots[0].task_info.task_id.clone();
+                    if ok {
+                        si.slots[0].task_info.result = proof_of(&id);
+                        si.slots[0].task_info.task_status = TaskStatus::Done;
+                        proved.insert(id);
+                    } else {
+                        si.slots[0].task_info.task_status = TaskStatus::Failed;
+                        si.slots[0].error = "gpu failed".to_string();
+                        si.slots[0].error_kind = FailureKind::Retryable;
+                    }
+                    si.slots[0].task_info.timers.done_at = Some(Instant::now());
+                }
+            }
+            Op::Expire => {
+                {
+                    let mut si = srv.server_info.lock();
+                    si.slots[0].lock_time_out = Duration::from_secs(0);
+                    si.server_task_get_back_time_out = Duration::from_secs(0);
+                }
+                // the lock time out has to be passed, not just reached
+                thread::sleep(Duration::from_millis(1));
+                // any lock call takes the server back
+                let res = rt.block_on(SnarkTaskService::lock_server_if_free(
+                    &srv,
+                    Request::new(GetWorkerStatusRequest {
+                        task_id: "reclaimer".to_string(),
+                        ..Default::default()
+                    }),
+                ));
+                check_call(&res);
+                let mut si = srv.server_info.lock();
+                // nothing but a task still proving keeps the server once its time ran out
+                if si.slots[0].status == ServerStatus::Working {
+                    assert_eq!(si.slots[0].task_info.task_status, TaskStatus::Ready);
+                }
+                si.server_task_get_back_time_out = SERVER_TASK_GET_BACK_TIME_OUT_DEFAULT;
+                drop(si);
+                // the reclaimer does not stay in line
+                let res = rt.block_on(srv.unlock_server(Request::new(UnlockServerRequest {
+                    task_id: "reclaimer".to_string(),
+                })));
+                check_call(&res);
+            }
+        }
+        check_invariants(&srv);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn test_lock_protocol(ops in prop::collection::vec(op(), 1..60)) {
+        run_ops(ops);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request};
+use window_post_snark_server::auth::{Principal, Role};
+use window_post_snark_server::params::{
+    file_digest, select_parameter_cache, verify_parameters, ParameterData, ParameterManifest,
+};
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{ManageParamCacheRequest, ParamCacheAction};
+
+#[test]
+fn test_select_parameter_cache() {
+    let missing = tempfile::tempdir().unwrap().path().join("nvme");
+    let empty = tempfile::tempdir().unwrap();
+    let filled = tempfile::tempdir().unwrap();
+    fs::write(filled.path().join("v28-test.params"), b"").unwrap();
+    let dirs = vec![
+        missing,
+        empty.path().to_path_buf(),
+        filled.path().to_path_buf(),
+    ];
+    assert_eq!(
+        select_parameter_cache(&dirs),
+        Some(filled.path().to_path_buf())
+    );
+    assert_eq!(select_parameter_cache(&dirs[..2]), None);
+}
+
+#[test]
+fn test_verify_parameters() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("good.params"), b"good").unwrap();
+    fs::write(dir.path().join("truncated.params"), b"trunc").unwrap();
+    let entry = |digest: String| ParameterData {
+        cid: String::new(),
+        digest,
+        sector_size: 2048,
+    };
+    let mut manifest = ParameterManifest::new();
+    let good = file_digest(&dir.path().join("good.params")).unwrap();
+    assert_eq!(good.len(), 32);
+    manifest.insert("good.params".to_string(), entry(good));
+    manifest.insert("truncated.params".to_string(), entry("0".repeat(32)));
+    // not needed by this server
+    manifest.insert("missing.params".to_string(), entry("0".repeat(32)));
+    assert_eq!(
+        verify_parameters(dir.path(), &manifest).unwrap(),
+        vec!["truncated.params"]
+    );
+}
+
+fn param_cache_request(
+    role: Role,
+    action: ParamCacheAction,
+    sector_size: u64,
+) -> Request<ManageParamCacheRequest> {
+    let mut r = Request::new(ManageParamCacheRequest {
+        action: action as i32,
+        sector_size,
+    });
+    r.extensions_mut().insert(Principal {
+        subject: "ops".to_string(),
+        role,
+    });
+    r
+}
+
+#[test]
+fn test_manage_param_cache() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let cached = rt
+        .block_on(srv.manage_param_cache(param_cache_request(
+            Role::Admin,
+            ParamCacheAction::ParamsEvict,
+            0,
+        )))
+        .unwrap()
+        .into_inner();
+    assert!(cached.params.is_empty());
+
+    // there is no window PoSt of that sector size to load parameters for
+    let err = rt
+        .block_on(srv.manage_param_cache(param_cache_request(
+            Role::Admin,
+            ParamCacheAction::ParamsReload,
+            3,
+        )))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+
+    let err = rt
+        .block_on(srv.manage_param_cache(param_cache_request(
+            Role::Submitter,
+            ParamCacheAction::ParamsList,
+            0,
+        )))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+}
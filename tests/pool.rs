@@ -0,0 +1,74 @@
+use filecoin_proofs::{PoStConfig, PoStType, SectorSize, SECTOR_SIZE_2_KIB};
+use std::time::Duration;
+use storage_proofs_core::api_version::ApiVersion;
+use tokio::runtime::Runtime;
+use window_post_snark_server::error::Error;
+use window_post_snark_server::pool::{ClientPool, EndpointScores, ProbePolicy};
+
+#[test]
+fn test_endpoint_scores() {
+    let mut scores = EndpointScores::new(3, ProbePolicy::default());
+    // not probed yet, all are tried
+    assert_eq!(scores.ranked(), vec![0, 1, 2]);
+    scores.record_probe(0, Some(Duration::from_millis(80)));
+    scores.record_probe(1, Some(Duration::from_millis(20)));
+    assert_eq!(scores.ranked(), vec![1, 0, 2]);
+
+    // one lost probe does not take a server out
+    scores.record_probe(1, None);
+    assert_eq!(scores.ranked(), vec![1, 0, 2]);
+    scores.record_probe(1, None);
+    assert_eq!(scores.ranked(), vec![0, 2]);
+    assert!(!scores.health()[1].healthy);
+    assert_eq!(scores.health()[1].failures, 2);
+
+    // back after its reboot
+    scores.record_probe(1, Some(Duration::from_millis(200)));
+    assert_eq!(scores.ranked(), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_smoothed_latency() {
+    let policy = ProbePolicy {
+        latency_weight: 0.5,
+        ..Default::default()
+    };
+    let mut scores = EndpointScores::new(1, policy);
+    scores.record_probe(0, Some(Duration::from_millis(100)));
+    scores.record_probe(0, Some(Duration::from_millis(300)));
+    let latency = scores.health()[0].latency.unwrap();
+    // computed in floating point
+    assert!(latency > Duration::from_millis(199) && latency < Duration::from_millis(201));
+}
+
+#[test]
+fn test_prove_window_post_without_servers() {
+    let rt = Runtime::new().unwrap();
+    let post_config = PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        challenge_count: 10,
+        sector_count: 2,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    };
+    // nothing listens there, every lock fails and no server is left to fail over to
+    let err = rt
+        .block_on(async {
+            let pool = ClientPool::new(
+                &["http://127.0.0.1:1", "http://127.0.0.1:2"],
+                Duration::from_secs(1),
+            )
+            .unwrap();
+            let res = pool
+                .prove_window_post("t1", &post_config, &(), &(), 1, Duration::from_millis(10))
+                .await;
+            assert_eq!(pool.health()[0].failures, 1);
+            res
+        })
+        .unwrap_err();
+    match err.downcast_ref::<Error>() {
+        Some(Error::NoUsefulPostServer(task_id, _)) => assert_eq!(task_id, "t1"),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
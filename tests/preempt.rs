@@ -0,0 +1,93 @@
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::Request;
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    PoStType, SnarkTaskRequestParams, SubmitBatchRequest,
+};
+use window_post_snark_server::tasks::TaskPayload;
+
+mod common;
+
+fn params(task_id: &str, typ: PoStType) -> SnarkTaskRequestParams {
+    let mut params = common::params(task_id);
+    params.post_config.as_mut().unwrap().typ = typ as i32;
+    params
+}
+
+fn server(preempt: bool, tasks: Vec<SnarkTaskRequestParams>) -> WindowPostSnarkServer {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    srv.set_preempt_window_post(preempt).unwrap();
+    rt.block_on(srv.submit_batch(Request::new(SubmitBatchRequest { tasks })))
+        .unwrap();
+    srv
+}
+
+#[test]
+fn test_winning_post_preempts_window_post() {
+    let srv = server(
+        true,
+        vec![
+            params("window", PoStType::Window),
+            params("other", PoStType::Window),
+            params("winning", PoStType::Winning),
+        ],
+    );
+    let mut si = srv.server_info.lock();
+    assert_eq!(si.slots[0].task_info.task_id, "window");
+    assert!(si.slots[0].task_info.preempt.is_cancelled());
+
+    // what the worker leaves when the task stops after its first partition
+    si.slots[0].task_info.checkpoint.push_proof(vec![1; 192]);
+    si.slots[0].task_info.checkpoint.keep_payload(TaskPayload {
+        vanilla_proof: b"vanilla".to_vec(),
+        pub_in: b"pub_in".to_vec(),
+    });
+    si.preempt_running_task(0);
+    assert_eq!(si.slots[0].task_info.task_id, "winning");
+    // the preempted task goes ahead of the tasks queued behind it
+    assert_eq!(si.task_queue.task_ids(), vec!["window", "other"]);
+    let window = si.task_queue.iter().next().unwrap();
+    assert_eq!(window.checkpoint.partitions_done(), 1);
+    assert_eq!(window.vanilla_proof, b"vanilla".to_vec());
+    assert!(!window.preempt.is_cancelled());
+    assert!(window.started_at.is_none());
+}
+
+#[test]
+fn test_no_preemption_unless_enabled() {
+    let srv = server(
+        false,
+        vec![
+            params("window", PoStType::Window),
+            params("winning", PoStType::Winning),
+        ],
+    );
+    assert!(!srv.server_info.lock().slots[0]
+        .task_info
+        .preempt
+        .is_cancelled());
+
+    // a window PoSt does not preempt another one
+    let srv = server(
+        true,
+        vec![params("a", PoStType::Window), params("b", PoStType::Window)],
+    );
+    assert!(!srv.server_info.lock().slots[0]
+        .task_info
+        .preempt
+        .is_cancelled());
+}
+
+#[test]
+fn test_preempted_task_runs_on_without_winning_post() {
+    let srv = server(true, vec![params("window", PoStType::Window)]);
+    let mut si = srv.server_info.lock();
+    si.slots[0].task_info.preempt.cancel();
+    si.preempt_running_task(0);
+    assert_eq!(si.slots[0].task_info.task_id, "window");
+    assert!(si.task_queue.is_empty());
+}
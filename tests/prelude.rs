@@ -0,0 +1,19 @@
+use window_post_snark_server::prelude::*;
+
+#[test]
+fn test_prelude() {
+    // what a client submitting tasks needs, without reaching into the generated module
+    let params = SnarkTaskRequestParams {
+        task_id: "a".to_string(),
+        payload_encoding: Encoding::Bincode.to_wire() as i32,
+        post_config: Some(proto::PoStConfig::default()),
+        ..Default::default()
+    };
+    assert_eq!(
+        params.payload_encoding,
+        proto::PayloadEncoding::Bincode as i32
+    );
+    assert_eq!(ServerStatus::Free.to_string(), "Free");
+    assert_eq!(TaskStatus::default(), TaskStatus::None);
+    assert!(!AuthConfig::default().is_enabled());
+}
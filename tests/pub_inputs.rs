@@ -0,0 +1,118 @@
+use filecoin_hashers::poseidon::PoseidonDomain;
+use filecoin_hashers::Domain;
+use filecoin_proofs::{
+    PoStConfig, PoStType, SectorSize, SECTOR_SIZE_2_KIB, WINDOW_POST_CHALLENGE_COUNT,
+};
+use storage_proofs_core::api_version::ApiVersion;
+use storage_proofs_core::sector::SectorId;
+use window_post_snark_server::codec::post_config_to_proto;
+use window_post_snark_server::pub_inputs::{field_randomness, RawPublicInputs, RawPublicSector};
+use window_post_snark_server::snark_proof_grpc::SnarkTaskRequestParams;
+use window_post_snark_server::tasks::{pub_in_digest, set_task_info};
+
+fn raw() -> RawPublicInputs {
+    RawPublicInputs {
+        randomness: [1; 32],
+        prover_id: [2; 32],
+        sectors: vec![
+            RawPublicSector {
+                sector_id: 7,
+                comm_r: [3; 32],
+            },
+            RawPublicSector {
+                sector_id: 8,
+                comm_r: [4; 32],
+            },
+        ],
+    }
+}
+
+fn params(raw: &RawPublicInputs) -> SnarkTaskRequestParams {
+    SnarkTaskRequestParams {
+        task_id: "a".to_string(),
+        vanilla_proof: vec![1],
+        post_config: Some(post_config_to_proto(&PoStConfig {
+            sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+            challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+            sector_count: 2,
+            typ: PoStType::Window,
+            priority: false,
+            api_version: ApiVersion::V1_1_0,
+        })),
+        randomness: raw.randomness.to_vec(),
+        prover_id: raw.prover_id.to_vec(),
+        pub_sectors: raw.sectors_to_proto(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_raw_pub_in_proto_round_trip() {
+    let raw = raw();
+    assert_eq!(
+        RawPublicInputs::from_proto(&params(&raw)).unwrap(),
+        Some(raw.clone())
+    );
+    assert_eq!(
+        RawPublicInputs::from_proto(&SnarkTaskRequestParams::default()).unwrap(),
+        None
+    );
+
+    let mut short = params(&raw);
+    short.randomness.pop();
+    let err = RawPublicInputs::from_proto(&short).unwrap_err();
+    assert!(err.to_string().contains("randomness"));
+}
+
+#[test]
+fn test_public_inputs_of_parts() {
+    let pub_in = raw().public_inputs::<PoseidonDomain>().unwrap();
+    assert_eq!(
+        pub_in.randomness,
+        PoseidonDomain::try_from_bytes(&[1; 32]).unwrap()
+    );
+    assert_eq!(pub_in.sectors.len(), 2);
+    assert_eq!(pub_in.sectors[1].id, SectorId::from(8));
+    assert_eq!(
+        pub_in.sectors[1].comm_r,
+        PoseidonDomain::try_from_bytes(&[4; 32]).unwrap()
+    );
+    assert_eq!(pub_in.k, None);
+}
+
+#[test]
+fn test_task_with_raw_pub_in() {
+    let raw = raw();
+    let task = set_task_info(params(&raw)).unwrap();
+    // derived from the sectors
+    assert_eq!(task.replicas_len, 2);
+    assert_eq!(task.pub_in_digest, raw.digest());
+    assert_ne!(task.pub_in_digest, pub_in_digest(b""));
+    assert_eq!(task.raw_pub_in, Some(raw.clone()));
+
+    let mut with_pub_in = params(&raw);
+    with_pub_in.pub_in = vec![1];
+    assert!(set_task_info(with_pub_in).is_err());
+    let mut wrong_len = params(&raw);
+    wrong_len.replicas_len = 3;
+    let err = set_task_info(wrong_len).unwrap_err();
+    assert!(err.to_string().contains("replicas_len"));
+    // not a field element
+    let mut bad_comm_r = params(&raw);
+    bad_comm_r.pub_sectors[0].comm_r = vec![0xff; 32];
+    let err = set_task_info(bad_comm_r).unwrap_err();
+    assert!(err.to_string().contains("pub_sectors"));
+    // randomness from the chain is masked to a field element rather than refused
+    let mut chain_randomness = params(&raw);
+    chain_randomness.randomness = vec![0xff; 32];
+    assert!(set_task_info(chain_randomness).is_ok());
+}
+
+#[test]
+fn test_field_randomness() {
+    let mut masked = [0xff; 32];
+    masked[31] = 0x3f;
+    assert_eq!(field_randomness([0xff; 32]), masked);
+    assert!(PoseidonDomain::try_from_bytes(&masked).is_ok());
+    assert_eq!(field_randomness([1; 32]), [1; 32]);
+}
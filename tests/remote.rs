@@ -0,0 +1,103 @@
+use filecoin_proofs::{
+    PoStConfig, PoStType, SectorSize, SECTOR_SIZE_2_KIB, WINDOW_POST_CHALLENGE_COUNT,
+};
+use std::path::PathBuf;
+use storage_proofs_core::api_version::ApiVersion;
+use window_post_snark_server::codec::post_config_to_proto;
+use window_post_snark_server::remote::{RemotePoSt, RemoteReplica};
+use window_post_snark_server::snark_proof_grpc::SnarkTaskRequestParams;
+use window_post_snark_server::tasks::set_task_info;
+
+fn remote() -> RemotePoSt {
+    RemotePoSt {
+        randomness: [1; 32],
+        prover_id: [2; 32],
+        replicas: vec![RemoteReplica {
+            sector_id: 7,
+            replica_path: PathBuf::from("/mnt/sealed/s-t01000-7"),
+            cache_dir: PathBuf::from("/mnt/cache/s-t01000-7"),
+            comm_r: [3; 32],
+        }],
+    }
+}
+
+fn post_config(typ: PoStType) -> PoStConfig {
+    PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        sector_count: 2,
+        typ,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    }
+}
+
+fn params(remote: &RemotePoSt, typ: PoStType) -> SnarkTaskRequestParams {
+    SnarkTaskRequestParams {
+        task_id: "a".to_string(),
+        post_config: Some(post_config_to_proto(&post_config(typ))),
+        randomness: remote.randomness.to_vec(),
+        prover_id: remote.prover_id.to_vec(),
+        replicas: remote.replicas_to_proto(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_remote_proto_round_trip() {
+    let remote = remote();
+    let p = params(&remote, PoStType::Window);
+    assert_eq!(RemotePoSt::from_proto(&p).unwrap(), Some(remote));
+    // tasks carrying vanilla proofs
+    assert_eq!(
+        RemotePoSt::from_proto(&SnarkTaskRequestParams::default()).unwrap(),
+        None
+    );
+
+    let mut short = p;
+    short.replicas[0].comm_r.pop();
+    let err = RemotePoSt::from_proto(&short).unwrap_err();
+    assert!(err.to_string().contains("replicas.comm_r"));
+}
+
+#[test]
+fn test_replicas_in_sector_order() {
+    let mut remote = remote();
+    let mut other = remote.replicas[0].clone();
+    other.sector_id = 3;
+    remote.replicas.push(other);
+    let p = params(&remote, PoStType::Window);
+    let sorted = RemotePoSt::from_proto(&p).unwrap().unwrap();
+    assert_eq!(
+        sorted
+            .replicas
+            .iter()
+            .map(|r| r.sector_id)
+            .collect::<Vec<_>>(),
+        vec![3, 7]
+    );
+
+    let mut twice = remote.clone();
+    twice.replicas[1].sector_id = 7;
+    let err = twice.sort_replicas().unwrap_err();
+    assert!(err.to_string().contains("sector 7 is sent twice"));
+    let err = set_task_info(params(&twice, PoStType::Window)).unwrap_err();
+    assert!(err.to_string().contains("replicas"));
+}
+
+#[test]
+fn test_full_post_task() {
+    let remote = remote();
+    let task = set_task_info(params(&remote, PoStType::Window)).unwrap();
+    assert_eq!(task.replicas_len, 1);
+    assert_eq!(task.remote, Some(remote.clone()));
+
+    assert!(set_task_info(params(&remote, PoStType::Winning)).is_err());
+    let mut with_vanilla = params(&remote, PoStType::Window);
+    with_vanilla.vanilla_proof = vec![1];
+    assert!(set_task_info(with_vanilla).is_err());
+    let mut wrong_len = params(&remote, PoStType::Window);
+    wrong_len.replicas_len = 2;
+    let err = set_task_info(wrong_len).unwrap_err();
+    assert!(err.to_string().contains("replicas_len"));
+}
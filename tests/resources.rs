@@ -0,0 +1,87 @@
+use filecoin_proofs::{
+    PoRepConfig, PoRepProofPartitions, PoStConfig, PoStType, SectorSize, SECTOR_SIZE_2_KIB,
+    SECTOR_SIZE_32_GIB, WINDOW_POST_CHALLENGE_COUNT,
+};
+use storage_proofs_core::api_version::ApiVersion;
+use window_post_snark_server::c2::C2Task;
+use window_post_snark_server::resources::{
+    proving_threads, task_memory_estimate, Resources, RuntimeConfig,
+};
+use window_post_snark_server::tasks::{run_on_prove_thread, TaskInfo, PROVE_THREAD_NAME};
+
+#[test]
+fn test_task_memory_estimate() {
+    let mut task_info = TaskInfo {
+        replicas_len: 5,
+        ..Default::default()
+    };
+    assert_eq!(task_memory_estimate(&task_info), 0);
+
+    task_info.post_config = Some(PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        sector_count: 2,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    });
+    // 5 replicas with 2 sectors per partition make 3 partitions
+    assert_eq!(task_memory_estimate(&task_info), 3 << 30);
+
+    // C2 tasks have no post config, their sector size is in the porep config
+    let c2 = TaskInfo {
+        c2: Some(C2Task {
+            porep_config: PoRepConfig {
+                sector_size: SectorSize(SECTOR_SIZE_32_GIB),
+                partitions: PoRepProofPartitions(10),
+                porep_id: [0; 32],
+                api_version: ApiVersion::V1_1_0,
+            },
+            prover_id: [0; 32],
+            sector_id: 1,
+        }),
+        ..Default::default()
+    };
+    assert_eq!(task_memory_estimate(&c2), 192 << 30);
+}
+
+#[test]
+fn test_proving_threads() {
+    let res = Resources {
+        memory_bytes: None,
+        cpus: 8,
+    };
+    let mut runtime = RuntimeConfig::default();
+    assert_eq!(proving_threads(&res, &runtime), 8);
+    runtime.worker_threads = 2;
+    assert_eq!(proving_threads(&res, &runtime), 6);
+    // a host with fewer cpus than runtime workers still proves
+    runtime.worker_threads = 16;
+    assert_eq!(proving_threads(&res, &runtime), 1);
+}
+
+#[test]
+fn test_prove_thread_outside_runtime() {
+    let runtime = RuntimeConfig {
+        worker_threads: 1,
+        max_blocking_threads: 1,
+        thread_name: "test-runtime".to_string(),
+    };
+    let rt = runtime.build().unwrap();
+    rt.block_on(async {
+        let name = tokio::spawn(async { std::thread::current().name().map(String::from) })
+            .await
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("test-runtime"));
+
+        let name = run_on_prove_thread(|| Ok(std::thread::current().name().map(String::from)))
+            .await
+            .unwrap();
+        assert_eq!(name.as_deref(), Some(PROVE_THREAD_NAME));
+
+        let e = run_on_prove_thread::<_, ()>(|| panic!("boom"))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("boom"));
+    });
+}
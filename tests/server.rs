@@ -2,6 +2,7 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use anyhow::{Result};
+use futures::FutureExt;
 use log::error;
 use signal_hook::consts::TERM_SIGNALS;
 use signal_hook::flag;
@@ -9,11 +10,14 @@ use tokio::runtime::Runtime;
 use tokio::sync::{mpsc, oneshot};
 use tonic::Request;
 use uuid::Uuid;
-use window_post_snark_server::server;
-use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::gpu::SlotDevice;
+use window_post_snark_server::server::{ServerInfo, Slot, WindowPostSnarkServer};
 use window_post_snark_server::client;
 use window_post_snark_server::snark_proof_grpc::{GetTaskResultRequest, GetWorkerStatusRequest, UnlockServerRequest};
 use window_post_snark_server::run;
+use window_post_snark_server::config::{ServerConfig, TimeoutsConfig};
+use window_post_snark_server::error::FailureKind;
+use window_post_snark_server::status::{ServerStatus, TaskStatus};
 
 async fn listen_exit_signal() {
     let term = Arc::new(AtomicBool::new(false));
@@ -35,17 +39,45 @@ fn run_s() {
     let rt = tokio::runtime::Runtime::new().unwrap();
     let (run_task_tx, _) = mpsc::unbounded_channel::<String>();
     let (server_exit_tx, server_exit_rx) = oneshot::channel::<String>();
-    let sv = WindowPostSnarkServer::new(run_task_tx);
-    let handle = rt.spawn(server::run_server(server_exit_rx, sv, "50051".to_string()));
+    let sv = WindowPostSnarkServer::builder().build(run_task_tx).unwrap();
+    let handle = rt.spawn(sv.serve(server_exit_rx.map(drop)));
 
     rt.block_on(listen_exit_signal());
     server_exit_tx.send("exit".to_string()).unwrap();
-    rt.block_on(async { handle.await.unwrap() });
+    rt.block_on(async { handle.await.unwrap().unwrap() });
     rt.shutdown_background();
 }
 
 fn run_all() {
-    run::run("50051".to_string(),Duration::from_secs(20),Duration::from_secs(100),Duration::from_secs(200))
+    let config = ServerConfig {
+        port: Some(50051),
+        timeouts: TimeoutsConfig {
+            server_lock_time_out: Some(20),
+            server_task_get_back_time_out: Some(100),
+            server_exit_time_out_after_task_done: Some(200),
+            grace_period: Some(600),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    run::run(run::RunSettings {
+        config,
+        task_threads: 0,
+        cpu_utilization: None,
+        limits: Default::default(),
+        dump: None,
+        auth: Default::default(),
+        priority_only: false,
+        audit_dir: None,
+        cpu_fallback: false,
+        verify_proofs: false,
+        preempt_window_post: false,
+        payload_dirs: vec![],
+        mdns: false,
+        runtime: Default::default(),
+        task_queue_capacity: 64,
+    })
+    .unwrap()
 }
 
 #[test]
@@ -74,11 +106,11 @@ fn test_lock_server_if_free() -> Result<()> {
             break;
         }
         let task_id = Uuid::new_v4().to_string();
-        let req = Request::new(GetWorkerStatusRequest { task_id });
+        let req = Request::new(GetWorkerStatusRequest { task_id, ..Default::default() });
         rt.block_on(async {
             match c.lock_server_if_free(req).await {
                 Ok(res) => {
-                    println!("{}", res.into_inner().msg)
+                    println!("{}", res.into_inner().status)
                 }
                 Err(s) => {
                     println!("{}", s.message())
@@ -92,11 +124,11 @@ fn test_lock_server_if_free() -> Result<()> {
     }
     rt.block_on(async { tokio::time::sleep(Duration::from_secs(10)).await });
     let task_id = Uuid::new_v4().to_string();
-    let req = Request::new(GetWorkerStatusRequest { task_id });
+    let req = Request::new(GetWorkerStatusRequest { task_id, ..Default::default() });
     rt.block_on(async {
         match c.lock_server_if_free(req).await {
             Ok(res) => {
-                println!("{}", res.into_inner().msg)
+                println!("{}", res.into_inner().status)
             }
             Err(s) => {
                 println!("{}", s.message())
@@ -124,7 +156,7 @@ fn test_unlock_server() -> Result<()> {
     //     rt.block_on(async {
     //         match c.lock_server_if_free(req1).await {
     //             Ok(res) => {
-    //                 println!("{}", res.into_inner().msg)
+    //                 println!("{}", res.into_inner().status)
     //             }
     //             Err(s) => {
     //                 println!("{}", s.message())
@@ -142,7 +174,7 @@ fn test_unlock_server() -> Result<()> {
     //
     //         match c.lock_server_if_free(req2).await {
     //             Ok(res) => {
-    //                 println!("{}", res.into_inner().msg)
+    //                 println!("{}", res.into_inner().status)
     //             }
     //             Err(s) => {
     //                 println!("{}", s.message())
@@ -154,15 +186,15 @@ fn test_unlock_server() -> Result<()> {
     let task_id = Uuid::new_v4().to_string();
     let task_id2 = Uuid::new_v4().to_string();
     let task_id3 = Uuid::new_v4().to_string();
-    let req1 = Request::new(GetWorkerStatusRequest { task_id: task_id.clone() });
-    let req2 = Request::new(GetWorkerStatusRequest { task_id: task_id2 });
-    let req3 = Request::new(GetWorkerStatusRequest { task_id: task_id3.clone() });
+    let req1 = Request::new(GetWorkerStatusRequest { task_id: task_id.clone(), ..Default::default() });
+    let req2 = Request::new(GetWorkerStatusRequest { task_id: task_id2, ..Default::default() });
+    let req3 = Request::new(GetWorkerStatusRequest { task_id: task_id3.clone(), ..Default::default() });
     let unlock_req1 = Request::new(UnlockServerRequest { task_id });
     let unlock_req2 = Request::new(UnlockServerRequest { task_id: task_id3 });
     rt.block_on(async {
         match c.lock_server_if_free(req1).await {
             Ok(res) => {
-                println!("{}", res.into_inner().msg)
+                println!("{}", res.into_inner().status)
             }
             Err(s) => {
                 println!("{}", s.message())
@@ -180,7 +212,7 @@ fn test_unlock_server() -> Result<()> {
 
         match c.lock_server_if_free(req2).await {
             Ok(res) => {
-                println!("{}", res.into_inner().msg)
+                println!("{}", res.into_inner().status)
             }
             Err(s) => {
                 println!("{}", s.message())
@@ -188,7 +220,7 @@ fn test_unlock_server() -> Result<()> {
         }
         match c.lock_server_if_free(req3).await {
             Ok(res) => {
-                println!("{}", res.into_inner().msg)
+                println!("{}", res.into_inner().status)
             }
             Err(s) => {
                 println!("{}", s.message())
@@ -224,4 +256,333 @@ fn test_get_snark_task_result() -> Result<()> {
     }});
 
     Ok(())
-}
\ No newline at end of file
+}
+#[test]
+fn test_lock_wait_queue() {
+    let mut si = ServerInfo::default();
+    assert_eq!(si.wait_for_lock("a".to_string(), None, Duration::from_secs(10)), 1);
+    assert_eq!(si.wait_for_lock("b".to_string(), None, Duration::from_secs(10)), 2);
+    // asking again keeps the place in line
+    assert_eq!(si.wait_for_lock("a".to_string(), None, Duration::from_secs(20)), 1);
+    assert_eq!(si.lock_wait_position("b"), Some(2));
+
+    si.lock_waiter_time_out = Duration::from_secs(0);
+    std::thread::sleep(Duration::from_millis(10));
+    si.prune_lock_waiters();
+    assert!(si.lock_waiters.is_empty());
+}
+
+#[test]
+fn test_start_draining() {
+    let mut si = ServerInfo::default();
+    si.wait_for_lock("a".to_string(), None, Duration::from_secs(10));
+    si.task_queue.push(window_post_snark_server::tasks::TaskInfo {
+        task_id: "b".to_string(),
+        ..Default::default()
+    });
+    si.start_draining();
+    assert!(si.draining);
+    assert!(si.lock_waiters.is_empty());
+    assert!(si.task_queue.is_empty());
+}
+
+#[test]
+fn test_slot_statuses() {
+    let mut si = ServerInfo::default();
+    si.task_queue.push(window_post_snark_server::tasks::TaskInfo {
+        task_id: "a".to_string(),
+        ..Default::default()
+    });
+    si.slots[0].error = "bad proof".to_string();
+    let slots = si.slot_statuses();
+    assert_eq!(slots.len(), 1);
+    assert_eq!(slots[0].task_id, "");
+    assert_eq!(slots[0].queue_len, 1);
+    assert_eq!(slots[0].last_error, "bad proof");
+}
+
+#[test]
+fn test_slot_timestamps() {
+    let mut si = ServerInfo::default();
+    let slots = si.slot_statuses();
+    assert_eq!(slots[0].created_at_ms, 0);
+    si.slots[0].task_info.created_at = Some(std::time::UNIX_EPOCH + Duration::from_millis(1500));
+    let slots = si.slot_statuses();
+    assert_eq!(slots[0].created_at_ms, 1500);
+    assert_eq!(slots[0].started_at_ms, 0);
+}
+
+#[test]
+fn test_prove_time_out() {
+    let mut si = ServerInfo::default();
+    let started_at = std::time::UNIX_EPOCH + Duration::from_secs(1000);
+    si.slots[0].status = ServerStatus::Working;
+    si.slots[0].task_info.task_id = "a".to_string();
+    si.slots[0].task_info.task_status = TaskStatus::Ready;
+    si.slots[0].task_info.started_at = Some(started_at);
+    // no limit by default
+    assert!(!si.check_prove_time_out(started_at + Duration::from_secs(3600)));
+    si.prove_time_out = Some(Duration::from_secs(60));
+    assert!(!si.check_prove_time_out(started_at + Duration::from_secs(30)));
+    assert!(si.check_prove_time_out(started_at + Duration::from_secs(90)));
+    assert!(si.slots[0].proving_hung);
+    assert_eq!(si.slots[0].task_info.task_status, TaskStatus::TimedOut);
+    assert_eq!(si.slots[0].error_kind, FailureKind::Retryable);
+    // only once
+    assert!(!si.check_prove_time_out(started_at + Duration::from_secs(120)));
+}
+
+#[test]
+fn test_hung_task_returns() {
+    let mut si = ServerInfo::default();
+    si.slots[0].proving_hung = true;
+    // the client fetched the failure, the server waits for the proving thread
+    assert!(!si.release(0));
+    assert_eq!(si.slots[0].status, ServerStatus::Unknown);
+    si.task_queue.push(window_post_snark_server::tasks::TaskInfo {
+        task_id: "b".to_string(),
+        ..Default::default()
+    });
+    // released like any other task, the queued one starts
+    assert!(si.finish_hung_task(0));
+    assert!(!si.slots[0].proving_hung);
+    assert_eq!(si.slots[0].status, ServerStatus::Working);
+    assert_eq!(si.slots[0].task_info.task_id, "b");
+}
+
+fn two_slots() -> ServerInfo {
+    let mut si = ServerInfo::default();
+    si.slots = vec!["01:00", "02:00"]
+        .into_iter()
+        .map(|id| {
+            Slot::new(SlotDevice {
+                device_id: id.to_string(),
+                ..Default::default()
+            })
+        })
+        .collect();
+    si
+}
+
+#[test]
+fn test_slots_lock_on_their_own() {
+    let mut si = two_slots();
+    si.lock_for(0, "a".to_string(), Duration::from_secs(60));
+    assert_eq!(si.status(), ServerStatus::Free);
+    assert_eq!(si.free_slot(), Some(1));
+    si.lock_for(1, "b".to_string(), Duration::from_secs(60));
+    assert_eq!(si.status(), ServerStatus::Locked);
+    assert_eq!(si.slot_of("b"), Some(1));
+    si.slots[0].status = ServerStatus::Working;
+    si.task_queue.push(window_post_snark_server::tasks::TaskInfo {
+        task_id: "c".to_string(),
+        task_status: TaskStatus::Ready,
+        ..Default::default()
+    });
+    // each slot reports its own state and the task holding it
+    let slots = si.slot_statuses();
+    assert_eq!(slots[0].task_id, "a");
+    assert_eq!(slots[1].task_id, "b");
+    assert_eq!(slots[1].status, ServerStatus::Locked.to_string());
+    // the queued task takes whichever slot is released first
+    assert!(si.release(1));
+    assert_eq!(si.slots[1].task_info.task_id, "c");
+    assert!(si.slots[1].has_task_to_start());
+    assert_eq!(si.slots[0].task_info.task_id, "a");
+}
+
+#[test]
+fn test_prove_time_out_of_one_slot() {
+    let mut si = two_slots();
+    let started_at = std::time::UNIX_EPOCH + Duration::from_secs(1000);
+    si.prove_time_out = Some(Duration::from_secs(60));
+    si.slots[0].status = ServerStatus::Working;
+    si.slots[0].task_info.task_id = "a".to_string();
+    si.slots[0].task_info.task_status = TaskStatus::Ready;
+    si.slots[0].task_info.started_at = Some(started_at);
+    si.slots[1].status = ServerStatus::Working;
+    si.task_queue.push(window_post_snark_server::tasks::TaskInfo {
+        task_id: "b".to_string(),
+        ..Default::default()
+    });
+    assert!(si.check_prove_time_out(started_at + Duration::from_secs(90)));
+    // the other slot still proves, the queue waits for it
+    assert!(!si.is_stuck());
+    assert_eq!(si.task_queue.len(), 1);
+    assert!(!si.release(0));
+    assert_eq!(si.slots[0].status, ServerStatus::Unknown);
+    assert_eq!(si.status(), ServerStatus::Working);
+    let slots = si.slot_statuses();
+    assert_eq!(slots.len(), 2);
+    assert_eq!(slots[0].status, ServerStatus::Unknown.to_string());
+    assert_eq!(slots[1].slot, 2);
+    assert_eq!(slots[1].device_id, "02:00");
+    assert_eq!(slots[1].status, ServerStatus::Working.to_string());
+}
+
+#[test]
+fn test_ok_response() {
+    let res = window_post_snark_server::server::ok_response("task a started");
+    // clients from before code still look for the message
+    assert_eq!(res.msg, "ok");
+    assert_eq!(res.code, tonic::Code::Ok as i32);
+    assert_eq!(res.server_version, window_post_snark_server::utils::version());
+    assert_eq!(res.detail, "task a started");
+}
+
+#[test]
+fn test_ack_result() {
+    use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+    use window_post_snark_server::snark_proof_grpc::AckResultRequest;
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    {
+        let mut si = srv.server_info.lock();
+        si.slots[0].status = ServerStatus::Working;
+        si.slots[0].task_info.task_id = "a".to_string();
+        si.slots[0].task_info.task_status = TaskStatus::Done;
+        si.slots[0].task_info.result = vec![1, 2, 3];
+    }
+    let get = || Request::new(GetTaskResultRequest { task_id: "a".to_string() });
+    let ack = || Request::new(AckResultRequest { task_id: "a".to_string() });
+    rt.block_on(async {
+        // the result can be fetched again until it is acked
+        for _ in 0..2 {
+            let res = srv.get_snark_task_result(get()).await.unwrap().into_inner();
+            assert_eq!(res.msg, "ok");
+            assert_eq!(res.result, vec![1, 2, 3]);
+        }
+        assert_eq!(srv.server_info.lock().slots[0].status, ServerStatus::Working);
+        assert_eq!(
+            SnarkTaskService::ack_result(&srv, ack()).await.unwrap().into_inner().code,
+            tonic::Code::Ok as i32
+        );
+        assert_eq!(srv.server_info.lock().slots[0].status, ServerStatus::Free);
+        let err = srv.get_snark_task_result(get()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+        let err = SnarkTaskService::ack_result(&srv, ack()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+    });
+}
+
+#[test]
+fn test_task_timers() {
+    use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let lock = |task_id: &str| {
+        Request::new(GetWorkerStatusRequest {
+            task_id: task_id.to_string(),
+            ..Default::default()
+        })
+    };
+    rt.block_on(async {
+        SnarkTaskService::lock_server_if_free(&srv, lock("a")).await.unwrap();
+        {
+            let mut si = srv.server_info.lock();
+            si.slots[0].status = ServerStatus::Working;
+            si.slots[0].task_info.task_status = TaskStatus::Done;
+            si.slots[0].task_info.timers.done_at = Some(std::time::Instant::now());
+            // the lock ran out long ago, but the task is done just now
+            si.slots[0].lock_time_out = Duration::from_secs(0);
+            si.server_task_get_back_time_out = Duration::from_secs(3600);
+        }
+        // the old lock does not shorten the time to get the result back
+        let status = SnarkTaskService::lock_server_if_free(&srv, lock("b")).await.unwrap().into_inner();
+        assert_eq!(status.status, ServerStatus::Working.to_string());
+        srv.server_info.lock().server_task_get_back_time_out = Duration::from_secs(0);
+        let status = SnarkTaskService::lock_server_if_free(&srv, lock("b")).await.unwrap().into_inner();
+        assert_eq!(status.status, ServerStatus::Free.to_string());
+        assert_eq!(srv.server_info.lock().slots[0].task_info.task_id, "b");
+    });
+}
+
+#[test]
+fn test_panic_holding_server_info() {
+    use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    let server_info = srv.server_info.clone();
+    let res = std::thread::spawn(move || {
+        let _si = server_info.lock();
+        panic!("handler bug");
+    })
+    .join();
+    assert!(res.is_err());
+    // the lock is released by the panic and the server keeps answering
+    let status = rt
+        .block_on(SnarkTaskService::lock_server_if_free(
+            &srv,
+            Request::new(GetWorkerStatusRequest {
+                task_id: "a".to_string(),
+                ..Default::default()
+            }),
+        ))
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.status, ServerStatus::Free.to_string());
+}
+
+#[test]
+fn test_submit_without_lock() {
+    use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+    use window_post_snark_server::snark_proof_grpc::{ApiVersion, PoStConfig, PoStType, SnarkTaskRequestParams};
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    srv.set_task_queue_capacity(2).unwrap();
+    let submit = |task_id: &str| {
+        rt.block_on(srv.do_snark_task(Request::new(SnarkTaskRequestParams {
+            task_id: task_id.to_string(),
+            replicas_len: 1,
+            post_config: Some(PoStConfig {
+                sector_size: 2048,
+                challenge_count: 10,
+                sector_count: 2,
+                typ: PoStType::Window as i32,
+                priority: false,
+                api_version: ApiVersion::V110 as i32,
+            }),
+            ..Default::default()
+        })))
+    };
+    // a free server starts the task right away, the next ones wait in order
+    assert_eq!(submit("a").unwrap().into_inner().queue_position, 0);
+    assert_eq!(srv.server_info.lock().slots[0].status, ServerStatus::Working);
+    assert_eq!(submit("b").unwrap().into_inner().queue_position, 1);
+    assert_eq!(submit("c").unwrap().into_inner().queue_position, 2);
+    assert_eq!(submit("d").unwrap_err().code(), tonic::Code::ResourceExhausted);
+    assert_eq!(srv.server_info.lock().task_queue.task_ids(), vec!["b", "c"]);
+}
+
+#[test]
+fn test_submit_unsupported_sector_size() {
+    use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+    use window_post_snark_server::snark_proof_grpc::{ApiVersion, PoStConfig, PoStType, SnarkTaskRequestParams};
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    // refused up front instead of panicking in the prover on a size it has no shape of
+    let err = rt
+        .block_on(srv.do_snark_task(Request::new(SnarkTaskRequestParams {
+            task_id: "bogus".to_string(),
+            replicas_len: 1,
+            pub_in: vec![1],
+            post_config: Some(PoStConfig {
+                sector_size: 3000,
+                challenge_count: 10,
+                sector_count: 2,
+                typ: PoStType::Window as i32,
+                priority: false,
+                api_version: ApiVersion::V110 as i32,
+            }),
+            ..Default::default()
+        })))
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    assert!(err.message().contains("post_config"));
+    assert_eq!(srv.server_info.lock().slots[0].status, ServerStatus::Free);
+}
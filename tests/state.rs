@@ -0,0 +1,68 @@
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request};
+use window_post_snark_server::auth::{Principal, Role};
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::DumpServerStateRequest;
+use window_post_snark_server::state::ServerState;
+use window_post_snark_server::status::TaskStatus;
+use window_post_snark_server::tasks::TaskInfo;
+
+fn request(role: Role) -> Request<DumpServerStateRequest> {
+    let mut r = Request::new(DumpServerStateRequest {});
+    r.extensions_mut().insert(Principal {
+        subject: "ops".to_string(),
+        role,
+    });
+    r
+}
+
+#[test]
+fn test_dump_server_state() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    {
+        let mut si = srv.server_info.lock();
+        si.lock_for(0, "t1".to_string(), Duration::from_secs(60));
+        // the worker moved the payloads out, their sizes stay
+        si.slots[0].task_info.pub_in_bytes = 10;
+        si.slots[0].task_info.vanilla_proof_bytes = 100;
+        si.slots[0].task_info.task_status = TaskStatus::Working;
+        si.task_queue.push(TaskInfo {
+            task_id: "t2".to_string(),
+            client_id: "miner-2".to_string(),
+            task_status: TaskStatus::Queued,
+            ..Default::default()
+        });
+        si.wait_for_lock("t3".to_string(), None, Duration::from_secs(60));
+    }
+
+    let dump = rt
+        .block_on(srv.dump_server_state(request(Role::Admin)))
+        .unwrap()
+        .into_inner();
+    let state: ServerState = serde_json::from_str(&dump.json).unwrap();
+    assert_eq!(state.status, "Locked");
+    assert_eq!(state.slots.len(), 1);
+    assert_eq!(state.slots[0].status, "Locked");
+    let task = state.slots[0].task.clone().unwrap();
+    assert_eq!(task.task_id, "t1");
+    assert_eq!(task.task_status, "Working");
+    assert_eq!(task.pub_in_bytes, 10);
+    assert_eq!(task.vanilla_proof_bytes, 100);
+    assert!(task.locked_for_ms.is_some());
+    assert_eq!(state.queue.len(), 1);
+    assert_eq!(state.queue[0].client_id, "miner-2");
+    assert_eq!(state.lock_waiters[0].task_id, "t3");
+    // sizes only, never the payloads
+    assert!(!dump.json.contains("\"pub_in\""));
+    assert!(!dump.json.contains("\"vanilla_proof\""));
+
+    let err = rt
+        .block_on(srv.dump_server_state(request(Role::Submitter)))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+}
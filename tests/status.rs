@@ -1,3 +1,4 @@
+use window_post_snark_server::snark_proof_grpc::TaskState;
 use window_post_snark_server::status::{ServerStatus, TaskStatus};
 
 #[test]
@@ -14,3 +15,15 @@ fn test_enum() {
     println!("{}", ServerStatus::default().to_string());
     println!("{}", TaskStatus::default().to_string())
 }
+
+#[test]
+fn test_task_states() {
+    assert!(TaskStatus::Failed.is_failed());
+    assert!(TaskStatus::Cancelled.is_failed());
+    assert!(TaskStatus::TimedOut.is_failed());
+    assert!(!TaskStatus::Done.is_failed());
+    assert!(!TaskStatus::Queued.is_failed());
+    assert_eq!(TaskStatus::Queued.to_string(), "Queued");
+    assert_eq!(TaskStatus::None.to_wire(), TaskState::TaskNone);
+    assert_eq!(TaskStatus::TimedOut.to_wire(), TaskState::TaskTimedOut);
+}
@@ -0,0 +1,216 @@
+use futures::StreamExt;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request};
+use window_post_snark_server::client::task_frames;
+use window_post_snark_server::server::{
+    collect_task_frames, WindowPostSnarkServer, RESULT_CHUNK_LEN, TASK_PAYLOAD_MAX_LEN,
+};
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    GetTaskResultRequest, SnarkTaskRequestParams, SubmitBatchRequest,
+};
+use window_post_snark_server::status::TaskStatus;
+use window_post_snark_server::tasks::Checkpoint;
+
+mod common;
+
+fn params(task_id: &str) -> SnarkTaskRequestParams {
+    SnarkTaskRequestParams {
+        // 5 replicas with 2 sectors per partition make 3 partitions
+        replicas_len: 5,
+        stream_partitions: true,
+        ..common::params(task_id)
+    }
+}
+
+fn result_request(task_id: &str) -> Request<GetTaskResultRequest> {
+    Request::new(GetTaskResultRequest {
+        task_id: task_id.to_string(),
+    })
+}
+
+#[test]
+fn test_checkpoint_watch() {
+    let rt = Runtime::new().unwrap();
+    let checkpoint = Checkpoint::default();
+    checkpoint.push_proof(vec![0; 192]);
+    // a watcher joining late gets the partitions proved so far first
+    let mut rx = checkpoint.watch();
+    checkpoint.push_proof(vec![1; 192]);
+    assert_eq!(rt.block_on(rx.recv()), Some(Ok((0, vec![0; 192]))));
+    assert_eq!(rt.block_on(rx.recv()), Some(Ok((1, vec![1; 192]))));
+
+    // the partitions of the result not streamed yet, then the end
+    let mut proof = vec![0; 192];
+    proof.extend(vec![1; 192]);
+    proof.extend(vec![2; 192]);
+    checkpoint.finish(&proof);
+    assert_eq!(rt.block_on(rx.recv()), Some(Ok((2, vec![2; 192]))));
+    assert_eq!(rt.block_on(rx.recv()), None);
+
+    let checkpoint = Checkpoint::default();
+    let mut rx = checkpoint.watch();
+    checkpoint.fail("gpu gone");
+    assert_eq!(rt.block_on(rx.recv()), Some(Err("gpu gone".to_string())));
+    assert_eq!(rt.block_on(rx.recv()), None);
+}
+
+#[test]
+fn test_stream_task_result() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    rt.block_on(srv.submit_batch(Request::new(SubmitBatchRequest {
+        tasks: vec![params("running"), params("queued")],
+    })))
+    .unwrap();
+    assert!(srv.server_info.lock().slots[0].task_info.stream_partitions);
+
+    let mut running = rt
+        .block_on(srv.stream_task_result(result_request("running")))
+        .unwrap()
+        .into_inner();
+    let mut queued = rt
+        .block_on(srv.stream_task_result(result_request("queued")))
+        .unwrap()
+        .into_inner();
+    let err = rt
+        .block_on(srv.stream_task_result(result_request("unknown")))
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), Code::NotFound);
+
+    // what the worker does as the partitions are proved
+    let mut si = srv.server_info.lock();
+    si.slots[0].task_info.checkpoint.push_proof(vec![0; 192]);
+    si.slots[0].task_info.checkpoint.push_proof(vec![1; 192]);
+    si.slots[0].task_info.checkpoint.push_proof(vec![2; 192]);
+    let result = [vec![0; 192], vec![1; 192], vec![2; 192]].concat();
+    si.slots[0].task_info.checkpoint.finish(&result);
+    si.slots[0].task_info.result = result;
+    si.slots[0].task_info.task_status = TaskStatus::Done;
+    drop(si);
+
+    let proofs = rt.block_on(running.by_ref().collect::<Vec<_>>());
+    assert_eq!(proofs.len(), 3);
+    for (k, p) in proofs.into_iter().enumerate() {
+        let p = p.unwrap();
+        assert_eq!(p.task_id, "running");
+        assert_eq!(p.partition, k as u32);
+        assert_eq!(p.partitions, 3);
+        assert_eq!(p.proof, vec![k as u8; 192]);
+    }
+
+    // a done task streams its result at once
+    let done = rt
+        .block_on(srv.stream_task_result(result_request("running")))
+        .unwrap()
+        .into_inner();
+    assert_eq!(rt.block_on(done.collect::<Vec<_>>()).len(), 3);
+
+    // the stream of a task failing after its first partition ends with the error
+    srv.server_info
+        .lock()
+        .task_queue
+        .get("queued")
+        .unwrap()
+        .checkpoint
+        .push_proof(vec![0; 192]);
+    srv.server_info
+        .lock()
+        .task_queue
+        .get("queued")
+        .unwrap()
+        .checkpoint
+        .fail("proving failed");
+    let first = rt.block_on(queued.next()).unwrap().unwrap();
+    assert_eq!(first.partition, 0);
+    let err = rt.block_on(queued.next()).unwrap().unwrap_err();
+    assert_eq!(err.code(), Code::Aborted);
+    assert!(rt.block_on(queued.next()).is_none());
+}
+
+#[test]
+fn test_task_frames() {
+    let rt = Runtime::new().unwrap();
+    let mut sent = params("chunked");
+    sent.vanilla_proof = (0..250).collect();
+    sent.pub_in = vec![7; 30];
+    let frames = task_frames(sent.clone(), 100);
+    // the params, 3 chunks of vanilla proofs and 1 of public inputs
+    assert_eq!(frames.len(), 5);
+    let received = rt
+        .block_on(collect_task_frames(
+            futures::stream::iter(frames.clone().into_iter().map(Ok)),
+            TASK_PAYLOAD_MAX_LEN,
+        ))
+        .unwrap();
+    assert_eq!(received, sent);
+
+    // payloads over the limit are refused
+    let err = rt
+        .block_on(collect_task_frames(
+            futures::stream::iter(frames.clone().into_iter().map(Ok)),
+            279,
+        ))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    rt.block_on(collect_task_frames(
+        futures::stream::iter(frames.clone().into_iter().map(Ok)),
+        280,
+    ))
+    .unwrap();
+
+    // chunks have to follow the params
+    let err = rt
+        .block_on(collect_task_frames(
+            futures::stream::iter(frames.into_iter().skip(1).map(Ok)),
+            TASK_PAYLOAD_MAX_LEN,
+        ))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = rt
+        .block_on(collect_task_frames(
+            futures::stream::iter(vec![]),
+            TASK_PAYLOAD_MAX_LEN,
+        ))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[test]
+fn test_download_task_result() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    rt.block_on(srv.do_snark_task(Request::new(params("download"))))
+        .unwrap();
+
+    // nothing but the header while the task proves
+    let chunks = rt
+        .block_on(srv.download_task_result(result_request("download")))
+        .unwrap()
+        .into_inner();
+    let chunks = rt.block_on(chunks.collect::<Vec<_>>());
+    assert_eq!(chunks.len(), 1);
+    let header = chunks[0].as_ref().unwrap().header.clone().unwrap();
+    assert_eq!(header.msg, TaskStatus::Working.to_string());
+
+    let result = vec![3; RESULT_CHUNK_LEN + 10];
+    let mut si = srv.server_info.lock();
+    si.slots[0].task_info.result = result.clone();
+    si.slots[0].task_info.task_status = TaskStatus::Done;
+    drop(si);
+    let chunks = rt
+        .block_on(srv.download_task_result(result_request("download")))
+        .unwrap()
+        .into_inner();
+    let chunks = rt.block_on(chunks.collect::<Vec<_>>());
+    assert_eq!(chunks.len(), 3);
+    let header = chunks[0].as_ref().unwrap().header.clone().unwrap();
+    assert_eq!(header.msg, "ok");
+    assert!(header.result.is_empty());
+    let downloaded: Vec<u8> = chunks.into_iter().flat_map(|c| c.unwrap().chunk).collect();
+    assert_eq!(downloaded, result);
+}
@@ -0,0 +1,405 @@
+use filecoin_proofs::{
+    PoStConfig, PoStType, SectorSize, SECTOR_SIZE_2_KIB, WINNING_POST_CHALLENGE_COUNT,
+};
+use std::fs;
+use std::time::Duration;
+use storage_proofs_core::api_version::ApiVersion;
+use window_post_snark_server::gpu::SlotDevice;
+use window_post_snark_server::snark_proof_grpc::SnarkTaskRequestParams;
+use window_post_snark_server::tasks::{
+    check_payload_path, prove_task, prove_task_with_fallback, pub_in_digest, set_task_info,
+    task_threads, PayloadBuffer, TaskInfo, TaskOwners, TaskQueue,
+};
+
+fn task(task_id: &str, priority: bool) -> TaskInfo {
+    client_task(task_id, priority, "")
+}
+
+fn client_task(task_id: &str, priority: bool, client_id: &str) -> TaskInfo {
+    TaskInfo {
+        task_id: task_id.to_string(),
+        priority,
+        client_id: client_id.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_task_queue_priority() {
+    let mut queue = TaskQueue::default();
+    assert_eq!(queue.push(task("a", false)), (1, vec![]));
+    assert_eq!(queue.push(task("b", false)), (2, vec![]));
+    // priority task jumps ahead of both normal tasks
+    assert_eq!(
+        queue.push(task("c", true)),
+        (1, vec!["a".to_string(), "b".to_string()])
+    );
+    // but not ahead of an earlier priority task
+    assert_eq!(
+        queue.push(task("d", true)),
+        (2, vec!["a".to_string(), "b".to_string()])
+    );
+    assert_eq!(queue.position("b"), Some(4));
+    assert_eq!(queue.pop().unwrap().task_id, "c");
+    assert_eq!(queue.pop().unwrap().task_id, "d");
+    assert_eq!(queue.pop().unwrap().task_id, "a");
+    assert_eq!(queue.len(), 1);
+}
+
+#[test]
+fn test_task_queue_capacity() {
+    let mut queue = TaskQueue::with_capacity(2);
+    queue.push(task("a", false));
+    assert!(!queue.is_full());
+    queue.push(task("b", false));
+    assert!(queue.is_full());
+    // a preempted task goes back in even when the queue is full
+    assert_eq!(queue.resume(task("r", false)), 1);
+    assert_eq!(queue.len(), 3);
+    assert!(!TaskQueue::default().is_full());
+}
+
+#[test]
+fn test_task_queue_round_robin_clients() {
+    let mut queue = TaskQueue::default();
+    queue.push(client_task("a0", false, "a"));
+    queue.push(client_task("a1", false, "a"));
+    queue.push(client_task("a2", false, "a"));
+    // the first task of another client only waits behind the first task of "a"
+    assert_eq!(
+        queue.push(client_task("b0", false, "b")),
+        (2, vec!["a1".to_string(), "a2".to_string()])
+    );
+    assert_eq!(
+        queue.push(client_task("b1", false, "b")),
+        (4, vec!["a2".to_string()])
+    );
+    assert_eq!(queue.task_ids(), vec!["a0", "b0", "a1", "b1", "a2"]);
+}
+
+#[test]
+fn test_task_threads() {
+    assert_eq!(task_threads(0, 0), 0);
+    assert_eq!(task_threads(4, 0), 4);
+    assert_eq!(task_threads(0, 8), 8);
+    // a task can ask for less than the server allows, but not for more
+    assert_eq!(task_threads(4, 8), 4);
+    assert_eq!(task_threads(16, 8), 8);
+}
+
+#[test]
+fn test_task_info_wipe() {
+    let mut t = TaskInfo {
+        task_id: "a".to_string(),
+        vanilla_proof: vec![1; 8],
+        pub_in: vec![2; 8],
+        result: vec![3; 8],
+        ..Default::default()
+    };
+    t.wipe();
+    assert!(t.vanilla_proof.is_empty());
+    assert!(t.pub_in.is_empty());
+    assert!(t.result.is_empty());
+    assert_eq!(t.task_id, "a");
+}
+
+#[test]
+fn test_task_payload() {
+    let mut t = TaskInfo {
+        task_id: "a".to_string(),
+        vanilla_proof: vec![1; 8],
+        pub_in: vec![2; 8],
+        ..Default::default()
+    };
+    let payload = t.take_payload();
+    assert!(t.vanilla_proof.is_empty());
+    assert!(t.pub_in.is_empty());
+    assert_eq!(payload.vanilla_proof, vec![1; 8]);
+    assert_eq!(payload.pub_in, vec![2; 8]);
+
+    let mut copy = t.clone();
+    copy.set_payload(payload);
+    assert_eq!(copy.vanilla_proof, vec![1; 8]);
+    assert_eq!(copy.pub_in, vec![2; 8]);
+    assert!(t.vanilla_proof.is_empty());
+}
+
+#[test]
+fn test_pub_in_digest() {
+    let digest = pub_in_digest(b"");
+    // sha256 of nothing
+    assert_eq!(digest[..4], [0xe3, 0xb0, 0xc4, 0x42]);
+    assert_eq!(digest.len(), 32);
+    assert_ne!(pub_in_digest(b"a"), digest);
+}
+
+#[test]
+fn test_task_owners() {
+    let mut owners = TaskOwners::default();
+    let time_out = Duration::from_secs(60);
+    assert!(owners.claim("a", "10.0.0.1", time_out).is_ok());
+    assert!(owners.claim("a", "10.0.0.1", time_out).is_ok());
+    // another peer can not use the id
+    assert!(owners.claim("a", "10.0.0.2", time_out).is_err());
+    assert!(owners.claim("b", "10.0.0.2", time_out).is_ok());
+    // until it was not used for the time out
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(owners
+        .claim("a", "10.0.0.2", Duration::from_millis(1))
+        .is_ok());
+}
+
+#[test]
+fn test_task_is_priority() {
+    assert!(!task("a", false).is_priority());
+    assert!(task("a", true).is_priority());
+    let winning = TaskInfo {
+        post_config: Some(PoStConfig {
+            sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+            challenge_count: WINNING_POST_CHALLENGE_COUNT,
+            sector_count: 1,
+            typ: PoStType::Winning,
+            priority: false,
+            api_version: ApiVersion::V1_1_0,
+        }),
+        ..Default::default()
+    };
+    assert!(winning.is_priority());
+}
+
+#[test]
+fn test_task_queue_resume() {
+    let mut winning = task("w", false);
+    winning.post_config = Some(PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        sector_count: 1,
+        typ: PoStType::Winning,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    });
+    let mut queue = TaskQueue::default();
+    queue.push(task("p", true));
+    queue.push(task("a", false));
+    queue.push(winning);
+    assert_eq!(queue.take_winning().unwrap().task_id, "w");
+    assert!(queue.take_winning().is_none());
+    // a preempted task goes behind the priority tasks only
+    assert_eq!(queue.resume(task("r", false)), 2);
+    assert_eq!(queue.task_ids(), vec!["p", "r", "a"]);
+}
+
+#[test]
+fn test_no_cpu_fallback_for_bad_tasks() {
+    // only failures the gpu may be to blame for are proved again
+    let mut t = task("a", false);
+    t.vanilla_proof = vec![1; 16];
+    let checkpoint = t.checkpoint.clone();
+    let err = prove_task_with_fallback(t, true, &SlotDevice::default()).unwrap_err();
+    assert!(err.to_string().contains("no post config"));
+    // the payloads are not copied for the retry, they are back in the checkpoint
+    assert_eq!(
+        checkpoint.take_payload().unwrap().vanilla_proof,
+        vec![1; 16]
+    );
+}
+
+#[test]
+fn test_failed_task_keeps_payload() {
+    let mut t = task("a", false);
+    t.vanilla_proof = vec![1; 16];
+    let checkpoint = t.checkpoint.clone();
+    assert!(prove_task(t).is_err());
+    // a failed task is dumped from its checkpoint, not from a copy made before proving
+    let payload = checkpoint.take_payload().unwrap();
+    assert_eq!(payload.vanilla_proof, vec![1; 16]);
+    assert!(checkpoint.take_payload().is_none());
+}
+
+#[test]
+fn test_cancel_token() {
+    let t = task("a", false);
+    let running = t.clone();
+    assert!(running.cancel.check("a").is_ok());
+    // cancelling the task held by the server stops the clone being proved
+    t.cancel.cancel();
+    assert!(running.cancel.is_cancelled());
+    let err = running.cancel.check("a").unwrap_err();
+    assert_eq!(err.to_string(), "task a was cancelled");
+}
+
+#[test]
+fn test_check_payload_path() {
+    let shared = tempfile::tempdir().unwrap();
+    let other = tempfile::tempdir().unwrap();
+    let dirs = vec![shared.path().canonicalize().unwrap()];
+    fs::write(shared.path().join("vanilla"), b"v").unwrap();
+    fs::write(other.path().join("vanilla"), b"v").unwrap();
+
+    let p =
+        check_payload_path("vanilla_proof_path", &shared.path().join("vanilla"), &dirs).unwrap();
+    assert_eq!(p, dirs[0].join("vanilla"));
+    // .. does not lead out of a payload dir
+    let escape = shared
+        .path()
+        .join("..")
+        .join(other.path().file_name().unwrap())
+        .join("vanilla");
+    assert!(check_payload_path("vanilla_proof_path", &escape, &dirs).is_err());
+    assert!(
+        check_payload_path("vanilla_proof_path", &other.path().join("vanilla"), &dirs).is_err()
+    );
+    assert!(
+        check_payload_path("vanilla_proof_path", &shared.path().join("missing"), &dirs).is_err()
+    );
+    // no payload dirs, no paths
+    assert!(check_payload_path("vanilla_proof_path", &shared.path().join("vanilla"), &[]).is_err());
+}
+
+#[test]
+fn test_vanilla_proof_path_or_bytes() {
+    let params = SnarkTaskRequestParams {
+        task_id: "a".to_string(),
+        vanilla_proof: vec![1],
+        vanilla_proof_path: "/shared/vanilla".to_string(),
+        ..Default::default()
+    };
+    let err = set_task_info(params).unwrap_err();
+    assert!(err.to_string().contains("vanilla_proof_path"));
+}
+
+#[test]
+fn test_payload_buffer_reuse() {
+    let dir = tempfile::tempdir().unwrap();
+    let big = dir.path().join("big");
+    let small = dir.path().join("small");
+    fs::write(&big, vec![7u8; 1 << 16]).unwrap();
+    fs::write(&small, b"vanilla").unwrap();
+    // a sector size no other test reads payloads of
+    let sector_size = 3 << 10;
+
+    let mut buf = PayloadBuffer::take(sector_size);
+    assert_eq!(buf.read_file(&big).unwrap().len(), 1 << 16);
+    drop(buf);
+
+    // the next task of the size reads into the same allocation
+    let mut buf = PayloadBuffer::take(sector_size);
+    assert!(buf.capacity() >= 1 << 16);
+    assert_eq!(buf.read_file(&small).unwrap(), b"vanilla");
+    assert!(buf.read_file(&dir.path().join("missing")).is_err());
+    drop(buf);
+
+    // other sizes do not share it
+    assert_eq!(PayloadBuffer::take(sector_size << 1).capacity(), 0);
+}
+
+fn window_params(pub_in_sectors: u64, replicas_len: u32) -> SnarkTaskRequestParams {
+    partition_params(pub_in_sectors, replicas_len, None)
+}
+
+fn partition_params(
+    pub_in_sectors: u64,
+    replicas_len: u32,
+    k: Option<usize>,
+) -> SnarkTaskRequestParams {
+    use filecoin_hashers::poseidon::PoseidonDomain;
+    use storage_proofs_core::sector::SectorId;
+    use storage_proofs_post::fallback::{PublicInputs, PublicSector};
+    let pub_in = PublicInputs::<PoseidonDomain> {
+        randomness: PoseidonDomain::default(),
+        prover_id: PoseidonDomain::default(),
+        sectors: (0..pub_in_sectors)
+            .map(|id| PublicSector {
+                id: SectorId::from(id),
+                comm_r: PoseidonDomain::default(),
+            })
+            .collect(),
+        k,
+    };
+    let post_config = PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        challenge_count: 10,
+        sector_count: 2,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    };
+    SnarkTaskRequestParams {
+        task_id: "a".to_string(),
+        pub_in: window_post_snark_server::codec::encode(
+            window_post_snark_server::codec::Encoding::Json,
+            &pub_in,
+        )
+        .unwrap(),
+        vanilla_proof: vec![1],
+        replicas_len,
+        post_config: Some(window_post_snark_server::codec::post_config_to_proto(
+            &post_config,
+        )),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_replicas_len_matches_pub_in() {
+    assert!(set_task_info(window_params(3, 3)).is_ok());
+    let err = set_task_info(window_params(3, 2)).unwrap_err();
+    assert!(err.to_string().contains("replicas_len"));
+    assert!(err.to_string().contains("3 sectors"));
+    let err = set_task_info(window_params(3, 0)).unwrap_err();
+    assert!(err.to_string().contains("replicas_len"));
+}
+
+#[test]
+fn test_partitions_override() {
+    // 3 sectors of 2 per partition make 2 partitions
+    let task = set_task_info(window_params(3, 3)).unwrap();
+    let post_config = task.post_config.clone().unwrap();
+    assert_eq!(task.partitions(&post_config), Some(2));
+    let mut params = window_params(3, 3);
+    params.partitions = 3;
+    let task = set_task_info(params.clone()).unwrap();
+    assert_eq!(task.partitions(&post_config), Some(3));
+    // too few to hold the sectors
+    params.partitions = 1;
+    let err = set_task_info(params).unwrap_err();
+    assert!(err.to_string().contains("partitions"));
+    // a single partition is None, as get_partitions_for_window_post has it
+    let task = set_task_info(window_params(1, 1)).unwrap();
+    assert_eq!(task.partitions(&post_config), None);
+}
+
+#[test]
+fn test_single_partition_of_sector_set() {
+    // partition 2 of a deadline, the way lotus sends one partition to each server
+    let task = set_task_info(partition_params(2, 2, Some(2))).unwrap();
+    let post_config = task.post_config.clone().unwrap();
+    assert_eq!(task.partition_index, Some(2));
+    assert_eq!(task.partitions(&post_config), None);
+    // partitions is of the whole sector set then
+    let mut params = partition_params(2, 2, Some(2));
+    params.partitions = 3;
+    let task = set_task_info(params.clone()).unwrap();
+    assert_eq!(task.partitions(&post_config), None);
+    params.partitions = 2;
+    let err = set_task_info(params).unwrap_err();
+    assert!(err.to_string().contains("pub_in"));
+    // more sectors than one partition holds
+    let err = set_task_info(partition_params(3, 3, Some(0))).unwrap_err();
+    assert!(err.to_string().contains("partitions"));
+}
+
+#[test]
+fn test_winning_post_task() {
+    let mut params = window_params(1, 1);
+    let mut post_config = params.post_config.clone().unwrap();
+    post_config.typ = window_post_snark_server::snark_proof_grpc::PoStType::Winning as i32;
+    post_config.sector_count = 1;
+    params.post_config = Some(post_config);
+    let task = set_task_info(params.clone()).unwrap();
+    assert!(task.is_winning());
+    assert_eq!(task.partitions(task.post_config.as_ref().unwrap()), None);
+    params.partitions = 2;
+    let err = set_task_info(params).unwrap_err();
+    assert!(err.to_string().contains("one partition"));
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request};
+use window_post_snark_server::auth::{Principal, Role};
+use window_post_snark_server::server::{
+    WindowPostSnarkServer, SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT,
+    SERVER_LOCK_TIME_OUT_DEFAULT,
+};
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::SetServerTimeoutsRequest;
+
+fn request(role: Role, req: SetServerTimeoutsRequest) -> Request<SetServerTimeoutsRequest> {
+    let mut r = Request::new(req);
+    r.extensions_mut().insert(Principal {
+        subject: "ops".to_string(),
+        role,
+    });
+    r
+}
+
+#[test]
+fn test_set_server_timeouts() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+
+    // nothing set, only tells the time outs in effect
+    let timeouts = rt
+        .block_on(srv.set_server_timeouts(request(Role::Admin, Default::default())))
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        timeouts.server_lock_time_out_ms,
+        SERVER_LOCK_TIME_OUT_DEFAULT.as_millis() as u64
+    );
+
+    let timeouts = rt
+        .block_on(srv.set_server_timeouts(request(
+            Role::Admin,
+            SetServerTimeoutsRequest {
+                server_task_get_back_time_out_ms: 300_000,
+                server_lock_time_out_max_ms: 600_000,
+                ..Default::default()
+            },
+        )))
+        .unwrap()
+        .into_inner();
+    assert_eq!(timeouts.server_task_get_back_time_out_ms, 300_000);
+    assert_eq!(timeouts.server_lock_time_out_max_ms, 600_000);
+    assert_eq!(
+        timeouts.server_exit_time_out_after_task_done_ms,
+        SERVER_EXIT_TIME_OUT_AFTER_TASK_DONE_DEFAULT.as_millis() as u64
+    );
+    {
+        let si = srv.server_info.lock();
+        assert_eq!(si.server_task_get_back_time_out, Duration::from_secs(300));
+        assert_eq!(si.server_lock_time_out_max, Duration::from_secs(600));
+        assert_eq!(si.server_lock_time_out, SERVER_LOCK_TIME_OUT_DEFAULT);
+    }
+
+    // a lock time out beyond the maximum, or too short, is refused and nothing is changed
+    for req in [
+        SetServerTimeoutsRequest {
+            server_lock_time_out_ms: 700_000,
+            server_task_get_back_time_out_ms: 120_000,
+            ..Default::default()
+        },
+        SetServerTimeoutsRequest {
+            server_lock_time_out_max_ms: 5_000,
+            ..Default::default()
+        },
+        SetServerTimeoutsRequest {
+            server_exit_time_out_after_task_done_ms: 1,
+            server_task_get_back_time_out_ms: 120_000,
+            ..Default::default()
+        },
+    ]
+    .iter()
+    {
+        let err = rt
+            .block_on(srv.set_server_timeouts(request(Role::Admin, req.clone())))
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        let si = srv.server_info.lock();
+        assert_eq!(si.server_task_get_back_time_out, Duration::from_secs(300));
+        assert_eq!(si.server_lock_time_out_max, Duration::from_secs(600));
+    }
+
+    let err = rt
+        .block_on(srv.set_server_timeouts(request(
+            Role::Submitter,
+            SetServerTimeoutsRequest {
+                server_task_get_back_time_out_ms: 1,
+                ..Default::default()
+            },
+        )))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert_eq!(
+        srv.server_info.lock().server_task_get_back_time_out,
+        Duration::from_secs(300)
+    );
+}
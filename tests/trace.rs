@@ -0,0 +1,45 @@
+use tonic::metadata::MetadataMap;
+use window_post_snark_server::trace::{TraceContext, TRACEPARENT_KEY};
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+#[test]
+fn test_parse_traceparent() {
+    let t = TraceContext::parse(TRACEPARENT).unwrap();
+    assert_eq!(t.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(t.parent_id, "00f067aa0ba902b7");
+    assert!(t.sampled());
+    assert_eq!(t.to_string(), TRACEPARENT);
+}
+
+#[test]
+fn test_reject_malformed_traceparent() {
+    // all zero trace id
+    assert!(
+        TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+    );
+    // upper case hex
+    assert!(
+        TraceContext::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none()
+    );
+    // version 00 has no more fields
+    assert!(TraceContext::parse(&format!("{}-extra", TRACEPARENT)).is_none());
+    assert!(
+        TraceContext::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none()
+    );
+    assert!(TraceContext::parse("").is_none());
+    // later versions may add fields
+    assert!(
+        TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra")
+            .is_some()
+    );
+}
+
+#[test]
+fn test_trace_from_metadata() {
+    let mut metadata = MetadataMap::new();
+    assert!(TraceContext::from_metadata(&metadata).is_none());
+    metadata.insert(TRACEPARENT_KEY, TRACEPARENT.parse().unwrap());
+    let t = TraceContext::from_metadata(&metadata).unwrap();
+    assert_eq!(t.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+}
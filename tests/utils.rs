@@ -0,0 +1,26 @@
+use std::env;
+use window_post_snark_server::utils::{build_info, lock_file_paths, version};
+
+#[test]
+fn test_lock_file_paths() {
+    env::remove_var("GPU_DEVICE_ORDINAL");
+    env::set_var("CUDA_VISIBLE_DEVICES", "0, 1");
+    let paths = lock_file_paths();
+    assert_eq!(paths.len(), 2);
+    assert!(paths[0].ends_with(".fil_wdpost_server.gpu-0.lock"));
+    assert!(paths[1].ends_with(".fil_wdpost_server.gpu-1.lock"));
+
+    env::set_var("CUDA_VISIBLE_DEVICES", "");
+    let paths = lock_file_paths();
+    assert_eq!(paths.len(), 1);
+    assert!(paths[0].ends_with(".fil_wdpost_server.lock"));
+}
+
+#[test]
+fn test_build_info() {
+    let build = build_info();
+    assert_eq!(build.version, version());
+    assert!(build.version.starts_with(env!("CARGO_PKG_VERSION")));
+    assert!(build.features.iter().all(|f| !f.is_empty()));
+    assert!(!build.features.contains(&"default".to_string()));
+}
@@ -0,0 +1,71 @@
+use filecoin_proofs::{
+    PoStConfig, PoStType, SectorSize, SECTOR_SIZE_2_KIB, WINDOW_POST_CHALLENGE_COUNT,
+};
+use storage_proofs_core::api_version::ApiVersion;
+use window_post_snark_server::codec::post_config_to_proto;
+use window_post_snark_server::snark_proof_grpc::{
+    PartitionVanillaProof, SectorVanillaProof, SnarkTaskRequestParams, VanillaProofs,
+};
+use window_post_snark_server::tasks::set_task_info;
+use window_post_snark_server::vanilla::check_vanilla_proofs;
+
+fn post_config() -> PoStConfig {
+    PoStConfig {
+        sector_size: SectorSize(SECTOR_SIZE_2_KIB),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        sector_count: 2,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    }
+}
+
+fn proofs(partitions: usize, sectors: usize, challenges: usize) -> VanillaProofs {
+    let sector = SectorVanillaProof {
+        comm_c: vec![0; 32],
+        comm_r_last: vec![0; 32],
+        inclusion_proofs: vec![vec![]; challenges],
+    };
+    VanillaProofs {
+        partitions: vec![
+            PartitionVanillaProof {
+                sectors: vec![sector; sectors],
+            };
+            partitions
+        ],
+    }
+}
+
+#[test]
+fn test_check_vanilla_proofs() {
+    let c = post_config();
+    assert!(check_vanilla_proofs(&proofs(2, 2, WINDOW_POST_CHALLENGE_COUNT), &c, 2).is_ok());
+    assert!(check_vanilla_proofs(&proofs(1, 2, WINDOW_POST_CHALLENGE_COUNT), &c, 2).is_err());
+    // the last partition is padded, it has all the sectors of a partition as well
+    assert!(check_vanilla_proofs(&proofs(2, 1, WINDOW_POST_CHALLENGE_COUNT), &c, 2).is_err());
+    assert!(check_vanilla_proofs(&proofs(2, 2, 1), &c, 2).is_err());
+
+    let mut p = proofs(1, 2, WINDOW_POST_CHALLENGE_COUNT);
+    p.partitions[0].sectors[1].comm_c = vec![0; 31];
+    assert!(check_vanilla_proofs(&p, &c, 1).is_err());
+}
+
+#[test]
+fn test_vanilla_proofs_refused_on_submit() {
+    let params = SnarkTaskRequestParams {
+        task_id: "typed".to_string(),
+        replicas_len: 3,
+        post_config: Some(post_config_to_proto(&post_config())),
+        // 3 replicas of 2 sectors per partition need 2 partitions
+        vanilla_proofs: Some(proofs(1, 2, WINDOW_POST_CHALLENGE_COUNT)),
+        ..Default::default()
+    };
+    let err = set_task_info(params.clone()).unwrap_err();
+    assert!(err.to_string().contains("vanilla_proofs"));
+
+    let params = SnarkTaskRequestParams {
+        vanilla_proof: vec![1],
+        ..params
+    };
+    assert!(set_task_info(params).is_err());
+}
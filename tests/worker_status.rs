@@ -0,0 +1,143 @@
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tonic::{Code, Request};
+use window_post_snark_server::gpu::SlotDevice;
+use window_post_snark_server::server::WindowPostSnarkServer;
+use window_post_snark_server::snark_proof_grpc::snark_task_service_server::SnarkTaskService;
+use window_post_snark_server::snark_proof_grpc::{
+    GetWorkerStatusRequest, LockServerWaitRequest, UnlockServerRequest,
+};
+use window_post_snark_server::status::ServerStatus;
+
+fn status_request(task_id: &str) -> Request<GetWorkerStatusRequest> {
+    Request::new(GetWorkerStatusRequest {
+        task_id: task_id.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_get_worker_status_does_not_lock() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+
+    let status = rt
+        .block_on(srv.get_worker_status(status_request("t1")))
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.status, ServerStatus::Free.to_string());
+    assert_eq!(status.slot, 0);
+    assert_eq!(status.slots.len(), 1);
+    assert!(!status.build.unwrap().version.is_empty());
+    // asking did not lock the server
+    assert_eq!(srv.server_info.lock().slots[0].status, ServerStatus::Free);
+
+    rt.block_on(srv.lock_server_if_free(status_request("t1")))
+        .unwrap();
+    let status = rt
+        .block_on(srv.get_worker_status(status_request("t2")))
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.status, ServerStatus::Locked.to_string());
+    assert_eq!(status.lock_task_id, "t1");
+    assert_eq!(status.lock_queue_position, 0);
+    assert_eq!(status.slot, 0);
+    {
+        let si = srv.server_info.lock();
+        assert_eq!(si.slots[0].status, ServerStatus::Locked);
+        assert_eq!(si.slots[0].task_info.task_id, "t1");
+        assert!(si.lock_waiters.is_empty());
+    }
+}
+
+#[test]
+fn test_priority_only_refuses_lock() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    srv.set_priority_only(true).unwrap();
+
+    let err = rt
+        .block_on(srv.lock_server_if_free(status_request("t1")))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    let wait = Request::new(LockServerWaitRequest {
+        task_id: "t1".to_string(),
+        ..Default::default()
+    });
+    let err = rt.block_on(srv.lock_server_wait(wait)).unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    // the refused task neither took the lock nor waits for it
+    {
+        let si = srv.server_info.lock();
+        assert_eq!(si.slots[0].status, ServerStatus::Free);
+        assert!(si.lock_waiters.is_empty());
+    }
+
+    let status = rt
+        .block_on(
+            srv.lock_server_if_free(Request::new(GetWorkerStatusRequest {
+                task_id: "t2".to_string(),
+                priority: true,
+                ..Default::default()
+            })),
+        )
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.status, ServerStatus::Free.to_string());
+    assert_eq!(srv.server_info.lock().slots[0].task_info.task_id, "t2");
+}
+
+fn lock_request(task_id: &str, slot: u32) -> Request<GetWorkerStatusRequest> {
+    Request::new(GetWorkerStatusRequest {
+        task_id: task_id.to_string(),
+        slot,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_lock_given_slot() {
+    let rt = Runtime::new().unwrap();
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let srv = WindowPostSnarkServer::new(tx);
+    srv.set_slots(vec![SlotDevice::default(), SlotDevice::default()])
+        .unwrap();
+
+    let status = rt
+        .block_on(srv.lock_server_if_free(lock_request("t1", 2)))
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.status, ServerStatus::Free.to_string());
+    assert_eq!(status.slot, 2);
+    // the other slot is still free, taken by a request for any slot
+    let status = rt
+        .block_on(srv.lock_server_if_free(lock_request("t2", 0)))
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.slot, 1);
+    // a task asking for a locked slot waits for that one
+    let status = rt
+        .block_on(srv.lock_server_if_free(lock_request("t3", 2)))
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.status, ServerStatus::Locked.to_string());
+    assert_eq!(status.slot, 0);
+    assert_eq!(status.lock_queue_position, 1);
+    rt.block_on(srv.unlock_server(Request::new(UnlockServerRequest {
+        task_id: "t2".to_string(),
+    })))
+    .unwrap();
+    assert!(srv.server_info.lock().slot_of("t3").is_none());
+    rt.block_on(srv.unlock_server(Request::new(UnlockServerRequest {
+        task_id: "t1".to_string(),
+    })))
+    .unwrap();
+    assert_eq!(srv.server_info.lock().slot_of("t3"), Some(1));
+
+    let err = rt
+        .block_on(srv.lock_server_if_free(lock_request("t4", 3)))
+        .unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+}